@@ -52,6 +52,7 @@ fn read_flow(args: &mut impl Iterator<Item = String>) -> Result<(), Box<dyn std:
         GetLastOptions {
             limit: 1,
             include_payload: true,
+            ..GetLastOptions::default()
         },
     )?;
 
@@ -1,46 +1,114 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Condvar, Mutex, TryLockError};
 use std::time::{Duration, Instant};
 
-use byteorder::{LittleEndian, WriteBytesExt};
-use rustls::pki_types::ServerName;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
 use rustls::{ClientConfig, ClientConnection};
 
 use crate::error::{Error, Result};
+use crate::fstree::{HashAlgorithmBlake3, HashAlgorithmId};
 use crate::protocol::{
-    read_frame, write_frame, Frame, DEFAULT_DIAL_TIMEOUT, DEFAULT_REQUEST_TIMEOUT, MSG_ERROR,
-    MSG_HELLO,
+    read_frame, write_frame, Frame, DEFAULT_BLOB_CHUNK_SIZE, DEFAULT_DIAL_TIMEOUT,
+    DEFAULT_IO_BUFFER_SIZE, DEFAULT_REQUEST_TIMEOUT, FRAME_HEADER_LEN, MAX_FRAME_SIZE,
+    MSG_APPEND_TURN, MSG_ATTACH_FS, MSG_ATTACH_FS_HEAD, MSG_BEGIN_BLOB, MSG_BLOB_CHUNK,
+    MSG_COMMIT_BLOB, MSG_CTX_CREATE, MSG_CTX_FORK, MSG_ERROR, MSG_HEALTH, MSG_HELLO, MSG_PUT_BLOB,
+    MSG_TRIM_CONTEXT,
 };
+use crate::trace::traced;
 
 pub type ClientOption = Arc<dyn Fn(&mut ClientOptions) + Send + Sync>;
 
+/// How often a blocked read wakes to check its `RequestContext` for
+/// cancellation while no frames are arriving.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Message types a `with_read_only` client refuses to send.
+const MUTATING_MSG_TYPES: &[u16] = &[
+    MSG_CTX_CREATE,
+    MSG_CTX_FORK,
+    MSG_APPEND_TURN,
+    MSG_ATTACH_FS,
+    MSG_PUT_BLOB,
+    MSG_BEGIN_BLOB,
+    MSG_BLOB_CHUNK,
+    MSG_COMMIT_BLOB,
+    MSG_ATTACH_FS_HEAD,
+    MSG_TRIM_CONTEXT,
+];
+
 #[derive(Debug, Clone)]
 pub struct ClientOptions {
     pub dial_timeout: Duration,
     pub request_timeout: Duration,
     pub client_tag: String,
+    /// Blobs larger than this are uploaded in chunks of this size.
+    pub blob_chunk_size: usize,
+    /// Bytes read from the socket at a time, and the most of a request
+    /// gathered into one write; see `with_io_buffer_size`.
+    pub io_buffer_size: usize,
+    /// Session id presented in HELLO so the server can rebind that session's
+    /// state to the new connection. Zero asks for a fresh session.
+    pub resume_session_id: u64,
+    /// Token the server issued with `resume_session_id`, proving the session
+    /// is ours to resume.
+    pub resume_token: [u8; 16],
+    /// Refuse mutating requests; see `with_read_only`.
+    pub read_only: bool,
+    /// PEM CA bundles trusted by `dial_tls`; see `with_ca_file`.
+    pub(crate) ca_bundles: Vec<CaBundle>,
+    /// Whether `dial_tls` trusts the system's native roots. Unset means yes
+    /// unless a CA bundle was given.
+    pub(crate) native_roots: std::option::Option<bool>,
+    /// rustls provider for `dial_tls`; see `with_crypto_provider`.
+    pub(crate) crypto_provider: std::option::Option<Arc<CryptoProvider>>,
     pub(crate) tls_config: std::option::Option<Arc<ClientConfig>>,
 }
 
+#[derive(Debug, Clone)]
+pub(crate) enum CaBundle {
+    File(PathBuf),
+    Pem(Vec<u8>),
+}
+
 impl Default for ClientOptions {
     fn default() -> Self {
         Self {
             dial_timeout: DEFAULT_DIAL_TIMEOUT,
             request_timeout: DEFAULT_REQUEST_TIMEOUT,
             client_tag: String::new(),
+            blob_chunk_size: DEFAULT_BLOB_CHUNK_SIZE,
+            io_buffer_size: DEFAULT_IO_BUFFER_SIZE,
+            resume_session_id: 0,
+            resume_token: [0; 16],
+            read_only: false,
+            ca_bundles: Vec::new(),
+            native_roots: None,
+            crypto_provider: None,
             tls_config: None,
         }
     }
 }
 
+/// Bounds the whole dial: TCP connect, the TLS handshake, and HELLO must all
+/// finish within `timeout` (or `request_timeout`, if that is shorter).
 pub fn with_dial_timeout(timeout: Duration) -> ClientOption {
     Arc::new(move |opts| opts.dial_timeout = timeout)
 }
 
+/// The longest each request may take, from sending to the full response
+/// (default 30s). A `RequestContext` deadline can shorten it for one call,
+/// and `RequestContext::override_request_timeout` can replace it for one
+/// call.
 pub fn with_request_timeout(timeout: Duration) -> ClientOption {
     Arc::new(move |opts| opts.request_timeout = timeout)
 }
@@ -50,6 +118,79 @@ pub fn with_client_tag(tag: impl Into<String>) -> ClientOption {
     Arc::new(move |opts| opts.client_tag = tag.clone())
 }
 
+/// Sets the size above which `put_blob` switches to the chunked upload
+/// protocol, and the size of each chunk. Must leave room for the frame
+/// header within `MAX_FRAME_SIZE`.
+pub fn with_blob_chunk_size(bytes: usize) -> ClientOption {
+    Arc::new(move |opts| opts.blob_chunk_size = bytes)
+}
+
+/// Sizes the connection's I/O buffering (default 64 KiB). Reads pull up to
+/// this many bytes per call, so a large or multi-frame response such as a
+/// `get_last` page takes few syscalls; a request's header and as much of its
+/// payload as fits go out in a single write, which over TLS also means fewer
+/// records. Buffered bytes never outlive a read deadline: frames already
+/// buffered are returned first and only then does the client block on the
+/// socket, with the request's timeout applied.
+pub fn with_io_buffer_size(bytes: usize) -> ClientOption {
+    Arc::new(move |opts| opts.io_buffer_size = bytes)
+}
+
+/// Asks the server to resume `session_id` rather than start a new session,
+/// presenting the `resume_token` the server issued with it (see
+/// `Client::resume_token`). If the server still holds that session (it
+/// disconnected recently, no other connection has claimed it, and the token
+/// and client tag match), `Client::session_id` keeps the old id and the
+/// contexts it created count as live again; otherwise a fresh session is
+/// issued. Servers that predate session resume ignore the request.
+pub fn with_resume_session(session_id: u64, resume_token: [u8; 16]) -> ClientOption {
+    Arc::new(move |opts| {
+        opts.resume_session_id = session_id;
+        opts.resume_token = resume_token;
+    })
+}
+
+/// Makes the client refuse every call that would change server state
+/// (`create_context`, `fork_context`, `append_turn`, `put_blob`,
+/// `attach_fs` and the like, including `raw_request` with those message
+/// types) with `Error::ReadOnly` before anything is sent. Reads work
+/// normally. This is a client-side guardrail for code handed a client it
+/// should only read with, not a substitute for server-side access control.
+pub fn with_read_only() -> ClientOption {
+    Arc::new(|opts| opts.read_only = true)
+}
+
+/// Trusts the CA certificates in a PEM file for `dial_tls`, instead of the
+/// system's native roots (see `with_native_roots` to keep those too). The
+/// file is read at dial time; an unreadable file or one with no valid
+/// certificates fails the dial with `Error::Tls`. May be given more than once.
+pub fn with_ca_file(path: impl Into<PathBuf>) -> ClientOption {
+    let path = path.into();
+    Arc::new(move |opts| opts.ca_bundles.push(CaBundle::File(path.clone())))
+}
+
+/// Like `with_ca_file`, with the PEM bundle supplied in memory.
+pub fn with_ca_pem(pem: impl Into<Vec<u8>>) -> ClientOption {
+    let pem = pem.into();
+    Arc::new(move |opts| opts.ca_bundles.push(CaBundle::Pem(pem.clone())))
+}
+
+/// Whether `dial_tls` trusts the system's native roots. They are trusted by
+/// default unless `with_ca_file`/`with_ca_pem` is given; pass true to trust
+/// them alongside a CA bundle.
+pub fn with_native_roots(enabled: bool) -> ClientOption {
+    Arc::new(move |opts| opts.native_roots = Some(enabled))
+}
+
+/// The rustls crypto provider `dial_tls` builds its TLS configuration
+/// with. Without this option the process-wide default is used if one is
+/// installed (`CryptoProvider::install_default`); if none is, ring is used
+/// for the connection without being installed, so an application that
+/// installs another provider (such as aws-lc-rs) later is not affected.
+pub fn with_crypto_provider(provider: Arc<CryptoProvider>) -> ClientOption {
+    Arc::new(move |opts| opts.crypto_provider = Some(provider.clone()))
+}
+
 #[cfg(test)]
 pub(crate) fn with_tls_config(config: Arc<ClientConfig>) -> ClientOption {
     Arc::new(move |opts| opts.tls_config = Some(config.clone()))
@@ -59,6 +200,8 @@ pub(crate) fn with_tls_config(config: Arc<ClientConfig>) -> ClientOption {
 pub struct RequestContext {
     deadline: std::option::Option<Instant>,
     cancelled: Arc<AtomicBool>,
+    /// Replaces the client's request timeout; see `override_request_timeout`.
+    request_timeout: std::option::Option<Duration>,
 }
 
 #[derive(Clone, Debug)]
@@ -77,6 +220,7 @@ impl RequestContext {
         Self {
             deadline: None,
             cancelled: Arc::new(AtomicBool::new(false)),
+            request_timeout: None,
         }
     }
 
@@ -84,6 +228,7 @@ impl RequestContext {
         Self {
             deadline: Some(deadline),
             cancelled: Arc::new(AtomicBool::new(false)),
+            request_timeout: None,
         }
     }
 
@@ -97,6 +242,7 @@ impl RequestContext {
             Self {
                 deadline: None,
                 cancelled: cancelled.clone(),
+                request_timeout: None,
             },
             CancelHandle { cancelled },
         )
@@ -106,9 +252,32 @@ impl RequestContext {
         self.cancelled.load(Ordering::SeqCst)
     }
 
+    /// The flag set by this context's `CancelHandle`, for APIs that take a
+    /// plain cancellation flag such as `fstree::capture_cancellable`.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
     pub fn deadline(&self) -> std::option::Option<Instant> {
         self.deadline
     }
+
+    /// Returns this context with `timeout` used in place of the client's
+    /// request timeout (`with_request_timeout`) for every request made with
+    /// it, whether longer or shorter. Use it for single calls known to be
+    /// slow, such as exporting a large context; the client default still
+    /// applies to requests made with other contexts, so nothing else loses
+    /// its safety timeout. The context's own deadline, if any, still caps
+    /// each request. Clones of the returned context keep the override.
+    pub fn override_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// The per-call request timeout set by `override_request_timeout`.
+    pub fn request_timeout(&self) -> std::option::Option<Duration> {
+        self.request_timeout
+    }
 }
 
 impl Default for RequestContext {
@@ -117,13 +286,53 @@ impl Default for RequestContext {
     }
 }
 
+/// Server status returned by `Client::health`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// How long the server process has been running.
+    pub uptime: Duration,
+    /// The server's version string.
+    pub version: String,
+    /// Whether the server could create a file in its data directory.
+    pub writable: bool,
+}
+
+/// A connection shared by any number of threads. Requests are pipelined:
+/// each caller writes its frame and then waits for the response with its
+/// `req_id`, taking a turn reading from the connection when no one else is
+/// and handing frames meant for other callers over through `in_flight`.
+///
+/// A `Client` is one connection, and its `req_id`s are scoped to it: they
+/// restart at 1 on every dial, and responses are only ever matched against
+/// requests sent on the same connection. Reconnecting wrappers replace the
+/// whole `Client`, so a late response from an earlier connection can never
+/// complete a request sent on a later one.
 pub struct Client {
     conn: Mutex<Connection>,
+    /// Write half of a plain TCP connection, so requests can be sent while
+    /// another caller is blocked reading. TLS connections write through
+    /// `conn`.
+    writer: std::option::Option<Mutex<TcpStream>>,
+    /// Requests awaiting a response, with the response once another caller
+    /// has read it.
+    in_flight: Mutex<HashMap<u64, std::option::Option<Frame>>>,
+    delivered: Condvar,
+    /// Last `req_id` issued on this connection; see `next_req_id`.
     req_id: AtomicU64,
     closed: AtomicBool,
     timeout: Duration,
     session_id: AtomicU64,
+    /// Secret that resumes this session after a disconnect, from HELLO.
+    resume_token: Mutex<[u8; 16]>,
+    /// Algorithm the server hashes filesystem blobs with, from HELLO.
+    hash_algorithm: AtomicU8,
     client_tag: String,
+    addr: String,
+    peer_addr: std::option::Option<SocketAddr>,
+    local_addr: std::option::Option<SocketAddr>,
+    pub(crate) blob_chunk_size: usize,
+    io_buffer_size: usize,
+    read_only: bool,
 }
 
 impl Client {
@@ -139,10 +348,72 @@ impl Client {
         self.session_id.load(Ordering::SeqCst)
     }
 
+    /// Token to pass to `with_resume_session` along with `session_id` when
+    /// reconnecting. All zeros if the server does not issue one.
+    pub fn resume_token(&self) -> [u8; 16] {
+        self.resume_token
+            .lock()
+            .map(|token| *token)
+            .unwrap_or_default()
+    }
+
+    /// Id of the algorithm the server hashes filesystem blobs with, as
+    /// reported in its HELLO response. Servers that do not report one use
+    /// BLAKE3.
+    pub fn hash_algorithm(&self) -> HashAlgorithmId {
+        self.hash_algorithm.load(Ordering::SeqCst)
+    }
+
     pub fn client_tag(&self) -> &str {
         &self.client_tag
     }
 
+    /// The address this client was dialed with.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// The server address the connection was actually made to, which may be
+    /// any one of the addresses `addr` resolved to.
+    pub fn peer_addr(&self) -> std::option::Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Whether the client was dialed `with_read_only`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The local end of the connection.
+    pub fn local_addr(&self) -> std::option::Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Sends an arbitrary message and returns the raw response frame.
+    ///
+    /// This is an escape hatch for exercising message types that do not yet
+    /// have a typed wrapper (e.g. against a custom server build). The frame
+    /// layout and message numbering carry no stability guarantees; prefer the
+    /// typed methods whenever one exists. Server `ERROR` frames are still
+    /// surfaced as `Error::Server`.
+    pub fn raw_request(
+        &self,
+        ctx: &RequestContext,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Frame> {
+        self.send_request_with_flags(ctx, msg_type, flags, payload)
+    }
+
+    /// Checks that the server is up and answering, without touching any
+    /// context. Cheap enough to call before starting a long session or as a
+    /// periodic liveness probe.
+    pub fn health(&self, ctx: &RequestContext) -> Result<HealthStatus> {
+        let frame = self.send_request(ctx, MSG_HEALTH, &[])?;
+        parse_health(&frame.payload)
+    }
+
     pub(crate) fn send_request(
         &self,
         ctx: &RequestContext,
@@ -158,6 +429,27 @@ impl Client {
         msg_type: u16,
         flags: u16,
         payload: &[u8],
+    ) -> Result<Frame> {
+        if self.read_only && MUTATING_MSG_TYPES.contains(&msg_type) {
+            return Err(Error::ReadOnly);
+        }
+        let span = trace_span!(
+            "cxdb.request",
+            msg_type,
+            flags,
+            req_id = tracing::field::Empty
+        );
+        traced(span, || {
+            self.send_request_inner(ctx, msg_type, flags, payload)
+        })
+    }
+
+    fn send_request_inner(
+        &self,
+        ctx: &RequestContext,
+        msg_type: u16,
+        flags: u16,
+        payload: &[u8],
     ) -> Result<Frame> {
         if self.closed.load(Ordering::SeqCst) {
             return Err(Error::ClientClosed);
@@ -167,16 +459,31 @@ impl Client {
             return Err(Error::Cancelled);
         }
 
-        let effective_deadline = self.compute_deadline(ctx)?;
-
-        let mut conn = self.conn.lock().map_err(|_| Error::ClientClosed)?;
-        conn.set_deadline(Some(effective_deadline))?;
+        // The server drops the connection on an oversized frame; fail before
+        // sending with an error that says why.
+        if payload.len() as u64 > MAX_FRAME_SIZE as u64 {
+            return Err(Error::PayloadTooLarge {
+                size: payload.len() as u64,
+                limit: MAX_FRAME_SIZE as u64,
+            });
+        }
 
-        let req_id = self.req_id.fetch_add(1, Ordering::SeqCst) + 1;
-        write_frame(&mut *conn, msg_type, flags, req_id, payload)?;
-        let frame = read_frame(&mut *conn)?;
+        let effective_deadline = self.compute_deadline(ctx)?;
 
-        conn.set_deadline(None)?;
+        let req_id = {
+            let mut in_flight = self.in_flight.lock().map_err(|_| Error::ClientClosed)?;
+            let req_id = self.next_req_id(&in_flight);
+            in_flight.insert(req_id, None);
+            req_id
+        };
+        trace_record!("req_id", req_id);
+        let result = self
+            .write_request(msg_type, flags, req_id, payload, effective_deadline)
+            .and_then(|_| self.wait_response(ctx, req_id, effective_deadline));
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(&req_id);
+        }
+        let frame = result?;
 
         if frame.header.msg_type == MSG_ERROR {
             return Err(parse_server_error(&frame.payload));
@@ -185,9 +492,265 @@ impl Client {
         Ok(frame)
     }
 
+    /// Issues the next request id. Ids only have to be unique among the
+    /// requests in flight on this connection, so the counter wraps around,
+    /// skipping 0 and any id whose request is still waiting for a response.
+    fn next_req_id(&self, in_flight: &HashMap<u64, std::option::Option<Frame>>) -> u64 {
+        loop {
+            let req_id = self.req_id.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+            if req_id != 0 && !in_flight.contains_key(&req_id) {
+                return req_id;
+            }
+        }
+    }
+
+    fn write_request(
+        &self,
+        msg_type: u16,
+        flags: u16,
+        req_id: u64,
+        payload: &[u8],
+        deadline: Instant,
+    ) -> Result<()> {
+        match &self.writer {
+            Some(writer) => {
+                let mut writer = writer.lock().map_err(|_| Error::ClientClosed)?;
+                let timeout = deadline
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1));
+                writer.set_write_timeout(Some(timeout)).map_err(Error::Io)?;
+                write_frame_buffered(
+                    &mut *writer,
+                    self.io_buffer_size,
+                    msg_type,
+                    flags,
+                    req_id,
+                    payload,
+                )
+            }
+            None => {
+                let mut conn = self.conn.lock().map_err(|_| Error::ClientClosed)?;
+                conn.set_deadline(Some(deadline))?;
+                write_frame_buffered(
+                    &mut *conn,
+                    self.io_buffer_size,
+                    msg_type,
+                    flags,
+                    req_id,
+                    payload,
+                )
+            }
+        }
+    }
+
+    /// Waits for the response to `req_id`, reading from the connection
+    /// whenever no other caller is.
+    fn wait_response(&self, ctx: &RequestContext, req_id: u64, deadline: Instant) -> Result<Frame> {
+        // Poll in short slices rather than one blocking read so cancel()
+        // takes effect while the request is still in flight.
+        loop {
+            if ctx.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+            let slice_end = deadline.min(now + CANCEL_POLL_INTERVAL);
+
+            let mut in_flight = self.in_flight.lock().map_err(|_| Error::ClientClosed)?;
+            if let Some(slot) = in_flight.get_mut(&req_id) {
+                if let Some(frame) = slot.take() {
+                    return Ok(frame);
+                }
+            }
+            let mut conn = match self.conn.try_lock() {
+                Ok(conn) => conn,
+                Err(TryLockError::WouldBlock) => {
+                    // Whoever is reading notifies once it hands the
+                    // connection back or delivers a frame.
+                    let _ = self
+                        .delivered
+                        .wait_timeout(in_flight, slice_end.saturating_duration_since(now));
+                    continue;
+                }
+                Err(TryLockError::Poisoned(_)) => return Err(Error::ClientClosed),
+            };
+            drop(in_flight);
+
+            let polled = conn
+                .set_read_deadline(Some(slice_end))
+                .and_then(|_| conn.poll_frame());
+            drop(conn);
+            let own = match polled {
+                Ok(Some(frame)) if frame.header.req_id == req_id => Some(frame),
+                Ok(Some(frame)) => {
+                    self.deliver(frame);
+                    None
+                }
+                Ok(None) => None,
+                Err(err) => {
+                    self.notify_waiters();
+                    return Err(err);
+                }
+            };
+            self.notify_waiters();
+            if let Some(frame) = own {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Hands `frame` to the caller waiting on its `req_id`. Returns it back
+    /// when no one is, e.g. a late response to a request that was cancelled
+    /// or timed out.
+    fn deliver(&self, frame: Frame) -> std::option::Option<Frame> {
+        let Ok(mut in_flight) = self.in_flight.lock() else {
+            return Some(frame);
+        };
+        match in_flight.get_mut(&frame.header.req_id) {
+            Some(slot) => {
+                *slot = Some(frame);
+                None
+            }
+            None => Some(frame),
+        }
+    }
+
+    fn notify_waiters(&self) {
+        let _guard = self.in_flight.lock();
+        self.delivered.notify_all();
+    }
+
+    /// Sends a subscription request and hands the server's acknowledgement,
+    /// then each pushed frame, to `on_push` until it returns false or `ctx` is cancelled or reaches its deadline.
+    ///
+    /// The connection is held for the whole subscription. On the way out a
+    /// frame carrying `stop_flag` is sent and pushes are drained until the
+    /// server echoes it, leaving the connection ready for the next request.
+    pub(crate) fn send_subscription(
+        &self,
+        ctx: &RequestContext,
+        msg_type: u16,
+        payload: &[u8],
+        stop_flag: u16,
+        mut on_push: impl FnMut(Frame) -> Result<bool>,
+    ) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::ClientClosed);
+        }
+
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let effective_deadline = self.compute_deadline(ctx)?;
+
+        // Holding the write half keeps other callers from sending requests
+        // while the subscription owns the connection; responses to requests
+        // already in flight are still handed over as they arrive.
+        let _writer = match &self.writer {
+            Some(writer) => Some(writer.lock().map_err(|_| Error::ClientClosed)?),
+            None => None,
+        };
+        let mut conn = self.conn.lock().map_err(|_| Error::ClientClosed)?;
+        conn.set_deadline(Some(effective_deadline))?;
+
+        let req_id = {
+            let in_flight = self.in_flight.lock().map_err(|_| Error::ClientClosed)?;
+            self.next_req_id(&in_flight)
+        };
+        write_frame_buffered(
+            &mut *conn,
+            self.io_buffer_size,
+            msg_type,
+            0,
+            req_id,
+            payload,
+        )?;
+        let ack = loop {
+            match conn.poll_frame()? {
+                Some(frame) if frame.header.req_id == req_id => break frame,
+                Some(frame) => {
+                    self.deliver(frame);
+                }
+                None => return Err(Error::Timeout),
+            }
+        };
+        if ack.header.msg_type == MSG_ERROR {
+            conn.set_deadline(None)?;
+            return Err(parse_server_error(&ack.payload));
+        }
+
+        // The ack carries the subscription's starting state (e.g. the current
+        // head for WATCH_HEAD), so it goes to `on_push` like any push.
+        let result = match on_push(ack) {
+            Ok(true) => loop {
+                if ctx.is_cancelled() {
+                    break Ok(());
+                }
+                let now = Instant::now();
+                let wait = match ctx.deadline() {
+                    Some(deadline) if deadline <= now => break Ok(()),
+                    Some(deadline) => (deadline - now).min(CANCEL_POLL_INTERVAL),
+                    None => CANCEL_POLL_INTERVAL,
+                };
+                conn.set_deadline(Some(now + wait))?;
+                let Some(frame) = conn.poll_frame()? else {
+                    continue;
+                };
+                let frame = if frame.header.req_id == req_id {
+                    frame
+                } else {
+                    match self.deliver(frame) {
+                        Some(frame) => frame,
+                        None => continue,
+                    }
+                };
+                if frame.header.msg_type == MSG_ERROR {
+                    break Err(parse_server_error(&frame.payload));
+                }
+                match on_push(frame) {
+                    Ok(true) => {}
+                    Ok(false) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            },
+            other => other.map(|_| ()),
+        };
+
+        let stop_deadline = Instant::now() + self.timeout;
+        conn.set_deadline(Some(stop_deadline))?;
+        write_frame_buffered(
+            &mut *conn,
+            self.io_buffer_size,
+            msg_type,
+            stop_flag,
+            req_id,
+            &[],
+        )?;
+        loop {
+            match conn.poll_frame()? {
+                Some(frame)
+                    if frame.header.msg_type == msg_type && frame.header.flags & stop_flag != 0 =>
+                {
+                    break
+                }
+                Some(frame) => {
+                    self.deliver(frame);
+                }
+                None if Instant::now() >= stop_deadline => return Err(Error::Timeout),
+                None => {}
+            }
+        }
+        conn.set_deadline(None)?;
+
+        result
+    }
+
     fn compute_deadline(&self, ctx: &RequestContext) -> Result<Instant> {
         let now = Instant::now();
-        let mut deadline = now + self.timeout;
+        let mut deadline = now + ctx.request_timeout.unwrap_or(self.timeout);
         if let Some(ctx_deadline) = ctx.deadline() {
             if ctx_deadline < deadline {
                 deadline = ctx_deadline;
@@ -199,14 +762,26 @@ impl Client {
         Ok(deadline)
     }
 
-    fn send_hello(&self, client_tag: &str) -> Result<()> {
-        let mut payload = Vec::with_capacity(2 + 2 + client_tag.len() + 4);
+    /// Sends HELLO, giving up at `dial_deadline` if that comes before the
+    /// request timeout.
+    fn send_hello(
+        &self,
+        client_tag: &str,
+        resume_session_id: u64,
+        resume_token: &[u8; 16],
+        dial_deadline: Instant,
+    ) -> Result<()> {
+        let mut payload = Vec::with_capacity(2 + 2 + client_tag.len() + 4 + 8 + 16);
         payload.write_u16::<LittleEndian>(1)?; // protocol version
         payload.write_u16::<LittleEndian>(client_tag.len() as u16)?;
         payload.extend_from_slice(client_tag.as_bytes());
         payload.write_u32::<LittleEndian>(0)?; // no metadata
+        if resume_session_id != 0 {
+            payload.write_u64::<LittleEndian>(resume_session_id)?;
+            payload.extend_from_slice(resume_token);
+        }
 
-        let ctx = RequestContext::with_timeout(self.timeout);
+        let ctx = RequestContext::with_deadline(dial_deadline);
         let frame = self.send_request_with_flags(&ctx, MSG_HELLO, 0, &payload)?;
 
         if frame.header.msg_type != MSG_HELLO {
@@ -222,75 +797,141 @@ impl Client {
             let session = u64::from_le_bytes(bytes);
             self.session_id.store(session, Ordering::SeqCst);
         }
+        if let Some(&hash_algorithm) = frame.payload.get(10) {
+            self.hash_algorithm.store(hash_algorithm, Ordering::SeqCst);
+        }
+        if let Some(token) = frame.payload.get(11..27) {
+            if let Ok(mut guard) = self.resume_token.lock() {
+                guard.copy_from_slice(token);
+            }
+        }
 
         Ok(())
     }
 }
 
 pub fn dial(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
+    let span = trace_span!(
+        "cxdb.dial",
+        addr,
+        tls = false,
+        session_id = tracing::field::Empty
+    );
+    traced(span, || dial_inner(addr, opts))
+}
+
+fn dial_inner(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
     let mut options = ClientOptions::default();
     for opt in opts {
         opt(&mut options);
     }
 
-    let stream = connect_tcp(addr, options.dial_timeout)?;
-    let conn = Connection::Plain(stream);
+    let dial_deadline = Instant::now() + options.dial_timeout;
+    let stream = connect_tcp(addr, dial_deadline)?;
+    let writer = stream.try_clone().map_err(Error::Io)?;
+    let conn = Connection::new(Stream::Plain(stream), options.io_buffer_size);
 
     let client = Client {
+        peer_addr: conn.stream.tcp().peer_addr().ok(),
+        local_addr: conn.stream.tcp().local_addr().ok(),
         conn: Mutex::new(conn),
+        writer: Some(Mutex::new(writer)),
+        in_flight: Mutex::new(HashMap::new()),
+        delivered: Condvar::new(),
         req_id: AtomicU64::new(0),
         closed: AtomicBool::new(false),
         timeout: options.request_timeout,
         session_id: AtomicU64::new(0),
+        resume_token: Mutex::new([0; 16]),
+        hash_algorithm: AtomicU8::new(HashAlgorithmBlake3),
         client_tag: options.client_tag.clone(),
+        addr: addr.to_string(),
+        blob_chunk_size: options.blob_chunk_size.max(1),
+        io_buffer_size: options.io_buffer_size,
+        read_only: options.read_only,
     };
 
-    if let Err(err) = client.send_hello(&options.client_tag) {
+    if let Err(err) = client.send_hello(
+        &options.client_tag,
+        options.resume_session_id,
+        &options.resume_token,
+        dial_deadline,
+    ) {
         let _ = client.close();
         return Err(err);
     }
+    trace_record!("session_id", client.session_id());
 
     Ok(client)
 }
 
 pub fn dial_tls(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
-    let _ = rustls::crypto::ring::default_provider().install_default();
+    let span = trace_span!(
+        "cxdb.dial",
+        addr,
+        tls = true,
+        session_id = tracing::field::Empty
+    );
+    traced(span, || dial_tls_inner(addr, opts))
+}
 
+fn dial_tls_inner(addr: &str, opts: impl IntoIterator<Item = ClientOption>) -> Result<Client> {
     let mut options = ClientOptions::default();
     for opt in opts {
         opt(&mut options);
     }
 
-    let stream = connect_tcp(addr, options.dial_timeout)?;
     let config = match options.tls_config.take() {
         Some(cfg) => cfg,
-        None => Arc::new(default_tls_config()?),
+        None => Arc::new(tls_config(&options)?),
     };
+    let dial_deadline = Instant::now() + options.dial_timeout;
+    let stream = connect_tcp(addr, dial_deadline)?;
 
     let server_name = server_name_from_addr(addr)?;
     let conn =
         ClientConnection::new(config, server_name).map_err(|err| Error::Tls(err.to_string()))?;
 
     let stream = rustls::StreamOwned::new(conn, stream);
+    let conn = Connection::new(Stream::Tls(Box::new(stream)), options.io_buffer_size);
 
     let client = Client {
-        conn: Mutex::new(Connection::Tls(Box::new(stream))),
+        peer_addr: conn.stream.tcp().peer_addr().ok(),
+        local_addr: conn.stream.tcp().local_addr().ok(),
+        conn: Mutex::new(conn),
+        writer: None,
+        in_flight: Mutex::new(HashMap::new()),
+        delivered: Condvar::new(),
         req_id: AtomicU64::new(0),
         closed: AtomicBool::new(false),
         timeout: options.request_timeout,
         session_id: AtomicU64::new(0),
+        resume_token: Mutex::new([0; 16]),
+        hash_algorithm: AtomicU8::new(HashAlgorithmBlake3),
         client_tag: options.client_tag.clone(),
+        addr: addr.to_string(),
+        blob_chunk_size: options.blob_chunk_size.max(1),
+        io_buffer_size: options.io_buffer_size,
+        read_only: options.read_only,
     };
 
-    if let Err(err) = client.send_hello(&options.client_tag) {
+    if let Err(err) = client.send_hello(
+        &options.client_tag,
+        options.resume_session_id,
+        &options.resume_token,
+        dial_deadline,
+    ) {
         let _ = client.close();
         return Err(err);
     }
+    trace_record!("session_id", client.session_id());
 
     Ok(client)
 }
 
-fn connect_tcp(addr: &str, timeout: Duration) -> Result<TcpStream> {
+/// Connects to the first reachable address for `addr`; all attempts share
+/// the time left until `deadline`.
+fn connect_tcp(addr: &str, deadline: Instant) -> Result<TcpStream> {
     let addrs = addr
         .to_socket_addrs()
         .map_err(Error::Io)?
@@ -298,7 +939,11 @@ fn connect_tcp(addr: &str, timeout: Duration) -> Result<TcpStream> {
 
     let mut last_err = None;
     for socket_addr in addrs {
-        match TcpStream::connect_timeout(&socket_addr, timeout) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::Timeout);
+        }
+        match TcpStream::connect_timeout(&socket_addr, remaining) {
             Ok(stream) => {
                 let _ = stream.set_nodelay(true);
                 return Ok(stream);
@@ -312,35 +957,100 @@ fn connect_tcp(addr: &str, timeout: Duration) -> Result<TcpStream> {
         .unwrap_or(Error::Io(std::io::Error::other("no addresses resolved"))))
 }
 
-fn default_tls_config() -> Result<ClientConfig> {
+fn tls_config(options: &ClientOptions) -> Result<ClientConfig> {
     let mut root_store = rustls::RootCertStore::empty();
-    let certs = rustls_native_certs::load_native_certs();
-    for cert in certs.certs {
-        root_store
-            .add(cert)
-            .map_err(|err| Error::Tls(err.to_string()))?;
+    if options
+        .native_roots
+        .unwrap_or(options.ca_bundles.is_empty())
+    {
+        let certs = rustls_native_certs::load_native_certs();
+        for cert in certs.certs {
+            root_store
+                .add(cert)
+                .map_err(|err| Error::Tls(err.to_string()))?;
+        }
     }
-    let config = ClientConfig::builder()
+    for bundle in &options.ca_bundles {
+        add_ca_bundle(&mut root_store, bundle)?;
+    }
+    let provider = options
+        .crypto_provider
+        .clone()
+        .or_else(|| CryptoProvider::get_default().cloned())
+        .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()));
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|err| Error::Tls(err.to_string()))?
         .with_root_certificates(root_store)
         .with_no_client_auth();
     Ok(config)
 }
 
+fn add_ca_bundle(root_store: &mut rustls::RootCertStore, bundle: &CaBundle) -> Result<()> {
+    let (pem, source) = match bundle {
+        CaBundle::File(path) => {
+            let pem = std::fs::read(path)
+                .map_err(|err| Error::Tls(format!("read CA file {}: {err}", path.display())))?;
+            (pem, path.display().to_string())
+        }
+        CaBundle::Pem(pem) => (pem.clone(), "CA PEM".to_string()),
+    };
+    let mut added = 0;
+    for cert in CertificateDer::pem_slice_iter(&pem) {
+        let cert = cert.map_err(|err| Error::Tls(format!("{source}: {err}")))?;
+        root_store
+            .add(cert)
+            .map_err(|err| Error::Tls(format!("{source}: {err}")))?;
+        added += 1;
+    }
+    if added == 0 {
+        return Err(Error::Tls(format!("{source}: no certificates found")));
+    }
+    Ok(())
+}
+
 fn server_name_from_addr(addr: &str) -> Result<ServerName<'static>> {
-    let host = if addr.starts_with('[') {
+    let host = if let Some(rest) = addr.strip_prefix('[') {
         // IPv6 in brackets
-        addr.split(']')
-            .next()
-            .unwrap_or("[]")
-            .trim_start_matches('[')
+        rest.split_once(']').map(|(host, _)| host).unwrap_or(rest)
+    } else if addr.parse::<IpAddr>().is_ok() {
+        // Bare IP with no port
+        addr
     } else {
         addr.rsplit_once(':').map(|(host, _)| host).unwrap_or(addr)
     };
+    // A zone id (`fe80::1%eth0`) picks the interface for a link-local
+    // address; it is not part of the name the certificate covers.
+    let host = host.split_once('%').map(|(ip, _)| ip).unwrap_or(host);
 
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ServerName::IpAddress(ip.into()));
+    }
     ServerName::try_from(host.to_string())
         .map_err(|_| Error::Tls(format!("invalid server name: {host}")))
 }
 
+fn parse_health(payload: &[u8]) -> Result<HealthStatus> {
+    if payload.len() < 13 {
+        return Err(Error::invalid_response(format!(
+            "health response too short ({} bytes)",
+            payload.len()
+        )));
+    }
+    let mut cursor = std::io::Cursor::new(payload);
+    let uptime_ms = cursor.read_u64::<LittleEndian>()?;
+    let writable = cursor.read_u8()? != 0;
+    let version_len = cursor.read_u32::<LittleEndian>()? as usize;
+    let version = payload
+        .get(13..13 + version_len)
+        .ok_or_else(|| Error::invalid_response("health response version truncated"))?;
+    Ok(HealthStatus {
+        uptime: Duration::from_millis(uptime_ms),
+        version: String::from_utf8_lossy(version).into_owned(),
+        writable,
+    })
+}
+
 fn parse_server_error(payload: &[u8]) -> Error {
     if payload.len() < 8 {
         return Error::server(0, "unknown error");
@@ -355,34 +1065,153 @@ fn parse_server_error(payload: &[u8]) -> Error {
     Error::server(code, detail)
 }
 
-pub(crate) enum Connection {
+/// Accumulates bytes across read timeouts so a timeout that lands mid-frame
+/// does not lose framing.
+struct FrameBuffer {
+    buf: Vec<u8>,
+    /// Scratch space for one socket read.
+    chunk: Vec<u8>,
+}
+
+impl FrameBuffer {
+    fn new(read_size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            chunk: vec![0u8; read_size.max(FRAME_HEADER_LEN)],
+        }
+    }
+
+    /// Returns the next complete frame, or None if the read timed out first.
+    fn poll(&mut self, reader: &mut impl Read) -> Result<std::option::Option<Frame>> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(frame));
+            }
+            match reader.read(&mut self.chunk) {
+                // A clean close between frames is a dropped connection, which
+                // the reconnecting client must recognize to redial.
+                Ok(0) if self.buf.is_empty() => {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "connection closed by server",
+                    )))
+                }
+                Ok(0) => return Err(Error::invalid_response("frame payload truncated")),
+                Ok(n) => self.buf.extend_from_slice(&self.chunk[..n]),
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> Result<std::option::Option<Frame>> {
+        if self.buf.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+        if len > MAX_FRAME_SIZE {
+            return Err(Error::invalid_response(format!(
+                "frame size {} exceeds maximum {}",
+                len, MAX_FRAME_SIZE
+            )));
+        }
+        let total = FRAME_HEADER_LEN + len as usize;
+        if self.buf.len() < total {
+            return Ok(None);
+        }
+        let frame = read_frame(&mut &self.buf[..total])?;
+        self.buf.drain(..total);
+        Ok(Some(frame))
+    }
+}
+
+/// Writes a frame through a buffer of up to `buffer_size` bytes, so the
+/// header and a payload that fits reach the socket in one write. Larger
+/// payloads bypass the buffer rather than being copied.
+fn write_frame_buffered(
+    writer: &mut impl Write,
+    buffer_size: usize,
+    msg_type: u16,
+    flags: u16,
+    req_id: u64,
+    payload: &[u8],
+) -> Result<()> {
+    let capacity = buffer_size.clamp(FRAME_HEADER_LEN, FRAME_HEADER_LEN + payload.len());
+    let mut buffered = std::io::BufWriter::with_capacity(capacity, writer);
+    write_frame(&mut buffered, msg_type, flags, req_id, payload)?;
+    buffered.flush()?;
+    Ok(())
+}
+
+pub(crate) enum Stream {
     Plain(TcpStream),
     Tls(Box<rustls::StreamOwned<ClientConnection, TcpStream>>),
 }
 
+impl Stream {
+    /// The TCP connection underneath, TLS or not.
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+/// A client stream plus any bytes read past the last complete frame.
+pub(crate) struct Connection {
+    stream: Stream,
+    frames: FrameBuffer,
+}
+
 impl Connection {
+    fn new(stream: Stream, read_size: usize) -> Self {
+        Self {
+            stream,
+            frames: FrameBuffer::new(read_size),
+        }
+    }
+
+    /// Reads until a full frame is buffered or the read deadline passes.
+    fn poll_frame(&mut self) -> Result<std::option::Option<Frame>> {
+        self.frames.poll(&mut self.stream)
+    }
+
     fn set_deadline(&mut self, deadline: std::option::Option<Instant>) -> Result<()> {
         let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
-        match self {
-            Connection::Plain(stream) => {
-                stream.set_read_timeout(timeout).map_err(Error::Io)?;
-                stream.set_write_timeout(timeout).map_err(Error::Io)?;
-            }
-            Connection::Tls(stream) => {
-                let tcp = stream.get_mut();
-                tcp.set_read_timeout(timeout).map_err(Error::Io)?;
-                tcp.set_write_timeout(timeout).map_err(Error::Io)?;
-            }
-        }
+        // A zero timeout means "block forever" to the OS; round up instead.
+        let timeout = timeout.map(|t| t.max(Duration::from_millis(1)));
+        let tcp = match &mut self.stream {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_mut(),
+        };
+        tcp.set_read_timeout(timeout).map_err(Error::Io)?;
+        tcp.set_write_timeout(timeout).map_err(Error::Io)?;
         Ok(())
     }
 
+    /// Like `set_deadline`, but leaves the write timeout alone for a
+    /// concurrent writer on the same socket.
+    fn set_read_deadline(&mut self, deadline: std::option::Option<Instant>) -> Result<()> {
+        let timeout = deadline.map(|d| {
+            d.saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1))
+        });
+        self.stream
+            .tcp()
+            .set_read_timeout(timeout)
+            .map_err(Error::Io)
+    }
+
     fn close(&mut self) -> Result<()> {
-        match self {
-            Connection::Plain(stream) => {
-                stream.shutdown(std::net::Shutdown::Both).map_err(Error::Io)
-            }
-            Connection::Tls(stream) => stream
+        match &mut self.stream {
+            Stream::Plain(stream) => stream.shutdown(std::net::Shutdown::Both).map_err(Error::Io),
+            Stream::Tls(stream) => stream
                 .get_mut()
                 .shutdown(std::net::Shutdown::Both)
                 .map_err(Error::Io),
@@ -390,27 +1219,27 @@ impl Connection {
     }
 }
 
-impl std::io::Read for Connection {
+impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         match self {
-            Connection::Plain(stream) => stream.read(buf),
-            Connection::Tls(stream) => stream.read(buf),
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
         }
     }
 }
 
 impl std::io::Write for Connection {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            Connection::Plain(stream) => stream.write(buf),
-            Connection::Tls(stream) => stream.write(buf),
+        match &mut self.stream {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
         }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        match self {
-            Connection::Plain(stream) => stream.flush(),
-            Connection::Tls(stream) => stream.flush(),
+        match &mut self.stream {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
         }
     }
 }
@@ -419,7 +1248,9 @@ impl std::io::Write for Connection {
 mod tests {
     use super::*;
     use crate::protocol::{read_frame, write_frame, FrameHeader, MSG_HELLO};
-    use crate::test_util::{decode_hex, load_fixture};
+    use crate::test_util::{
+        accept_hello, answer_hello, decode_hex, load_fixture, mock_responder, mock_server,
+    };
     use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
     use rustls::ServerConfig;
     use std::net::TcpListener;
@@ -443,6 +1274,25 @@ mod tests {
         assert_eq!(payload, hello_payload(tag));
     }
 
+    #[test]
+    fn server_name_handles_ip_literals_and_zones() {
+        let ip = |s: &str| ServerName::IpAddress(s.parse::<IpAddr>().unwrap().into());
+        assert_eq!(
+            server_name_from_addr("[fe80::1%eth0]:9010").unwrap(),
+            ip("fe80::1")
+        );
+        assert_eq!(server_name_from_addr("[::1]:9010").unwrap(), ip("::1"));
+        assert_eq!(server_name_from_addr("::1").unwrap(), ip("::1"));
+        assert_eq!(
+            server_name_from_addr("10.0.0.5:9010").unwrap(),
+            ip("10.0.0.5")
+        );
+        assert_eq!(
+            server_name_from_addr("cxdb.example.com:9010").unwrap(),
+            ServerName::try_from("cxdb.example.com").unwrap()
+        );
+    }
+
     #[test]
     fn hello_payloads_match_fixtures() {
         let fixture = load_fixture("hello_empty");
@@ -526,17 +1376,12 @@ mod tests {
         let addr = listener.local_addr().unwrap();
 
         let server_handle = thread::spawn(move || {
-            let (tcp, _) = listener.accept().unwrap();
+            let (tcp, client_addr) = listener.accept().unwrap();
             let conn = rustls::ServerConnection::new(server_config).unwrap();
             let mut stream = rustls::StreamOwned::new(conn, tcp.try_clone().unwrap());
 
-            let frame = read_frame(&mut stream).unwrap();
-            assert_eq!(frame.header.msg_type, MSG_HELLO);
-
-            let mut resp = Vec::new();
-            resp.write_u64::<LittleEndian>(123).unwrap();
-            resp.write_u16::<LittleEndian>(1).unwrap();
-            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+            answer_hello(&mut stream, 123);
+            client_addr
         });
 
         let mut root = rustls::RootCertStore::empty();
@@ -550,7 +1395,131 @@ mod tests {
         let client = dial_tls(&addr_str, vec![with_tls_config(client_config)]).unwrap();
         assert_eq!(client.session_id(), 123);
 
+        // "localhost" may resolve to several addresses; report the one used.
+        assert_eq!(client.peer_addr(), Some(addr));
+        assert_eq!(client.local_addr(), Some(server_handle.join().unwrap()));
+    }
+
+    #[test]
+    fn tls_dial_trusts_ca_file() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                rustls::pki_types::PrivateKeyDer::from(
+                    rustls::pki_types::PrivatePkcs8KeyDer::from(key_pair.serialize_der()),
+                ),
+            )
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_handle = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(server_config).unwrap();
+            let mut stream = rustls::StreamOwned::new(conn, tcp);
+            answer_hello(&mut stream, 1);
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, cert.pem()).unwrap();
+        let addr_str = format!("localhost:{}", addr.port());
+        dial_tls(&addr_str, vec![with_ca_file(&ca_path)]).unwrap();
         server_handle.join().unwrap();
+
+        // Bad bundles fail before connecting.
+        let err = dial_tls(&addr_str, vec![with_ca_pem(b"not a certificate".to_vec())])
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Tls(msg) if msg.contains("no certificates")));
+        let err = dial_tls(
+            &addr_str,
+            vec![with_ca_file(dir.path().join("missing.pem"))],
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(err, Error::Tls(msg) if msg.contains("missing.pem")));
+    }
+
+    #[test]
+    fn tls_config_uses_given_crypto_provider() {
+        let mut provider = rustls::crypto::ring::default_provider();
+        provider.cipher_suites.truncate(1);
+        let suite = provider.cipher_suites[0].suite();
+
+        let mut options = ClientOptions::default();
+        for opt in [
+            with_crypto_provider(Arc::new(provider)),
+            with_native_roots(false),
+        ] {
+            opt(&mut options);
+        }
+        let config = tls_config(&options).unwrap();
+        let suites = &config.crypto_provider().cipher_suites;
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suites[0].suite(), suite);
+    }
+
+    #[test]
+    fn dial_timeout_bounds_hello() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            // Accept, then never answer HELLO.
+            let (_stream, _) = listener.accept().unwrap();
+            let _ = done_rx.recv();
+        });
+
+        let start = Instant::now();
+        let err = dial(
+            &addr,
+            vec![
+                with_dial_timeout(Duration::from_millis(200)),
+                with_request_timeout(Duration::from_secs(30)),
+            ],
+        )
+        .err()
+        .expect("dial should time out");
+        assert!(matches!(err, Error::Timeout), "{err:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        drop(done_tx);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn request_timeout_override_applies_to_one_context() {
+        let (addr, server) = mock_responder(|req| {
+            // Answer every request slower than the client's default timeout.
+            thread::sleep(Duration::from_millis(300));
+            let head = crate::test_util::head_payload(1, 2, 1);
+            Some((req.header.msg_type, head))
+        });
+
+        let client = dial(
+            &addr,
+            vec![with_request_timeout(Duration::from_millis(100))],
+        )
+        .unwrap();
+        let slow = RequestContext::background().override_request_timeout(Duration::from_secs(5));
+        assert_eq!(slow.request_timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(client.get_head(&slow, 1).unwrap().head_turn_id, 2);
+
+        let err = client
+            .get_head(&RequestContext::background(), 1)
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout), "{err:?}");
+
+        client.close().unwrap();
+        server.join().unwrap();
     }
 
     #[test]
@@ -562,20 +1531,8 @@ mod tests {
 
     #[test]
     fn error_response_yields_server_error() {
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
-
-        let handle = thread::spawn(move || {
-            let (mut stream, _) = listener.accept().unwrap();
-
-            let frame = read_frame(&mut stream).unwrap();
-            assert_eq!(frame.header.msg_type, MSG_HELLO);
-            let mut resp = Vec::new();
-            resp.write_u64::<LittleEndian>(1).unwrap();
-            resp.write_u16::<LittleEndian>(1).unwrap();
-            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
-
-            let req = read_frame(&mut stream).unwrap();
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
             let mut err_payload = Vec::new();
             err_payload.write_u32::<LittleEndian>(404).unwrap();
             let detail = b"not found";
@@ -584,7 +1541,7 @@ mod tests {
                 .unwrap();
             err_payload.extend_from_slice(detail);
             write_frame(
-                &mut stream,
+                stream,
                 crate::protocol::MSG_ERROR,
                 0,
                 req.header.req_id,
@@ -593,7 +1550,7 @@ mod tests {
             .unwrap();
         });
 
-        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        let client = dial(&addr, Vec::new()).unwrap();
         let ctx = RequestContext::background();
         let payload = 0u64.to_le_bytes();
         let err = client
@@ -610,6 +1567,259 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn raw_request_returns_response_frame() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, 200);
+            assert_eq!(req.header.flags, 3);
+            assert_eq!(req.payload, b"ping");
+            write_frame(stream, 201, 7, req.header.req_id, b"pong").unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let frame = client
+            .raw_request(&RequestContext::background(), 200, 3, b"ping")
+            .unwrap();
+        assert_eq!(frame.header.msg_type, 201);
+        assert_eq!(frame.header.flags, 7);
+        assert_eq!(frame.payload, b"pong");
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn req_ids_wrap_around_skipping_zero_and_ids_in_flight() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, hello) = accept_hello(&listener, 1);
+            let mut ids = vec![hello.header.req_id];
+            for _ in 0..2 {
+                let req = read_frame(&mut stream).unwrap();
+                ids.push(req.header.req_id);
+                write_frame(&mut stream, 200, 0, req.header.req_id, b"ok").unwrap();
+            }
+            ids
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        client.req_id.store(u64::MAX - 1, Ordering::SeqCst);
+        // A request still waiting on id 1 keeps it from being reissued.
+        client.in_flight.lock().unwrap().insert(1, None);
+        let ctx = RequestContext::background();
+        client.raw_request(&ctx, 200, 0, &[]).unwrap();
+        client.raw_request(&ctx, 200, 0, &[]).unwrap();
+
+        assert_eq!(handle.join().unwrap(), vec![1, u64::MAX, 2]);
+    }
+
+    #[test]
+    fn read_only_client_refuses_mutations_without_sending() {
+        let (addr, handle) = mock_server(move |stream| {
+            // The refused calls never reach the server: the first frame
+            // after HELLO is the read.
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, crate::protocol::MSG_GET_HEAD);
+            let head = crate::test_util::head_payload(1, 5, 4);
+            write_frame(stream, req.header.msg_type, 0, req.header.req_id, &head).unwrap();
+        });
+
+        let client = dial(&addr, vec![with_read_only()]).unwrap();
+        assert!(client.is_read_only());
+        let ctx = RequestContext::background();
+        assert!(matches!(
+            client.create_context(&ctx, 0),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(client.fork_context(&ctx, 5), Err(Error::ReadOnly)));
+        let append = crate::turn::AppendRequest::new(1, "cxdb.ConversationItem", 3, vec![0x80]);
+        assert!(matches!(
+            client.append_turn(&ctx, &append),
+            Err(Error::ReadOnly)
+        ));
+        let blob = crate::fs::PutBlobRequest {
+            data: b"data".to_vec(),
+        };
+        assert!(matches!(client.put_blob(&ctx, &blob), Err(Error::ReadOnly)));
+
+        assert_eq!(client.get_head(&ctx, 1).unwrap().head_turn_id, 5);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn mutating_msg_types_cover_every_write() {
+        // Every message type in protocol.rs, and whether it changes state.
+        let classified: &[(&str, bool)] = &[
+            ("MSG_HELLO", false),
+            ("MSG_CTX_CREATE", true),
+            ("MSG_CTX_FORK", true),
+            ("MSG_GET_HEAD", false),
+            ("MSG_APPEND_TURN", true),
+            ("MSG_GET_LAST", false),
+            ("MSG_GET_BLOB", false),
+            ("MSG_ATTACH_FS", true),
+            ("MSG_PUT_BLOB", true),
+            ("MSG_WATCH_HEAD", false),
+            ("MSG_BEGIN_BLOB", true),
+            ("MSG_BLOB_CHUNK", true),
+            ("MSG_COMMIT_BLOB", true),
+            ("MSG_HAS_BLOBS", false),
+            ("MSG_GET_HEAD_AT", false),
+            ("MSG_LIST_CONTEXTS", false),
+            ("MSG_ATTACH_FS_HEAD", true),
+            ("MSG_TRIM_CONTEXT", true),
+            ("MSG_FIND_SNAPSHOT_REFS", false),
+            ("MSG_HEALTH", false),
+            ("MSG_VERIFY_SNAPSHOT", false),
+            ("MSG_ERROR", false),
+        ];
+        let declared: Vec<(&str, u16)> = include_str!("protocol.rs")
+            .lines()
+            .filter_map(|line| {
+                let rest = line.strip_prefix("pub const MSG_")?;
+                let (name, value) = rest.split_once(": u16 = ")?;
+                let name = &line["pub const ".len().."pub const MSG_".len() + name.len()];
+                Some((name, value.trim_end_matches(';').parse().ok()?))
+            })
+            .collect();
+        assert_eq!(declared.len(), classified.len());
+        for (name, value) in declared {
+            let (_, mutating) = classified
+                .iter()
+                .find(|(known, _)| *known == name)
+                .unwrap_or_else(|| panic!("{name} is not classified"));
+            assert_eq!(
+                MUTATING_MSG_TYPES.contains(&value),
+                *mutating,
+                "{name} ({value})"
+            );
+        }
+    }
+
+    #[test]
+    fn health_parses_server_status() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_HEALTH);
+            assert!(req.payload.is_empty());
+            let mut resp = Vec::new();
+            resp.write_u64::<LittleEndian>(90_500).unwrap();
+            resp.write_u8(1).unwrap();
+            resp.write_u32::<LittleEndian>(5).unwrap();
+            resp.extend_from_slice(b"1.2.3");
+            write_frame(stream, MSG_HEALTH, 0, req.header.req_id, &resp).unwrap();
+
+            let req = read_frame(stream).unwrap();
+            write_frame(stream, MSG_HEALTH, 0, req.header.req_id, &[0u8; 12]).unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let status = client.health(&ctx).unwrap();
+        assert_eq!(
+            status,
+            HealthStatus {
+                uptime: Duration::from_millis(90_500),
+                version: "1.2.3".into(),
+                writable: true,
+            }
+        );
+        assert!(matches!(
+            client.health(&ctx),
+            Err(Error::InvalidResponse(_))
+        ));
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn io_buffer_size_keeps_framing_and_deadlines() {
+        let big = vec![7u8; 300 * 1024];
+        let expected = big.clone();
+
+        let (addr, handle) = mock_server(move |stream| {
+            // Two responses in one write: the second must stay buffered.
+            let first = read_frame(stream).unwrap();
+            let second = read_frame(stream).unwrap();
+            let mut out = Vec::new();
+            write_frame(&mut out, 200, 0, first.header.req_id, &big).unwrap();
+            write_frame(&mut out, 200, 0, second.header.req_id, b"small").unwrap();
+            stream.write_all(&out).unwrap();
+
+            // Never answer the third request.
+            let _ = read_frame(stream);
+            let _ = read_frame(stream);
+        });
+
+        let client = Arc::new(
+            dial(
+                &addr,
+                vec![
+                    with_io_buffer_size(16),
+                    with_request_timeout(Duration::from_millis(300)),
+                ],
+            )
+            .unwrap(),
+        );
+        let ctx = RequestContext::background();
+        let other = {
+            let client = client.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || client.raw_request(&ctx, 200, 0, b"second").unwrap())
+        };
+        // Give the other request a head start so both are in flight.
+        thread::sleep(Duration::from_millis(50));
+        let frame = client.raw_request(&ctx, 200, 0, b"first").unwrap();
+        let small = other.join().unwrap();
+        let (big_frame, small_frame) = if frame.payload.len() > 5 {
+            (frame, small)
+        } else {
+            (small, frame)
+        };
+        assert_eq!(big_frame.payload, expected);
+        assert_eq!(small_frame.payload, b"small");
+
+        let start = Instant::now();
+        let err = client.raw_request(&ctx, 200, 0, b"hang").unwrap_err();
+        assert!(matches!(err, Error::Timeout), "got {err:?}");
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        client.close().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn cancel_interrupts_in_flight_request() {
+        let (addr, handle) = mock_server(move |stream| {
+            // Never answer the first request until the next one arrives, then
+            // answer both in order: the stale reply must be skipped.
+            let hung = read_frame(stream).unwrap();
+            let next = read_frame(stream).unwrap();
+            write_frame(stream, 200, 0, hung.header.req_id, b"late").unwrap();
+            write_frame(stream, 200, 0, next.header.req_id, b"fresh").unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let (ctx, cancel) = RequestContext::cancellable();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+
+        let start = Instant::now();
+        let err = client.raw_request(&ctx, 200, 0, b"hang").unwrap_err();
+        assert!(matches!(err, Error::Cancelled), "got {err:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+
+        let frame = client
+            .raw_request(&RequestContext::background(), 200, 0, b"next")
+            .unwrap();
+        assert_eq!(frame.payload, b"fresh");
+        handle.join().unwrap();
+    }
+
     fn hello_payload(tag: &str) -> Vec<u8> {
         let mut payload = Vec::new();
         payload.write_u16::<LittleEndian>(1).unwrap();
@@ -638,4 +1848,34 @@ mod tests {
             )),
         )
     }
+
+    #[test]
+    fn concurrent_requests_are_pipelined() {
+        let (addr, server) = mock_server(move |stream| {
+            // Neither request is answered until both have arrived, and the
+            // answers go out in reverse order.
+            let first = read_frame(stream).unwrap();
+            let second = read_frame(stream).unwrap();
+            for req in [second, first] {
+                write_frame(stream, 200, 0, req.header.req_id, &req.payload).unwrap();
+            }
+        });
+
+        let client = Arc::new(dial(&addr, Vec::<ClientOption>::new()).unwrap());
+        let calls: Vec<_> = [b"one".to_vec(), b"two".to_vec()]
+            .into_iter()
+            .map(|body| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    let ctx = RequestContext::with_timeout(Duration::from_secs(5));
+                    let frame = client.raw_request(&ctx, 200, 0, &body).unwrap();
+                    assert_eq!(frame.payload, body);
+                })
+            })
+            .collect();
+        for call in calls {
+            call.join().unwrap();
+        }
+        server.join().unwrap();
+    }
 }
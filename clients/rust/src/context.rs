@@ -1,11 +1,21 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::Arc;
+
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::client::{Client, RequestContext};
 use crate::error::{Error, Result};
-use crate::protocol::{MSG_CTX_CREATE, MSG_CTX_FORK, MSG_GET_HEAD};
+use crate::protocol::{
+    MSG_CTX_CREATE, MSG_CTX_FORK, MSG_GET_HEAD, MSG_GET_HEAD_AT, MSG_LIST_CONTEXTS, MSG_WATCH_HEAD,
+    WATCH_FLAG_STOP, WATCH_FLAG_UPDATE,
+};
+use crate::turn::{parse_turn_page, AppendRequest, GetLastOptions, TurnRecord};
+use crate::types::{
+    ContextMetadata, ConversationItem, Provenance, TypeIDConversationItem,
+    TypeVersionConversationItem,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ContextHead {
@@ -14,6 +24,63 @@ pub struct ContextHead {
     pub head_depth: u32,
 }
 
+/// One context as reported by `Client::list_contexts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextSummary {
+    pub context_id: u64,
+    pub head_turn_id: u64,
+    /// Turns on the head's chain, including any inherited from a fork base.
+    pub turn_count: u64,
+    /// When the head last moved (Unix milliseconds), or when the context was
+    /// created if it has no turns.
+    pub updated_at_unix_ms: u64,
+}
+
+/// Server-enforced limits for a context, counting everything reachable from
+/// its head: turns (including those inherited from a fork base), their
+/// payload bytes, and attached filesystem snapshot content. `None` leaves the
+/// limit to the server default; `Some(ContextQuota::UNLIMITED)` lifts it.
+/// Appends and attaches past a limit fail with an error for which
+/// `Error::is_quota_exceeded` is true.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContextQuota {
+    pub max_turns: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl ContextQuota {
+    /// A limit the server never enforces, for opting out of its default.
+    pub const UNLIMITED: u64 = u64::MAX;
+}
+
+/// Sets part of the `ContextMetadata` written with a new context's first
+/// turn; see `Client::create_context_with_metadata`.
+pub type ContextOption = Arc<dyn Fn(&mut ContextMetadata) + Send + Sync>;
+
+pub fn with_title(title: impl Into<String>) -> ContextOption {
+    let title = title.into();
+    Arc::new(move |meta| meta.title = title.clone())
+}
+
+pub fn with_labels(labels: impl IntoIterator<Item = impl Into<String>>) -> ContextOption {
+    let labels: Vec<String> = labels.into_iter().map(Into::into).collect();
+    Arc::new(move |meta| meta.labels.extend(labels.iter().cloned()))
+}
+
+pub fn with_custom(key: impl Into<String>, value: impl Into<String>) -> ContextOption {
+    let (key, value) = (key.into(), value.into());
+    Arc::new(move |meta| {
+        meta.custom.insert(key.clone(), value.clone());
+    })
+}
+
+pub fn with_provenance(provenance: Provenance) -> ContextOption {
+    Arc::new(move |meta| meta.provenance = Some(provenance.clone()))
+}
+
+/// `ConversationItem` field carrying `ContextMetadata`.
+const CONTEXT_METADATA_FIELD: u64 = 30;
+
 impl Client {
     pub fn create_context(&self, ctx: &RequestContext, base_turn_id: u64) -> Result<ContextHead> {
         let mut payload = Vec::with_capacity(8);
@@ -29,12 +96,259 @@ impl Client {
         parse_context_head(&frame.payload)
     }
 
+    /// `create_context` with a quota for the new context.
+    pub fn create_context_with_quota(
+        &self,
+        ctx: &RequestContext,
+        base_turn_id: u64,
+        quota: ContextQuota,
+    ) -> Result<ContextHead> {
+        let payload = quota_payload(base_turn_id, quota)?;
+        let frame = self.send_request(ctx, MSG_CTX_CREATE, &payload)?;
+        parse_context_head(&frame.payload)
+    }
+
+    /// `fork_context` with a quota for the new context.
+    pub fn fork_context_with_quota(
+        &self,
+        ctx: &RequestContext,
+        base_turn_id: u64,
+        quota: ContextQuota,
+    ) -> Result<ContextHead> {
+        let payload = quota_payload(base_turn_id, quota)?;
+        let frame = self.send_request(ctx, MSG_CTX_FORK, &payload)?;
+        parse_context_head(&frame.payload)
+    }
+
+    /// Creates an empty context and appends `first` as its first turn,
+    /// carrying the metadata built from `opts` on top of any `first` already
+    /// has. The server reads a context's metadata from the root turn of its
+    /// chain, so this is the only turn that can set it; forks inherit their
+    /// base chain's metadata. Returns the head after the append.
+    pub fn create_context_with_metadata(
+        &self,
+        ctx: &RequestContext,
+        first: &ConversationItem,
+        opts: impl IntoIterator<Item = ContextOption>,
+    ) -> Result<ContextHead> {
+        let mut item = first.clone();
+        let meta = item
+            .context_metadata
+            .get_or_insert_with(ContextMetadata::default);
+        for opt in opts {
+            opt(meta);
+        }
+        if meta.client_tag.is_empty() {
+            meta.client_tag = self.client_tag().to_string();
+        }
+        let payload = crate::encoding::encode_msgpack(&item)?;
+
+        let head = self.create_context(ctx, 0)?;
+        let result = self.append_turn(
+            ctx,
+            &AppendRequest::new(
+                head.context_id,
+                TypeIDConversationItem,
+                TypeVersionConversationItem,
+                payload,
+            ),
+        )?;
+        Ok(ContextHead {
+            context_id: head.context_id,
+            head_turn_id: result.turn_id,
+            head_depth: result.depth,
+        })
+    }
+
     pub fn get_head(&self, ctx: &RequestContext, context_id: u64) -> Result<ContextHead> {
         let mut payload = Vec::with_capacity(8);
         payload.write_u64::<LittleEndian>(context_id)?;
         let frame = self.send_request(ctx, MSG_GET_HEAD, &payload)?;
         parse_context_head(&frame.payload)
     }
+
+    /// Lists contexts in id order, starting after context id `after` (0 for
+    /// the first). Returns up to `limit` contexts, or every remaining one
+    /// when `limit` is 0; pass the last id returned as `after` to continue.
+    /// The server answers in pages, which are requested until the limit is
+    /// reached or no contexts remain.
+    pub fn list_contexts(
+        &self,
+        ctx: &RequestContext,
+        limit: u32,
+        after: u64,
+    ) -> Result<Vec<ContextSummary>> {
+        let mut contexts = Vec::new();
+        let mut after = after;
+        loop {
+            let want = if limit == 0 {
+                0
+            } else {
+                limit - contexts.len() as u32
+            };
+            let mut payload = Vec::with_capacity(12);
+            payload.write_u64::<LittleEndian>(after)?;
+            payload.write_u32::<LittleEndian>(want)?;
+            let frame = self.send_request(ctx, MSG_LIST_CONTEXTS, &payload)?;
+            let (page, has_more) = parse_context_list(&frame.payload)?;
+            let Some(last) = page.last() else {
+                return Ok(contexts);
+            };
+            after = last.context_id;
+            contexts.extend(page);
+            if !has_more || (limit != 0 && contexts.len() >= limit as usize) {
+                return Ok(contexts);
+            }
+        }
+    }
+
+    /// Returns the head the context had at `timestamp_ms` (Unix
+    /// milliseconds): the latest turn on its current chain appended at or
+    /// before then, including turns a fork inherited from its base. A context
+    /// with no turns that old yields `head_turn_id` 0.
+    pub fn get_head_at(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        timestamp_ms: u64,
+    ) -> Result<ContextHead> {
+        let mut payload = Vec::with_capacity(16);
+        payload.write_u64::<LittleEndian>(context_id)?;
+        payload.write_u64::<LittleEndian>(timestamp_ms)?;
+        let frame = self.send_request(ctx, MSG_GET_HEAD_AT, &payload)?;
+        parse_context_head(&frame.payload)
+    }
+
+    /// Returns the context's metadata (title, labels, custom map, provenance),
+    /// decoded from `ConversationItem` field 30 of the root turn of its chain.
+    /// Contexts without turns or without metadata yield the default (empty)
+    /// metadata.
+    pub fn get_metadata(&self, ctx: &RequestContext, context_id: u64) -> Result<ContextMetadata> {
+        let head = self.get_head(ctx, context_id)?;
+        if head.head_turn_id == 0 {
+            return Ok(ContextMetadata::default());
+        }
+
+        // The root sits `depth` turns behind the head. Appends racing this
+        // call push it further back, so step back again until depth 0.
+        let mut offset = head.head_depth;
+        let root = loop {
+            let page = self.get_last_page(
+                ctx,
+                context_id,
+                GetLastOptions {
+                    limit: 1,
+                    include_payload: true,
+                    offset,
+                    ..GetLastOptions::default()
+                },
+            )?;
+            let Some(record) = page.records.into_iter().next() else {
+                return Ok(ContextMetadata::default());
+            };
+            if record.depth == 0 {
+                break record;
+            }
+            offset += record.depth;
+        };
+
+        let fields = crate::encoding::decode_msgpack(&root.payload)?;
+        let Some(value) = fields.get(&CONTEXT_METADATA_FIELD) else {
+            return Ok(ContextMetadata::default());
+        };
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, value)
+            .map_err(|err| Error::invalid_response(format!("msgpack encode error: {err}")))?;
+        crate::encoding::decode_msgpack_into(&bytes)
+    }
+
+    /// Blocks, calling `on_update` with the new head and its turn each time
+    /// the context's head advances, until `on_update` returns false or `ctx`
+    /// is cancelled or reaches its deadline. Both ways of stopping return
+    /// `Ok`.
+    ///
+    /// The connection is dedicated to the watch while it runs; other calls on
+    /// this client wait until it returns.
+    pub fn watch_head(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        mut on_update: impl FnMut(ContextHead, TurnRecord) -> bool,
+    ) -> Result<()> {
+        self.watch_head_from(ctx, context_id, &mut None, &mut on_update)
+    }
+
+    /// `watch_head` that continues a watch begun on another connection.
+    /// `last_seen` is the head turn the caller last observed and is kept up
+    /// to date as updates arrive. If the head has moved past it, the new head
+    /// is delivered first; turns appended while no watch was active are
+    /// coalesced into that one update. With `None` the watch starts from the
+    /// current head.
+    pub(crate) fn watch_head_from(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        last_seen: &mut std::option::Option<u64>,
+        on_update: &mut impl FnMut(ContextHead, TurnRecord) -> bool,
+    ) -> Result<()> {
+        let mut payload = Vec::with_capacity(8);
+        payload.write_u64::<LittleEndian>(context_id)?;
+        loop {
+            if let Some(seen) = *last_seen {
+                let head = self.get_head(ctx, context_id)?;
+                if head.head_turn_id != seen {
+                    *last_seen = Some(head.head_turn_id);
+                    let opts = GetLastOptions {
+                        limit: 1,
+                        include_payload: true,
+                        ..GetLastOptions::default()
+                    };
+                    if let Some(record) = self.get_last(ctx, context_id, opts)?.pop() {
+                        if !on_update(head, record) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            // Set when the head moved between the catch-up above and the
+            // subscription taking effect; the next pass catches up again.
+            let mut behind = false;
+            self.send_subscription(ctx, MSG_WATCH_HEAD, &payload, WATCH_FLAG_STOP, |frame| {
+                let head = parse_context_head(&frame.payload)?;
+                if frame.header.flags & WATCH_FLAG_UPDATE == 0 {
+                    match *last_seen {
+                        Some(seen) if seen != head.head_turn_id => {
+                            behind = true;
+                            return Ok(false);
+                        }
+                        Some(_) => {}
+                        None => *last_seen = Some(head.head_turn_id),
+                    }
+                    return Ok(true);
+                }
+                let record = parse_turn_page(&frame.payload[20..])?
+                    .records
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| Error::invalid_response("watch update without turn"))?;
+                *last_seen = Some(head.head_turn_id);
+                Ok(on_update(head, record))
+            })?;
+            if !behind {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// CTX_CREATE / CTX_FORK payload with the trailing quota fields (0 = unset).
+fn quota_payload(base_turn_id: u64, quota: ContextQuota) -> Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(24);
+    payload.write_u64::<LittleEndian>(base_turn_id)?;
+    payload.write_u64::<LittleEndian>(quota.max_turns.unwrap_or(0))?;
+    payload.write_u64::<LittleEndian>(quota.max_bytes.unwrap_or(0))?;
+    Ok(payload)
 }
 
 fn parse_context_head(payload: &[u8]) -> Result<ContextHead> {
@@ -52,10 +366,45 @@ fn parse_context_head(payload: &[u8]) -> Result<ContextHead> {
     })
 }
 
+fn parse_context_list(payload: &[u8]) -> Result<(Vec<ContextSummary>, bool)> {
+    let too_short = || {
+        Error::invalid_response(format!(
+            "list contexts response too short ({} bytes)",
+            payload.len()
+        ))
+    };
+    if payload.len() < 4 {
+        return Err(too_short());
+    }
+    let mut cursor = std::io::Cursor::new(payload);
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    if payload.len() < 4 + count * 32 + 1 {
+        return Err(too_short());
+    }
+    let mut contexts = Vec::with_capacity(count);
+    for _ in 0..count {
+        contexts.push(ContextSummary {
+            context_id: cursor.read_u64::<LittleEndian>()?,
+            head_turn_id: cursor.read_u64::<LittleEndian>()?,
+            turn_count: cursor.read_u64::<LittleEndian>()?,
+            updated_at_unix_ms: cursor.read_u64::<LittleEndian>()?,
+        });
+    }
+    let has_more = cursor.read_u8()? != 0;
+    Ok((contexts, has_more))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_util::{decode_hex, load_fixture};
+    use crate::client::dial;
+    use crate::protocol::{read_frame, write_frame};
+    use crate::test_util::{
+        decode_hex, head_payload, load_fixture, mock_server, page_payload, update_payload,
+    };
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
 
     fn payload_u64(value: u64) -> Vec<u8> {
         let mut payload = Vec::with_capacity(8);
@@ -77,4 +426,303 @@ mod tests {
         assert_eq!(fixture.msg_type, MSG_GET_HEAD);
         assert_eq!(decode_hex(&fixture.payload_hex), payload_u64(42));
     }
+
+    /// Answers the client's WATCH_HEAD request and returns the watch req_id.
+    fn answer_watch(stream: &mut TcpStream) -> u64 {
+        let watch = read_frame(stream).unwrap();
+        assert_eq!(watch.header.msg_type, MSG_WATCH_HEAD);
+        assert_eq!(watch.payload, payload_u64(7));
+        let req_id = watch.header.req_id;
+        write_frame(stream, MSG_WATCH_HEAD, 0, req_id, &head_payload(7, 1, 0)).unwrap();
+        req_id
+    }
+
+    fn finish_watch(stream: &mut TcpStream, req_id: u64) {
+        let stop = read_frame(stream).unwrap();
+        assert_eq!(stop.header.msg_type, MSG_WATCH_HEAD);
+        assert_eq!(stop.header.flags, WATCH_FLAG_STOP);
+        assert_eq!(stop.header.req_id, req_id);
+        write_frame(stream, MSG_WATCH_HEAD, WATCH_FLAG_STOP, req_id, &[]).unwrap();
+
+        // The connection must be usable again once the watch ends.
+        let req = read_frame(stream).unwrap();
+        assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+        write_frame(
+            stream,
+            MSG_GET_HEAD,
+            0,
+            req.header.req_id,
+            &head_payload(7, 3, 2),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn get_head_at_sends_timestamp() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD_AT);
+            let mut expected = payload_u64(7);
+            expected.extend_from_slice(&1_700_000_000_000u64.to_le_bytes());
+            assert_eq!(req.payload, expected);
+            let resp = head_payload(7, 3, 2);
+            write_frame(stream, MSG_GET_HEAD_AT, 0, req.header.req_id, &resp).unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let head = client
+            .get_head_at(&RequestContext::background(), 7, 1_700_000_000_000)
+            .unwrap();
+        assert_eq!(
+            (head.context_id, head.head_turn_id, head.head_depth),
+            (7, 3, 2)
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn list_contexts_requests_pages_until_limit() {
+        let (addr, handle) = mock_server(move |stream| {
+            // The server caps the first page at two contexts.
+            for (after, want, ids, has_more) in
+                [(0u64, 3u32, vec![4u64, 9], true), (9, 1, vec![12], true)]
+            {
+                let req = read_frame(stream).unwrap();
+                assert_eq!(req.header.msg_type, MSG_LIST_CONTEXTS);
+                let mut expected = payload_u64(after);
+                expected.extend_from_slice(&want.to_le_bytes());
+                assert_eq!(req.payload, expected);
+
+                let mut resp = (ids.len() as u32).to_le_bytes().to_vec();
+                for id in ids {
+                    for field in [id, id * 10, 3, 1_700_000_000_000 + id] {
+                        resp.extend_from_slice(&field.to_le_bytes());
+                    }
+                }
+                resp.push(has_more as u8);
+                write_frame(stream, MSG_LIST_CONTEXTS, 0, req.header.req_id, &resp).unwrap();
+            }
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let contexts = client
+            .list_contexts(&RequestContext::background(), 3, 0)
+            .unwrap();
+        let ids: Vec<u64> = contexts.iter().map(|c| c.context_id).collect();
+        assert_eq!(ids, vec![4, 9, 12]);
+        assert_eq!(
+            contexts[2],
+            ContextSummary {
+                context_id: 12,
+                head_turn_id: 120,
+                turn_count: 3,
+                updated_at_unix_ms: 1_700_000_000_012,
+            }
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn watch_head_delivers_updates_until_callback_stops() {
+        let (addr, handle) = mock_server(|stream| {
+            let req_id = answer_watch(stream);
+            for (turn_id, body) in [(2u64, b"first".as_slice()), (3, b"second")] {
+                let payload = update_payload(7, turn_id, turn_id as u32 - 1, body);
+                write_frame(stream, MSG_WATCH_HEAD, WATCH_FLAG_UPDATE, req_id, &payload).unwrap();
+            }
+            finish_watch(stream, req_id);
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let mut seen = Vec::new();
+        client
+            .watch_head(&ctx, 7, |head, turn| {
+                assert_eq!(head.head_turn_id, turn.turn_id);
+                seen.push((turn.turn_id, turn.payload));
+                seen.len() < 2
+            })
+            .unwrap();
+        assert_eq!(seen, vec![(2, b"first".to_vec()), (3, b"second".to_vec())]);
+
+        let head = client.get_head(&ctx, 7).unwrap();
+        assert_eq!(head.head_turn_id, 3);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn watch_head_ends_on_cancel() {
+        let (addr, handle) = mock_server(|stream| {
+            let req_id = answer_watch(stream);
+            finish_watch(stream, req_id);
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let (ctx, cancel) = RequestContext::cancellable();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+        client.watch_head(&ctx, 7, |_, _| true).unwrap();
+        canceller.join().unwrap();
+
+        let head = client.get_head(&RequestContext::background(), 7).unwrap();
+        assert_eq!(head.head_turn_id, 3);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn create_context_with_quota_sends_limits() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_CTX_CREATE);
+            let mut expected = payload_u64(0);
+            expected.extend(payload_u64(100));
+            expected.extend(payload_u64(0));
+            assert_eq!(req.payload, expected);
+            let resp = head_payload(9, 0, 0);
+            write_frame(stream, MSG_CTX_CREATE, 0, req.header.req_id, &resp).unwrap();
+
+            let req = read_frame(stream).unwrap();
+            let mut detail = Vec::new();
+            detail.write_u32::<LittleEndian>(507).unwrap();
+            let msg = b"context 9 would hold 101 turns (limit 100)";
+            detail.write_u32::<LittleEndian>(msg.len() as u32).unwrap();
+            detail.extend_from_slice(msg);
+            write_frame(
+                stream,
+                crate::protocol::MSG_ERROR,
+                0,
+                req.header.req_id,
+                &detail,
+            )
+            .unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let quota = ContextQuota {
+            max_turns: Some(100),
+            max_bytes: None,
+        };
+        let head = client.create_context_with_quota(&ctx, 0, quota).unwrap();
+        assert_eq!(head.context_id, 9);
+
+        let err = client.get_head(&ctx, 9).unwrap_err();
+        assert!(err.is_quota_exceeded(), "{err}");
+        assert!(!Error::server(404, "context").is_quota_exceeded());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn create_context_with_metadata_writes_first_turn() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_CTX_CREATE);
+            assert_eq!(req.payload, payload_u64(0));
+            let resp = head_payload(7, 0, 0);
+            write_frame(stream, MSG_CTX_CREATE, 0, req.header.req_id, &resp).unwrap();
+
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, crate::protocol::MSG_APPEND_TURN);
+            assert_eq!(&req.payload[..8], &7u64.to_le_bytes());
+            let mut ack = Vec::new();
+            ack.write_u64::<LittleEndian>(7).unwrap();
+            ack.write_u64::<LittleEndian>(1).unwrap();
+            ack.write_u32::<LittleEndian>(0).unwrap();
+            ack.extend_from_slice(&[0u8; 32]);
+            write_frame(
+                stream,
+                crate::protocol::MSG_APPEND_TURN,
+                0,
+                req.header.req_id,
+                &ack,
+            )
+            .unwrap();
+
+            // context_id, parent, type_id, version, encoding, compression,
+            // uncompressed_len and hash precede the length-prefixed payload.
+            let type_len = u32::from_le_bytes(req.payload[16..20].try_into().unwrap()) as usize;
+            let start = 20 + type_len + 16 + 32;
+            let len =
+                u32::from_le_bytes(req.payload[start..start + 4].try_into().unwrap()) as usize;
+            req.payload[start + 4..start + 4 + len].to_vec()
+        });
+
+        let client = dial(&addr, vec![crate::client::with_client_tag("agent-a")]).unwrap();
+        let opts = vec![
+            with_title("Nightly eval"),
+            with_labels(["eval", "nightly"]),
+            with_custom("env", "ci"),
+        ];
+        let first = crate::types::new_user_input("hi", Vec::new());
+        let head = client
+            .create_context_with_metadata(&RequestContext::background(), &first, opts)
+            .unwrap();
+        assert_eq!(head.context_id, 7);
+        assert_eq!(head.head_turn_id, 1);
+
+        let payload = handle.join().unwrap();
+        let fields = crate::encoding::decode_msgpack(&payload).unwrap();
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &fields[&CONTEXT_METADATA_FIELD]).unwrap();
+        let meta: ContextMetadata = crate::encoding::decode_msgpack_into(&bytes).unwrap();
+        assert_eq!(meta.title, "Nightly eval");
+        assert_eq!(meta.labels, vec!["eval".to_string(), "nightly".to_string()]);
+        assert_eq!(meta.custom.get("env").map(String::as_str), Some("ci"));
+        assert_eq!(meta.client_tag, "agent-a");
+    }
+
+    #[test]
+    fn get_metadata_decodes_root_turn_field_30() {
+        let (addr, handle) = mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+            let resp = head_payload(7, 3, 2);
+            write_frame(stream, MSG_GET_HEAD, 0, req.header.req_id, &resp).unwrap();
+
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, crate::protocol::MSG_GET_LAST);
+            // limit 1, include payload, offset = head depth
+            assert_eq!(&req.payload[8..], &[1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+
+            let metadata = rmpv::Value::Map(vec![
+                (rmpv::Value::from(2), rmpv::Value::from("Nightly eval")),
+                (
+                    rmpv::Value::from(3),
+                    rmpv::Value::Array(vec![rmpv::Value::from("eval")]),
+                ),
+                (
+                    rmpv::Value::from(10),
+                    rmpv::Value::Map(vec![(rmpv::Value::from(20), rmpv::Value::from("jay"))]),
+                ),
+            ]);
+            let item = rmpv::Value::Map(vec![
+                (rmpv::Value::from(1), rmpv::Value::from("system")),
+                (rmpv::Value::from(30), metadata),
+            ]);
+            let mut body = Vec::new();
+            rmpv::encode::write_value(&mut body, &item).unwrap();
+            let resp = page_payload(1, 0, &body);
+            write_frame(
+                stream,
+                crate::protocol::MSG_GET_LAST,
+                0,
+                req.header.req_id,
+                &resp,
+            )
+            .unwrap();
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let metadata = client
+            .get_metadata(&RequestContext::background(), 7)
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(metadata.title, "Nightly eval");
+        assert_eq!(metadata.labels, vec!["eval".to_string()]);
+        assert!(metadata.client_tag.is_empty());
+        assert_eq!(metadata.provenance.unwrap().on_behalf_of, "jay");
+    }
 }
@@ -74,7 +74,7 @@ pub fn DecodeMsgpackInto<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
     decode_msgpack_into(data)
 }
 
-fn normalize_map_keys_to_string(value: &mut Value) {
+pub(crate) fn normalize_map_keys_to_string(value: &mut Value) {
     match value {
         Value::Map(entries) => {
             for (k, v) in entries.iter_mut() {
@@ -16,6 +16,19 @@ pub enum Error {
     Timeout,
     Cancelled,
     QueueFull,
+    /// A request payload was larger than a frame may carry; nothing was sent.
+    PayloadTooLarge {
+        size: u64,
+        limit: u64,
+    },
+    /// The server has not yet seen the turn a read asked to reflect
+    /// (`GetLastOptions::min_head_turn_id`); retrying later may succeed.
+    NotReady {
+        min_head_turn_id: u64,
+    },
+    /// A mutating call was made on a client dialed `with_read_only`; nothing
+    /// was sent.
+    ReadOnly,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -45,6 +58,17 @@ impl fmt::Display for Error {
             Error::Timeout => write!(f, "cxdb: deadline exceeded"),
             Error::Cancelled => write!(f, "cxdb: request cancelled"),
             Error::QueueFull => write!(f, "cxdb: request queue full"),
+            Error::PayloadTooLarge { size, limit } => write!(
+                f,
+                "cxdb: payload of {size} bytes exceeds the {limit}-byte frame limit; \
+                 send large blobs with the chunked upload (put_blob_stream, or a \
+                 with_blob_chunk_size below the limit)"
+            ),
+            Error::NotReady { min_head_turn_id } => write!(
+                f,
+                "cxdb: server has not yet seen turn {min_head_turn_id}; retry the read"
+            ),
+            Error::ReadOnly => write!(f, "cxdb: client is read-only"),
         }
     }
 }
@@ -92,4 +116,10 @@ impl Error {
             detail: detail.into(),
         })
     }
+
+    /// Whether the server refused an append or attach because it would take
+    /// the context past its turn or byte quota (error code 507).
+    pub fn is_quota_exceeded(&self) -> bool {
+        is_server_error(self, 507)
+    }
 }
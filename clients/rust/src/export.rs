@@ -0,0 +1,275 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSONL export of a context's turns.
+//!
+//! Each line is one turn, oldest first, as a JSON object with the turn's
+//! ids, depth, type and encoding, `payload_hash` in hex, and either
+//! `payload` (a msgpack payload converted to JSON, with integer map keys
+//! written as strings) or `payload_hex` (the stored bytes, for payloads
+//! that are compressed or not msgpack).
+
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::client::{Client, RequestContext};
+use crate::encoding::normalize_map_keys_to_string;
+use crate::error::{Error, Result};
+use crate::protocol::{COMPRESSION_NONE, ENCODING_MSGPACK};
+use crate::turn::{GetLastOptions, TurnRecord};
+
+/// Turns fetched per `get_last_page` request.
+const EXPORT_PAGE_SIZE: u32 = 100;
+
+/// What `export_context` wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub turns_written: u64,
+    pub bytes_written: u64,
+    /// False when the export stopped early because `ctx` was cancelled.
+    pub complete: bool,
+}
+
+#[derive(Serialize)]
+struct ExportedTurn<'a> {
+    turn_id: u64,
+    parent_id: u64,
+    depth: u32,
+    type_id: &'a str,
+    type_version: u32,
+    encoding: u32,
+    compression: u32,
+    payload_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_hex: Option<String>,
+}
+
+impl Client {
+    /// Writes every turn of `context_id` to `out` as JSONL, oldest first.
+    /// See `export_context_with_progress`.
+    pub fn export_context(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        out: &mut impl Write,
+    ) -> Result<ExportSummary> {
+        self.export_context_with_progress(ctx, context_id, out, |_, _| {})
+    }
+
+    /// Like `export_context`, calling `on_progress(turns_written,
+    /// bytes_written)` after each turn. The export covers the turns up to
+    /// the head as of the start; later appends are left out.
+    ///
+    /// `ctx` is checked between turns. Once it is cancelled the export
+    /// stops, flushes `out`, and returns what was written with `complete`
+    /// set to false rather than failing, so an aborted export of a large
+    /// context keeps its output.
+    pub fn export_context_with_progress(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        out: &mut impl Write,
+        mut on_progress: impl FnMut(u64, u64),
+    ) -> Result<ExportSummary> {
+        let mut summary = ExportSummary {
+            turns_written: 0,
+            bytes_written: 0,
+            complete: false,
+        };
+        let head = match self.get_head(ctx, context_id) {
+            Err(Error::Cancelled) => return Ok(summary),
+            other => other?,
+        };
+        let total = if head.head_turn_id == 0 {
+            0
+        } else {
+            head.head_depth as u64 + 1
+        };
+
+        // Pages are addressed by offset from the current head, so turns
+        // appended since the start push the window back by as many.
+        let mut grown = 0u64;
+        let mut next_depth = 0u64;
+        while next_depth < total {
+            if ctx.is_cancelled() {
+                out.flush()?;
+                return Ok(summary);
+            }
+            let remaining = total - next_depth;
+            let limit = remaining.min(EXPORT_PAGE_SIZE as u64);
+            let opts = GetLastOptions {
+                limit: limit as u32,
+                include_payload: true,
+                offset: u32::try_from(remaining - limit + grown).map_err(|_| {
+                    Error::invalid_response(format!("context {context_id} is too deep to export"))
+                })?,
+                ..GetLastOptions::default()
+            };
+            let page = match self.get_last_page(ctx, context_id, opts) {
+                Err(Error::Cancelled) => {
+                    out.flush()?;
+                    return Ok(summary);
+                }
+                other => other?,
+            };
+
+            let Some(first) = page.records.first() else {
+                break;
+            };
+            if first.depth as u64 > next_depth {
+                grown += first.depth as u64 - next_depth;
+                continue;
+            }
+
+            for record in &page.records {
+                if (record.depth as u64) < next_depth {
+                    continue;
+                }
+                if record.depth as u64 >= total {
+                    break;
+                }
+                if ctx.is_cancelled() {
+                    out.flush()?;
+                    return Ok(summary);
+                }
+                let line = export_line(record)?;
+                out.write_all(&line)?;
+                summary.turns_written += 1;
+                summary.bytes_written += line.len() as u64;
+                next_depth = record.depth as u64 + 1;
+                on_progress(summary.turns_written, summary.bytes_written);
+            }
+        }
+
+        out.flush()?;
+        summary.complete = true;
+        Ok(summary)
+    }
+}
+
+/// One JSONL line, newline included.
+fn export_line(record: &TurnRecord) -> Result<Vec<u8>> {
+    let payload = if record.encoding == ENCODING_MSGPACK && record.compression == COMPRESSION_NONE {
+        msgpack_to_json(&record.payload)
+    } else {
+        None
+    };
+    let payload_hex = payload.is_none().then(|| hex(&record.payload));
+    let turn = ExportedTurn {
+        turn_id: record.turn_id,
+        parent_id: record.parent_id,
+        depth: record.depth,
+        type_id: &record.type_id,
+        type_version: record.type_version,
+        encoding: record.encoding,
+        compression: record.compression,
+        payload_hash: hex(&record.payload_hash),
+        payload,
+        payload_hex,
+    };
+    let mut line = serde_json::to_vec(&turn)
+        .map_err(|err| Error::invalid_response(format!("turn {}: {err}", record.turn_id)))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// The msgpack value in `data` as JSON, or None if it is not valid msgpack
+/// or has no JSON form.
+fn msgpack_to_json(data: &[u8]) -> Option<serde_json::Value> {
+    let mut value = rmpv::decode::read_value(&mut std::io::Cursor::new(data)).ok()?;
+    normalize_map_keys_to_string(&mut value);
+    serde_json::to_value(&value).ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{read_frame, write_frame, MSG_GET_HEAD, MSG_GET_LAST};
+    use crate::test_util::{head_payload, mock_server, page_payload};
+
+    /// Serves a context of `turns` turns, one `get_last_page` request per
+    /// turn, oldest first.
+    fn start_export_server(turns: u32) -> (String, std::thread::JoinHandle<()>) {
+        mock_server(move |stream| {
+            let req = read_frame(stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+            let head = head_payload(1, turns as u64, turns - 1);
+            write_frame(stream, MSG_GET_HEAD, 0, req.header.req_id, &head).unwrap();
+            for depth in 0..turns {
+                let Ok(req) = read_frame(stream) else {
+                    return;
+                };
+                assert_eq!(req.header.msg_type, MSG_GET_LAST);
+                let body = crate::encode_msgpack(&std::collections::BTreeMap::from([(
+                    1u64,
+                    format!("turn {depth}"),
+                )]))
+                .unwrap();
+                let page = page_payload(depth as u64 + 1, depth, &body);
+                write_frame(stream, MSG_GET_LAST, 0, req.header.req_id, &page).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn export_context_writes_turns_oldest_first_and_reports_progress() {
+        let (addr, server) = start_export_server(3);
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+
+        let mut out = Vec::new();
+        let mut progress = Vec::new();
+        let summary = client
+            .export_context_with_progress(&RequestContext::background(), 1, &mut out, |t, b| {
+                progress.push((t, b))
+            })
+            .unwrap();
+        assert!(summary.complete);
+        assert_eq!(summary.turns_written, 3);
+        assert_eq!(summary.bytes_written, out.len() as u64);
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[2], (3, out.len() as u64));
+
+        let lines: Vec<serde_json::Value> = out
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["turn_id"], 1);
+        assert_eq!(lines[2]["depth"], 2);
+        assert_eq!(lines[1]["payload"]["1"], "turn 1");
+        assert!(lines[1].get("payload_hex").is_none());
+
+        client.close().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn export_context_keeps_partial_output_when_cancelled() {
+        let (addr, server) = start_export_server(3);
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+
+        let (ctx, cancel) = RequestContext::cancellable();
+        let mut out = Vec::new();
+        let summary = client
+            .export_context_with_progress(&ctx, 1, &mut out, |turns, _| {
+                if turns == 2 {
+                    cancel.cancel();
+                }
+            })
+            .unwrap();
+        assert!(!summary.complete);
+        assert_eq!(summary.turns_written, 2);
+        assert_eq!(out.iter().filter(|b| **b == b'\n').count(), 2);
+
+        client.close().unwrap();
+        server.join().unwrap();
+    }
+}
@@ -2,17 +2,39 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
 
 use crate::client::{Client, RequestContext};
 use crate::error::{Error, Result};
-use crate::protocol::{ENCODING_MSGPACK, MSG_APPEND_TURN, MSG_ATTACH_FS, MSG_PUT_BLOB};
-use crate::turn::{AppendRequest, AppendResult};
+use crate::fstree::{hash_algorithm_for_id, HashAlgorithm};
+use crate::protocol::{
+    APPEND_FLAG_FS_ROOT, ENCODING_MSGPACK, MSG_APPEND_TURN, MSG_ATTACH_FS, MSG_ATTACH_FS_HEAD,
+    MSG_BEGIN_BLOB, MSG_BLOB_CHUNK, MSG_COMMIT_BLOB, MSG_FIND_SNAPSHOT_REFS, MSG_GET_BLOB,
+    MSG_HAS_BLOBS, MSG_PUT_BLOB, MSG_VERIFY_SNAPSHOT,
+};
+use crate::turn::{append_flags, parse_append_result, AppendRequest, AppendResult};
+
+/// Hashes sent per HAS_BLOBS request (32 bytes each), keeping frames small.
+const HAS_BLOBS_BATCH: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct AttachFsRequest {
     pub turn_id: u64,
     pub fs_root_hash: [u8; 32],
+    /// Capture metadata for the server to store with the attachment.
+    pub meta: Option<SnapshotMeta>,
+}
+
+/// Capture metadata recorded alongside an attached filesystem snapshot, so
+/// later queries can report its size and age without walking the tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotMeta {
+    pub captured_at_unix_ms: u64,
+    pub file_count: u64,
+    pub dir_count: u64,
+    pub total_bytes: u64,
+    pub duration_ms: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,6 +43,27 @@ pub struct AttachFsResult {
     pub fs_root_hash: [u8; 32],
 }
 
+/// What the server found checking a snapshot with `verify_snapshot`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Trees the server read and parsed.
+    pub trees_checked: u64,
+    /// File and symlink blobs checked for presence.
+    pub blobs_checked: u64,
+    /// Entries whose tree or blob is missing, or whose tree is unreadable.
+    pub missing: u64,
+    /// Paths of those entries, in walk order ("" is the root). The server
+    /// lists at most 1000; `missing` counts them all.
+    pub missing_paths: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether every object the snapshot references is stored.
+    pub fn is_intact(&self) -> bool {
+        self.missing == 0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PutBlobRequest {
     pub data: Vec<u8>,
@@ -34,50 +77,234 @@ pub struct PutBlobResult {
 
 impl Client {
     pub fn attach_fs(&self, ctx: &RequestContext, req: &AttachFsRequest) -> Result<AttachFsResult> {
-        let mut payload = Vec::with_capacity(40);
+        let mut payload = Vec::with_capacity(80);
         payload.write_u64::<LittleEndian>(req.turn_id)?;
         payload.extend_from_slice(&req.fs_root_hash);
+        if let Some(meta) = &req.meta {
+            payload.write_u64::<LittleEndian>(meta.captured_at_unix_ms)?;
+            payload.write_u64::<LittleEndian>(meta.file_count)?;
+            payload.write_u64::<LittleEndian>(meta.dir_count)?;
+            payload.write_u64::<LittleEndian>(meta.total_bytes)?;
+            payload.write_u64::<LittleEndian>(meta.duration_ms)?;
+        }
 
         let frame = self.send_request(ctx, MSG_ATTACH_FS, &payload)?;
-        if frame.payload.len() < 40 {
-            return Err(Error::invalid_response(format!(
-                "attach fs response too short ({} bytes)",
-                frame.payload.len()
-            )));
-        }
+        parse_attach_fs_result(frame.payload)
+    }
 
-        let mut cursor = std::io::Cursor::new(frame.payload);
-        let turn_id = cursor.read_u64::<LittleEndian>()?;
-        let mut hash = [0u8; 32];
-        cursor.read_exact(&mut hash)?;
+    /// Attaches a snapshot to the current head turn of `context_id`. The
+    /// server reads the head and attaches under one lock, so a turn appended
+    /// concurrently cannot slip in between; the result names the turn that
+    /// was used. Fails if the context has no turns.
+    pub fn attach_fs_to_head(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        fs_root_hash: [u8; 32],
+    ) -> Result<AttachFsResult> {
+        let mut payload = Vec::with_capacity(40);
+        payload.write_u64::<LittleEndian>(context_id)?;
+        payload.extend_from_slice(&fs_root_hash);
 
-        Ok(AttachFsResult {
-            turn_id,
-            fs_root_hash: hash,
-        })
+        let frame = self.send_request(ctx, MSG_ATTACH_FS_HEAD, &payload)?;
+        parse_attach_fs_result(frame.payload)
+    }
+
+    /// Lists the turns `fs_root_hash` is directly attached to, in ascending
+    /// order, e.g. to check what deleting a snapshot would affect before GC.
+    /// Descendants of those turns that inherit the snapshot are not listed.
+    pub fn find_snapshot_refs(
+        &self,
+        ctx: &RequestContext,
+        fs_root_hash: [u8; 32],
+    ) -> Result<Vec<u64>> {
+        let frame = self.send_request(ctx, MSG_FIND_SNAPSHOT_REFS, &fs_root_hash)?;
+        parse_find_snapshot_refs_resp(&frame.payload)
     }
 
+    /// Asks the server to check that every tree and blob the snapshot
+    /// `fs_root_hash` references is stored, e.g. before relying on it for a
+    /// restore. Trees are read in full; file contents are only checked for
+    /// presence. The server holds its store lock for the walk, so a very
+    /// large snapshot may need a longer request timeout.
+    pub fn verify_snapshot(
+        &self,
+        ctx: &RequestContext,
+        fs_root_hash: [u8; 32],
+    ) -> Result<VerifyReport> {
+        let frame = self.send_request(ctx, MSG_VERIFY_SNAPSHOT, &fs_root_hash)?;
+        parse_verify_snapshot_resp(&frame.payload)
+    }
+
+    /// Fetches the blob stored under `hash`.
+    pub fn get_blob(&self, ctx: &RequestContext, hash: [u8; 32]) -> Result<Vec<u8>> {
+        let frame = self.send_request(ctx, MSG_GET_BLOB, &hash)?;
+        parse_get_blob_result(frame.payload)
+    }
+
+    /// Stores a blob, hashing it with the algorithm the server reported in
+    /// its HELLO response.
     pub fn put_blob(&self, ctx: &RequestContext, req: &PutBlobRequest) -> Result<PutBlobResult> {
-        let hash = blake3::hash(&req.data);
-        let mut payload = Vec::with_capacity(36 + req.data.len());
-        payload.extend_from_slice(hash.as_bytes());
-        payload.write_u32::<LittleEndian>(req.data.len() as u32)?;
-        payload.extend_from_slice(&req.data);
+        let hash = self.blob_hash_algorithm()?.hash(&req.data);
+        self.put_blob_with_hash(ctx, hash, &req.data)
+    }
+
+    /// The algorithm matching `Client::hash_algorithm`.
+    fn blob_hash_algorithm(&self) -> Result<Arc<dyn HashAlgorithm>> {
+        let id = self.hash_algorithm();
+        hash_algorithm_for_id(id).ok_or_else(|| {
+            Error::invalid_response(format!("server hashes blobs with unknown algorithm {id}"))
+        })
+    }
+
+    /// Stores a blob under a caller-computed content hash, e.g. one keyed
+    /// for a hash namespace; the server rejects hashes that do not match
+    /// any algorithm it accepts.
+    ///
+    /// Blobs larger than the client's blob chunk size are sent with the
+    /// chunked upload protocol.
+    pub fn put_blob_with_hash(
+        &self,
+        ctx: &RequestContext,
+        hash: [u8; 32],
+        data: &[u8],
+    ) -> Result<PutBlobResult> {
+        if data.len() > self.blob_chunk_size {
+            return self.put_blob_chunked(ctx, hash, &mut &data[..], data.len() as u64);
+        }
+
+        let mut payload = Vec::with_capacity(36 + data.len());
+        payload.extend_from_slice(&hash);
+        payload.write_u32::<LittleEndian>(data.len() as u32)?;
+        payload.extend_from_slice(data);
 
         let frame = self.send_request(ctx, MSG_PUT_BLOB, &payload)?;
-        if frame.payload.len() < 33 {
-            return Err(Error::invalid_response(format!(
-                "put blob response too short ({} bytes)",
-                frame.payload.len()
-            )));
+        parse_put_blob_result(&frame.payload)
+    }
+
+    /// Stores a blob of `len` bytes read from `reader`, without holding the
+    /// whole blob in memory. BEGIN_BLOB needs the content hash up front, so
+    /// the reader is read twice: once to hash (with the server's algorithm,
+    /// as `put_blob` does) and, after seeking
+    /// back to where it started, once to upload. Use
+    /// `put_blob_stream_with_hash` when the hash is already known.
+    pub fn put_blob_stream<R: Read + Seek>(
+        &self,
+        ctx: &RequestContext,
+        mut reader: R,
+        len: u64,
+    ) -> Result<PutBlobResult> {
+        let start = reader.stream_position()?;
+        let mut hasher = self.blob_hash_algorithm()?.hasher();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut hashed = 0u64;
+        while hashed < len {
+            let want = buf.len().min((len - hashed) as usize);
+            let n = reader.read(&mut buf[..want])?;
+            if n == 0 {
+                return Err(short_read(hashed, len));
+            }
+            hasher.update(&buf[..n]);
+            hashed += n as u64;
         }
-        let mut hash_bytes = [0u8; 32];
-        hash_bytes.copy_from_slice(&frame.payload[0..32]);
-        let was_new = frame.payload[32] == 1;
-        Ok(PutBlobResult {
-            hash: hash_bytes,
-            was_new,
-        })
+        reader.seek(SeekFrom::Start(start))?;
+        self.put_blob_stream_with_hash(ctx, hasher.finalize(), reader, len)
+    }
+
+    /// Stores a blob of `len` bytes read once from `reader` under a
+    /// caller-computed content hash. Only one chunk is buffered at a time.
+    pub fn put_blob_stream_with_hash(
+        &self,
+        ctx: &RequestContext,
+        hash: [u8; 32],
+        mut reader: impl Read,
+        len: u64,
+    ) -> Result<PutBlobResult> {
+        if len > self.blob_chunk_size as u64 {
+            return self.put_blob_chunked(ctx, hash, &mut reader, len);
+        }
+        let mut data = vec![0u8; len as usize];
+        read_full(&mut reader, &mut data, 0, len)?;
+        self.put_blob_with_hash(ctx, hash, &data)
+    }
+
+    /// BEGIN_BLOB / BLOB_CHUNK / COMMIT_BLOB. The server keys pending uploads
+    /// by hash, so calling this again after a reconnect resumes from the bytes
+    /// it already holds instead of starting over.
+    fn put_blob_chunked(
+        &self,
+        ctx: &RequestContext,
+        hash: [u8; 32],
+        reader: &mut dyn Read,
+        len: u64,
+    ) -> Result<PutBlobResult> {
+        let mut payload = Vec::with_capacity(40);
+        payload.extend_from_slice(&hash);
+        payload.write_u64::<LittleEndian>(len)?;
+        let frame = self.send_request(ctx, MSG_BEGIN_BLOB, &payload)?;
+        let (upload_id, mut received) = parse_blob_upload_resp(&frame.payload)?;
+        if upload_id == 0 {
+            return Ok(PutBlobResult {
+                hash,
+                was_new: false,
+            });
+        }
+
+        // Bytes consumed from `reader` so far; the reader only moves forward,
+        // so bytes the server already holds are skipped rather than sent.
+        let mut position = 0u64;
+        let mut chunk = Vec::with_capacity(self.blob_chunk_size.min(len as usize));
+        while received < len {
+            if received < position {
+                return Err(Error::invalid_response(format!(
+                    "blob upload moved back to {received} bytes after {position} were sent"
+                )));
+            }
+            let skipped =
+                std::io::copy(&mut reader.take(received - position), &mut std::io::sink())?;
+            position += skipped;
+            if position < received {
+                return Err(short_read(position, len));
+            }
+
+            let chunk_len = (self.blob_chunk_size as u64).min(len - received);
+            chunk.resize(chunk_len as usize, 0);
+            read_full(reader, &mut chunk, received, len)?;
+            position += chunk_len;
+
+            let mut payload = Vec::with_capacity(20 + chunk.len());
+            payload.write_u64::<LittleEndian>(upload_id)?;
+            payload.write_u64::<LittleEndian>(received)?;
+            payload.write_u32::<LittleEndian>(chunk.len() as u32)?;
+            payload.extend_from_slice(&chunk);
+            let frame = self.send_request(ctx, MSG_BLOB_CHUNK, &payload)?;
+            let (_, next) = parse_blob_upload_resp(&frame.payload)?;
+            if next <= received {
+                return Err(Error::invalid_response(format!(
+                    "blob chunk did not advance upload (at {received} bytes)"
+                )));
+            }
+            received = next;
+        }
+
+        let frame = self.send_request(ctx, MSG_COMMIT_BLOB, &upload_id.to_le_bytes())?;
+        parse_put_blob_result(&frame.payload)
+    }
+
+    /// Reports, for each hash in order, whether the server already stores
+    /// that blob. Nothing is uploaded; large lists are sent in batches.
+    pub fn has_blobs(&self, ctx: &RequestContext, hashes: &[[u8; 32]]) -> Result<Vec<bool>> {
+        let mut present = Vec::with_capacity(hashes.len());
+        for batch in hashes.chunks(HAS_BLOBS_BATCH) {
+            let mut payload = Vec::with_capacity(4 + batch.len() * 32);
+            payload.write_u32::<LittleEndian>(batch.len() as u32)?;
+            for hash in batch {
+                payload.extend_from_slice(hash);
+            }
+            let frame = self.send_request(ctx, MSG_HAS_BLOBS, &payload)?;
+            present.extend(parse_has_blobs_resp(&frame.payload, batch.len())?);
+        }
+        Ok(present)
     }
 
     pub fn put_blob_if_absent(
@@ -119,38 +346,169 @@ impl Client {
             payload.extend_from_slice(&req.idempotency_key);
         }
 
-        let mut flags = 0u16;
+        let mut flags = append_flags(req);
         if let Some(hash) = fs_root_hash {
-            flags |= 1;
+            flags |= APPEND_FLAG_FS_ROOT;
             payload.extend_from_slice(&hash);
         }
 
         let frame = self.send_request_with_flags(ctx, MSG_APPEND_TURN, flags, &payload)?;
-        if frame.payload.len() < 52 {
-            return Err(Error::invalid_response(format!(
-                "append response too short ({} bytes)",
-                frame.payload.len()
-            )));
+        parse_append_result(&frame.payload)
+    }
+}
+
+/// Fills `buf` from `reader`; `offset` and `len` only shape the error when
+/// the reader ends early.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8], offset: u64, len: u64) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Err(short_read(offset + filled as u64, len)),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
         }
-        let mut cursor = std::io::Cursor::new(frame.payload);
-        let context_id = cursor.read_u64::<LittleEndian>()?;
-        let turn_id = cursor.read_u64::<LittleEndian>()?;
-        let depth = cursor.read_u32::<LittleEndian>()?;
-        let mut hash = [0u8; 32];
-        cursor.read_exact(&mut hash)?;
-        Ok(AppendResult {
-            context_id,
-            turn_id,
-            depth,
-            payload_hash: hash,
-        })
     }
+    Ok(())
+}
+
+fn short_read(read: u64, len: u64) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        format!("blob reader ended after {read} of {len} bytes"),
+    ))
+}
+
+fn parse_put_blob_result(payload: &[u8]) -> Result<PutBlobResult> {
+    if payload.len() < 33 {
+        return Err(Error::invalid_response(format!(
+            "put blob response too short ({} bytes)",
+            payload.len()
+        )));
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&payload[0..32]);
+    Ok(PutBlobResult {
+        hash,
+        was_new: payload[32] == 1,
+    })
+}
+
+fn parse_has_blobs_resp(payload: &[u8], expected: usize) -> Result<Vec<bool>> {
+    let count = payload
+        .get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+    if count != Some(expected) || payload.len() != 4 + expected {
+        return Err(Error::invalid_response(format!(
+            "has blobs response does not cover {expected} hashes ({} bytes)",
+            payload.len()
+        )));
+    }
+    Ok(payload[4..].iter().map(|&b| b == 1).collect())
+}
+
+fn parse_find_snapshot_refs_resp(payload: &[u8]) -> Result<Vec<u64>> {
+    let count = payload
+        .get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+    match count {
+        Some(count) if payload.len() == 4 + count * 8 => Ok(payload[4..]
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()),
+        _ => Err(Error::invalid_response(format!(
+            "malformed find snapshot refs response ({} bytes)",
+            payload.len()
+        ))),
+    }
+}
+
+fn parse_verify_snapshot_resp(payload: &[u8]) -> Result<VerifyReport> {
+    let malformed = || {
+        Error::invalid_response(format!(
+            "malformed verify snapshot response ({} bytes)",
+            payload.len()
+        ))
+    };
+    let mut cursor = std::io::Cursor::new(payload);
+    let mut report = VerifyReport {
+        trees_checked: cursor.read_u64::<LittleEndian>().map_err(|_| malformed())?,
+        blobs_checked: cursor.read_u64::<LittleEndian>().map_err(|_| malformed())?,
+        missing: cursor.read_u64::<LittleEndian>().map_err(|_| malformed())?,
+        missing_paths: Vec::new(),
+    };
+    let count = cursor.read_u32::<LittleEndian>().map_err(|_| malformed())?;
+    for _ in 0..count {
+        let len = cursor.read_u32::<LittleEndian>().map_err(|_| malformed())? as usize;
+        let start = cursor.position() as usize;
+        let path = payload.get(start..start + len).ok_or_else(malformed)?;
+        let path = String::from_utf8(path.to_vec())
+            .map_err(|_| Error::invalid_response("snapshot path not utf8"))?;
+        cursor.set_position((start + len) as u64);
+        report.missing_paths.push(path);
+    }
+    Ok(report)
+}
+
+/// Returns (upload_id, bytes received so far).
+fn parse_blob_upload_resp(payload: &[u8]) -> Result<(u64, u64)> {
+    if payload.len() < 16 {
+        return Err(Error::invalid_response(format!(
+            "blob upload response too short ({} bytes)",
+            payload.len()
+        )));
+    }
+    let mut cursor = std::io::Cursor::new(payload);
+    let upload_id = cursor.read_u64::<LittleEndian>()?;
+    let received = cursor.read_u64::<LittleEndian>()?;
+    Ok((upload_id, received))
+}
+
+fn parse_attach_fs_result(payload: Vec<u8>) -> Result<AttachFsResult> {
+    if payload.len() < 40 {
+        return Err(Error::invalid_response(format!(
+            "attach fs response too short ({} bytes)",
+            payload.len()
+        )));
+    }
+
+    let mut cursor = std::io::Cursor::new(payload);
+    let turn_id = cursor.read_u64::<LittleEndian>()?;
+    let mut hash = [0u8; 32];
+    cursor.read_exact(&mut hash)?;
+
+    Ok(AttachFsResult {
+        turn_id,
+        fs_root_hash: hash,
+    })
+}
+
+fn parse_get_blob_result(mut payload: Vec<u8>) -> Result<Vec<u8>> {
+    if payload.len() < 4 {
+        return Err(Error::invalid_response(format!(
+            "get blob response too short ({} bytes)",
+            payload.len()
+        )));
+    }
+    let len = u32::from_le_bytes(payload[..4].try_into().unwrap()) as usize;
+    if payload.len() - 4 != len {
+        return Err(Error::invalid_response(format!(
+            "get blob response length mismatch: header says {len}, got {}",
+            payload.len() - 4
+        )));
+    }
+    payload.drain(..4);
+    Ok(payload)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_util::{decode_hex, load_fixture};
+    use crate::client::{dial, with_blob_chunk_size};
+    use crate::protocol::{read_frame, write_frame, MSG_HELLO};
+    use crate::test_util::{decode_hex, hello_response, load_fixture, mock_server};
+    use std::net::TcpListener;
+    use std::thread;
 
     fn build_append_payload(req: &AppendRequest, fs_root_hash: Option<[u8; 32]>) -> Vec<u8> {
         let encoding = if req.encoding == 0 {
@@ -226,8 +584,273 @@ mod tests {
             idempotency_key: Vec::new(),
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         let payload = build_append_payload(&req, Some([0xBB; 32]));
         assert_eq!(decode_hex(&fixture.payload_hex), payload);
     }
+
+    /// Chunks a fake server received, as (offset, data).
+    type Chunks = Vec<(u64, Vec<u8>)>;
+
+    /// Accepts one chunked upload of `hash`, claiming the first four bytes
+    /// already arrived, and returns the chunks received.
+    fn spawn_chunked_blob_server(hash: [u8; 32]) -> (String, thread::JoinHandle<Chunks>) {
+        mock_server(move |stream| {
+            let upload_resp = |upload_id: u64, received: u64| {
+                let mut resp = Vec::new();
+                resp.write_u64::<LittleEndian>(upload_id).unwrap();
+                resp.write_u64::<LittleEndian>(received).unwrap();
+                resp
+            };
+
+            let begin = read_frame(stream).unwrap();
+            assert_eq!(begin.header.msg_type, MSG_BEGIN_BLOB);
+            assert_eq!(&begin.payload[0..32], &hash);
+            assert_eq!(&begin.payload[32..40], &10u64.to_le_bytes());
+            // Pretend the first four bytes arrived before a reconnect.
+            let resp = upload_resp(5, 4);
+            write_frame(stream, MSG_BEGIN_BLOB, 0, begin.header.req_id, &resp).unwrap();
+
+            let mut chunks = Vec::new();
+            loop {
+                let frame = read_frame(stream).unwrap();
+                if frame.header.msg_type == MSG_COMMIT_BLOB {
+                    assert_eq!(frame.payload, 5u64.to_le_bytes());
+                    let mut resp = hash.to_vec();
+                    resp.push(1);
+                    write_frame(stream, MSG_COMMIT_BLOB, 0, frame.header.req_id, &resp).unwrap();
+                    break;
+                }
+                assert_eq!(frame.header.msg_type, MSG_BLOB_CHUNK);
+                let mut cursor = std::io::Cursor::new(&frame.payload);
+                assert_eq!(cursor.read_u64::<LittleEndian>().unwrap(), 5);
+                let offset = cursor.read_u64::<LittleEndian>().unwrap();
+                let len = cursor.read_u32::<LittleEndian>().unwrap() as u64;
+                chunks.push((offset, frame.payload[20..].to_vec()));
+                let resp = upload_resp(5, offset + len);
+                write_frame(stream, MSG_BLOB_CHUNK, 0, frame.header.req_id, &resp).unwrap();
+            }
+            chunks
+        })
+    }
+
+    #[test]
+    fn put_blob_chunks_large_payloads_and_resumes_from_server_offset() {
+        let data = b"0123456789".to_vec();
+        let hash = *blake3::hash(&data).as_bytes();
+        let (addr, handle) = spawn_chunked_blob_server(hash);
+
+        let client = dial(&addr, [with_blob_chunk_size(4)]).unwrap();
+        let ctx = RequestContext::background();
+        let result = client.put_blob(&ctx, &PutBlobRequest { data }).unwrap();
+        assert_eq!(result.hash, hash);
+        assert!(result.was_new);
+
+        let chunks = handle.join().unwrap();
+        assert_eq!(
+            chunks,
+            vec![(4, b"4567".to_vec()), (8, b"89".to_vec())],
+            "upload should resume at the server's offset"
+        );
+    }
+
+    #[test]
+    fn put_blob_stream_hashes_then_uploads_from_reader() {
+        let data = b"0123456789";
+        let hash = *blake3::hash(data).as_bytes();
+        let (addr, handle) = spawn_chunked_blob_server(hash);
+
+        let client = dial(&addr, [with_blob_chunk_size(4)]).unwrap();
+        let ctx = RequestContext::background();
+        // Leading bytes before the reader's position are not part of the blob.
+        let mut reader = std::io::Cursor::new(b"xx0123456789".to_vec());
+        reader.set_position(2);
+        let result = client.put_blob_stream(&ctx, reader, 10).unwrap();
+        assert_eq!(result.hash, hash);
+
+        let chunks = handle.join().unwrap();
+        assert_eq!(chunks, vec![(4, b"4567".to_vec()), (8, b"89".to_vec())]);
+
+        let short = std::io::Cursor::new(b"0123".to_vec());
+        let err = client.put_blob_stream(&ctx, short, 10).unwrap_err();
+        assert!(err.to_string().contains("4 of 10 bytes"), "{err}");
+    }
+
+    #[test]
+    fn put_blob_hashes_with_the_servers_algorithm() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = read_frame(&mut stream).unwrap();
+            let mut resp = hello_response(1);
+            resp[10] = crate::fstree::HashAlgorithmSha256;
+            write_frame(&mut stream, MSG_HELLO, 0, hello.header.req_id, &resp).unwrap();
+            let frame = read_frame(&mut stream).unwrap();
+            assert_eq!(frame.header.msg_type, MSG_PUT_BLOB);
+            let mut resp = frame.payload[..32].to_vec();
+            resp.push(1);
+            write_frame(&mut stream, MSG_PUT_BLOB, 0, frame.header.req_id, &resp).unwrap();
+        });
+
+        let client = dial(&addr.to_string(), Vec::new()).unwrap();
+        assert_eq!(client.hash_algorithm(), crate::fstree::HashAlgorithmSha256);
+        let ctx = RequestContext::background();
+        let data = b"hello".to_vec();
+        let result = client.put_blob(&ctx, &PutBlobRequest { data }).unwrap();
+        assert_eq!(
+            hex::encode(result.hash),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn attach_fs_sends_capture_metadata() {
+        let (addr, handle) = mock_server(move |stream| {
+            let mut payloads = Vec::new();
+            for _ in 0..2 {
+                let frame = read_frame(stream).unwrap();
+                assert_eq!(frame.header.msg_type, MSG_ATTACH_FS);
+                let resp = frame.payload[..40].to_vec();
+                write_frame(stream, MSG_ATTACH_FS, 0, frame.header.req_id, &resp).unwrap();
+                payloads.push(frame.payload);
+            }
+            payloads
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let meta = SnapshotMeta {
+            captured_at_unix_ms: 1_748_736_000_000,
+            file_count: 4210,
+            dir_count: 312,
+            total_bytes: 9_000_000,
+            duration_ms: 850,
+        };
+        for meta in [None, Some(meta)] {
+            let req = AttachFsRequest {
+                turn_id: 99,
+                fs_root_hash: [0xAA; 32],
+                meta,
+            };
+            let result = client.attach_fs(&ctx, &req).unwrap();
+            assert_eq!(result.turn_id, 99);
+        }
+
+        let payloads = handle.join().unwrap();
+        assert_eq!(payloads[0].len(), 40);
+        assert_eq!(payloads[1].len(), 80);
+        assert_eq!(&payloads[1][40..48], &1_748_736_000_000u64.to_le_bytes());
+        assert_eq!(&payloads[1][48..56], &4210u64.to_le_bytes());
+        assert_eq!(&payloads[1][72..80], &850u64.to_le_bytes());
+    }
+
+    #[test]
+    fn attach_fs_to_head_sends_context_id_and_reports_turn() {
+        let (addr, handle) = mock_server(move |stream| {
+            let frame = read_frame(stream).unwrap();
+            assert_eq!(frame.header.msg_type, MSG_ATTACH_FS_HEAD);
+            let mut resp = 41u64.to_le_bytes().to_vec();
+            resp.extend_from_slice(&frame.payload[8..40]);
+            write_frame(stream, MSG_ATTACH_FS_HEAD, 0, frame.header.req_id, &resp).unwrap();
+            frame.payload
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let result = client.attach_fs_to_head(&ctx, 7, [0xBB; 32]).unwrap();
+        assert_eq!(result.turn_id, 41);
+        assert_eq!(result.fs_root_hash, [0xBB; 32]);
+
+        let payload = handle.join().unwrap();
+        assert_eq!(payload.len(), 40);
+        assert_eq!(&payload[..8], &7u64.to_le_bytes());
+    }
+
+    #[test]
+    fn find_snapshot_refs_sends_hash_and_parses_turn_ids() {
+        let (addr, handle) = mock_server(move |stream| {
+            let frame = read_frame(stream).unwrap();
+            assert_eq!(frame.header.msg_type, MSG_FIND_SNAPSHOT_REFS);
+            let mut resp = 2u32.to_le_bytes().to_vec();
+            resp.extend_from_slice(&3u64.to_le_bytes());
+            resp.extend_from_slice(&9u64.to_le_bytes());
+            write_frame(
+                stream,
+                MSG_FIND_SNAPSHOT_REFS,
+                0,
+                frame.header.req_id,
+                &resp,
+            )
+            .unwrap();
+            frame.payload
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let turns = client.find_snapshot_refs(&ctx, [0xCC; 32]).unwrap();
+        assert_eq!(turns, vec![3, 9]);
+
+        let payload = handle.join().unwrap();
+        assert_eq!(payload, vec![0xCC; 32]);
+    }
+
+    #[test]
+    fn verify_snapshot_parses_missing_paths() {
+        let (addr, handle) = mock_server(move |stream| {
+            let frame = read_frame(stream).unwrap();
+            assert_eq!(frame.header.msg_type, MSG_VERIFY_SNAPSHOT);
+            let mut resp = Vec::new();
+            for n in [4u64, 10, 3] {
+                resp.extend_from_slice(&n.to_le_bytes());
+            }
+            resp.extend_from_slice(&2u32.to_le_bytes());
+            for path in ["gone", "src/main.rs"] {
+                resp.extend_from_slice(&(path.len() as u32).to_le_bytes());
+                resp.extend_from_slice(path.as_bytes());
+            }
+            write_frame(stream, MSG_VERIFY_SNAPSHOT, 0, frame.header.req_id, &resp).unwrap();
+            frame.payload
+        });
+
+        let client = dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let report = client.verify_snapshot(&ctx, [0xAB; 32]).unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.trees_checked, 4);
+        assert_eq!(report.blobs_checked, 10);
+        assert_eq!(report.missing, 3);
+        assert_eq!(report.missing_paths, vec!["gone", "src/main.rs"]);
+        assert_eq!(handle.join().unwrap(), vec![0xAB; 32]);
+
+        assert!(parse_verify_snapshot_resp(&[0u8; 20]).is_err());
+    }
+
+    #[test]
+    fn put_blob_over_frame_limit_fails_before_sending() {
+        let (addr, handle) = mock_server(move |stream| {
+            // The client closes without sending anything else.
+            read_frame(stream).is_err()
+        });
+
+        let client = dial(&addr, [with_blob_chunk_size(usize::MAX)]).unwrap();
+        let ctx = RequestContext::background();
+        let data = vec![0u8; crate::protocol::MAX_FRAME_SIZE as usize];
+        let err = client.put_blob(&ctx, &PutBlobRequest { data }).unwrap_err();
+        match &err {
+            Error::PayloadTooLarge { size, limit } => {
+                assert_eq!(*size, crate::protocol::MAX_FRAME_SIZE as u64 + 36);
+                assert_eq!(*limit, crate::protocol::MAX_FRAME_SIZE as u64);
+            }
+            other => panic!("expected PayloadTooLarge, got {other:?}"),
+        }
+        assert!(err.to_string().contains("chunked upload"));
+
+        client.close().unwrap();
+        assert!(handle.join().unwrap());
+    }
 }
@@ -0,0 +1,72 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::capture::{FstreeError, Result};
+
+/// On-disk record of blobs already uploaded to a particular server.
+///
+/// Each server address gets its own subdirectory holding one marker file per
+/// uploaded hash (contents: upload time in unix milliseconds). The cache is
+/// purely an optimization: deleting the directory only costs extra uploads.
+#[derive(Debug, Clone)]
+pub struct UploadCache {
+    dir: PathBuf,
+}
+
+impl UploadCache {
+    pub fn open(root: impl AsRef<Path>, server_addr: &str) -> Result<Self> {
+        let dir = root.as_ref().join(server_key(server_addr));
+        fs::create_dir_all(&dir).map_err(|err| FstreeError::io_at(&dir, err))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.marker_path(hash).is_file()
+    }
+
+    /// When `hash` was recorded as uploaded, if it is in the cache.
+    pub fn uploaded_at(&self, hash: &[u8; 32]) -> std::option::Option<SystemTime> {
+        let data = fs::read_to_string(self.marker_path(hash)).ok()?;
+        let millis: u64 = data.trim().parse().ok()?;
+        Some(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    pub fn record(&self, hash: &[u8; 32]) -> Result<()> {
+        let path = self.marker_path(hash);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| FstreeError::io_at(parent, err))?;
+        }
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        fs::write(&path, millis.to_string()).map_err(|err| FstreeError::io_at(&path, err))
+    }
+
+    fn marker_path(&self, hash: &[u8; 32]) -> PathBuf {
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+}
+
+/// Maps a server address to a directory name, replacing characters that are
+/// not portable in file names (`:` in ports, `[`/`]`/`%` in IPv6 literals).
+fn server_key(addr: &str) -> String {
+    addr.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -1,27 +1,36 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime};
-
-use blake3::Hasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use crate::encoding::encode_msgpack;
 
-use super::options::{Options, SnapshotOption};
+use super::hash::{hash_algorithm_for_id, HashAlgorithm, HashAlgorithmBlake3Keyed};
+use super::manifest::{CaptureManifest, MANIFEST_ENTRY_NAME};
+use super::options::{with_hash_algorithm, Options, SnapshotOption};
+use super::sniff::is_binary_file;
 use super::types::{
-    EntryKindDirectory, EntryKindFile, EntryKindSymlink, FileRef, Snapshot, SnapshotStats,
-    TreeEntry,
+    DeviceNumber, EntryKind, EntryKindBlockDevice, EntryKindCharDevice, EntryKindDirectory,
+    EntryKindFifo, EntryKindFile, EntryKindSymlink, FileRef, SkippedFile, Snapshot, SnapshotStats,
+    TimingBreakdown, TreeEntry,
 };
+use super::xattrs::read_xattrs;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FstreeErrorKind {
     TooManyFiles,
+    /// A single directory holds more entries than `with_max_dir_entries` allows.
+    TooManyDirEntries,
     FileTooLarge,
     CyclicLink,
+    /// The caller's cancellation flag was set mid-capture.
+    Cancelled,
     Io,
     Msgpack,
     Client,
@@ -32,11 +41,19 @@ pub enum FstreeErrorKind {
 pub struct FstreeError {
     pub kind: FstreeErrorKind,
     pub detail: String,
+    /// The file or directory an `Io` error concerns, when known.
+    pub path: std::option::Option<PathBuf>,
+    /// The underlying `io::Error`'s kind, for `Io` errors, so callers can
+    /// tell e.g. a permission problem from a missing file.
+    pub io_kind: std::option::Option<std::io::ErrorKind>,
 }
 
 impl std::fmt::Display for FstreeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "fstree: {}", self.detail)
+        match &self.path {
+            Some(path) => write!(f, "fstree: {}: {}", path.display(), self.detail),
+            None => write!(f, "fstree: {}", self.detail),
+        }
     }
 }
 
@@ -47,6 +64,24 @@ impl FstreeError {
         Self {
             kind,
             detail: detail.into(),
+            path: None,
+            io_kind: None,
+        }
+    }
+
+    /// An `Io` error not tied to a particular path.
+    pub(crate) fn io(err: std::io::Error) -> Self {
+        Self {
+            io_kind: Some(err.kind()),
+            ..Self::new(FstreeErrorKind::Io, err.to_string())
+        }
+    }
+
+    /// An `Io` error from operating on `path`.
+    pub(crate) fn io_at(path: &Path, err: std::io::Error) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            ..Self::io(err)
         }
     }
 }
@@ -54,6 +89,8 @@ impl FstreeError {
 #[allow(non_upper_case_globals)]
 pub const ErrTooManyFiles: FstreeErrorKind = FstreeErrorKind::TooManyFiles;
 #[allow(non_upper_case_globals)]
+pub const ErrTooManyDirEntries: FstreeErrorKind = FstreeErrorKind::TooManyDirEntries;
+#[allow(non_upper_case_globals)]
 pub const ErrFileTooLarge: FstreeErrorKind = FstreeErrorKind::FileTooLarge;
 #[allow(non_upper_case_globals)]
 pub const ErrCyclicLink: FstreeErrorKind = FstreeErrorKind::CyclicLink;
@@ -64,50 +101,260 @@ pub fn capture(
     root: impl AsRef<Path>,
     opts: impl IntoIterator<Item = SnapshotOption>,
 ) -> Result<Snapshot> {
-    let start = SystemTime::now();
-    let abs_root = fs::canonicalize(root.as_ref())
-        .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
+    capture_inner(root.as_ref(), opts, None)
+}
 
-    let metadata = fs::metadata(&abs_root)
-        .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
-    if !metadata.is_dir() {
+/// Like `capture`, but gives up with `FstreeErrorKind::Cancelled` once
+/// `cancel` is set. The flag is checked before each directory entry, so a
+/// single large file still hashes to completion. Pass
+/// `RequestContext::cancel_flag` to abort along with a request.
+pub fn capture_cancellable(
+    root: impl AsRef<Path>,
+    opts: impl IntoIterator<Item = SnapshotOption>,
+    cancel: Arc<AtomicBool>,
+) -> Result<Snapshot> {
+    capture_inner(root.as_ref(), opts, Some(cancel))
+}
+
+/// Captures `root` and compares it with `expected`, a snapshot recorded
+/// earlier, without uploading anything. Returns the sorted relative paths
+/// that drifted: files and symlinks added, removed, or changed in content,
+/// kind, or mode, and directories added or removed. An empty list means the
+/// root hashes match.
+///
+/// `opts` should match those `expected` was captured with, since exclusions
+/// and mode normalization change what is compared; the hash algorithm is
+/// always taken from `expected`, except that a keyed snapshot needs the same
+/// `with_hash_namespace` key in `opts`.
+pub fn verify_against(
+    root: impl AsRef<Path>,
+    expected: &Snapshot,
+    opts: impl IntoIterator<Item = SnapshotOption>,
+) -> Result<Vec<String>> {
+    let mut opts: Vec<SnapshotOption> = opts.into_iter().collect();
+    if expected.hash_algorithm != HashAlgorithmBlake3Keyed {
+        let alg = hash_algorithm_for_id(expected.hash_algorithm).ok_or_else(|| {
+            FstreeError::new(
+                FstreeErrorKind::Other,
+                format!("unknown hash algorithm {}", expected.hash_algorithm),
+            )
+        })?;
+        opts.push(with_hash_algorithm(alg));
+    }
+
+    let live = capture_inner(root.as_ref(), opts, None)?;
+    if live.hash_algorithm != expected.hash_algorithm {
         return Err(FstreeError::new(
             FstreeErrorKind::Other,
-            format!("root is not a directory: {}", abs_root.display()),
+            "keyed snapshot requires with_hash_namespace",
         ));
     }
+    if live.root_hash == expected.root_hash {
+        return Ok(Vec::new());
+    }
+
+    let live_entries = entry_index(&live)?;
+    let expected_entries = entry_index(expected)?;
+    let mut drifted: Vec<String> = live_entries
+        .iter()
+        .filter(|(path, entry)| expected_entries.get(*path) != Some(entry))
+        .map(|(path, _)| path.clone())
+        .collect();
+    drifted.extend(
+        expected_entries
+            .keys()
+            .filter(|path| !live_entries.contains_key(*path))
+            .cloned(),
+    );
+    drifted.sort();
+    Ok(drifted)
+}
+
+/// What `verify_against` compares an entry by: kind, mode and hash.
+type EntryKey = (EntryKind, u32, [u8; 32]);
+
+/// Every path in `snapshot` with its `EntryKey`. Directories compare by
+/// presence only, so a changed file does not also report each of its
+/// ancestors.
+fn entry_index(snapshot: &Snapshot) -> Result<HashMap<String, EntryKey>> {
+    let mut index = HashMap::new();
+    snapshot.walk(|path, entry| {
+        let key = if entry.kind == EntryKindDirectory {
+            (entry.kind, 0, [0u8; 32])
+        } else {
+            (entry.kind, entry.mode, entry.hash)
+        };
+        index.insert(path.to_string(), key);
+        Ok(())
+    })?;
+    Ok(index)
+}
+
+/// A blob handed to a `BlobSink` as soon as capture has hashed it.
+pub(crate) enum StreamedBlob<'a> {
+    /// An encoded tree object, sent after every blob it references.
+    Tree(&'a [u8]),
+    /// A symlink's target path.
+    Symlink(&'a [u8]),
+    /// A file small enough to have been read into memory whole.
+    FileBytes(&'a [u8]),
+    /// A larger file, hashed and rewound to its start so its content can
+    /// be streamed from the same handle.
+    FileReader { file: fs::File, size: u64 },
+}
+
+/// Receives each blob with its hash during `capture_with_sink`. Errors abort
+/// the capture.
+pub(crate) type BlobSink<'s> = dyn FnMut([u8; 32], StreamedBlob<'_>) -> Result<()> + 's;
+
+fn capture_inner(
+    root: &Path,
+    opts: impl IntoIterator<Item = SnapshotOption>,
+    cancel: std::option::Option<Arc<AtomicBool>>,
+) -> Result<Snapshot> {
+    capture_with_sink(root, opts, cancel, None)
+}
 
+/// Captures `root`, passing each new blob to `sink` (if any) as it is
+/// produced. Files up to `sink`'s buffer limit are read once, into memory;
+/// larger ones are hashed and then rewound.
+pub(crate) fn capture_with_sink(
+    root: &Path,
+    opts: impl IntoIterator<Item = SnapshotOption>,
+    cancel: std::option::Option<Arc<AtomicBool>>,
+    sink: std::option::Option<(&mut BlobSink<'_>, u64)>,
+) -> Result<Snapshot> {
+    let start = SystemTime::now();
     let mut options = Options::default();
     for opt in opts {
         opt(&mut options);
     }
 
-    let mut builder = Builder::new(options);
+    // Canonicalizing would silently resolve a symlinked root, which the
+    // walk below never does for links inside the tree.
+    if !options.follow_symlinks {
+        let link_meta = fs::symlink_metadata(root).map_err(|err| FstreeError::io_at(root, err))?;
+        if link_meta.file_type().is_symlink() {
+            return Err(FstreeError::new(
+                FstreeErrorKind::Other,
+                format!(
+                    "root is a symlink and follow_symlinks is off: {} (capture its target or enable with_follow_symlinks)",
+                    root.display()
+                ),
+            ));
+        }
+    }
+
+    let abs_root = fs::canonicalize(root).map_err(|err| FstreeError::io_at(root, err))?;
+
+    let metadata = fs::metadata(&abs_root).map_err(|err| FstreeError::io_at(&abs_root, err))?;
+    if !metadata.is_dir() {
+        return Err(FstreeError::new(
+            FstreeErrorKind::Other,
+            format!("root is not a directory: {}", abs_root.display()),
+        ));
+    }
+
+    let hash_algorithm = options.hash_algorithm.id();
+    let mut builder = Builder::new(options, cancel);
+    builder.sink = sink;
     let root_hash = builder.build_tree(&abs_root, Path::new(""))?;
+    let unique_file_bytes = builder.files.values().map(|file| file.size).sum();
 
     Ok(Snapshot {
         root_hash,
         trees: builder.trees,
         files: builder.files,
         symlinks: builder.symlinks,
+        skipped: builder.skipped,
         captured_at: start,
+        hash_algorithm,
         stats: SnapshotStats {
             file_count: builder.file_count,
             dir_count: builder.dir_count,
             symlink_count: builder.symlink_count,
+            special_count: builder.special_count,
             total_bytes: builder.total_bytes,
+            logical_bytes: builder.logical_bytes,
+            unique_file_bytes,
+            hardlink_count: builder.hardlink_count,
+            truncated: builder.truncated,
             duration: start.elapsed().unwrap_or(Duration::from_secs(0)),
+            timing: builder.timing,
         },
     })
 }
 
+/// Decodes a tree object. Errors give the byte offset where decoding failed
+/// and what was expected there versus what was found.
 pub fn deserialize_tree(data: &[u8]) -> Result<Vec<TreeEntry>> {
-    crate::encoding::decode_msgpack_into(data)
-        .map_err(|err| FstreeError::new(FstreeErrorKind::Msgpack, err.to_string()))
+    let entries: Vec<TreeEntry> = crate::encoding::decode_msgpack_into(data).map_err(|err| {
+        let detail = locate_tree_error(data).unwrap_or_else(|| err.to_string());
+        FstreeError::new(
+            FstreeErrorKind::Msgpack,
+            format!("invalid tree msgpack {detail}"),
+        )
+    })?;
+    for (index, entry) in entries.iter().enumerate() {
+        check_entry_name(&entry.name).map_err(|reason| {
+            FstreeError::new(
+                FstreeErrorKind::Msgpack,
+                format!("invalid tree: entry {index}: {reason}"),
+            )
+        })?;
+    }
+    Ok(entries)
+}
+
+/// Rejects names that could escape the directory a tree is written into:
+/// `.`, `..`, and anything containing a path separator or NUL.
+pub(crate) fn check_entry_name(name: &str) -> std::result::Result<(), String> {
+    if name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(format!("unsafe entry name {name:?}"));
+    }
+    Ok(())
 }
 
-struct Builder {
+/// Walks `data` entry by entry to find where a failed tree decode went wrong.
+fn locate_tree_error(data: &[u8]) -> std::option::Option<String> {
+    let mut cursor = std::io::Cursor::new(data);
+    let count = match rmp::decode::read_array_len(&mut cursor) {
+        Ok(count) => count,
+        Err(rmp::decode::ValueReadError::TypeMismatch(marker)) => {
+            return Some(format!("at byte 0: expected array, found {marker:?}"))
+        }
+        Err(err) => return Some(format!("at byte {}: {err}", cursor.position())),
+    };
+    for index in 0..count {
+        let offset = cursor.position();
+        let value = match rmpv::decode::read_value(&mut cursor) {
+            Ok(value) => value,
+            Err(err) => {
+                return Some(format!(
+                    "at byte {}: entry {index}: {err}",
+                    cursor.position()
+                ))
+            }
+        };
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &value).ok()?;
+        match crate::encoding::decode_msgpack_into::<TreeEntry>(&bytes) {
+            Ok(_) => continue,
+            Err(crate::error::Error::InvalidResponse(msg)) => {
+                let msg = msg.trim_start_matches("msgpack decode error: ");
+                return Some(format!("at byte {offset}: entry {index}: {msg}"));
+            }
+            Err(err) => return Some(format!("at byte {offset}: entry {index}: {err}")),
+        }
+    }
+    None
+}
+
+struct Builder<'s, 'f> {
     options: Options,
+    /// Where blobs go as they are hashed, with the largest file size read
+    /// into memory for it; see `capture_with_sink`.
+    sink: std::option::Option<(&'s mut BlobSink<'f>, u64)>,
     trees: HashMap<[u8; 32], Vec<u8>>,
     files: HashMap<[u8; 32], FileRef>,
     symlinks: HashMap<[u8; 32], String>,
@@ -115,13 +362,29 @@ struct Builder {
     file_count: usize,
     dir_count: usize,
     symlink_count: usize,
+    special_count: usize,
     total_bytes: u64,
+    logical_bytes: u64,
+    hardlink_count: usize,
+    /// Entries for multiply-linked files already read, keyed by `(dev, ino)`,
+    /// so further links reuse the hash instead of re-reading the content.
+    linked: HashMap<(u64, u64), TreeEntry>,
+    /// Set once `max_files` is hit under `partial_on_limit`; every directory
+    /// still being walked then stops and writes what it has.
+    truncated: bool,
+    /// The directory being walked was excluded and is only searched for
+    /// re-included paths; see `with_reinclude_under_excluded_dirs`.
+    under_excluded: bool,
+    skipped: Vec<SkippedFile>,
+    cancel: std::option::Option<Arc<AtomicBool>>,
+    timing: TimingBreakdown,
 }
 
-impl Builder {
-    fn new(options: Options) -> Self {
+impl<'s, 'f> Builder<'s, 'f> {
+    fn new(options: Options, cancel: std::option::Option<Arc<AtomicBool>>) -> Self {
         Self {
             options,
+            sink: None,
             trees: HashMap::new(),
             files: HashMap::new(),
             symlinks: HashMap::new(),
@@ -129,13 +392,33 @@ impl Builder {
             file_count: 0,
             dir_count: 0,
             symlink_count: 0,
+            special_count: 0,
             total_bytes: 0,
+            logical_bytes: 0,
+            hardlink_count: 0,
+            linked: HashMap::new(),
+            truncated: false,
+            under_excluded: false,
+            skipped: Vec::new(),
+            cancel,
+            timing: TimingBreakdown::default(),
         }
     }
 
     fn build_tree(&mut self, abs_path: &Path, rel_path: &Path) -> Result<[u8; 32]> {
-        if let Ok(real_path) = fs::canonicalize(abs_path) {
-            if self.visited.contains(&real_path) {
+        if self.beyond_max_depth(rel_path) {
+            return self.write_tree(rel_path, Vec::new());
+        }
+
+        // Without following symlinks the walk can't revisit a directory, so
+        // only pay for canonicalize when a cycle is actually possible.
+        let real_path = if self.options.may_follow_symlinks() {
+            timed(&mut self.timing.walk, || fs::canonicalize(abs_path).ok())
+        } else {
+            None
+        };
+        if let Some(real_path) = &real_path {
+            if self.visited.contains(real_path) {
                 return Err(FstreeError::new(
                     FstreeErrorKind::CyclicLink,
                     "cyclic symbolic link detected",
@@ -145,42 +428,146 @@ impl Builder {
         }
 
         let mut entries = Vec::new();
-        let dir_entries = fs::read_dir(abs_path)
-            .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
+        // Entries are processed in name order, not the filesystem's, so a
+        // capture cut short by `max_files` keeps the same files every run.
+        let dir_entries = timed(&mut self.timing.walk, || {
+            fs::read_dir(abs_path).map(|entries| {
+                let mut entries: Vec<fs::DirEntry> = entries.filter_map(|e| e.ok()).collect();
+                entries.sort_by_key(|entry| entry.file_name());
+                entries
+            })
+        })
+        .map_err(|err| FstreeError::io_at(abs_path, err))?;
 
         for entry in dir_entries {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(_) => continue,
-            };
+            if self.truncated {
+                break;
+            }
+            if self.is_cancelled() {
+                return Err(FstreeError::new(
+                    FstreeErrorKind::Cancelled,
+                    "capture cancelled",
+                ));
+            }
             let file_name = entry.file_name();
             let name = file_name.to_string_lossy().to_string();
             let child_rel = rel_path.join(&name);
-            let child_abs = abs_path.join(&name);
+            // The on-disk name, which `name` only approximates when it is
+            // not valid UTF-8.
+            let child_abs = abs_path.join(&file_name);
             let rel_str = child_rel.to_string_lossy();
 
-            if self.options.should_exclude(
-                &rel_str,
-                entry.file_type().map(|t| t.is_dir()).unwrap_or(false),
-            ) {
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            // Below an excluded directory only included paths are kept.
+            let excluded = if self.under_excluded {
+                !self.options.is_included(&rel_str, is_dir)
+            } else {
+                self.options.should_exclude(&rel_str, is_dir)
+            };
+            if excluded && !(is_dir && self.options.reinclude_under_excluded_dirs) {
+                continue;
+            }
+            if self.options.embed_manifest.is_some() && child_rel == Path::new(MANIFEST_ENTRY_NAME)
+            {
+                self.skipped.push(SkippedFile {
+                    path: MANIFEST_ENTRY_NAME.to_string(),
+                    reason: "reserved name".to_string(),
+                });
                 continue;
             }
 
-            let metadata = if self.options.follow_symlinks {
-                fs::metadata(&child_abs)
-            } else {
-                fs::symlink_metadata(&child_abs)
-            };
-            let metadata = match metadata {
+            // Names that trees refuse to load (e.g. a legal unix name
+            // holding `\`) would make the snapshot unreadable; leave them out.
+            if check_entry_name(&name).is_err() {
+                // The name's own `\` must survive the separator rewrite.
+                let parent = rel_path.to_string_lossy().replace('\\', "/");
+                self.skipped.push(SkippedFile {
+                    path: if parent.is_empty() {
+                        name
+                    } else {
+                        format!("{parent}/{name}")
+                    },
+                    reason: "unsafe name".to_string(),
+                });
+                continue;
+            }
+
+            let metadata = timed(&mut self.timing.stat, || {
+                if self.options.follow_symlinks {
+                    fs::metadata(&child_abs)
+                } else {
+                    fs::symlink_metadata(&child_abs)
+                }
+            });
+            let mut metadata = match metadata {
                 Ok(meta) => meta,
                 Err(_) => continue,
             };
+            let followed = self.options.follow_symlinks
+                || (metadata.file_type().is_symlink() && self.options.follows_symlink(&rel_str));
+            if followed && metadata.file_type().is_symlink() {
+                metadata = match timed(&mut self.timing.stat, || fs::metadata(&child_abs)) {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+            }
+
+            if self.options.exclude_binary
+                && metadata.is_file()
+                && timed(&mut self.timing.hash, || is_binary_file(&child_abs)).unwrap_or(false)
+            {
+                self.skipped.push(SkippedFile {
+                    path: rel_str.replace('\\', "/"),
+                    reason: "binary".to_string(),
+                });
+                continue;
+            }
+
+            if self
+                .options
+                .max_dir_entries
+                .is_some_and(|max| entries.len() >= max)
+            {
+                return Err(FstreeError::new(
+                    FstreeErrorKind::TooManyDirEntries,
+                    format!(
+                        "directory has more than {} entries: {}",
+                        entries.len(),
+                        abs_path.display()
+                    ),
+                ));
+            }
 
-            match self.build_entry(&child_abs, &child_rel, &name, &metadata) {
-                Ok(entry) => entries.push(entry),
+            let parent_excluded = std::mem::replace(&mut self.under_excluded, excluded);
+            let built = self.build_entry(&child_abs, &child_rel, &name, &metadata);
+            self.under_excluded = parent_excluded;
+            match built {
+                // An excluded directory with nothing included below it.
+                Ok(entry) if excluded && self.is_empty_tree(&entry.hash) => self.dir_count -= 1,
+                Ok(mut entry) => {
+                    if self.options.xattrs {
+                        entry.xattrs =
+                            timed(&mut self.timing.stat, || read_xattrs(&child_abs, followed))
+                                .map_err(|err| {
+                                    let err = FstreeError::io_at(&child_abs, err);
+                                    FstreeError {
+                                        detail: format!("reading xattrs: {}", err.detail),
+                                        ..err
+                                    }
+                                })?;
+                    }
+                    entries.push(entry)
+                }
                 Err(err) => {
+                    if err.kind == FstreeErrorKind::TooManyFiles && self.options.partial_on_limit {
+                        self.truncated = true;
+                        break;
+                    }
                     if err.kind == FstreeErrorKind::TooManyFiles
+                        || err.kind == FstreeErrorKind::TooManyDirEntries
                         || err.kind == FstreeErrorKind::CyclicLink
+                        || err.kind == FstreeErrorKind::Cancelled
+                        || err.kind == FstreeErrorKind::Client
                     {
                         return Err(err);
                     }
@@ -190,18 +577,94 @@ impl Builder {
             }
         }
 
-        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        if rel_path.as_os_str().is_empty() {
+            if let Some(manifest) = self.manifest_entry()? {
+                entries.push(manifest);
+            }
+        }
+
+        let hash = self.write_tree(rel_path, entries)?;
+
+        if let Some(real_path) = &real_path {
+            self.visited.remove(real_path);
+        }
+
+        Ok(hash)
+    }
+
+    /// Encodes a directory in canonical order (see `TreeObject`) so equal
+    /// directories always hash alike. Lookups go by name, so only the first
+    /// entry of each name is kept; the rest (e.g. distinct non-UTF-8 names
+    /// that decode to the same string) are listed in `Snapshot::skipped`
+    /// with reason "duplicate name".
+    fn write_tree(&mut self, rel_path: &Path, mut entries: Vec<TreeEntry>) -> Result<[u8; 32]> {
+        let started = Instant::now();
+        entries.sort_by(|a, b| {
+            (&a.name, a.kind, &a.hash, a.mode).cmp(&(&b.name, b.kind, &b.hash, b.mode))
+        });
+        entries.dedup_by(|later, kept| {
+            if later.name != kept.name {
+                return false;
+            }
+            self.skipped.push(SkippedFile {
+                path: rel_path
+                    .join(&later.name)
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+                reason: "duplicate name".to_string(),
+            });
+            true
+        });
         let tree_bytes = encode_msgpack(&entries)
             .map_err(|err| FstreeError::new(FstreeErrorKind::Msgpack, err.to_string()))?;
-        let hash = blake3::hash(&tree_bytes);
-        self.trees.insert(*hash.as_bytes(), tree_bytes);
+        let hash = self.options.hash_algorithm.hash(&tree_bytes);
+        self.timing.encode += started.elapsed();
+        if let Some((sink, _)) = &mut self.sink {
+            sink(hash, StreamedBlob::Tree(&tree_bytes))?;
+        }
+        self.trees.insert(hash, tree_bytes);
         self.dir_count += 1;
+        Ok(hash)
+    }
 
-        if let Ok(real_path) = fs::canonicalize(abs_path) {
-            self.visited.remove(&real_path);
-        }
+    /// The inlined `with_embed_manifest` entry for the root tree, if asked
+    /// for. It is not counted in the snapshot's stats.
+    fn manifest_entry(&self) -> Result<std::option::Option<TreeEntry>> {
+        let Some(provenance) = &self.options.embed_manifest else {
+            return Ok(None);
+        };
+        let data = CaptureManifest::new(provenance.clone(), &self.options).encode()?;
+        Ok(Some(TreeEntry {
+            name: MANIFEST_ENTRY_NAME.to_string(),
+            kind: EntryKindFile,
+            mode: 0o644,
+            size: data.len() as u64,
+            hash: self.options.hash_algorithm.hash(&data),
+            hash_alg: self.options.hash_algorithm.id(),
+            inline_content: Some(data),
+            device: None,
+            xattrs: BTreeMap::new(),
+        }))
+    }
 
-        Ok(*hash.as_bytes())
+    fn is_empty_tree(&self, hash: &[u8; 32]) -> bool {
+        // An empty tree encodes as a zero-length msgpack array.
+        self.trees
+            .get(hash)
+            .is_some_and(|bytes| bytes.as_slice() == [0x90])
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+    }
+
+    /// Directories deeper than `max_depth` are recorded as empty trees.
+    fn beyond_max_depth(&self, rel_path: &Path) -> bool {
+        self.options
+            .max_depth
+            .is_some_and(|max| rel_path.components().count() > max)
     }
 
     fn build_entry(
@@ -211,21 +674,32 @@ impl Builder {
         name: &str,
         metadata: &fs::Metadata,
     ) -> Result<TreeEntry> {
-        let mode = metadata.permissions().perm_mode() & 0o7777;
+        let mut mode = metadata.permissions().perm_mode() & 0o7777;
+        if self.options.normalize_modes {
+            mode = normalize_mode(metadata, mode);
+        }
 
-        if metadata.file_type().is_symlink() && !self.options.follow_symlinks {
-            let target = fs::read_link(abs_path)
-                .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
+        // A followed symlink arrives with its target's metadata.
+        if metadata.file_type().is_symlink() {
+            let target = timed(&mut self.timing.stat, || fs::read_link(abs_path))
+                .map_err(|err| FstreeError::io_at(abs_path, err))?;
             let target_str = target.to_string_lossy().to_string();
-            let hash = blake3::hash(target_str.as_bytes());
+            let hash = self.options.hash_algorithm.hash(target_str.as_bytes());
+            if let Some((sink, _)) = &mut self.sink {
+                sink(hash, StreamedBlob::Symlink(target_str.as_bytes()))?;
+            }
             self.symlink_count += 1;
-            self.symlinks.insert(*hash.as_bytes(), target_str.clone());
+            self.symlinks.insert(hash, target_str.clone());
             return Ok(TreeEntry {
                 name: name.to_string(),
                 kind: EntryKindSymlink,
                 mode,
                 size: target_str.len() as u64,
-                hash: *hash.as_bytes(),
+                hash,
+                hash_alg: self.options.hash_algorithm.id(),
+                inline_content: None,
+                device: None,
+                xattrs: BTreeMap::new(),
             });
         }
 
@@ -237,6 +711,35 @@ impl Builder {
                 mode,
                 size: 0,
                 hash: dir_hash,
+                hash_alg: self.options.hash_algorithm.id(),
+                inline_content: None,
+                device: None,
+                xattrs: BTreeMap::new(),
+            });
+        }
+
+        if !metadata.is_file() {
+            // Opening a FIFO would block and devices have no content to
+            // hash; the walk drops these errors.
+            let Some((kind, device)) =
+                special_file(metadata).filter(|_| self.options.special_files)
+            else {
+                return Err(FstreeError::new(
+                    FstreeErrorKind::Other,
+                    format!("unsupported file type: {}", rel_path.display()),
+                ));
+            };
+            self.special_count += 1;
+            return Ok(TreeEntry {
+                name: name.to_string(),
+                kind,
+                mode,
+                size: 0,
+                hash: [0u8; 32],
+                hash_alg: self.options.hash_algorithm.id(),
+                inline_content: None,
+                device,
+                xattrs: BTreeMap::new(),
             });
         }
 
@@ -255,42 +758,170 @@ impl Builder {
             ));
         }
 
-        let hash = hash_file(abs_path)
-            .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
-        self.files.insert(
-            hash,
-            FileRef {
-                path: abs_path.to_path_buf(),
-                size,
+        let link_key = hardlink_key(metadata);
+        if let Some(linked) = link_key.and_then(|key| self.linked.get(&key)) {
+            let entry = TreeEntry {
+                name: name.to_string(),
+                mode,
+                ..linked.clone()
+            };
+            self.file_count += 1;
+            self.logical_bytes += size;
+            self.hardlink_count += 1;
+            return Ok(entry);
+        }
+
+        let alg = self.options.hash_algorithm.as_ref();
+        let (hash, inline_content) = if size < self.options.inline_threshold {
+            let (hash, data) = timed(&mut self.timing.hash, || {
+                fs::read(abs_path).map(|data| (alg.hash(&data), data))
+            })
+            .map_err(|err| FstreeError::io_at(abs_path, err))?;
+            (hash, Some(data))
+        } else {
+            let hash = match &mut self.sink {
+                Some((sink, buffer_limit)) if size <= *buffer_limit => {
+                    let (hash, data) = timed(&mut self.timing.hash, || {
+                        fs::read(abs_path).map(|data| (alg.hash(&data), data))
+                    })
+                    .map_err(|err| FstreeError::io_at(abs_path, err))?;
+                    sink(hash, StreamedBlob::FileBytes(&data))?;
+                    hash
+                }
+                Some((sink, _)) => {
+                    let (hash, file) = timed(&mut self.timing.hash, || {
+                        let mut file = fs::File::open(abs_path)?;
+                        let hash = hash_reader(alg, &mut file)?;
+                        file.seek(SeekFrom::Start(0))?;
+                        Ok::<_, std::io::Error>((hash, file))
+                    })
+                    .map_err(|err| FstreeError::io_at(abs_path, err))?;
+                    sink(hash, StreamedBlob::FileReader { file, size })?;
+                    hash
+                }
+                None => timed(&mut self.timing.hash, || hash_file(alg, abs_path))
+                    .map_err(|err| FstreeError::io_at(abs_path, err))?,
+            };
+            self.files.insert(
                 hash,
-            },
-        );
+                FileRef {
+                    path: abs_path.to_path_buf(),
+                    size,
+                    hash,
+                },
+            );
+            (hash, None)
+        };
         self.file_count += 1;
         self.total_bytes += size;
+        self.logical_bytes += size;
 
-        Ok(TreeEntry {
+        let entry = TreeEntry {
             name: name.to_string(),
             kind: EntryKindFile,
             mode,
             size,
             hash,
-        })
+            hash_alg: self.options.hash_algorithm.id(),
+            inline_content,
+            device: None,
+            xattrs: BTreeMap::new(),
+        };
+        if let Some(key) = link_key {
+            self.linked.insert(key, entry.clone());
+        }
+        Ok(entry)
     }
 }
 
-fn hash_file(path: &Path) -> std::io::Result<[u8; 32]> {
-    let mut file = fs::File::open(path)?;
-    let mut hasher = Hasher::new();
+/// Runs `f`, adding the time it took to `total`.
+fn timed<T>(total: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let value = f();
+    *total += started.elapsed();
+    value
+}
+
+fn normalize_mode(metadata: &fs::Metadata, mode: u32) -> u32 {
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        0o777
+    } else if file_type.is_dir() || mode & 0o100 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// `(dev, ino)` for a file with more than one link; None for singly-linked
+/// files and on platforms without inode numbers.
+#[cfg(unix)]
+fn hardlink_key(metadata: &fs::Metadata) -> std::option::Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hardlink_key(_metadata: &fs::Metadata) -> std::option::Option<(u64, u64)> {
+    None
+}
+
+/// Kind and device numbers of a FIFO or device node; None for anything else.
+#[cfg(unix)]
+fn special_file(
+    metadata: &fs::Metadata,
+) -> std::option::Option<(EntryKind, std::option::Option<DeviceNumber>)> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    let file_type = metadata.file_type();
+    if file_type.is_fifo() {
+        Some((EntryKindFifo, None))
+    } else if file_type.is_char_device() {
+        Some((EntryKindCharDevice, Some(device_number(metadata.rdev()))))
+    } else if file_type.is_block_device() {
+        Some((EntryKindBlockDevice, Some(device_number(metadata.rdev()))))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file(
+    _metadata: &fs::Metadata,
+) -> std::option::Option<(EntryKind, std::option::Option<DeviceNumber>)> {
+    None
+}
+
+/// Splits `st_rdev` the way the platform's `major()`/`minor()` macros do.
+#[cfg(unix)]
+fn device_number(rdev: u64) -> DeviceNumber {
+    if cfg!(any(target_os = "macos", target_os = "ios")) {
+        DeviceNumber {
+            major: ((rdev >> 24) & 0xff) as u32,
+            minor: (rdev & 0xff_ffff) as u32,
+        }
+    } else {
+        DeviceNumber {
+            major: (((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff)) as u32,
+            minor: (((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff)) as u32,
+        }
+    }
+}
+
+fn hash_file(alg: &dyn HashAlgorithm, path: &Path) -> std::io::Result<[u8; 32]> {
+    hash_reader(alg, &mut fs::File::open(path)?)
+}
+
+fn hash_reader(alg: &dyn HashAlgorithm, reader: &mut impl Read) -> std::io::Result<[u8; 32]> {
+    let mut hasher = alg.hasher();
     let mut buf = [0u8; 8192];
     loop {
-        let n = file.read(&mut buf)?;
+        let n = reader.read(&mut buf)?;
         if n == 0 {
             break;
         }
         hasher.update(&buf[..n]);
     }
-    let hash = hasher.finalize();
-    Ok(*hash.as_bytes())
+    Ok(hasher.finalize())
 }
 
 trait PermissionsExt {
@@ -0,0 +1,121 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::client::{Client, RequestContext};
+
+use super::capture::{deserialize_tree, FstreeError, FstreeErrorKind, Result as FstreeResult};
+use super::snapshot::split_path;
+use super::types::{EntryKindDirectory, EntryKindFile, TreeEntry};
+
+/// An uploaded snapshot browsed over the wire. Trees are fetched from the
+/// server as paths are resolved and cached for the life of the handle, so
+/// only the directories actually visited are transferred.
+pub struct SnapshotHandle<'a> {
+    client: &'a Client,
+    ctx: RequestContext,
+    root_hash: [u8; 32],
+    trees: Mutex<HashMap<[u8; 32], Vec<TreeEntry>>>,
+}
+
+impl Client {
+    /// Opens the snapshot rooted at `root_hash` for lazy browsing. Nothing is
+    /// fetched until the handle is used; `ctx` applies to every request the
+    /// handle makes.
+    pub fn open_snapshot(&self, ctx: &RequestContext, root_hash: [u8; 32]) -> SnapshotHandle<'_> {
+        SnapshotHandle {
+            client: self,
+            ctx: ctx.clone(),
+            root_hash,
+            trees: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl SnapshotHandle<'_> {
+    pub fn root_hash(&self) -> [u8; 32] {
+        self.root_hash
+    }
+
+    /// Entries of the directory at `path`; an empty path (or "/") lists the
+    /// root.
+    pub fn list(&self, path: &str) -> FstreeResult<Vec<TreeEntry>> {
+        let Some(entry) = self.resolve(path)? else {
+            return self.tree(self.root_hash);
+        };
+        if entry.kind != EntryKindDirectory {
+            return Err(FstreeError::new(
+                FstreeErrorKind::Other,
+                format!("not a directory: {path}"),
+            ));
+        }
+        self.tree(entry.hash)
+    }
+
+    /// The tree entry at `path`, without fetching any file content.
+    pub fn stat(&self, path: &str) -> FstreeResult<TreeEntry> {
+        self.resolve(path)?
+            .ok_or_else(|| FstreeError::new(FstreeErrorKind::Other, "empty path"))
+    }
+
+    /// Content of the file at `path`. Inlined files are answered from their
+    /// tree; other files are fetched as blobs. Symlinks return their target.
+    pub fn read(&self, path: &str) -> FstreeResult<Vec<u8>> {
+        let entry = self.stat(path)?;
+        if entry.kind == EntryKindDirectory {
+            return Err(FstreeError::new(
+                FstreeErrorKind::Other,
+                format!("path is a directory: {path}"),
+            ));
+        }
+        if entry.kind == EntryKindFile {
+            if let Some(inline) = entry.inline_content {
+                return Ok(inline);
+            }
+        }
+        self.client
+            .get_blob(&self.ctx, entry.hash)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))
+    }
+
+    /// The entry at `path`, or None for the root, which has no entry.
+    fn resolve(&self, path: &str) -> FstreeResult<Option<TreeEntry>> {
+        let parts = split_path(path);
+        let mut current_hash = self.root_hash;
+        for (idx, part) in parts.iter().enumerate() {
+            let found = self
+                .tree(current_hash)?
+                .into_iter()
+                .find(|entry| entry.name == *part)
+                .ok_or_else(|| {
+                    FstreeError::new(FstreeErrorKind::Other, format!("path not found: {path}"))
+                })?;
+            if idx == parts.len() - 1 {
+                return Ok(Some(found));
+            }
+            if found.kind != EntryKindDirectory {
+                return Err(FstreeError::new(
+                    FstreeErrorKind::Other,
+                    format!("not a directory: {part}"),
+                ));
+            }
+            current_hash = found.hash;
+        }
+        Ok(None)
+    }
+
+    fn tree(&self, hash: [u8; 32]) -> FstreeResult<Vec<TreeEntry>> {
+        if let Some(entries) = self.trees.lock().unwrap().get(&hash) {
+            return Ok(entries.clone());
+        }
+        let data = self
+            .client
+            .get_blob(&self.ctx, hash)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+        let entries = deserialize_tree(&data)?;
+        self.trees.lock().unwrap().insert(hash, entries.clone());
+        Ok(entries)
+    }
+}
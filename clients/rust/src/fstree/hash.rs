@@ -0,0 +1,133 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+#![allow(non_upper_case_globals)]
+
+use std::sync::Arc;
+
+use sha2::Digest;
+
+/// Identifier recorded in tree entries so readers can pick the matching verifier.
+pub type HashAlgorithmId = u8;
+
+pub const HashAlgorithmBlake3: HashAlgorithmId = 0;
+pub const HashAlgorithmSha256: HashAlgorithmId = 1;
+pub const HashAlgorithmBlake3Keyed: HashAlgorithmId = 2;
+
+/// Content-addressing hash used for files, symlink targets, and tree objects.
+pub trait HashAlgorithm: Send + Sync {
+    fn id(&self) -> HashAlgorithmId;
+
+    fn hasher(&self) -> Box<dyn ContentHasher>;
+
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Incremental hasher produced by a `HashAlgorithm`.
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(self: Box<Self>) -> [u8; 32];
+}
+
+/// BLAKE3-256, the default algorithm.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3;
+
+impl HashAlgorithm for Blake3 {
+    fn id(&self) -> HashAlgorithmId {
+        HashAlgorithmBlake3
+    }
+
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        Box::new(blake3::Hasher::new())
+    }
+
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        *blake3::hash(data).as_bytes()
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> [u8; 32] {
+        *blake3::Hasher::finalize(&self).as_bytes()
+    }
+}
+
+/// BLAKE3 in keyed mode, for per-tenant content addresses. The same bytes
+/// hash differently under different keys, so a tenant cannot confirm that
+/// another tenant stored some known content by probing for its hash; the
+/// price is no dedup across keys. The server must list the key in
+/// `CXDB_HASH_KEYS` to accept the blobs.
+#[derive(Clone)]
+pub struct KeyedBlake3 {
+    key: [u8; 32],
+}
+
+impl KeyedBlake3 {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+}
+
+impl std::fmt::Debug for KeyedBlake3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyedBlake3").finish_non_exhaustive()
+    }
+}
+
+impl HashAlgorithm for KeyedBlake3 {
+    fn id(&self) -> HashAlgorithmId {
+        HashAlgorithmBlake3Keyed
+    }
+
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        Box::new(blake3::Hasher::new_keyed(&self.key))
+    }
+
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        *blake3::keyed_hash(&self.key, data).as_bytes()
+    }
+}
+
+/// SHA-256, for deployments that require FIPS-approved digests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256;
+
+impl HashAlgorithm for Sha256 {
+    fn id(&self) -> HashAlgorithmId {
+        HashAlgorithmSha256
+    }
+
+    fn hasher(&self) -> Box<dyn ContentHasher> {
+        Box::new(sha2::Sha256::new())
+    }
+}
+
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> [u8; 32] {
+        Digest::finalize(*self).into()
+    }
+}
+
+/// Returns the built-in algorithm for an identifier, if any. Keyed BLAKE3
+/// has no entry, since the identifier alone does not carry the key.
+pub fn hash_algorithm_for_id(id: HashAlgorithmId) -> Option<Arc<dyn HashAlgorithm>> {
+    match id {
+        HashAlgorithmBlake3 => Some(Arc::new(Blake3)),
+        HashAlgorithmSha256 => Some(Arc::new(Sha256)),
+        _ => None,
+    }
+}
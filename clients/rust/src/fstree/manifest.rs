@@ -0,0 +1,115 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! The capture manifest embedded by `with_embed_manifest`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::{decode_msgpack_into, encode_msgpack};
+use crate::types::Provenance;
+
+use super::capture::{FstreeError, FstreeErrorKind, Result};
+use super::options::Options;
+
+/// Name of the root tree entry holding the manifest. When a manifest is
+/// embedded, a file of this name at the top of the captured directory is
+/// left out and listed in `Snapshot::skipped` as "reserved name".
+pub const MANIFEST_ENTRY_NAME: &str = ".cxdb-manifest";
+
+/// Capture-time metadata stored in a snapshot's root tree. Encoded as
+/// msgpack with map keys in sorted order, so equal manifests always encode
+/// to equal bytes and leave the root hash reproducible.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CaptureManifest {
+    /// Version of this library that made the capture.
+    #[serde(rename = "1")]
+    pub tool_version: String,
+    /// Who captured and where, as given to `with_embed_manifest`.
+    /// `captured_at` and `host_name` are taken from here rather than read
+    /// from the clock, so that re-capturing with the same provenance gives
+    /// the same root hash.
+    #[serde(rename = "2")]
+    pub provenance: Provenance,
+    #[serde(rename = "3")]
+    pub options: ManifestOptions,
+}
+
+/// The capture options that decide what a snapshot holds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestOptions {
+    #[serde(rename = "1")]
+    pub exclude_patterns: Vec<String>,
+    /// Whether a `with_exclude_func` filter was set; the function itself
+    /// cannot be recorded.
+    #[serde(rename = "2")]
+    pub exclude_func: bool,
+    #[serde(rename = "3")]
+    pub include_patterns: Vec<String>,
+    #[serde(rename = "4")]
+    pub reinclude_under_excluded_dirs: bool,
+    #[serde(rename = "5")]
+    pub follow_symlinks: bool,
+    #[serde(rename = "6")]
+    pub follow_symlink_patterns: Vec<String>,
+    #[serde(rename = "7")]
+    pub max_file_size: i64,
+    #[serde(rename = "8")]
+    pub max_files: u64,
+    #[serde(rename = "9", default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<u64>,
+    #[serde(rename = "10", default, skip_serializing_if = "Option::is_none")]
+    pub max_dir_entries: Option<u64>,
+    #[serde(rename = "11")]
+    pub hash_algorithm: u8,
+    #[serde(rename = "12")]
+    pub normalize_modes: bool,
+    #[serde(rename = "13")]
+    pub inline_threshold: u64,
+    #[serde(rename = "14")]
+    pub partial_on_limit: bool,
+    #[serde(rename = "15")]
+    pub exclude_binary: bool,
+    #[serde(rename = "16")]
+    pub special_files: bool,
+    #[serde(rename = "17")]
+    pub xattrs: bool,
+}
+
+impl CaptureManifest {
+    pub(crate) fn new(provenance: Provenance, options: &Options) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            provenance,
+            options: ManifestOptions {
+                exclude_patterns: options.exclude_patterns.clone(),
+                exclude_func: options.exclude_fn.is_some(),
+                include_patterns: options.include_patterns.clone(),
+                reinclude_under_excluded_dirs: options.reinclude_under_excluded_dirs,
+                follow_symlinks: options.follow_symlinks,
+                follow_symlink_patterns: options.follow_symlink_patterns.clone(),
+                max_file_size: options.max_file_size,
+                max_files: options.max_files as u64,
+                max_depth: options.max_depth.map(|depth| depth as u64),
+                max_dir_entries: options.max_dir_entries.map(|count| count as u64),
+                hash_algorithm: options.hash_algorithm.id(),
+                normalize_modes: options.normalize_modes,
+                inline_threshold: options.inline_threshold,
+                partial_on_limit: options.partial_on_limit,
+                exclude_binary: options.exclude_binary,
+                special_files: options.special_files,
+                xattrs: options.xattrs,
+            },
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        encode_msgpack(self)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Msgpack, err.to_string()))
+    }
+
+    /// Decodes the content of a `MANIFEST_ENTRY_NAME` entry.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        decode_msgpack_into(data)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Msgpack, err.to_string()))
+    }
+}
@@ -1,27 +1,45 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+mod cache;
 mod capture;
+mod handle;
+mod hash;
+mod manifest;
 mod options;
 mod snapshot;
+mod sniff;
 mod tracker;
 mod types;
 mod upload;
+mod xattrs;
 
+pub use cache::UploadCache;
 pub use capture::{
-    capture, deserialize_tree, ErrCyclicLink, ErrFileTooLarge, ErrTooManyFiles, FstreeError,
-    FstreeErrorKind,
+    capture, capture_cancellable, deserialize_tree, verify_against, ErrCyclicLink, ErrFileTooLarge,
+    ErrTooManyDirEntries, ErrTooManyFiles, FstreeError, FstreeErrorKind,
 };
+pub use handle::SnapshotHandle;
+pub use hash::{
+    hash_algorithm_for_id, Blake3, ContentHasher, HashAlgorithm, HashAlgorithmBlake3,
+    HashAlgorithmBlake3Keyed, HashAlgorithmId, HashAlgorithmSha256, KeyedBlake3, Sha256,
+};
+pub use manifest::{CaptureManifest, ManifestOptions, MANIFEST_ENTRY_NAME};
 pub use options::{
-    with_exclude, with_exclude_func, with_follow_symlinks, with_max_file_size, with_max_files,
-    Options, SnapshotOption,
+    with_dry_run, with_embed_manifest, with_exclude, with_exclude_binary, with_exclude_func,
+    with_follow_symlinks, with_follow_symlinks_matching, with_hash_algorithm, with_hash_namespace,
+    with_include, with_inline_threshold, with_max_depth, with_max_dir_entries, with_max_file_size,
+    with_max_files, with_mode_normalization, with_partial_on_limit,
+    with_reinclude_under_excluded_dirs, with_special_files, with_xattrs, Options, SnapshotOption,
 };
 pub use tracker::Tracker;
 pub use types::{
-    EntryKind, EntryKindDirectory, EntryKindFile, EntryKindSymlink, FileRef, Snapshot,
-    SnapshotDiff, SnapshotStats, TreeEntry, TreeObject,
+    DeviceNumber, EntryKind, EntryKindBlockDevice, EntryKindCharDevice, EntryKindDirectory,
+    EntryKindFifo, EntryKindFile, EntryKindSymlink, FileRef, SkippedFile, Snapshot, SnapshotDiff,
+    SnapshotStats, TimingBreakdown, TreeEntry, TreeObject,
 };
-pub use upload::{capture_and_upload, upload_and_attach, UploadResult};
+pub use upload::{capture_and_stream_upload, capture_and_upload, upload_and_attach, UploadResult};
+pub use xattrs::apply_xattrs;
 
 /// Go-parity alias for snapshot option type.
 pub type Option = SnapshotOption;
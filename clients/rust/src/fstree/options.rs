@@ -8,15 +8,36 @@ use std::sync::Arc;
 
 use glob::Pattern;
 
+use crate::types::Provenance;
+
+use super::hash::{Blake3, HashAlgorithm, KeyedBlake3};
+
 pub type SnapshotOption = Arc<dyn Fn(&mut Options) + Send + Sync>;
 
 #[derive(Clone)]
 pub struct Options {
     pub exclude_patterns: Vec<String>,
     pub exclude_fn: std::option::Option<Arc<dyn Fn(&str, bool) -> bool + Send + Sync>>,
+    pub include_patterns: Vec<String>,
+    pub reinclude_under_excluded_dirs: bool,
     pub follow_symlinks: bool,
+    /// Symlinks to follow when `follow_symlinks` is off; see
+    /// `with_follow_symlinks_matching`.
+    pub follow_symlink_patterns: Vec<String>,
     pub max_file_size: i64,
     pub max_files: usize,
+    pub max_depth: std::option::Option<usize>,
+    pub max_dir_entries: std::option::Option<usize>,
+    pub hash_algorithm: Arc<dyn HashAlgorithm>,
+    pub normalize_modes: bool,
+    pub inline_threshold: u64,
+    pub dry_run: bool,
+    pub partial_on_limit: bool,
+    pub exclude_binary: bool,
+    pub special_files: bool,
+    pub xattrs: bool,
+    /// Provenance for the manifest `with_embed_manifest` adds.
+    pub embed_manifest: std::option::Option<Provenance>,
 }
 
 impl Default for Options {
@@ -24,9 +45,23 @@ impl Default for Options {
         Self {
             exclude_patterns: Vec::new(),
             exclude_fn: None,
+            include_patterns: Vec::new(),
+            reinclude_under_excluded_dirs: false,
             follow_symlinks: false,
+            follow_symlink_patterns: Vec::new(),
             max_file_size: 100 * 1024 * 1024,
             max_files: 100_000,
+            max_depth: None,
+            max_dir_entries: None,
+            hash_algorithm: Arc::new(Blake3),
+            normalize_modes: false,
+            inline_threshold: 0,
+            dry_run: false,
+            partial_on_limit: false,
+            exclude_binary: false,
+            special_files: false,
+            xattrs: false,
+            embed_manifest: None,
         }
     }
 }
@@ -46,10 +81,45 @@ where
     Arc::new(move |opts| opts.exclude_fn = Some(func.clone()))
 }
 
+/// Keeps paths matching `patterns` even when an exclude pattern or
+/// `with_exclude_func` matches them. Patterns match like exclude patterns.
+/// By default a path inside an excluded directory is never seen, so it can
+/// only be re-included by also enabling `with_reinclude_under_excluded_dirs`.
+pub fn with_include(patterns: impl IntoIterator<Item = impl Into<String>>) -> SnapshotOption {
+    let patterns: Vec<String> = patterns.into_iter().map(|p| p.into()).collect();
+    Arc::new(move |opts| {
+        opts.include_patterns.extend(patterns.clone());
+    })
+}
+
+/// Chooses what wins when a directory is excluded but paths below it match
+/// an include pattern. Off by default, matching gitignore: the excluded
+/// directory is pruned and nothing under it is captured. When on, capture
+/// still walks excluded directories (slower) and keeps the included paths
+/// found there, along with just the directories leading to them.
+pub fn with_reinclude_under_excluded_dirs(enabled: bool) -> SnapshotOption {
+    Arc::new(move |opts| opts.reinclude_under_excluded_dirs = enabled)
+}
+
+/// Follow symlinks instead of recording them. Without this, `capture` also
+/// refuses a root path that is itself a symlink.
 pub fn with_follow_symlinks() -> SnapshotOption {
     Arc::new(|opts| opts.follow_symlinks = true)
 }
 
+/// Follows only the symlinks whose relative path matches one of
+/// `patterns`, recording every other symlink as a link. Patterns match like
+/// exclude patterns. Followed links get the same cycle detection as with
+/// `with_follow_symlinks`. The root path must still not be a symlink.
+pub fn with_follow_symlinks_matching(
+    patterns: impl IntoIterator<Item = impl Into<String>>,
+) -> SnapshotOption {
+    let patterns: Vec<String> = patterns.into_iter().map(|p| p.into()).collect();
+    Arc::new(move |opts| {
+        opts.follow_symlink_patterns.extend(patterns.clone());
+    })
+}
+
 pub fn with_max_file_size(bytes: i64) -> SnapshotOption {
     Arc::new(move |opts| opts.max_file_size = bytes)
 }
@@ -58,33 +128,139 @@ pub fn with_max_files(count: usize) -> SnapshotOption {
     Arc::new(move |opts| opts.max_files = count)
 }
 
+/// Leaves out files whose first few KiB look binary (a NUL byte, or the magic
+/// number of a compressed, archive, or image format), whatever their size or
+/// name. Only that prefix is read. Each one is listed in `Snapshot::skipped`
+/// with reason "binary".
+pub fn with_exclude_binary() -> SnapshotOption {
+    Arc::new(|opts| opts.exclude_binary = true)
+}
+
+/// Captures FIFOs and character and block devices (e.g. a container
+/// rootfs's `/dev`) as entries of their own kinds, recording device numbers
+/// instead of content. Without it they are left out, as are sockets either
+/// way.
+pub fn with_special_files() -> SnapshotOption {
+    Arc::new(|opts| opts.special_files = true)
+}
+
+/// Records each entry's extended attributes (SELinux labels, capabilities,
+/// `user.*` attributes) in `TreeEntry::xattrs`, for `apply_xattrs` to
+/// restore. Off by default since it costs extra syscalls per entry; has no
+/// effect off unix. Failing to read an entry's attributes fails the capture,
+/// except on filesystems without xattr support.
+pub fn with_xattrs() -> SnapshotOption {
+    Arc::new(|opts| opts.xattrs = true)
+}
+
+/// On reaching `max_files`, stop adding entries instead of failing: the
+/// trees built so far are finalized and the snapshot's
+/// `SnapshotStats::truncated` is set, leaving callers to decide whether a
+/// partial snapshot will do. Directories are walked in name order, so an
+/// unchanged tree yields the same partial snapshot every time.
+pub fn with_partial_on_limit() -> SnapshotOption {
+    Arc::new(|opts| opts.partial_on_limit = true)
+}
+
+/// Stops descending below `depth` directory levels (the root is depth 0).
+/// Directories past the limit are recorded as empty trees; files at or above
+/// the limit are captured normally.
+pub fn with_max_depth(depth: usize) -> SnapshotOption {
+    Arc::new(move |opts| opts.max_depth = Some(depth))
+}
+
+/// Fails the capture with `FstreeErrorKind::TooManyDirEntries` as soon as a
+/// single directory yields more than `count` entries (after exclusions),
+/// instead of building and hashing an enormous tree object for it.
+pub fn with_max_dir_entries(count: usize) -> SnapshotOption {
+    Arc::new(move |opts| opts.max_dir_entries = Some(count))
+}
+
+/// Selects the content-addressing hash for files, symlinks, and trees.
+/// Defaults to BLAKE3; the server must be configured for the same algorithm.
+pub fn with_hash_algorithm(alg: Arc<dyn HashAlgorithm>) -> SnapshotOption {
+    Arc::new(move |opts| opts.hash_algorithm = alg.clone())
+}
+
+/// Hashes files, symlinks, and trees with BLAKE3 keyed by `key` (see
+/// `KeyedBlake3`), giving each tenant its own content-address space. Keep
+/// the key secret: anyone holding it can probe for the tenant's content.
+pub fn with_hash_namespace(key: [u8; 32]) -> SnapshotOption {
+    with_hash_algorithm(Arc::new(KeyedBlake3::new(key)))
+}
+
+/// Canonicalizes captured modes so tree hashes ignore umask noise: files
+/// become 0644 or 0755 (by the owner exec bit), directories 0755, and
+/// symlinks 0777. Leave off when exact modes are needed for materialization.
+pub fn with_mode_normalization() -> SnapshotOption {
+    Arc::new(|opts| opts.normalize_modes = true)
+}
+
+/// Stores files smaller than `bytes` inside their tree entry instead of as
+/// separate blobs, saving a round trip per file for trees of small configs.
+pub fn with_inline_threshold(bytes: u64) -> SnapshotOption {
+    Arc::new(move |opts| opts.inline_threshold = bytes)
+}
+
+/// Adds a `.cxdb-manifest` file to the root tree recording `provenance`,
+/// this library's version, and the capture options (see `CaptureManifest`),
+/// so the snapshot describes how it was made. Read it back with
+/// `get_file_at_path(".cxdb-manifest")` and `CaptureManifest::decode`. The
+/// manifest is always inlined and changes the root hash, which stays
+/// reproducible as long as `provenance` (including `captured_at`) is the
+/// same.
+pub fn with_embed_manifest(provenance: Provenance) -> SnapshotOption {
+    Arc::new(move |opts| opts.embed_manifest = Some(provenance.clone()))
+}
+
+/// Makes `upload_and_attach` and `capture_and_upload` only report what they
+/// would upload: the returned `UploadResult` counts blobs the server is
+/// missing as uploaded, but nothing is sent and nothing is attached.
+pub fn with_dry_run() -> SnapshotOption {
+    Arc::new(|opts| opts.dry_run = true)
+}
+
 impl Options {
+    /// Whether `rel_path` is left out of a capture: it matches an exclude
+    /// pattern or `exclude_fn` and no include pattern.
     pub fn should_exclude(&self, rel_path: &str, is_dir: bool) -> bool {
-        if let Some(func) = &self.exclude_fn {
-            if func(rel_path, is_dir) {
-                return true;
-            }
-        }
+        let excluded = self
+            .exclude_fn
+            .as_ref()
+            .is_some_and(|func| func(rel_path, is_dir))
+            || matches_any(&self.exclude_patterns, rel_path, is_dir);
+        excluded && !self.is_included(rel_path, is_dir)
+    }
 
-        let rel_path = normalize_path(rel_path);
-        let basename = Path::new(&rel_path)
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("");
-
-        for pattern in &self.exclude_patterns {
-            if is_double_star_dir(pattern, &rel_path, is_dir) {
-                return true;
-            }
-            if matches_glob(pattern, &rel_path) {
-                return true;
-            }
-            if matches_glob(pattern, basename) {
-                return true;
-            }
-        }
-        false
+    /// Whether `rel_path` matches an include pattern.
+    pub fn is_included(&self, rel_path: &str, is_dir: bool) -> bool {
+        matches_any(&self.include_patterns, rel_path, is_dir)
+    }
+
+    /// Whether a symlink at `rel_path` is followed rather than recorded.
+    pub fn follows_symlink(&self, rel_path: &str) -> bool {
+        self.follow_symlinks || matches_any(&self.follow_symlink_patterns, rel_path, false)
     }
+
+    /// Whether any symlink might be followed, so the walk can revisit a
+    /// directory.
+    pub(crate) fn may_follow_symlinks(&self) -> bool {
+        self.follow_symlinks || !self.follow_symlink_patterns.is_empty()
+    }
+}
+
+fn matches_any(patterns: &[String], rel_path: &str, is_dir: bool) -> bool {
+    let rel_path = normalize_path(rel_path);
+    let basename = Path::new(&rel_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+
+    patterns.iter().any(|pattern| {
+        is_double_star_dir(pattern, &rel_path, is_dir)
+            || matches_glob(pattern, &rel_path)
+            || matches_glob(pattern, basename)
+    })
 }
 
 fn normalize_path(path: &str) -> String {
@@ -2,13 +2,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fs::File;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use super::capture::deserialize_tree;
 use super::types::{
-    EntryKindDirectory, EntryKindFile, EntryKindSymlink, Snapshot, SnapshotDiff, TreeEntry,
+    EntryKindBlockDevice, EntryKindCharDevice, EntryKindDirectory, EntryKindFifo, EntryKindFile,
+    EntryKindSymlink, Snapshot, SnapshotDiff, TreeEntry,
 };
 use super::{FstreeError, FstreeErrorKind};
+use crate::fs::SnapshotMeta;
 
 impl Snapshot {
     pub fn get_file(&self, hash: [u8; 32]) -> Result<File, FstreeError> {
@@ -18,8 +22,7 @@ impl Snapshot {
                 format!("file not found: {}", hash_prefix(&hash)),
             )
         })?;
-        File::open(&file_ref.path)
-            .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))
+        File::open(&file_ref.path).map_err(|err| FstreeError::io_at(&file_ref.path, err))
     }
 
     pub fn get_tree(&self, hash: [u8; 32]) -> Result<Vec<TreeEntry>, FstreeError> {
@@ -74,6 +77,47 @@ impl Snapshot {
         Ok(paths)
     }
 
+    /// Writes a text listing of every entry, one per line in walk order
+    /// (name-sorted within each directory, parents before children):
+    ///
+    /// ```text
+    /// <path>\t<file|dir|symlink|fifo|chardev|blockdev>\t<mode, octal>\t<size>\t<hash, hex>
+    /// ```
+    ///
+    /// Directory hashes are tree hashes. Backslashes, tabs and newlines in
+    /// paths are escaped so each entry stays on one line. Only the in-memory
+    /// trees are read, so this works without the files or a blob store.
+    pub fn write_manifest(&self, out: &mut dyn Write) -> Result<(), FstreeError> {
+        self.walk(|path, entry| {
+            let kind = if entry.kind == EntryKindDirectory {
+                "dir"
+            } else if entry.kind == EntryKindSymlink {
+                "symlink"
+            } else if entry.kind == EntryKindFifo {
+                "fifo"
+            } else if entry.kind == EntryKindCharDevice {
+                "chardev"
+            } else if entry.kind == EntryKindBlockDevice {
+                "blockdev"
+            } else {
+                "file"
+            };
+            let hash: String = entry.hash.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(
+                out,
+                "{}\t{}\t{:04o}\t{}\t{}",
+                escape_manifest_path(path),
+                kind,
+                entry.mode,
+                entry.size,
+                hash
+            )
+            .map_err(FstreeError::io)
+        })
+    }
+
+    /// Looks up a path. Regular files come back with an open handle, except
+    /// inlined ones, whose bytes are already in `TreeEntry::inline_content`.
     pub fn get_file_at_path(
         &self,
         path: &str,
@@ -104,7 +148,7 @@ impl Snapshot {
             };
 
             if idx == parts.len() - 1 {
-                if found.kind == EntryKindFile {
+                if found.kind == EntryKindFile && found.inline_content.is_none() {
                     let file = self.get_file(found.hash)?;
                     return Ok(Some((found, Some(file))));
                 }
@@ -177,6 +221,22 @@ impl Snapshot {
 
         Ok(diff)
     }
+
+    /// Capture metadata to send with `attach_fs`, so the server can report
+    /// the snapshot's size and capture time.
+    pub fn meta(&self) -> SnapshotMeta {
+        SnapshotMeta {
+            captured_at_unix_ms: self
+                .captured_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            file_count: self.stats.file_count as u64,
+            dir_count: self.stats.dir_count as u64,
+            total_bytes: self.stats.total_bytes,
+            duration_ms: self.stats.duration.as_millis() as u64,
+        }
+    }
 }
 
 impl SnapshotDiff {
@@ -189,7 +249,7 @@ impl SnapshotDiff {
     }
 }
 
-fn split_path(path: &str) -> Vec<String> {
+pub(super) fn split_path(path: &str) -> Vec<String> {
     let normalized = path.replace('\\', "/");
     let normalized = Path::new(&normalized);
     let mut parts = Vec::new();
@@ -203,6 +263,12 @@ fn split_path(path: &str) -> Vec<String> {
     parts
 }
 
+fn escape_manifest_path(path: &str) -> String {
+    path.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
 fn hash_prefix(hash: &[u8; 32]) -> String {
     hash[..4].iter().map(|b| format!("{:02x}", b)).collect()
 }
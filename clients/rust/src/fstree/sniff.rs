@@ -0,0 +1,44 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Binary detection for `with_exclude_binary`, from a file's first bytes.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// How many leading bytes of a file are examined.
+pub(crate) const SNIFF_LEN: u64 = 8192;
+
+/// Compressed, archive, and image formats whose headers need not contain a
+/// NUL byte. Executables and object files (ELF, Mach-O, PE, wasm, class
+/// files) all do, so the NUL check catches them.
+const BINARY_MAGIC: &[&[u8]] = &[
+    b"PK\x03\x04",
+    b"\x1f\x8b",
+    b"BZh",
+    b"\xfd7zXZ\0",
+    b"\x28\xb5\x2f\xfd",
+    b"7z\xbc\xaf\x27\x1c",
+    b"\x89PNG\r\n\x1a\n",
+    b"\xff\xd8\xff",
+    b"GIF87a",
+    b"GIF89a",
+    b"%PDF-",
+];
+
+/// Whether `head`, the start of a file, looks like binary content: it holds
+/// a NUL byte or starts with a known binary magic number.
+pub(crate) fn looks_binary(head: &[u8]) -> bool {
+    head.contains(&0) || BINARY_MAGIC.iter().any(|magic| head.starts_with(magic))
+}
+
+/// Reads at most `SNIFF_LEN` bytes of the file at `path` and checks them
+/// with `looks_binary`.
+pub(crate) fn is_binary_file(path: &Path) -> std::io::Result<bool> {
+    let mut head = Vec::with_capacity(SNIFF_LEN as usize);
+    fs::File::open(path)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)?;
+    Ok(looks_binary(&head))
+}
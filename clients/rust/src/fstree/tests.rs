@@ -2,11 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
-use crate::test_util::decode_hex;
+use crate::test_util::{decode_hex, mock_responder};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
 use tempfile::TempDir;
 
 #[derive(Debug, Deserialize)]
@@ -120,6 +122,19 @@ fn capture_basic_tree_stats() {
     assert_eq!(files.len(), 4);
 }
 
+#[test]
+fn capture_reports_timing_per_phase() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    let timing = snap.stats.timing;
+    assert!(timing.walk > Duration::ZERO, "{timing:?}");
+    assert!(timing.stat > Duration::ZERO, "{timing:?}");
+    assert!(timing.hash > Duration::ZERO, "{timing:?}");
+    assert!(timing.encode > Duration::ZERO, "{timing:?}");
+}
+
 #[test]
 fn capture_deterministic_hash() {
     let dir = TempDir::new().unwrap();
@@ -163,6 +178,47 @@ fn capture_exclude_patterns() {
     assert_eq!(files.len(), 1);
 }
 
+#[test]
+fn capture_include_under_excluded_dir_follows_option() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("build/gen")).unwrap();
+    fs::create_dir_all(dir.path().join("build/tmp")).unwrap();
+    fs::write(dir.path().join("build/gen/schema.json"), "{}").unwrap();
+    fs::write(dir.path().join("build/tmp/out.o"), "obj").unwrap();
+    fs::write(dir.path().join("app.log"), "log").unwrap();
+    fs::write(dir.path().join("keep.log"), "keep").unwrap();
+    let opts = || {
+        vec![
+            with_exclude(["build", "*.log"]),
+            with_include(["*.json", "keep.log"]),
+        ]
+    };
+
+    // By default the excluded directory is pruned, as in gitignore.
+    let files = capture(dir.path(), opts()).unwrap().list_files().unwrap();
+    assert_eq!(files, vec!["keep.log"]);
+
+    let mut with_reinclude = opts();
+    with_reinclude.push(with_reinclude_under_excluded_dirs(true));
+    let snap = capture(dir.path(), with_reinclude).unwrap();
+    let mut files = snap.list_files().unwrap();
+    files.sort();
+    assert_eq!(files, vec!["build/gen/schema.json", "keep.log"]);
+    // build/tmp held nothing included and is left out entirely.
+    let mut paths = Vec::new();
+    snap.walk(|path, _| {
+        paths.push(path.to_string());
+        Ok(())
+    })
+    .unwrap();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec!["build", "build/gen", "build/gen/schema.json", "keep.log"]
+    );
+    assert_eq!(snap.stats.dir_count, 3);
+}
+
 #[cfg(unix)]
 #[test]
 fn capture_symlinks() {
@@ -178,6 +234,113 @@ fn capture_symlinks() {
     assert_eq!(snap.symlinks.len(), 1);
 }
 
+#[cfg(target_os = "linux")]
+#[test]
+fn capture_special_files_only_when_enabled() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("plain.txt"), "plain").unwrap();
+    let status = std::process::Command::new("mkfifo")
+        .arg(dir.path().join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    symlink("/dev/null", dir.path().join("null")).unwrap();
+
+    // Without the option the FIFO is left out rather than opened.
+    let snap = capture(dir.path(), vec![with_follow_symlinks()]).unwrap();
+    let names: Vec<String> = snap
+        .get_root_entries()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(names, vec!["plain.txt"]);
+    assert_eq!(snap.stats.special_count, 0);
+
+    let snap = capture(
+        dir.path(),
+        vec![with_follow_symlinks(), with_special_files()],
+    )
+    .unwrap();
+    let entries = snap.get_root_entries().unwrap();
+    assert_eq!(snap.stats.special_count, 2);
+    assert_eq!(snap.stats.file_count, 1);
+    let null = entries.iter().find(|e| e.name == "null").unwrap();
+    assert_eq!(null.kind, EntryKindCharDevice);
+    assert_eq!(null.device, Some(DeviceNumber { major: 1, minor: 3 }));
+    assert_eq!(null.hash, [0u8; 32]);
+    let pipe = entries.iter().find(|e| e.name == "pipe").unwrap();
+    assert_eq!(pipe.kind, EntryKindFifo);
+    assert_eq!(pipe.device, None);
+
+    let tree = deserialize_tree(&snap.trees[&snap.root_hash]).unwrap();
+    assert_eq!(tree, entries);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn capture_with_xattrs_records_and_reapplies_them() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("labelled.txt");
+    fs::write(&file, "labelled").unwrap();
+    if xattr::set(&file, "user.cxdb.test", b"\x00label").is_err() {
+        // The temp filesystem does not take user xattrs.
+        return;
+    }
+
+    let plain = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert!(plain.get_root_entries().unwrap()[0].xattrs.is_empty());
+
+    let snap = capture(dir.path(), vec![with_xattrs()]).unwrap();
+    assert_ne!(snap.root_hash, plain.root_hash);
+    let entries = deserialize_tree(&snap.trees[&snap.root_hash]).unwrap();
+    assert_eq!(
+        entries[0].xattrs.get("user.cxdb.test").map(Vec::as_slice),
+        Some(&b"\x00label"[..])
+    );
+
+    let restored = dir.path().join("restored.txt");
+    fs::write(&restored, "labelled").unwrap();
+    apply_xattrs(&restored, &entries[0]).unwrap();
+    assert_eq!(
+        xattr::get(&restored, "user.cxdb.test").unwrap(),
+        Some(b"\x00label".to_vec())
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn capture_keeps_one_entry_per_lossy_name() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Both names decode to "a\u{FFFD}".
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\xff")), "one").unwrap();
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\xfe")), "two").unwrap();
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    let entries = snap.get_root_entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "a\u{FFFD}");
+    assert_eq!(
+        snap.skipped,
+        vec![SkippedFile {
+            path: "a\u{FFFD}".to_string(),
+            reason: "duplicate name".to_string(),
+        }]
+    );
+
+    // Whichever the directory listing returns first, the same one is kept.
+    let one = *blake3::hash(b"one").as_bytes();
+    let two = *blake3::hash(b"two").as_bytes();
+    assert_eq!(entries[0].hash, one.min(two));
+    let again = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(again.root_hash, snap.root_hash);
+}
+
 #[cfg(unix)]
 #[test]
 fn capture_mode_bits() {
@@ -257,6 +420,37 @@ fn snapshot_get_file_at_path() {
     assert!(contents.contains("package main"));
 }
 
+#[test]
+fn snapshot_write_manifest_lists_entries_in_walk_order() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    let mut out = Vec::new();
+    snap.write_manifest(&mut out).unwrap();
+    let manifest = String::from_utf8(out).unwrap();
+
+    let rows: Vec<Vec<&str>> = manifest
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    let paths: Vec<&str> = rows.iter().map(|row| row[0]).collect();
+    assert_eq!(
+        paths,
+        ["README.md", "script.sh", "src", "src/lib.go", "src/main.go"]
+    );
+    assert_eq!(rows[2][1], "dir");
+    let readme_hash: String = blake3::hash(b"# Test")
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(rows[0][1..4], ["file", "0644", "6"]);
+    assert_eq!(rows[0][4], readme_hash);
+    #[cfg(unix)]
+    assert_eq!(rows[1][2], "0755");
+}
+
 #[cfg(unix)]
 #[test]
 fn capture_follow_symlinks_detects_cycle() {
@@ -270,6 +464,216 @@ fn capture_follow_symlinks_detects_cycle() {
     assert_eq!(err.kind, ErrCyclicLink);
 }
 
+#[cfg(unix)]
+#[test]
+fn capture_follows_only_matching_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    let shared = TempDir::new().unwrap();
+    fs::write(shared.path().join("lib.rs"), "pub fn f() {}").unwrap();
+    fs::create_dir_all(dir.path().join("vendor")).unwrap();
+    symlink(shared.path(), dir.path().join("vendor").join("dep")).unwrap();
+    symlink(shared.path(), dir.path().join("other")).unwrap();
+
+    let snap = capture(
+        dir.path(),
+        vec![with_follow_symlinks_matching(["vendor/*"])],
+    )
+    .unwrap();
+    let root = snap.get_root_entries().unwrap();
+    let other = root.iter().find(|e| e.name == "other").unwrap();
+    assert_eq!(other.kind, EntryKindSymlink);
+    let vendor = root.iter().find(|e| e.name == "vendor").unwrap();
+    let dep = &snap.get_tree(vendor.hash).unwrap()[0];
+    assert_eq!(dep.name, "dep");
+    assert_eq!(dep.kind, EntryKindDirectory);
+    assert_eq!(snap.get_tree(dep.hash).unwrap()[0].name, "lib.rs");
+
+    // A followed link back into the tree is still caught.
+    symlink(dir.path(), dir.path().join("vendor").join("loop")).unwrap();
+    let err = capture(
+        dir.path(),
+        vec![with_follow_symlinks_matching(["vendor/*"])],
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrCyclicLink);
+}
+
+#[cfg(unix)]
+#[test]
+fn capture_without_follow_keeps_looping_symlink() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("loop")).unwrap();
+    symlink(dir.path(), dir.path().join("loop").join("self")).unwrap();
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(snap.stats.dir_count, 2);
+    assert_eq!(snap.stats.symlink_count, 1);
+}
+
+#[test]
+fn capture_io_error_reports_path_and_kind() {
+    let dir = TempDir::new().unwrap();
+    let missing = dir.path().join("missing");
+
+    let err = capture(&missing, Vec::<SnapshotOption>::new()).unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Io);
+    assert_eq!(err.io_kind, Some(std::io::ErrorKind::NotFound));
+    assert_eq!(err.path.as_deref(), Some(missing.as_path()));
+    assert!(
+        err.to_string().contains(&missing.display().to_string()),
+        "{err}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn capture_symlinked_root_requires_follow() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("real")).unwrap();
+    fs::write(dir.path().join("real").join("a.txt"), "a").unwrap();
+    symlink(dir.path().join("real"), dir.path().join("link")).unwrap();
+
+    let err = capture(dir.path().join("link"), Vec::<SnapshotOption>::new()).unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Other);
+    assert!(err.detail.contains("symlink"), "{}", err.detail);
+
+    let snap = capture(dir.path().join("link"), vec![with_follow_symlinks()]).unwrap();
+    assert_eq!(snap.stats.file_count, 1);
+}
+
+#[test]
+fn capture_reports_unique_file_bytes() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("copy")).unwrap();
+    for name in ["a.bin", "copy/a.bin", "copy/again.bin"] {
+        fs::write(dir.path().join(name), vec![7u8; 100]).unwrap();
+    }
+    fs::write(dir.path().join("other.bin"), vec![8u8; 50]).unwrap();
+    fs::write(dir.path().join("tiny"), "x").unwrap();
+
+    let snap = capture(dir.path(), vec![with_inline_threshold(2)]).unwrap();
+    assert_eq!(snap.stats.total_bytes, 351);
+    assert_eq!(snap.stats.unique_file_bytes, 150);
+}
+
+#[cfg(unix)]
+#[test]
+fn capture_hashes_hardlinked_content_once() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a.bin"), vec![1u8; 100]).unwrap();
+    fs::create_dir_all(dir.path().join("sub")).unwrap();
+    fs::hard_link(
+        dir.path().join("a.bin"),
+        dir.path().join("sub").join("b.bin"),
+    )
+    .unwrap();
+    fs::write(dir.path().join("c.bin"), vec![2u8; 10]).unwrap();
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(snap.stats.file_count, 3);
+    assert_eq!(snap.stats.hardlink_count, 1);
+    assert_eq!(snap.stats.total_bytes, 110);
+    assert_eq!(snap.stats.logical_bytes, 210);
+
+    let (a, _) = snap.get_file_at_path("a.bin").unwrap().unwrap();
+    let (b, _) = snap.get_file_at_path("sub/b.bin").unwrap().unwrap();
+    assert_eq!(a.hash, b.hash);
+    assert_eq!(a.size, b.size);
+}
+
+#[test]
+fn capture_inlines_files_below_threshold() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("small.toml"), "a = 1").unwrap();
+    fs::write(dir.path().join("large.bin"), vec![7u8; 64]).unwrap();
+
+    let snap = capture(dir.path(), vec![with_inline_threshold(16)]).unwrap();
+    assert_eq!(snap.stats.file_count, 2);
+    assert_eq!(snap.files.len(), 1);
+
+    let (small, handle) = snap.get_file_at_path("small.toml").unwrap().unwrap();
+    assert_eq!(small.inline_content.as_deref(), Some(b"a = 1".as_slice()));
+    assert_eq!(small.hash, *blake3::hash(b"a = 1").as_bytes());
+    assert!(handle.is_none());
+
+    let (large, handle) = snap.get_file_at_path("large.bin").unwrap().unwrap();
+    assert!(large.inline_content.is_none());
+    assert!(snap.files.contains_key(&large.hash));
+    assert!(handle.is_some());
+
+    let plain = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(plain.files.len(), 2);
+    assert_ne!(plain.root_hash, snap.root_hash);
+}
+
+#[cfg(unix)]
+#[test]
+fn capture_skips_names_trees_cannot_hold() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("a\\b.txt"), "x").unwrap();
+    fs::write(dir.path().join("ok.txt"), "y").unwrap();
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(snap.stats.file_count, 1);
+    assert_eq!(snap.skipped.len(), 1);
+    assert_eq!(snap.skipped[0].path, "sub/a\\b.txt");
+    assert_eq!(snap.skipped[0].reason, "unsafe name");
+}
+
+#[test]
+fn capture_embeds_manifest_reproducibly() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+    let provenance = crate::types::Provenance {
+        service_name: "snapshotter".to_string(),
+        host_name: "build-1".to_string(),
+        captured_at: 1_700_000_000_000,
+        env_vars: Some(HashMap::from([
+            ("REGION".to_string(), "us-east-1".to_string()),
+            ("STAGE".to_string(), "prod".to_string()),
+        ])),
+        ..Default::default()
+    };
+    let opts = || {
+        vec![
+            with_exclude(["*.log"]),
+            with_embed_manifest(provenance.clone()),
+        ]
+    };
+
+    let snap = capture(dir.path(), opts()).unwrap();
+    let (entry, handle) = snap
+        .get_file_at_path(MANIFEST_ENTRY_NAME)
+        .unwrap()
+        .expect("manifest entry");
+    assert!(handle.is_none());
+    let manifest = CaptureManifest::decode(entry.inline_content.as_deref().unwrap()).unwrap();
+    assert_eq!(manifest.provenance, provenance);
+    assert_eq!(manifest.tool_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(manifest.options.exclude_patterns, vec!["*.log".to_string()]);
+
+    assert_eq!(
+        capture(dir.path(), opts()).unwrap().root_hash,
+        snap.root_hash
+    );
+    let plain = capture(dir.path(), vec![with_exclude(["*.log"])]).unwrap();
+    assert_ne!(plain.root_hash, snap.root_hash);
+    assert_eq!(plain.stats.file_count, snap.stats.file_count);
+
+    // A file of the reserved name on disk is left out for the manifest.
+    write_file(dir.path().join(MANIFEST_ENTRY_NAME), b"forged", 0o644);
+    let shadowed = capture(dir.path(), opts()).unwrap();
+    assert_eq!(shadowed.root_hash, snap.root_hash);
+    assert_eq!(shadowed.skipped[0].reason, "reserved name");
+}
+
 #[test]
 fn capture_max_file_size_is_enforced() {
     let dir = TempDir::new().unwrap();
@@ -279,6 +683,39 @@ fn capture_max_file_size_is_enforced() {
     assert_eq!(snap.stats.file_count, 0);
 }
 
+#[test]
+fn capture_exclude_binary_skips_by_content() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.path().join("empty"), "").unwrap();
+    fs::write(dir.path().join("out/app"), b"\x7fELF\x02\x01\x01\0\0").unwrap();
+    fs::write(dir.path().join("bundle.dat"), b"\x1f\x8b\x08rest").unwrap();
+    // A NUL past the sniffed prefix is not seen.
+    let mut late_nul = vec![b'a'; 10_000];
+    late_nul[9_000] = 0;
+    fs::write(dir.path().join("late.txt"), &late_nul).unwrap();
+
+    let snap = capture(dir.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(snap.stats.file_count, 5);
+    assert!(snap.skipped.is_empty());
+
+    let snap = capture(dir.path(), vec![with_exclude_binary()]).unwrap();
+    let mut files = snap.list_files().unwrap();
+    files.sort();
+    assert_eq!(files, vec!["empty", "late.txt", "main.rs"]);
+    let mut skipped: Vec<_> = snap
+        .skipped
+        .iter()
+        .map(|s| (s.path.as_str(), s.reason.as_str()))
+        .collect();
+    skipped.sort();
+    assert_eq!(
+        skipped,
+        vec![("bundle.dat", "binary"), ("out/app", "binary")]
+    );
+}
+
 #[test]
 fn capture_max_files_is_enforced() {
     let dir = TempDir::new().unwrap();
@@ -289,6 +726,143 @@ fn capture_max_files_is_enforced() {
     assert_eq!(err.kind, ErrTooManyFiles);
 }
 
+#[test]
+fn capture_partial_on_limit_keeps_entries_walked_so_far() {
+    let dir = TempDir::new().unwrap();
+    let sub = dir.path().join("sub");
+    fs::create_dir(&sub).unwrap();
+    for i in 0..3 {
+        fs::write(sub.join(format!("{i}.txt")), format!("{i}")).unwrap();
+        fs::write(dir.path().join(format!("top{i}.txt")), format!("top{i}")).unwrap();
+    }
+
+    let snap = capture(
+        dir.path(),
+        vec![with_max_files(10), with_partial_on_limit()],
+    )
+    .unwrap();
+    assert!(!snap.stats.truncated);
+    assert_eq!(snap.stats.file_count, 6);
+
+    let snap = capture(dir.path(), vec![with_max_files(4), with_partial_on_limit()]).unwrap();
+    assert!(snap.stats.truncated);
+    assert_eq!(snap.stats.file_count, 4);
+    assert_eq!(snap.files.len(), 4);
+
+    // Every tree built before the limit is finalized and reachable.
+    let mut seen = 0;
+    snap.walk(|_, entry| {
+        if entry.kind == EntryKindFile {
+            seen += 1;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(seen, 4);
+}
+
+#[test]
+fn capture_partial_on_limit_keeps_files_in_name_order() {
+    let dir = TempDir::new().unwrap();
+    // Created out of order so filesystem order is unlikely to match.
+    for name in ["m", "c", "x", "a", "q", "f"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+
+    let snap = capture(dir.path(), vec![with_max_files(3), with_partial_on_limit()]).unwrap();
+    assert!(snap.stats.truncated);
+    let mut names = Vec::new();
+    snap.walk(|path, _| {
+        names.push(path.to_string());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(names, vec!["a", "c", "f"]);
+}
+
+#[test]
+fn verify_against_reports_drifted_paths() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.path().join("README"), "readme").unwrap();
+    fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+    let recorded = capture(dir.path(), vec![with_hash_algorithm(Arc::new(Sha256))]).unwrap();
+
+    // The recorded snapshot's hash algorithm is used even if opts omit it.
+    assert!(verify_against(dir.path(), &recorded, Vec::new())
+        .unwrap()
+        .is_empty());
+
+    fs::write(dir.path().join("src/main.rs"), "fn main() { changed() }").unwrap();
+    fs::remove_file(dir.path().join("notes.txt")).unwrap();
+    fs::create_dir(dir.path().join("empty")).unwrap();
+    let drifted = verify_against(dir.path(), &recorded, Vec::new()).unwrap();
+    assert_eq!(drifted, vec!["empty", "notes.txt", "src/main.rs"]);
+}
+
+#[test]
+fn capture_max_dir_entries_fails_on_wide_directory() {
+    let dir = TempDir::new().unwrap();
+    let wide = dir.path().join("cache");
+    fs::create_dir(&wide).unwrap();
+    for i in 0..4 {
+        fs::write(wide.join(format!("{i}.bin")), "x").unwrap();
+    }
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+    let snap = capture(dir.path(), vec![with_max_dir_entries(4)]).unwrap();
+    assert_eq!(snap.stats.file_count, 5);
+
+    let err = capture(dir.path(), vec![with_max_dir_entries(3)]).unwrap_err();
+    assert_eq!(err.kind, ErrTooManyDirEntries);
+    assert!(err.detail.contains("cache"), "{}", err.detail);
+
+    // Excluded entries do not count towards the limit.
+    let opts = vec![with_max_dir_entries(3), with_exclude(["0.bin"])];
+    assert!(capture(dir.path(), opts).is_ok());
+}
+
+#[test]
+fn capture_cancellable_stops_when_flag_is_set() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+
+    let (ctx, cancel) = crate::RequestContext::cancellable();
+    let snap = capture_cancellable(dir.path(), Vec::new(), ctx.cancel_flag()).unwrap();
+    assert_eq!(
+        snap.root_hash,
+        capture(dir.path(), Vec::new()).unwrap().root_hash
+    );
+
+    cancel.cancel();
+    let err = capture_cancellable(dir.path(), Vec::new(), ctx.cancel_flag()).unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Cancelled);
+}
+
+#[test]
+fn capture_max_depth_truncates_deep_dirs() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("a").join("b").join("c")).unwrap();
+    fs::write(dir.path().join("top.txt"), "top").unwrap();
+    fs::write(dir.path().join("a").join("one.txt"), "one").unwrap();
+    fs::write(dir.path().join("a").join("b").join("two.txt"), "two").unwrap();
+    fs::write(
+        dir.path().join("a").join("b").join("c").join("three.txt"),
+        "three",
+    )
+    .unwrap();
+
+    let snap = capture(dir.path(), vec![with_max_depth(1)]).unwrap();
+    let mut files = snap.list_files().unwrap();
+    files.sort();
+    assert_eq!(files, vec!["a/one.txt".to_string(), "top.txt".to_string()]);
+
+    let (entry, _) = snap.get_file_at_path("a/b").unwrap().expect("entry");
+    assert_eq!(entry.kind, EntryKindDirectory);
+    assert!(snap.get_tree(entry.hash).unwrap().is_empty());
+}
+
 #[test]
 fn tracker_snapshot_if_changed() {
     let dir = TempDir::new().unwrap();
@@ -306,3 +880,432 @@ fn tracker_snapshot_if_changed() {
     assert!(!changed2);
     assert!(snap2.is_none());
 }
+
+#[test]
+fn capture_with_sha256_hashes_content_and_trees() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+
+    let snap = capture(dir.path(), vec![with_hash_algorithm(Arc::new(Sha256))]).unwrap();
+    assert_eq!(snap.hash_algorithm, HashAlgorithmSha256);
+
+    let root_bytes = snap.trees.get(&snap.root_hash).expect("root tree");
+    assert_eq!(Sha256.hash(root_bytes), snap.root_hash);
+
+    let (entry, _) = snap.get_file_at_path("README.md").unwrap().expect("entry");
+    assert_eq!(entry.hash_alg, HashAlgorithmSha256);
+    assert_eq!(entry.hash, Sha256.hash(b"# Test"));
+    assert_ne!(entry.hash, *blake3::hash(b"# Test").as_bytes());
+
+    let algorithm = hash_algorithm_for_id(entry.hash_alg).expect("known algorithm");
+    assert_eq!(algorithm.hash(b"# Test"), entry.hash);
+}
+
+#[test]
+fn capture_with_hash_namespace_uses_keyed_blake3() {
+    let dir = TempDir::new().unwrap();
+    seed_workspace(dir.path());
+
+    let tenant_a = capture(dir.path(), vec![with_hash_namespace([1; 32])]).unwrap();
+    let tenant_b = capture(dir.path(), vec![with_hash_namespace([2; 32])]).unwrap();
+    assert_eq!(tenant_a.hash_algorithm, HashAlgorithmBlake3Keyed);
+    assert_ne!(tenant_a.root_hash, tenant_b.root_hash);
+
+    let (entry, _) = tenant_a
+        .get_file_at_path("README.md")
+        .unwrap()
+        .expect("entry");
+    assert_eq!(entry.hash_alg, HashAlgorithmBlake3Keyed);
+    assert_eq!(
+        entry.hash,
+        *blake3::keyed_hash(&[1; 32], b"# Test").as_bytes()
+    );
+    assert_ne!(entry.hash, *blake3::hash(b"# Test").as_bytes());
+    assert!(hash_algorithm_for_id(entry.hash_alg).is_none());
+
+    assert!(
+        verify_against(dir.path(), &tenant_a, vec![with_hash_namespace([1; 32])])
+            .unwrap()
+            .is_empty()
+    );
+    let err = verify_against(dir.path(), &tenant_a, Vec::new()).unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Other);
+}
+
+#[test]
+fn upload_cache_is_keyed_by_server() {
+    let dir = TempDir::new().unwrap();
+    let hash = [7u8; 32];
+
+    let cache_a = UploadCache::open(dir.path(), "127.0.0.1:9009").unwrap();
+    let cache_b = UploadCache::open(dir.path(), "[::1]:9009").unwrap();
+    assert_ne!(cache_a.dir(), cache_b.dir());
+    assert!(!cache_a.contains(&hash));
+
+    cache_a.record(&hash).unwrap();
+    assert!(cache_a.contains(&hash));
+    assert!(cache_a.uploaded_at(&hash).is_some());
+    assert!(!cache_b.contains(&hash));
+    assert!(cache_b.uploaded_at(&hash).is_none());
+}
+
+#[test]
+fn upload_with_cache_skips_previously_uploaded_blobs() {
+    use crate::protocol::MSG_PUT_BLOB;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let workspace = TempDir::new().unwrap();
+    seed_workspace(workspace.path());
+    let cache_dir = TempDir::new().unwrap();
+    let snap = capture(workspace.path(), Vec::<SnapshotOption>::new()).unwrap();
+
+    let puts = Arc::new(AtomicUsize::new(0));
+    let server_puts = puts.clone();
+    let (addr, _server) = mock_responder(move |frame| {
+        let resp = match frame.header.msg_type {
+            MSG_PUT_BLOB => {
+                server_puts.fetch_add(1, Ordering::SeqCst);
+                let mut resp = frame.payload[..32].to_vec();
+                resp.push(1);
+                resp
+            }
+            other => panic!("unexpected message type {other}"),
+        };
+        Some((frame.header.msg_type, resp))
+    });
+
+    let ctx = crate::RequestContext::background();
+    let client = crate::dial(&addr, Vec::new()).unwrap();
+    let first = snap
+        .upload_with_cache(&ctx, &client, cache_dir.path())
+        .unwrap();
+    let total = snap.trees.len() + snap.files.len() + snap.symlinks.len();
+    assert_eq!(first.cache_hits, 0);
+    assert_eq!(puts.load(Ordering::SeqCst), total);
+
+    let second = snap
+        .upload_with_cache(&ctx, &client, cache_dir.path())
+        .unwrap();
+    assert_eq!(second.cache_hits, total);
+    assert_eq!(second.files_uploaded + second.trees_uploaded, 0);
+    assert_eq!(puts.load(Ordering::SeqCst), total);
+}
+
+#[test]
+fn capture_and_stream_upload_sends_each_blob_once_during_walk() {
+    use crate::protocol::{MSG_BEGIN_BLOB, MSG_PUT_BLOB};
+    use std::sync::Mutex;
+
+    let workspace = TempDir::new().unwrap();
+    seed_workspace(workspace.path());
+    // Same content as README.md, so the same blob.
+    write_file(workspace.path().join("COPY.md"), b"# Test", 0o644);
+    // Larger than the chunk size, so hashed, rewound, and offered via BEGIN_BLOB.
+    write_file(workspace.path().join("big.bin"), &[7u8; 4096], 0o644);
+
+    // Hashes sent whole, and hashes offered through BEGIN_BLOB.
+    let received = Arc::new(Mutex::new((Vec::new(), Vec::new())));
+    let server_received = received.clone();
+    let (addr, _server) = mock_responder(move |frame| {
+        let hash = frame.payload.get(..32).map(<[u8]>::to_vec);
+        let resp = match frame.header.msg_type {
+            MSG_PUT_BLOB => {
+                server_received.lock().unwrap().0.push(hash.unwrap());
+                let mut resp = frame.payload[..32].to_vec();
+                resp.push(1);
+                resp
+            }
+            MSG_BEGIN_BLOB => {
+                // Upload id 0: the server already has it.
+                server_received.lock().unwrap().1.push(hash.unwrap());
+                vec![0u8; 16]
+            }
+            other => panic!("unexpected message type {other}"),
+        };
+        Some((frame.header.msg_type, resp))
+    });
+
+    let ctx = crate::RequestContext::background();
+    let client = crate::dial(&addr, vec![crate::with_blob_chunk_size(1024)]).unwrap();
+    let (snap, result) = capture_and_stream_upload(
+        &ctx,
+        &client,
+        workspace.path(),
+        Vec::<SnapshotOption>::new(),
+    )
+    .unwrap();
+    let expected = capture(workspace.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_eq!(snap.root_hash, expected.root_hash);
+    assert_eq!(result.root_hash, expected.root_hash);
+
+    let large = expected.files.values().filter(|f| f.size > 1024).count();
+    assert_eq!(large, 1);
+    let (puts, begins) = received.lock().unwrap().clone();
+    assert_eq!(begins.len(), large);
+    assert_eq!(
+        puts.len(),
+        expected.trees.len() + expected.files.len() - large + expected.symlinks.len()
+    );
+    // The root tree is the last blob produced.
+    assert_eq!(puts.last().unwrap()[..], expected.root_hash[..]);
+    assert_eq!(result.trees_uploaded, expected.trees.len());
+    assert_eq!(result.files_skipped, large);
+    assert_eq!(result.files_uploaded, expected.files.len() - large);
+}
+
+#[test]
+fn dry_run_counts_missing_blobs_without_uploading() {
+    use crate::protocol::MSG_HAS_BLOBS;
+
+    let workspace = TempDir::new().unwrap();
+    seed_workspace(workspace.path());
+    let snap = capture(workspace.path(), Vec::<SnapshotOption>::new()).unwrap();
+    let stored = snap.root_hash;
+
+    let (addr, _server) = mock_responder(move |frame| {
+        let resp = match frame.header.msg_type {
+            // Only the root tree is already stored.
+            MSG_HAS_BLOBS => {
+                let mut resp = frame.payload[..4].to_vec();
+                resp.extend(
+                    frame.payload[4..]
+                        .chunks(32)
+                        .map(|hash| (hash == stored) as u8),
+                );
+                resp
+            }
+            other => panic!("dry run sent message type {other}"),
+        };
+        Some((frame.header.msg_type, resp))
+    });
+
+    let ctx = crate::RequestContext::background();
+    let client = crate::dial(&addr, Vec::new()).unwrap();
+    let result =
+        upload_and_attach(&ctx, &client, workspace.path(), 7, vec![with_dry_run()]).unwrap();
+
+    let expected_bytes: u64 = snap
+        .trees
+        .iter()
+        .filter(|(hash, _)| **hash != stored)
+        .map(|(_, data)| data.len() as u64)
+        .chain(snap.files.values().map(|f| f.size))
+        .chain(snap.symlinks.values().map(|t| t.len() as u64))
+        .sum();
+    assert_eq!(result.root_hash, stored);
+    assert_eq!(result.trees_skipped, 1);
+    assert_eq!(result.trees_uploaded, snap.trees.len() - 1);
+    assert_eq!(
+        result.files_uploaded,
+        snap.files.len() + snap.symlinks.len()
+    );
+    assert_eq!(result.files_skipped, 0);
+    assert_eq!(result.bytes_uploaded, expected_bytes as i64);
+}
+
+#[test]
+fn append_turn_snapshotting_uploads_then_appends_with_root() {
+    use crate::protocol::{APPEND_FLAG_FS_ROOT, MSG_APPEND_TURN, MSG_PUT_BLOB};
+    use std::sync::Mutex;
+
+    let workspace = TempDir::new().unwrap();
+    seed_workspace(workspace.path());
+    let expected = capture(workspace.path(), Vec::<SnapshotOption>::new()).unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let server_seen = seen.clone();
+    let (addr, _server) = mock_responder(move |frame| {
+        server_seen.lock().unwrap().push(frame.header.msg_type);
+        let resp = match frame.header.msg_type {
+            MSG_PUT_BLOB => {
+                let mut resp = frame.payload[..32].to_vec();
+                resp.push(1);
+                resp
+            }
+            MSG_APPEND_TURN => {
+                assert_ne!(frame.header.flags & APPEND_FLAG_FS_ROOT, 0);
+                let root = &frame.payload[frame.payload.len() - 32..];
+                let mut resp = Vec::new();
+                resp.extend_from_slice(&5u64.to_le_bytes());
+                resp.extend_from_slice(&9u64.to_le_bytes());
+                resp.extend_from_slice(&1u32.to_le_bytes());
+                // Echo the attached root in the hash slot for the check below.
+                resp.extend_from_slice(root);
+                resp
+            }
+            other => panic!("unexpected message type {other}"),
+        };
+        Some((frame.header.msg_type, resp))
+    });
+
+    let ctx = crate::RequestContext::background();
+    let client = crate::dial(&addr, Vec::new()).unwrap();
+    let item = crate::types::new_assistant_turn("done");
+    let (appended, upload) = client
+        .append_turn_snapshotting(&ctx, 5, &item, workspace.path(), Vec::new())
+        .unwrap();
+    assert_eq!(appended.turn_id, 9);
+    assert_eq!(appended.payload_hash, expected.root_hash);
+    assert_eq!(upload.root_hash, expected.root_hash);
+    assert!(upload.files_uploaded > 0);
+    // Every blob goes up before the turn that references them.
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.last(), Some(&MSG_APPEND_TURN));
+    assert_eq!(seen.iter().filter(|t| **t == MSG_APPEND_TURN).count(), 1);
+
+    let err = client
+        .append_turn_snapshotting(&ctx, 5, &item, workspace.path(), vec![with_dry_run()])
+        .unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Other);
+}
+
+#[cfg(unix)]
+#[test]
+fn capture_mode_normalization_ignores_umask_noise() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let build = |file_mode: u32, script_mode: u32, dir_mode: u32| {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join("data.txt"), "data").unwrap();
+        fs::write(dir.path().join("run.sh"), "#!/bin/sh").unwrap();
+        fs::set_permissions(
+            dir.path().join("sub").join("data.txt"),
+            fs::Permissions::from_mode(file_mode),
+        )
+        .unwrap();
+        fs::set_permissions(
+            dir.path().join("run.sh"),
+            fs::Permissions::from_mode(script_mode),
+        )
+        .unwrap();
+        fs::set_permissions(dir.path().join("sub"), fs::Permissions::from_mode(dir_mode)).unwrap();
+        dir
+    };
+
+    let a = build(0o600, 0o700, 0o700);
+    let b = build(0o664, 0o775, 0o775);
+
+    let raw_a = capture(a.path(), Vec::<SnapshotOption>::new()).unwrap();
+    let raw_b = capture(b.path(), Vec::<SnapshotOption>::new()).unwrap();
+    assert_ne!(raw_a.root_hash, raw_b.root_hash);
+
+    let norm_a = capture(a.path(), vec![with_mode_normalization()]).unwrap();
+    let norm_b = capture(b.path(), vec![with_mode_normalization()]).unwrap();
+    assert_eq!(norm_a.root_hash, norm_b.root_hash);
+
+    let mode_of = |path: &str| norm_a.get_file_at_path(path).unwrap().unwrap().0.mode;
+    assert_eq!(mode_of("run.sh"), 0o755);
+    assert_eq!(mode_of("sub"), 0o755);
+    assert_eq!(mode_of("sub/data.txt"), 0o644);
+}
+
+#[test]
+fn deserialize_tree_reports_offset_and_types() {
+    let err = deserialize_tree(&crate::encode_msgpack(&"tree").unwrap()).unwrap_err();
+    assert_eq!(err.kind, FstreeErrorKind::Msgpack);
+    assert!(
+        err.detail
+            .starts_with("invalid tree msgpack at byte 0: expected array, found FixStr"),
+        "{}",
+        err.detail
+    );
+
+    // A valid first entry, then one whose kind (key 2) is a string.
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    let snap = capture(dir.path(), Vec::new()).unwrap();
+    let entries = deserialize_tree(&snap.trees[&snap.root_hash]).unwrap();
+    let first = crate::encode_msgpack(&entries[0]).unwrap();
+    let bad = rmpv::Value::Map(vec![(rmpv::Value::from("2"), rmpv::Value::from("file"))]);
+    let mut bytes = vec![0x92];
+    bytes.extend_from_slice(&first);
+    rmpv::encode::write_value(&mut bytes, &bad).unwrap();
+
+    let err = deserialize_tree(&bytes).unwrap_err();
+    let prefix = format!(
+        "invalid tree msgpack at byte {}: entry 1: ",
+        1 + first.len()
+    );
+    assert!(err.detail.starts_with(&prefix), "{}", err.detail);
+    assert!(err.detail.contains("string"), "{}", err.detail);
+}
+
+#[test]
+fn deserialize_tree_rejects_path_traversal_names() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a.txt"), "a").unwrap();
+    let snap = capture(dir.path(), Vec::new()).unwrap();
+    let entry = deserialize_tree(&snap.trees[&snap.root_hash]).unwrap()[0].clone();
+
+    for name in [".", "..", "../../etc", "a/b", "a\\b", "a\0b"] {
+        let mut bad = entry.clone();
+        bad.name = name.to_string();
+        let bytes = crate::encode_msgpack(&vec![entry.clone(), bad]).unwrap();
+        let err = deserialize_tree(&bytes).unwrap_err();
+        assert_eq!(err.kind, FstreeErrorKind::Msgpack);
+        assert!(
+            err.detail
+                .starts_with("invalid tree: entry 1: unsafe entry name"),
+            "{}",
+            err.detail
+        );
+    }
+}
+
+#[test]
+fn open_snapshot_fetches_trees_lazily_and_caches_them() {
+    use crate::protocol::MSG_GET_BLOB;
+    use std::sync::Mutex;
+
+    let workspace = TempDir::new().unwrap();
+    seed_workspace(workspace.path());
+    let snap = capture(workspace.path(), Vec::<SnapshotOption>::new()).unwrap();
+
+    let mut blobs = snap.trees.clone();
+    for (hash, file) in &snap.files {
+        blobs.insert(*hash, fs::read(&file.path).unwrap());
+    }
+    let fetched = Arc::new(Mutex::new(Vec::new()));
+    let server_fetched = fetched.clone();
+
+    let (addr, _server) = mock_responder(move |frame| {
+        let resp = match frame.header.msg_type {
+            MSG_GET_BLOB => {
+                let hash: [u8; 32] = frame.payload[..].try_into().unwrap();
+                server_fetched.lock().unwrap().push(hash);
+                let data = &blobs[&hash];
+                let mut resp = (data.len() as u32).to_le_bytes().to_vec();
+                resp.extend_from_slice(data);
+                resp
+            }
+            other => panic!("unexpected message type {other}"),
+        };
+        Some((frame.header.msg_type, resp))
+    });
+
+    let ctx = crate::RequestContext::background();
+    let client = crate::dial(&addr, Vec::new()).unwrap();
+    let handle = client.open_snapshot(&ctx, snap.root_hash);
+    assert!(fetched.lock().unwrap().is_empty());
+
+    let root: Vec<String> = handle
+        .list("")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(root, vec!["README.md", "script.sh", "src"]);
+    assert_eq!(handle.read("src/main.go").unwrap(), b"package main");
+    let stat = handle.stat("src/lib.go").unwrap();
+    assert_eq!(stat.kind, EntryKindFile);
+    assert_eq!(stat.size, 27);
+    assert!(handle.read("src").is_err());
+    assert!(handle.list("README.md").is_err());
+    assert!(handle.stat("src/missing.go").is_err());
+
+    // Root and src trees once each, plus the one file read.
+    let fetched = fetched.lock().unwrap();
+    assert_eq!(fetched.len(), 3);
+    assert_eq!(fetched[0], snap.root_hash);
+}
@@ -3,17 +3,33 @@
 
 #![allow(non_upper_case_globals)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
+use super::hash::{HashAlgorithmBlake3, HashAlgorithmId};
+
 pub type EntryKind = u8;
 
 pub const EntryKindFile: EntryKind = 0;
 pub const EntryKindDirectory: EntryKind = 1;
 pub const EntryKindSymlink: EntryKind = 2;
+/// Special files, captured only under `with_special_files`. They have no
+/// content: `hash` is all zeros and devices carry `TreeEntry::device`.
+pub const EntryKindFifo: EntryKind = 3;
+pub const EntryKindCharDevice: EntryKind = 4;
+pub const EntryKindBlockDevice: EntryKind = 5;
+
+/// Major and minor numbers of a device node.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeviceNumber {
+    #[serde(rename = "1")]
+    pub major: u32,
+    #[serde(rename = "2")]
+    pub minor: u32,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TreeEntry {
@@ -28,8 +44,68 @@ pub struct TreeEntry {
     #[serde(rename = "5")]
     #[serde(with = "serde_bytes")]
     pub hash: [u8; 32],
+    /// Algorithm that produced `hash`. Omitted on the wire for BLAKE3 so
+    /// existing trees keep their encoding (and therefore their hashes).
+    #[serde(rename = "6", default, skip_serializing_if = "is_blake3")]
+    pub hash_alg: HashAlgorithmId,
+    /// File bytes stored in the tree itself (see `with_inline_threshold`).
+    /// When set, no separate blob is uploaded for `hash`.
+    #[serde(
+        rename = "7",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bytes"
+    )]
+    pub inline_content: Option<Vec<u8>>,
+    /// Set for `EntryKindCharDevice` and `EntryKindBlockDevice` entries.
+    #[serde(rename = "8", default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceNumber>,
+    /// Extended attributes by name, captured on unix under `with_xattrs`.
+    /// Empty (and omitted on the wire) otherwise.
+    #[serde(
+        rename = "9",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "xattr_map"
+    )]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+/// Encodes xattr values as msgpack binary rather than integer arrays.
+mod xattr_map {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_bytes::{ByteBuf, Bytes};
+
+    pub fn serialize<S: Serializer>(
+        map: &BTreeMap<String, Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        map.iter()
+            .map(|(name, value)| (name, Bytes::new(value)))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Vec<u8>>, D::Error> {
+        let map = BTreeMap::<String, ByteBuf>::deserialize(deserializer)?;
+        Ok(map
+            .into_iter()
+            .map(|(name, value)| (name, value.into_vec()))
+            .collect())
+    }
+}
+
+fn is_blake3(alg: &HashAlgorithmId) -> bool {
+    *alg == HashAlgorithmBlake3
 }
 
+/// A directory's entries. `capture` writes them in a total order, by name
+/// (bytewise), then kind, hash and mode, with names unique within a tree, so
+/// identical directories encode to identical bytes and hashes.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TreeObject {
     pub entries: Vec<TreeEntry>,
@@ -41,8 +117,20 @@ pub struct Snapshot {
     pub trees: HashMap<[u8; 32], Vec<u8>>,
     pub files: HashMap<[u8; 32], FileRef>,
     pub symlinks: HashMap<[u8; 32], String>,
+    /// Files left out of the capture by content checks such as
+    /// `with_exclude_binary`, or because their names cannot be stored in a
+    /// tree (reason "unsafe name"), in walk order.
+    pub skipped: Vec<SkippedFile>,
     pub stats: SnapshotStats,
     pub captured_at: SystemTime,
+    pub hash_algorithm: HashAlgorithmId,
+}
+
+/// A file `capture` passed over, and why (e.g. "binary").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone)]
@@ -57,8 +145,41 @@ pub struct SnapshotStats {
     pub file_count: usize,
     pub dir_count: usize,
     pub symlink_count: usize,
+    /// FIFOs and device nodes captured under `with_special_files`.
+    pub special_count: usize,
+    /// File bytes on disk: content reached through several hardlinks is
+    /// counted once.
     pub total_bytes: u64,
+    /// File bytes as seen by walking every path, hardlinks included.
+    pub logical_bytes: u64,
+    /// Size of each distinct file blob, counted once however many entries
+    /// share its content. Inlined files are not blobs and are left out.
+    pub unique_file_bytes: u64,
+    /// File entries that were hardlinks to content already hashed in this
+    /// capture, and so were not read again. Always zero off unix.
+    pub hardlink_count: usize,
+    /// Capture stopped early at `max_files` under `with_partial_on_limit`,
+    /// so the snapshot holds only the entries walked before the limit.
+    pub truncated: bool,
     pub duration: Duration,
+    /// Where `duration` went, phase by phase.
+    pub timing: TimingBreakdown,
+}
+
+/// Time a capture spent in each phase, summed over the whole walk. Time
+/// outside these phases (exclusion matching, bookkeeping) is not counted,
+/// so the parts add up to somewhat less than `SnapshotStats::duration`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimingBreakdown {
+    /// Opening and listing directories, and resolving them for cycle
+    /// detection.
+    pub walk: Duration,
+    /// Reading metadata, symlink targets and extended attributes.
+    pub stat: Duration,
+    /// Reading and hashing file content, including binary sniffing.
+    pub hash: Duration,
+    /// Sorting, encoding and hashing tree objects.
+    pub encode: Duration,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -2,10 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::client::RequestContext;
-use crate::fs::PutBlobRequest;
+use crate::encoding::encode_msgpack;
+use crate::turn::{AppendRequest, AppendResult};
+use crate::types::{ConversationItem, TypeIDConversationItem, TypeVersionConversationItem};
 use crate::Client;
 
-use super::capture::{FstreeError, FstreeErrorKind, Result as FstreeResult};
+use super::cache::UploadCache;
+use super::capture::{
+    capture_with_sink, FstreeError, FstreeErrorKind, Result as FstreeResult, StreamedBlob,
+};
+use super::options::{Options, SnapshotOption};
 use super::types::Snapshot;
 
 #[derive(Debug, Clone, Default)]
@@ -16,18 +22,90 @@ pub struct UploadResult {
     pub files_uploaded: usize,
     pub files_skipped: usize,
     pub bytes_uploaded: i64,
+    /// Blobs skipped without contacting the server because the local upload
+    /// cache already recorded them.
+    pub cache_hits: usize,
 }
 
 impl Snapshot {
     pub fn upload(&self, ctx: &RequestContext, client: &Client) -> FstreeResult<UploadResult> {
+        self.upload_inner(ctx, client, None)
+    }
+
+    /// Like `upload`, but consults an on-disk cache under `cache_dir` (keyed
+    /// by the client's server address) and skips `put_blob` for hashes that a
+    /// previous run already uploaded. Successful uploads are recorded.
+    pub fn upload_with_cache(
+        &self,
+        ctx: &RequestContext,
+        client: &Client,
+        cache_dir: impl AsRef<std::path::Path>,
+    ) -> FstreeResult<UploadResult> {
+        let cache = UploadCache::open(cache_dir, client.addr())?;
+        self.upload_inner(ctx, client, Some(&cache))
+    }
+
+    /// Computes the `UploadResult` that `upload` would produce without
+    /// sending any blob: one HAS_BLOBS round trip per few thousand objects
+    /// tells which the server is missing.
+    pub fn plan_upload(&self, ctx: &RequestContext, client: &Client) -> FstreeResult<UploadResult> {
+        // (hash, size, is_tree) for every object the snapshot references.
+        let objects: Vec<([u8; 32], u64, bool)> = self
+            .trees
+            .iter()
+            .map(|(hash, data)| (*hash, data.len() as u64, true))
+            .chain(self.files.iter().map(|(hash, f)| (*hash, f.size, false)))
+            .chain(
+                self.symlinks
+                    .iter()
+                    .map(|(hash, target)| (*hash, target.len() as u64, false)),
+            )
+            .collect();
+        let hashes: Vec<[u8; 32]> = objects.iter().map(|(hash, _, _)| *hash).collect();
+        let present = client
+            .has_blobs(ctx, &hashes)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+
+        let mut result = UploadResult {
+            root_hash: self.root_hash,
+            ..UploadResult::default()
+        };
+        for ((_, size, is_tree), present) in objects.into_iter().zip(present) {
+            match (is_tree, present) {
+                (true, true) => result.trees_skipped += 1,
+                (true, false) => result.trees_uploaded += 1,
+                (false, true) => result.files_skipped += 1,
+                (false, false) => result.files_uploaded += 1,
+            }
+            if !present {
+                result.bytes_uploaded += size as i64;
+            }
+        }
+        Ok(result)
+    }
+
+    fn upload_inner(
+        &self,
+        ctx: &RequestContext,
+        client: &Client,
+        cache: Option<&UploadCache>,
+    ) -> FstreeResult<UploadResult> {
+        let cached = |hash: &[u8; 32]| cache.is_some_and(|c| c.contains(hash));
         let mut result = UploadResult {
             root_hash: self.root_hash,
             ..UploadResult::default()
         };
 
-        for data in self.trees.values() {
-            let was_new = upload_blob(ctx, client, data.to_vec())
+        for (hash, data) in &self.trees {
+            if cached(hash) {
+                result.cache_hits += 1;
+                continue;
+            }
+            let was_new = upload_blob(ctx, client, *hash, data)
                 .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+            if let Some(cache) = cache {
+                cache.record(hash)?;
+            }
             if was_new {
                 result.trees_uploaded += 1;
                 result.bytes_uploaded += data.len() as i64;
@@ -36,23 +114,40 @@ impl Snapshot {
             }
         }
 
-        for file_ref in self.files.values() {
-            let content = std::fs::read(&file_ref.path)
-                .map_err(|err| FstreeError::new(FstreeErrorKind::Io, err.to_string()))?;
-            let was_new = upload_blob(ctx, client, content.clone())
+        for (hash, file_ref) in &self.files {
+            if cached(hash) {
+                result.cache_hits += 1;
+                continue;
+            }
+            // Stream from disk so large files are never held in memory whole.
+            let file = std::fs::File::open(&file_ref.path)
+                .map_err(|err| FstreeError::io_at(&file_ref.path, err))?;
+            let was_new = client
+                .put_blob_stream_with_hash(ctx, *hash, file, file_ref.size)
+                .map(|result| result.was_new)
                 .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+            if let Some(cache) = cache {
+                cache.record(hash)?;
+            }
             if was_new {
                 result.files_uploaded += 1;
-                result.bytes_uploaded += content.len() as i64;
+                result.bytes_uploaded += file_ref.size as i64;
             } else {
                 result.files_skipped += 1;
             }
         }
 
-        for target in self.symlinks.values() {
-            let bytes = target.as_bytes().to_vec();
-            let was_new = upload_blob(ctx, client, bytes.clone())
+        for (hash, target) in &self.symlinks {
+            if cached(hash) {
+                result.cache_hits += 1;
+                continue;
+            }
+            let bytes = target.as_bytes();
+            let was_new = upload_blob(ctx, client, *hash, bytes)
                 .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+            if let Some(cache) = cache {
+                cache.record(hash)?;
+            }
             if was_new {
                 result.files_uploaded += 1;
                 result.bytes_uploaded += bytes.len() as i64;
@@ -68,40 +163,162 @@ impl Snapshot {
 fn upload_blob(
     ctx: &RequestContext,
     client: &Client,
-    data: Vec<u8>,
+    hash: [u8; 32],
+    data: &[u8],
 ) -> Result<bool, crate::error::Error> {
-    let result = client.put_blob(ctx, &PutBlobRequest { data })?;
+    // Blobs are keyed by the snapshot's own hashes so non-BLAKE3 snapshots
+    // upload under the same addresses their trees reference.
+    let result = client.put_blob_with_hash(ctx, hash, data)?;
     Ok(result.was_new)
 }
 
+/// Captures `root`, uploads it, and attaches it to `turn_id`. With
+/// `with_dry_run` the result only reports what would be uploaded.
 pub fn upload_and_attach(
     ctx: &RequestContext,
     client: &Client,
     root: impl AsRef<std::path::Path>,
     turn_id: u64,
-    opts: impl IntoIterator<Item = super::options::SnapshotOption>,
+    opts: impl IntoIterator<Item = SnapshotOption>,
 ) -> FstreeResult<UploadResult> {
-    let snapshot = super::capture::capture(root, opts)?;
-    let result = snapshot.upload(ctx, client)?;
+    let opts: Vec<SnapshotOption> = opts.into_iter().collect();
+    let dry_run = is_dry_run(&opts);
+    let (snapshot, result) = capture_and_upload(ctx, client, root, opts)?;
+    if dry_run {
+        return Ok(result);
+    }
     client
         .attach_fs(
             ctx,
             &crate::fs::AttachFsRequest {
                 turn_id,
                 fs_root_hash: snapshot.root_hash,
+                meta: Some(snapshot.meta()),
             },
         )
         .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
     Ok(result)
 }
 
+impl Client {
+    /// Captures `root`, uploads the blobs the server is missing, and appends
+    /// `item` to `context_id` with the snapshot attached in the same request,
+    /// so the turn never exists without its snapshot. `with_dry_run` is
+    /// rejected, since the turn would have to be appended regardless.
+    pub fn append_turn_snapshotting(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        item: &ConversationItem,
+        root: impl AsRef<std::path::Path>,
+        opts: impl IntoIterator<Item = SnapshotOption>,
+    ) -> FstreeResult<(AppendResult, UploadResult)> {
+        let opts: Vec<SnapshotOption> = opts.into_iter().collect();
+        if is_dry_run(&opts) {
+            return Err(FstreeError::new(
+                FstreeErrorKind::Other,
+                "append_turn_snapshotting does not support with_dry_run",
+            ));
+        }
+        let payload = encode_msgpack(item)
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Msgpack, err.to_string()))?;
+        let (snapshot, upload) = capture_and_upload(ctx, self, root, opts)?;
+        let req = AppendRequest::new(
+            context_id,
+            TypeIDConversationItem,
+            TypeVersionConversationItem,
+            payload,
+        );
+        let appended = self
+            .append_turn_with_fs(ctx, &req, Some(snapshot.root_hash))
+            .map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+        Ok((appended, upload))
+    }
+}
+
+/// Captures `root` and uploads it. With `with_dry_run` nothing is uploaded
+/// and the result reports what would have been.
 pub fn capture_and_upload(
     ctx: &RequestContext,
     client: &Client,
     root: impl AsRef<std::path::Path>,
-    opts: impl IntoIterator<Item = super::options::SnapshotOption>,
-) -> FstreeResult<(super::types::Snapshot, UploadResult)> {
+    opts: impl IntoIterator<Item = SnapshotOption>,
+) -> FstreeResult<(Snapshot, UploadResult)> {
+    let opts: Vec<SnapshotOption> = opts.into_iter().collect();
+    let dry_run = is_dry_run(&opts);
     let snapshot = super::capture::capture(root, opts)?;
-    let result = snapshot.upload(ctx, client)?;
+    let result = if dry_run {
+        snapshot.plan_upload(ctx, client)?
+    } else {
+        snapshot.upload(ctx, client)?
+    };
     Ok((snapshot, result))
 }
+
+/// Like `capture_and_upload`, but uploads each blob as soon as the walk
+/// produces it: files right after they are hashed, trees once their
+/// children are in. Files up to the client's blob chunk size are read once
+/// and sent from memory; larger ones are rewound after hashing and read
+/// again only if the server does not already hold them. An upload failure
+/// stops the walk. With `with_dry_run` this is `capture_and_upload`.
+pub fn capture_and_stream_upload(
+    ctx: &RequestContext,
+    client: &Client,
+    root: impl AsRef<std::path::Path>,
+    opts: impl IntoIterator<Item = SnapshotOption>,
+) -> FstreeResult<(Snapshot, UploadResult)> {
+    let opts: Vec<SnapshotOption> = opts.into_iter().collect();
+    if is_dry_run(&opts) {
+        return capture_and_upload(ctx, client, root, opts);
+    }
+
+    let mut result = UploadResult::default();
+    let mut sent = std::collections::HashSet::new();
+    let mut sink = |hash: [u8; 32], blob: StreamedBlob<'_>| -> FstreeResult<()> {
+        // Identical files or subtrees are produced once per occurrence.
+        if !sent.insert(hash) {
+            return Ok(());
+        }
+        let (was_new, size, is_tree) = match blob {
+            StreamedBlob::Tree(data) => (upload_blob(ctx, client, hash, data), data.len(), true),
+            StreamedBlob::Symlink(data) | StreamedBlob::FileBytes(data) => {
+                (upload_blob(ctx, client, hash, data), data.len(), false)
+            }
+            StreamedBlob::FileReader { file, size } => (
+                client
+                    .put_blob_stream_with_hash(ctx, hash, file, size)
+                    .map(|result| result.was_new),
+                size as usize,
+                false,
+            ),
+        };
+        let was_new =
+            was_new.map_err(|err| FstreeError::new(FstreeErrorKind::Client, err.to_string()))?;
+        match (is_tree, was_new) {
+            (true, true) => result.trees_uploaded += 1,
+            (true, false) => result.trees_skipped += 1,
+            (false, true) => result.files_uploaded += 1,
+            (false, false) => result.files_skipped += 1,
+        }
+        if was_new {
+            result.bytes_uploaded += size as i64;
+        }
+        Ok(())
+    };
+    let snapshot = capture_with_sink(
+        root.as_ref(),
+        opts,
+        Some(ctx.cancel_flag()),
+        Some((&mut sink, client.blob_chunk_size as u64)),
+    )?;
+    result.root_hash = snapshot.root_hash;
+    Ok((snapshot, result))
+}
+
+fn is_dry_run(opts: &[SnapshotOption]) -> bool {
+    let mut options = Options::default();
+    for opt in opts {
+        opt(&mut options);
+    }
+    options.dry_run
+}
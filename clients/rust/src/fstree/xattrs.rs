@@ -0,0 +1,94 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Extended attributes for `with_xattrs`.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use super::types::TreeEntry;
+
+/// Reads the extended attributes of `path`, or of the symlink itself unless
+/// `follow_symlinks`. Attributes with non-UTF-8 names are left out. A
+/// filesystem without xattr support, or a file removed mid-walk, yields an
+/// empty map.
+#[cfg(unix)]
+pub(crate) fn read_xattrs(
+    path: &Path,
+    follow_symlinks: bool,
+) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let names = if follow_symlinks {
+        xattr::list_deref(path)
+    } else {
+        xattr::list(path)
+    };
+    let names = match names {
+        Ok(names) => names,
+        Err(err) if is_absent(&err) => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut xattrs = BTreeMap::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            continue;
+        };
+        let value = if follow_symlinks {
+            xattr::get_deref(path, &name)
+        } else {
+            xattr::get(path, &name)
+        };
+        match value {
+            Ok(Some(value)) => {
+                xattrs.insert(key.to_string(), value);
+            }
+            // Removed between listing and reading.
+            Ok(None) => {}
+            Err(err) if is_absent(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn read_xattrs(
+    _path: &Path,
+    _follow_symlinks: bool,
+) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    Ok(BTreeMap::new())
+}
+
+#[cfg(unix)]
+fn is_absent(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::NotFound
+    )
+}
+
+/// Sets the extended attributes recorded in `entry` on `path` (the symlink
+/// itself for symlink entries), for callers writing a snapshot back to
+/// disk. Attributes already on `path` but absent from `entry` are left in
+/// place. Setting `security.*` or `trusted.*` attributes usually needs
+/// privileges the caller must already have.
+#[cfg(unix)]
+pub fn apply_xattrs(path: &Path, entry: &TreeEntry) -> io::Result<()> {
+    for (name, value) in &entry.xattrs {
+        xattr::set(path, name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply_xattrs(_path: &Path, entry: &TreeEntry) -> io::Result<()> {
+    if entry.xattrs.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are only supported on unix",
+        ))
+    }
+}
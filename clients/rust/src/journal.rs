@@ -0,0 +1,356 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Disk-backed journal for `with_persistent_queue`.
+//!
+//! `queue.log` in the journal directory is an append-only sequence of
+//! records, each `kind: u8, seq: u64, len: u32, body: [len]u8, crc32: u32`
+//! (the crc covers everything before it). Kinds 1 and 2 record an
+//! `append_turn` or `put_blob` before it is queued; kind 3 acknowledges the
+//! operation with the same `seq` once it has settled. On open, operations
+//! without an acknowledgement are returned for replay and the file is
+//! rewritten to hold only them. A torn final record is discarded; a bad
+//! record anywhere else, or one of an unknown kind, makes `open` fail
+//! rather than drop the records after it.
+//!
+//! `open` takes an exclusive lock on `queue.lock` in the same directory,
+//! held until the journal is dropped, so two processes cannot replay and
+//! compact the same queue.
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::{Error, Result};
+use crate::turn::AppendRequest;
+
+const KIND_APPEND_TURN: u8 = 1;
+const KIND_PUT_BLOB: u8 = 2;
+const KIND_ACK: u8 = 3;
+
+/// kind, seq and len.
+const RECORD_HEADER_LEN: usize = 1 + 8 + 4;
+
+const FLAG_DEDUP_ITEM_ID: u8 = 1 << 0;
+const FLAG_CHECKPOINT: u8 = 1 << 1;
+
+/// An operation that can be journaled and replayed.
+#[derive(Debug, Clone)]
+pub(crate) enum JournalOp {
+    AppendTurn(AppendRequest),
+    PutBlob(Vec<u8>),
+}
+
+pub(crate) struct QueueJournal {
+    state: Mutex<JournalState>,
+    /// Holds the directory's exclusive lock for the journal's lifetime.
+    _lock: File,
+}
+
+struct JournalState {
+    file: File,
+    next_seq: u64,
+    /// Recorded operations not yet acknowledged.
+    pending: BTreeSet<u64>,
+}
+
+impl QueueJournal {
+    /// Opens (or creates) the journal in `dir`, returning it together with
+    /// the unacknowledged operations in the order they were recorded.
+    pub(crate) fn open(dir: &Path) -> Result<(Self, Vec<(u64, JournalOp)>)> {
+        std::fs::create_dir_all(dir)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join("queue.lock"))?;
+        if let Err(err) = lock.try_lock() {
+            return Err(journal_error(format!(
+                "{} is in use by another process: {err}",
+                dir.display()
+            )));
+        }
+        let path = dir.join("queue.log");
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut ops = Vec::new();
+        let mut acked = BTreeSet::new();
+        let mut next_seq = 1;
+        let mut offset = 0;
+        while offset < data.len() {
+            let rest = &data[offset..];
+            let Some((kind, seq, body, len)) = split_record(rest) else {
+                if record_is_torn(rest) {
+                    break;
+                }
+                return Err(journal_error(format!(
+                    "{} has a corrupt record at offset {offset}",
+                    path.display()
+                )));
+            };
+            offset += len;
+            next_seq = next_seq.max(seq + 1);
+            if kind == KIND_ACK {
+                acked.insert(seq);
+                continue;
+            }
+            let op = decode_op(kind, body).ok_or_else(|| {
+                journal_error(format!(
+                    "{} has an unreadable record of kind {kind} at offset {}",
+                    path.display(),
+                    offset - len
+                ))
+            })?;
+            ops.push((seq, op));
+        }
+        ops.retain(|(seq, _)| !acked.contains(seq));
+
+        // Keep only what is still pending, so the file does not grow across runs.
+        let mut compacted = Vec::new();
+        for (seq, op) in &ops {
+            compacted.extend_from_slice(&encode_op_record(*seq, op)?);
+        }
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&compacted)?;
+            tmp.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, &path)?;
+        let file = OpenOptions::new().append(true).read(true).open(&path)?;
+
+        let journal = Self {
+            state: Mutex::new(JournalState {
+                file,
+                next_seq,
+                pending: ops.iter().map(|(seq, _)| *seq).collect(),
+            }),
+            _lock: lock,
+        };
+        Ok((journal, ops))
+    }
+
+    /// Durably records `op` before it is queued and returns its sequence
+    /// number.
+    pub(crate) fn record(&self, op: &JournalOp) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq;
+        let record = encode_op_record(seq, op)?;
+        state.file.write_all(&record)?;
+        state.file.sync_data()?;
+        state.next_seq += 1;
+        state.pending.insert(seq);
+        Ok(seq)
+    }
+
+    /// Marks `seq` settled so it is not replayed. The acknowledgement is
+    /// synced before returning. A call the server applied but that crashed
+    /// before this point is still replayed, so delivery is at least once and
+    /// a replayed append without an idempotency key adds a second turn. Once
+    /// nothing is pending the file is emptied.
+    pub(crate) fn ack(&self, seq: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.pending.remove(&seq) {
+            return Ok(());
+        }
+        if state.pending.is_empty() {
+            state.file.set_len(0)?;
+        } else {
+            let record = encode_record(KIND_ACK, seq, &[])?;
+            state.file.write_all(&record)?;
+        }
+        state.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Splits the first whole, checksummed record off `data`: its kind, seq,
+/// body and total length.
+fn split_record(data: &[u8]) -> Option<(u8, u64, &[u8], usize)> {
+    let header = data.get(..RECORD_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[9..13].try_into().ok()?) as usize;
+    let total = RECORD_HEADER_LEN + len + 4;
+    let record = data.get(..total)?;
+    let crc = u32::from_le_bytes(record[total - 4..].try_into().ok()?);
+    if crc32fast::hash(&record[..total - 4]) != crc {
+        return None;
+    }
+    let seq = u64::from_le_bytes(header[1..9].try_into().ok()?);
+    Some((header[0], seq, &record[RECORD_HEADER_LEN..total - 4], total))
+}
+
+/// Whether `data`, which does not start with a valid record, is what a
+/// crash while appending the last record leaves: a record cut short, or a
+/// whole one whose bytes did not all reach the disk, with nothing after it.
+fn record_is_torn(data: &[u8]) -> bool {
+    let Some(header) = data.get(..RECORD_HEADER_LEN) else {
+        return true;
+    };
+    let len = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+    RECORD_HEADER_LEN + len + 4 >= data.len()
+}
+
+fn journal_error(msg: String) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+fn encode_record(kind: u8, seq: u64, body: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + body.len() + 4);
+    buf.write_u8(kind)?;
+    buf.write_u64::<LittleEndian>(seq)?;
+    buf.write_u32::<LittleEndian>(body.len() as u32)?;
+    buf.extend_from_slice(body);
+    let crc = crc32fast::hash(&buf);
+    buf.write_u32::<LittleEndian>(crc)?;
+    Ok(buf)
+}
+
+fn encode_op_record(seq: u64, op: &JournalOp) -> Result<Vec<u8>> {
+    match op {
+        JournalOp::AppendTurn(req) => {
+            let mut body = Vec::with_capacity(64 + req.payload.len());
+            body.write_u64::<LittleEndian>(req.context_id)?;
+            body.write_u64::<LittleEndian>(req.parent_turn_id)?;
+            write_bytes(&mut body, req.type_id.as_bytes())?;
+            body.write_u32::<LittleEndian>(req.type_version)?;
+            body.write_u32::<LittleEndian>(req.encoding)?;
+            body.write_u32::<LittleEndian>(req.compression)?;
+            let mut flags = 0;
+            if req.dedup_by_item_id {
+                flags |= FLAG_DEDUP_ITEM_ID;
+            }
+            if req.checkpoint {
+                flags |= FLAG_CHECKPOINT;
+            }
+            body.write_u8(flags)?;
+            write_bytes(&mut body, &req.payload)?;
+            write_bytes(&mut body, &req.idempotency_key)?;
+            encode_record(KIND_APPEND_TURN, seq, &body)
+        }
+        JournalOp::PutBlob(data) => encode_record(KIND_PUT_BLOB, seq, data),
+    }
+}
+
+fn decode_op(kind: u8, body: &[u8]) -> Option<JournalOp> {
+    match kind {
+        KIND_APPEND_TURN => {
+            let mut cursor = std::io::Cursor::new(body);
+            let context_id = cursor.read_u64::<LittleEndian>().ok()?;
+            let parent_turn_id = cursor.read_u64::<LittleEndian>().ok()?;
+            let type_id = String::from_utf8(read_bytes(&mut cursor)?).ok()?;
+            let type_version = cursor.read_u32::<LittleEndian>().ok()?;
+            let encoding = cursor.read_u32::<LittleEndian>().ok()?;
+            let compression = cursor.read_u32::<LittleEndian>().ok()?;
+            let flags = cursor.read_u8().ok()?;
+            let payload = read_bytes(&mut cursor)?;
+            let idempotency_key = read_bytes(&mut cursor)?;
+            Some(JournalOp::AppendTurn(AppendRequest {
+                context_id,
+                parent_turn_id,
+                type_id,
+                type_version,
+                payload,
+                idempotency_key,
+                encoding,
+                compression,
+                dedup_by_item_id: flags & FLAG_DEDUP_ITEM_ID != 0,
+                checkpoint: flags & FLAG_CHECKPOINT != 0,
+            }))
+        }
+        KIND_PUT_BLOB => Some(JournalOp::PutBlob(body.to_vec())),
+        _ => None,
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) -> std::io::Result<()> {
+    buf.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    buf.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn read_bytes(cursor: &mut std::io::Cursor<&[u8]>) -> Option<Vec<u8>> {
+    let len = cursor.read_u32::<LittleEndian>().ok()? as usize;
+    let mut bytes = vec![0u8; len];
+    cursor.read_exact(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_refuses_corruption_before_the_last_record() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let (journal, _) = QueueJournal::open(dir.path()).unwrap();
+            journal
+                .record(&JournalOp::PutBlob(b"one".to_vec()))
+                .unwrap();
+            journal
+                .record(&JournalOp::PutBlob(b"two".to_vec()))
+                .unwrap();
+        }
+        let path = dir.path().join("queue.log");
+        let mut data = std::fs::read(&path).unwrap();
+        data[RECORD_HEADER_LEN] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let err = QueueJournal::open(dir.path()).err().unwrap();
+        assert!(
+            err.to_string().contains("corrupt record at offset 0"),
+            "{err}"
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), data, "file left untouched");
+    }
+
+    #[test]
+    fn open_refuses_unknown_record_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let record = encode_record(9, 1, b"from a newer client").unwrap();
+        std::fs::write(dir.path().join("queue.log"), record).unwrap();
+
+        let err = QueueJournal::open(dir.path()).err().unwrap();
+        assert!(err.to_string().contains("kind 9"), "{err}");
+    }
+
+    #[test]
+    fn open_drops_a_torn_last_record() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let (journal, _) = QueueJournal::open(dir.path()).unwrap();
+            journal
+                .record(&JournalOp::PutBlob(b"kept".to_vec()))
+                .unwrap();
+            journal
+                .record(&JournalOp::PutBlob(b"torn".to_vec()))
+                .unwrap();
+        }
+        let path = dir.path().join("queue.log");
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 2]).unwrap();
+
+        let (_, pending) = QueueJournal::open(dir.path()).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(matches!(&pending[0].1, JournalOp::PutBlob(data) if data == b"kept"));
+    }
+
+    #[test]
+    fn a_directory_is_used_by_one_journal_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let (journal, _) = QueueJournal::open(dir.path()).unwrap();
+        let err = QueueJournal::open(dir.path()).err().unwrap();
+        assert!(err.to_string().contains("in use"), "{err}");
+
+        drop(journal);
+        QueueJournal::open(dir.path()).unwrap();
+    }
+}
@@ -5,14 +5,23 @@
 //!
 //! Exposes a synchronous TCP/TLS client, reconnecting wrapper, fstree snapshots,
 //! and canonical conversation types plus msgpack helpers.
+//!
+//! The `tracing` feature emits debug-level spans for requests, dials,
+//! reconnects and queued operations.
+
+#[macro_use]
+mod trace;
 
 pub mod client;
 pub mod context;
 pub mod encoding;
 pub mod error;
+pub mod export;
 pub mod fs;
+mod journal;
 pub mod protocol;
 pub mod reconnect;
+pub mod resilient;
 pub mod telemetry;
 pub mod turn;
 
@@ -22,17 +31,30 @@ pub mod types;
 #[cfg(test)]
 mod test_util;
 pub use crate::client::{
-    dial, dial_tls, with_client_tag, with_dial_timeout, with_request_timeout, Client, ClientOption,
+    dial, dial_tls, with_blob_chunk_size, with_ca_file, with_ca_pem, with_client_tag,
+    with_crypto_provider, with_dial_timeout, with_io_buffer_size, with_native_roots,
+    with_read_only, with_request_timeout, with_resume_session, Client, ClientOption, HealthStatus,
     RequestContext,
 };
-pub use crate::context::ContextHead;
+pub use crate::context::{
+    with_custom, with_labels, with_provenance, with_title, ContextHead, ContextOption,
+    ContextQuota, ContextSummary,
+};
 pub use crate::encoding::{decode_msgpack, decode_msgpack_into, encode_msgpack};
 pub use crate::error::{is_server_error, Error, Result, ServerError};
-pub use crate::fs::{AttachFsRequest, AttachFsResult, PutBlobRequest, PutBlobResult};
+pub use crate::export::ExportSummary;
+pub use crate::fs::{
+    AttachFsRequest, AttachFsResult, PutBlobRequest, PutBlobResult, SnapshotMeta, VerifyReport,
+};
+pub use crate::protocol::{Frame, FrameHeader};
 pub use crate::reconnect::{
-    dial_reconnecting, dial_tls_reconnecting, DialFunc, ReconnectOption, ReconnectingClient,
+    dial_reconnecting, dial_tls_reconnecting, BackoffInfo, DialFunc, ReconnectInfo,
+    ReconnectOption, ReconnectingClient,
+};
+pub use crate::resilient::{dial_resilient, dial_tls_resilient, ResilientClient};
+pub use crate::turn::{
+    AppendRequest, AppendResult, GetLastOptions, RawTurn, TurnHeader, TurnPage, TurnRecord,
 };
-pub use crate::turn::{AppendRequest, AppendResult, GetLastOptions, TurnRecord};
 
 // Re-export shared constants for parity with Go names.
 #[allow(non_upper_case_globals)]
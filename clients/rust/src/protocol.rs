@@ -17,8 +17,30 @@ pub const MSG_GET_LAST: u16 = 6;
 pub const MSG_GET_BLOB: u16 = 9;
 pub const MSG_ATTACH_FS: u16 = 10;
 pub const MSG_PUT_BLOB: u16 = 11;
+pub const MSG_WATCH_HEAD: u16 = 12;
+pub const MSG_BEGIN_BLOB: u16 = 13;
+pub const MSG_BLOB_CHUNK: u16 = 14;
+pub const MSG_COMMIT_BLOB: u16 = 15;
+pub const MSG_HAS_BLOBS: u16 = 16;
+pub const MSG_GET_HEAD_AT: u16 = 17;
+pub const MSG_LIST_CONTEXTS: u16 = 18;
+pub const MSG_ATTACH_FS_HEAD: u16 = 19;
+pub const MSG_TRIM_CONTEXT: u16 = 20;
+pub const MSG_FIND_SNAPSHOT_REFS: u16 = 21;
+pub const MSG_HEALTH: u16 = 22;
+pub const MSG_VERIFY_SNAPSHOT: u16 = 23;
 pub const MSG_ERROR: u16 = 255;
 
+pub const APPEND_FLAG_FS_ROOT: u16 = 1 << 0;
+pub const APPEND_FLAG_DEDUP_ITEM_ID: u16 = 1 << 1;
+pub const APPEND_FLAG_CHECKPOINT: u16 = 1 << 2;
+pub const GET_LAST_FLAG_SUMMARY: u16 = 1 << 0;
+pub const GET_LAST_FLAG_MIN_HEAD: u16 = 1 << 1;
+pub const GET_LAST_FLAG_ITEM_TYPES: u16 = 1 << 2;
+
+pub const WATCH_FLAG_UPDATE: u16 = 1 << 0;
+pub const WATCH_FLAG_STOP: u16 = 1 << 1;
+
 pub const ENCODING_MSGPACK: u32 = 1;
 pub const COMPRESSION_NONE: u32 = 0;
 pub const COMPRESSION_ZSTD: u32 = 1;
@@ -27,6 +49,10 @@ pub const DEFAULT_DIAL_TIMEOUT: Duration = Duration::from_secs(5);
 pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024; // 64 MiB
+pub const DEFAULT_BLOB_CHUNK_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 64 * 1024; // 64 KiB
+
+pub(crate) const FRAME_HEADER_LEN: usize = 16;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameHeader {
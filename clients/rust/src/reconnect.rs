@@ -4,33 +4,85 @@
 #![allow(clippy::type_complexity)]
 
 use std::cmp;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crossbeam_channel::{bounded, select, Receiver, Sender};
 
-use crate::client::{dial, dial_tls, Client, ClientOption, RequestContext};
+use crate::client::{dial, dial_tls, with_resume_session, Client, ClientOption, RequestContext};
+use crate::context::ContextHead;
 use crate::error::{Error, Result};
+use crate::journal::{JournalOp, QueueJournal};
+use crate::trace::traced;
 
 pub const DEFAULT_MAX_RETRIES: usize = 5;
 pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
 pub const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
 pub const DEFAULT_QUEUE_SIZE: usize = 10_000;
+pub const DEFAULT_WORKER_CONCURRENCY: usize = 1;
 
 pub type DialFunc = Arc<dyn Fn() -> Result<Client> + Send + Sync>;
 
 pub type ReconnectOption = Arc<dyn Fn(&mut ReconnectConfig) + Send + Sync>;
 
+/// Details of a successful reconnect, passed to `with_reconnect_info` callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectInfo {
+    pub session_id: u64,
+    /// True when the server resumed the previous session, so `session_id` is
+    /// unchanged.
+    pub resumed: bool,
+    /// Dial attempts made, including the successful one.
+    pub attempts: usize,
+    /// Time from detecting the broken connection to re-establishing it.
+    pub downtime: Duration,
+}
+
+/// A reconnect attempt about to be made, passed to `with_backoff_observer`
+/// callbacks before the client waits out `delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffInfo<'a> {
+    /// 1-based attempt number.
+    pub attempt: usize,
+    pub max_attempts: usize,
+    /// How long the client waits before dialing; zero for the first attempt.
+    pub delay: Duration,
+    /// Why the previous attempt failed; None for the first attempt.
+    pub last_error: Option<&'a Error>,
+}
+
+pub type BackoffObserver = Arc<dyn Fn(&BackoffInfo<'_>) + Send + Sync>;
+
+/// Decides whether a failed call should reconnect and run again; see
+/// `with_should_reconnect`.
+pub type ReconnectPredicate = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
 #[derive(Clone)]
 pub struct ReconnectConfig {
     pub max_retries: usize,
     pub retry_delay: Duration,
     pub max_retry_delay: Duration,
     pub queue_size: usize,
+    /// Cap on the payload bytes of queued and running requests; see
+    /// `with_max_queued_bytes`.
+    pub max_queued_bytes: Option<usize>,
+    pub worker_concurrency: usize,
+    /// Requests per second and burst size; see `with_rate_limit`.
+    pub rate_limit: Option<(f64, usize)>,
     pub on_reconnect: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+    pub on_reconnect_info: Option<Arc<dyn Fn(&ReconnectInfo) + Send + Sync>>,
+    pub on_backoff: Option<BackoffObserver>,
+    /// Replaces `is_connection_error`; see `with_should_reconnect`.
+    pub should_reconnect: Option<ReconnectPredicate>,
     pub dial_func: Option<DialFunc>,
+    pub initial_dial_retry: bool,
+    pub session_resume: bool,
+    /// Directory for the queue journal; see `with_persistent_queue`.
+    pub persistent_queue: Option<PathBuf>,
 }
 
 impl Default for ReconnectConfig {
@@ -40,8 +92,17 @@ impl Default for ReconnectConfig {
             retry_delay: DEFAULT_RETRY_DELAY,
             max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
             queue_size: DEFAULT_QUEUE_SIZE,
+            max_queued_bytes: None,
+            worker_concurrency: DEFAULT_WORKER_CONCURRENCY,
+            rate_limit: None,
             on_reconnect: None,
+            on_reconnect_info: None,
+            on_backoff: None,
+            should_reconnect: None,
             dial_func: None,
+            initial_dial_retry: false,
+            session_resume: true,
+            persistent_queue: None,
         }
     }
 }
@@ -62,6 +123,41 @@ pub fn with_queue_size(size: usize) -> ReconnectOption {
     Arc::new(move |cfg| cfg.queue_size = size)
 }
 
+/// Bounds the queue by memory as well as by count: a call whose payload
+/// (the turn payload or blob data) would take the bytes held by queued and
+/// running requests past `bytes` fails with `Error::QueueFull`, like a call
+/// that finds the queue full. Bytes are returned once a request finishes.
+/// A single call larger than `bytes` is still accepted when nothing else is
+/// queued, so it can make progress. Unlimited by default.
+pub fn with_max_queued_bytes(bytes: usize) -> ReconnectOption {
+    Arc::new(move |cfg| cfg.max_queued_bytes = Some(bytes))
+}
+
+/// Lets up to `n` queued requests be in flight on the connection at once
+/// (default 1), so independent reads such as `get_last` and `get_head` are
+/// pipelined instead of waiting for each other's responses. Reads leave the
+/// queue in order but may complete out of order. Writes (appends, blob
+/// uploads, context creation, attachments) and `watch_head` stay on a
+/// single lane and run one at a time in the order they were queued, so two
+/// appends to a context are never reordered; reads may overtake them. When
+/// the connection fails, one worker reconnects while the others wait for it
+/// and then retry their requests on the new connection.
+pub fn with_worker_concurrency(n: usize) -> ReconnectOption {
+    Arc::new(move |cfg| cfg.worker_concurrency = n.max(1))
+}
+
+/// Limits this client to `per_sec` requests per second on average, with
+/// bursts of up to `burst`, as a safety valve against a runaway caller
+/// (e.g. an agent loop appending turns as fast as it can). A call made
+/// while the limit is exhausted blocks until it may proceed, or fails with
+/// `Error::Timeout` / `Error::Cancelled` if its context ends first. A
+/// non-positive rate disables the limit.
+pub fn with_rate_limit(per_sec: f64, burst: usize) -> ReconnectOption {
+    Arc::new(move |cfg| {
+        cfg.rate_limit = (per_sec > 0.0).then_some((per_sec, burst.max(1)));
+    })
+}
+
 pub fn with_on_reconnect<F>(f: F) -> ReconnectOption
 where
     F: Fn(u64) + Send + Sync + 'static,
@@ -70,36 +166,245 @@ where
     Arc::new(move |cfg| cfg.on_reconnect = Some(f.clone()))
 }
 
-#[cfg(test)]
-pub(crate) fn with_dial_func(func: DialFunc) -> ReconnectOption {
+/// Like `with_on_reconnect`, but also reports how many dial attempts the
+/// reconnect took and how long the client was disconnected.
+pub fn with_reconnect_info<F>(f: F) -> ReconnectOption
+where
+    F: Fn(&ReconnectInfo) + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    Arc::new(move |cfg| cfg.on_reconnect_info = Some(f.clone()))
+}
+
+/// Calls `f` at the start of every dial attempt made while reconnecting (or
+/// retrying the initial dial), with the attempt number, the backoff delay
+/// about to be slept and the previous attempt's error, so operators can log
+/// e.g. "reconnect attempt 3/5, next retry in 800ms".
+pub fn with_backoff_observer<F>(f: F) -> ReconnectOption
+where
+    F: Fn(&BackoffInfo<'_>) + Send + Sync + 'static,
+{
+    let f: BackoffObserver = Arc::new(f);
+    Arc::new(move |cfg| cfg.on_backoff = Some(f.clone()))
+}
+
+/// Overrides how the reconnecting client establishes connections.
+///
+/// The function is invoked for the initial dial and again on every reconnect
+/// attempt, so it can re-resolve the server address (DNS failover, service
+/// discovery) each time. When set, the `addr` passed to `dial_reconnecting`
+/// is ignored.
+pub fn with_dial_func(func: DialFunc) -> ReconnectOption {
     Arc::new(move |cfg| cfg.dial_func = Some(func.clone()))
 }
 
+/// Retries the initial dial with the configured backoff (up to
+/// `max_retries` attempts) instead of failing immediately, so a client
+/// created while the server is restarting waits for it to come up.
+pub fn with_initial_dial_retry(enabled: bool) -> ReconnectOption {
+    Arc::new(move |cfg| cfg.initial_dial_retry = enabled)
+}
+
+/// Whether reconnects ask the server to resume the previous session (on by
+/// default). A resumed session keeps its `session_id`, and the contexts it
+/// created stay attributed to it. Only the built-in dialer presents the
+/// prior id; a `with_dial_func` dialer can apply `with_resume_session` itself.
+pub fn with_session_resume(enabled: bool) -> ReconnectOption {
+    Arc::new(move |cfg| cfg.session_resume = enabled)
+}
+
+/// Journals `append_turn` and `put_blob` calls to `dir/queue.log` so that
+/// ones still unconfirmed when the process exits are sent again by the next
+/// `dial_reconnecting` given the same directory.
+///
+/// Each call is written and synced to the journal before it is queued, and
+/// marked done once the server has answered it (successfully or with a
+/// server error). Calls that end with a connection error, timeout or
+/// cancellation, or that were still queued when the process stopped, stay
+/// in the journal. On startup they are replayed in their original order
+/// before the client is returned; replay stops at the first connection
+/// error and leaves the rest for next time.
+///
+/// Delivery is at least once: a call the server applied just before a crash
+/// is sent again. A replayed append without an `idempotency_key` writes a
+/// second turn, so give appends one to make the replay harmless. Other
+/// operations are not journaled.
+///
+/// Only one process may use a directory at a time: dialing fails while
+/// another client holds it, as it does when the journal is corrupt
+/// anywhere but in its last record.
+pub fn with_persistent_queue(dir: impl Into<PathBuf>) -> ReconnectOption {
+    let dir = dir.into();
+    Arc::new(move |cfg| cfg.persistent_queue = Some(dir.clone()))
+}
+
+/// Decides which failed calls reconnect and run again, in place of
+/// `is_connection_error`. Use it to treat server errors such as an expired
+/// session as a reason to re-dial; call `is_connection_error` from `f` to
+/// keep the default cases as well. Errors `f` rejects are returned to the
+/// caller as is.
+pub fn with_should_reconnect<F>(f: F) -> ReconnectOption
+where
+    F: Fn(&Error) -> bool + Send + Sync + 'static,
+{
+    let f: ReconnectPredicate = Arc::new(f);
+    Arc::new(move |cfg| cfg.should_reconnect = Some(f.clone()))
+}
+
+impl ReconnectConfig {
+    /// The configured `should_reconnect`, or `is_connection_error`.
+    pub(crate) fn reconnect_predicate(&self) -> ReconnectPredicate {
+        self.should_reconnect
+            .clone()
+            .unwrap_or_else(|| Arc::new(is_connection_error))
+    }
+}
+
 pub struct ReconnectingClient {
     inner: Arc<Inner>,
-    worker: Mutex<Option<thread::JoinHandle<()>>>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
 }
 
 struct Inner {
     client: Mutex<Option<Arc<Client>>>,
     dial_func: DialFunc,
+    /// Session id and resume token of the most recent connection, presented
+    /// by the built-in dialer when `session_resume` is on.
+    last_session: LastSession,
 
     max_retries: usize,
     retry_delay: Duration,
     max_retry_delay: Duration,
     on_reconnect: Option<Arc<dyn Fn(u64) + Send + Sync>>,
-
+    on_reconnect_info: Option<Arc<dyn Fn(&ReconnectInfo) + Send + Sync>>,
+    on_backoff: Option<BackoffObserver>,
+    should_reconnect: ReconnectPredicate,
+    /// Held while reconnecting, so workers that hit the same broken
+    /// connection reconnect once between them.
+    reconnect_lock: Mutex<()>,
+    rate_limiter: Option<Mutex<TokenBucket>>,
+    /// Payload bytes held by queued and running requests.
+    queued_bytes: Arc<AtomicUsize>,
+    max_queued_bytes: Option<usize>,
+    pending: Arc<Pending>,
+    journal: Option<QueueJournal>,
+
+    /// Requests run one at a time, in order, by the first worker.
     queue_tx: Sender<QueuedRequest>,
     queue_rx: Receiver<QueuedRequest>,
+    /// Reads any worker may run; only used with more than one worker.
+    read_queue_tx: Sender<QueuedRequest>,
+    read_queue_rx: Receiver<QueuedRequest>,
+    concurrent_reads: bool,
     shutdown_tx: Sender<()>,
     shutdown_rx: Receiver<()>,
     closed: AtomicBool,
 }
 
+/// Refills at `rate` tokens per second up to `burst`; each request takes one.
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: usize) -> Self {
+        Self {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn try_take(&mut self) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
 struct QueuedRequest {
     ctx: RequestContext,
     op: Arc<dyn Fn(&Client) -> Result<()> + Send + Sync>,
     result_tx: Sender<Result<()>>,
+    bytes: QueuedBytes,
+    pending: PendingGuard,
+}
+
+impl QueuedRequest {
+    /// Reports `result` to the caller, releasing the request's bytes first
+    /// so a caller that sees the result also sees them freed. The request
+    /// stops counting as pending only after the result is sent, so `flush`
+    /// returns after every caller has its answer.
+    fn finish(self, result: Result<()>) {
+        let QueuedRequest {
+            result_tx,
+            bytes,
+            pending,
+            ..
+        } = self;
+        drop(bytes);
+        let _ = result_tx.send(result);
+        drop(pending);
+    }
+}
+
+/// Requests queued or running, for `flush`.
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// A request's place in `Inner::pending`, given up when it is dropped.
+#[derive(Default)]
+struct PendingGuard(Option<Arc<Pending>>);
+
+impl PendingGuard {
+    fn new(pending: &Arc<Pending>) -> Self {
+        *pending.count.lock().unwrap() += 1;
+        Self(Some(pending.clone()))
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.0 {
+            let mut count = pending.count.lock().unwrap();
+            *count -= 1;
+            if *count == 0 {
+                pending.idle.notify_all();
+            }
+        }
+    }
+}
+
+/// A request's share of `Inner::queued_bytes`, given back when it is
+/// dropped: once the request has run, or if it never makes it into the
+/// queue.
+#[derive(Default)]
+struct QueuedBytes {
+    counter: Option<Arc<AtomicUsize>>,
+    bytes: usize,
+}
+
+impl Drop for QueuedBytes {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.counter {
+            counter.fetch_sub(self.bytes, Ordering::SeqCst);
+        }
+    }
 }
 
 pub fn dial_reconnecting(
@@ -129,55 +434,169 @@ fn dial_reconnecting_inner(
         opt(&mut cfg);
     }
 
-    let options: Vec<ClientOption> = opts.into_iter().collect();
-
-    let dial_func: DialFunc = cfg.dial_func.clone().unwrap_or_else(|| {
-        let addr = addr.to_string();
-        let opts = options.clone();
-        Arc::new(move || {
-            if use_tls {
-                dial_tls(&addr, opts.clone())
-            } else {
-                dial(&addr, opts.clone())
-            }
-        })
+    let last_session = LastSession::default();
+    let dial_func = cfg.dial_func.clone().unwrap_or_else(|| {
+        default_dial_func(
+            addr,
+            use_tls,
+            opts,
+            cfg.session_resume,
+            last_session.clone(),
+        )
     });
 
+    let (journal, pending) = match &cfg.persistent_queue {
+        Some(dir) => {
+            let (journal, pending) = QueueJournal::open(dir)?;
+            (Some(journal), pending)
+        }
+        None => (None, Vec::new()),
+    };
+
     let (queue_tx, queue_rx) = bounded(cfg.queue_size);
-    let (shutdown_tx, shutdown_rx) = bounded(1);
+    let (read_queue_tx, read_queue_rx) = bounded(cfg.queue_size);
+    let (shutdown_tx, shutdown_rx) = bounded(cfg.worker_concurrency.max(1));
 
-    let client = Arc::new(dial_func()?);
+    let client = if cfg.initial_dial_retry {
+        Arc::new(initial_dial_with_retry(&dial_func, &cfg)?)
+    } else {
+        Arc::new(dial_func()?)
+    };
+    record_session(&last_session, &client);
 
     let inner = Arc::new(Inner {
         client: Mutex::new(Some(client)),
         dial_func: dial_func.clone(),
+        last_session,
         max_retries: cfg.max_retries,
         retry_delay: cfg.retry_delay,
         max_retry_delay: cfg.max_retry_delay,
         on_reconnect: cfg.on_reconnect.clone(),
+        on_reconnect_info: cfg.on_reconnect_info.clone(),
+        on_backoff: cfg.on_backoff.clone(),
+        should_reconnect: cfg.reconnect_predicate(),
+        reconnect_lock: Mutex::new(()),
+        rate_limiter: cfg
+            .rate_limit
+            .map(|(per_sec, burst)| Mutex::new(TokenBucket::new(per_sec, burst))),
+        queued_bytes: Arc::new(AtomicUsize::new(0)),
+        max_queued_bytes: cfg.max_queued_bytes,
+        pending: Arc::new(Pending::default()),
+        journal,
         queue_tx,
         queue_rx: queue_rx.clone(),
+        read_queue_tx,
+        read_queue_rx,
+        concurrent_reads: cfg.worker_concurrency > 1,
         shutdown_tx: shutdown_tx.clone(),
         shutdown_rx: shutdown_rx.clone(),
         closed: AtomicBool::new(false),
     });
 
-    let worker_inner = inner.clone();
-    let handle = thread::spawn(move || sender_loop(worker_inner));
+    let workers = (0..cfg.worker_concurrency.max(1))
+        .map(|index| {
+            let worker_inner = inner.clone();
+            thread::spawn(move || sender_loop(worker_inner, index == 0))
+        })
+        .collect();
 
-    Ok(ReconnectingClient {
+    let client = ReconnectingClient {
         inner,
-        worker: Mutex::new(Some(handle)),
+        workers: Mutex::new(workers),
+    };
+    client.replay_journal(pending);
+    Ok(client)
+}
+
+/// Session id and resume token of a client's most recent connection.
+pub(crate) type LastSession = Arc<Mutex<(u64, [u8; 16])>>;
+
+/// Remember `client`'s session for the next dial, returning the session id
+/// it replaces.
+pub(crate) fn record_session(last: &LastSession, client: &Client) -> u64 {
+    match last.lock() {
+        Ok(mut last) => {
+            std::mem::replace(&mut *last, (client.session_id(), client.resume_token())).0
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Dialer for `addr` used when no `with_dial_func` is given. With
+/// `session_resume`, each dial after the first asks to resume the session
+/// stored in `last_session`.
+pub(crate) fn default_dial_func(
+    addr: &str,
+    use_tls: bool,
+    opts: impl IntoIterator<Item = ClientOption>,
+    session_resume: bool,
+    last_session: LastSession,
+) -> DialFunc {
+    let addr = addr.to_string();
+    let opts: Vec<ClientOption> = opts.into_iter().collect();
+    Arc::new(move || {
+        let mut opts = opts.clone();
+        let (prior, token) = last_session.lock().map(|last| *last).unwrap_or_default();
+        if session_resume && prior != 0 {
+            opts.push(with_resume_session(prior, token));
+        }
+        if use_tls {
+            dial_tls(&addr, opts)
+        } else {
+            dial(&addr, opts)
+        }
     })
 }
 
+pub(crate) fn initial_dial_with_retry(
+    dial_func: &DialFunc,
+    cfg: &ReconnectConfig,
+) -> Result<Client> {
+    let mut delay = cfg.retry_delay;
+    let mut last_err: Option<Error> = None;
+
+    let max_attempts = cmp::max(cfg.max_retries, 1);
+    for attempt in 1..=max_attempts {
+        notify_backoff(
+            &cfg.on_backoff,
+            attempt,
+            max_attempts,
+            delay,
+            last_err.as_ref(),
+        );
+        if attempt > 1 {
+            thread::sleep(delay);
+            delay = cmp::min(delay * 2, cfg.max_retry_delay);
+        }
+        match dial_func() {
+            Ok(client) => return Ok(client),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or(Error::ClientClosed))
+}
+
 impl ReconnectingClient {
     pub fn close(&self) -> Result<()> {
         if self.inner.closed.swap(true, Ordering::SeqCst) {
             return Ok(());
         }
-        let _ = self.inner.shutdown_tx.send(());
-        if let Some(handle) = self.worker.lock().ok().and_then(|mut h| h.take()) {
+        // Wake `flush` callers; taking the lock orders this after their
+        // check of `closed`.
+        {
+            let _count = self.inner.pending.count.lock().unwrap();
+            self.inner.pending.idle.notify_all();
+        }
+        let workers = self
+            .workers
+            .lock()
+            .map(|mut w| std::mem::take(&mut *w))
+            .unwrap_or_default();
+        for _ in &workers {
+            let _ = self.inner.shutdown_tx.send(());
+        }
+        for handle in workers {
             let _ = handle.join();
         }
         if let Some(client) = self.inner.client.lock().ok().and_then(|mut c| c.take()) {
@@ -204,8 +623,67 @@ impl ReconnectingClient {
             .unwrap_or_default()
     }
 
+    /// Server address of the current connection; None while disconnected.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner
+            .client
+            .lock()
+            .ok()
+            .and_then(|c| c.as_ref().and_then(|client| client.peer_addr()))
+    }
+
+    /// Local address of the current connection; None while disconnected.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner
+            .client
+            .lock()
+            .ok()
+            .and_then(|c| c.as_ref().and_then(|client| client.local_addr()))
+    }
+
+    /// Runs `f` against the current connection, bypassing the queue and
+    /// reconnect handling; None while disconnected. Meant for `Client`
+    /// methods not yet mirrored here. The connection is not held locked
+    /// while `f` runs, so a reconnect may replace it in the meantime.
+    pub fn with_client<R>(&self, f: impl FnOnce(&Client) -> R) -> Option<R> {
+        let client = self.inner.client.lock().ok()?.as_ref().cloned()?;
+        Some(f(&client))
+    }
+
     pub fn queue_length(&self) -> usize {
-        self.inner.queue_rx.len()
+        self.inner.queue_rx.len() + self.inner.read_queue_rx.len()
+    }
+
+    /// Payload bytes held by queued and running requests, as counted
+    /// against `with_max_queued_bytes`.
+    pub fn queued_bytes(&self) -> usize {
+        self.inner.queued_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Blocks until every queued and running request has finished, without
+    /// closing the client. Requests enqueued while waiting are waited for
+    /// too. Fails with `Error::Timeout` if the queue is still busy after
+    /// `timeout`, and with `Error::ClientClosed` once the client is closed.
+    pub fn flush(&self, timeout: Duration) -> Result<()> {
+        if self.inner.closed.load(Ordering::SeqCst) {
+            return Err(Error::ClientClosed);
+        }
+        let pending = &self.inner.pending;
+        let closed = &self.inner.closed;
+        let count = pending.count.lock().unwrap();
+        let (_count, wait) = pending
+            .idle
+            .wait_timeout_while(count, timeout, |count| {
+                *count > 0 && !closed.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        if closed.load(Ordering::SeqCst) {
+            return Err(Error::ClientClosed);
+        }
+        if wait.timed_out() {
+            return Err(Error::Timeout);
+        }
+        Ok(())
     }
 
     pub fn create_context(
@@ -242,6 +720,39 @@ impl ReconnectingClient {
         Ok(value)
     }
 
+    /// `Client::health` through the queue, reconnecting if the connection
+    /// has dropped.
+    pub fn health(&self, ctx: &RequestContext) -> Result<crate::client::HealthStatus> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "Health", move |client| {
+            let status = client.health(&ctx_clone)?;
+            *result_clone.lock().unwrap() = Some(status);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
+    /// `Client::verify_snapshot` through the queue.
+    pub fn verify_snapshot(
+        &self,
+        ctx: &RequestContext,
+        fs_root_hash: [u8; 32],
+    ) -> Result<crate::fs::VerifyReport> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "VerifySnapshot", move |client| {
+            let report = client.verify_snapshot(&ctx_clone, fs_root_hash)?;
+            *result_clone.lock().unwrap() = Some(report);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
     pub fn get_head(
         &self,
         ctx: &RequestContext,
@@ -250,7 +761,7 @@ impl ReconnectingClient {
         let result = Arc::new(Mutex::new(None));
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "GetHead", move |client| {
+        self.enqueue_read(ctx, "GetHead", move |client| {
             let head = client.get_head(&ctx_clone, context_id)?;
             *result_clone.lock().unwrap() = Some(head);
             Ok(())
@@ -259,6 +770,59 @@ impl ReconnectingClient {
         Ok(value)
     }
 
+    pub fn list_contexts(
+        &self,
+        ctx: &RequestContext,
+        limit: u32,
+        after: u64,
+    ) -> Result<Vec<crate::context::ContextSummary>> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "ListContexts", move |client| {
+            let contexts = client.list_contexts(&ctx_clone, limit, after)?;
+            *result_clone.lock().unwrap() = Some(contexts);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
+    pub fn get_head_at(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        timestamp_ms: u64,
+    ) -> Result<crate::context::ContextHead> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "GetHeadAt", move |client| {
+            let head = client.get_head_at(&ctx_clone, context_id, timestamp_ms)?;
+            *result_clone.lock().unwrap() = Some(head);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
+    pub fn get_metadata(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+    ) -> Result<crate::types::ContextMetadata> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "GetMetadata", move |client| {
+            let metadata = client.get_metadata(&ctx_clone, context_id)?;
+            *result_clone.lock().unwrap() = Some(metadata);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
     pub fn append_turn(
         &self,
         ctx: &RequestContext,
@@ -266,13 +830,17 @@ impl ReconnectingClient {
     ) -> Result<crate::turn::AppendResult> {
         let result = Arc::new(Mutex::new(None));
         let req = req.clone();
+        let seq = self.journal_record(|| JournalOp::AppendTurn(req.clone()))?;
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "AppendTurn", move |client| {
+        let bytes = req.payload.len();
+        let outcome = self.enqueue_sized(ctx, "AppendTurn", bytes, move |client| {
             let res = client.append_turn(&ctx_clone, &req)?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
-        })?;
+        });
+        self.journal_settle(seq, &outcome);
+        outcome?;
         let value = result.lock().unwrap().take().unwrap();
         Ok(value)
     }
@@ -286,8 +854,26 @@ impl ReconnectingClient {
         let result = Arc::new(Mutex::new(None));
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "GetLast", move |client| {
-            let res = client.get_last(&ctx_clone, context_id, opts)?;
+        self.enqueue_read(ctx, "GetLast", move |client| {
+            let res = client.get_last(&ctx_clone, context_id, opts.clone())?;
+            *result_clone.lock().unwrap() = Some(res);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
+    pub fn get_last_page(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: crate::turn::GetLastOptions,
+    ) -> Result<crate::turn::TurnPage> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue_read(ctx, "GetLastPage", move |client| {
+            let res = client.get_last_page(&ctx_clone, context_id, opts.clone())?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
         })?;
@@ -313,6 +899,24 @@ impl ReconnectingClient {
         Ok(value)
     }
 
+    pub fn attach_fs_to_head(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        fs_root_hash: [u8; 32],
+    ) -> Result<crate::fs::AttachFsResult> {
+        let result = Arc::new(Mutex::new(None));
+        let ctx_clone = ctx.clone();
+        let result_clone = result.clone();
+        self.enqueue(ctx, "AttachFsToHead", move |client| {
+            let res = client.attach_fs_to_head(&ctx_clone, context_id, fs_root_hash)?;
+            *result_clone.lock().unwrap() = Some(res);
+            Ok(())
+        })?;
+        let value = result.lock().unwrap().take().unwrap();
+        Ok(value)
+    }
+
     pub fn put_blob(
         &self,
         ctx: &RequestContext,
@@ -320,13 +924,17 @@ impl ReconnectingClient {
     ) -> Result<crate::fs::PutBlobResult> {
         let result = Arc::new(Mutex::new(None));
         let req = req.clone();
+        let seq = self.journal_record(|| JournalOp::PutBlob(req.data.clone()))?;
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "PutBlob", move |client| {
+        let bytes = req.data.len();
+        let outcome = self.enqueue_sized(ctx, "PutBlob", bytes, move |client| {
             let res = client.put_blob(&ctx_clone, &req)?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
-        })?;
+        });
+        self.journal_settle(seq, &outcome);
+        outcome?;
         let value = result.lock().unwrap().take().unwrap();
         Ok(value)
     }
@@ -338,9 +946,10 @@ impl ReconnectingClient {
     ) -> Result<([u8; 32], bool)> {
         let result = Arc::new(Mutex::new(None));
         let ctx_clone = ctx.clone();
+        let bytes = data.len();
         let data = Arc::new(data);
         let result_clone = result.clone();
-        self.enqueue(ctx, "PutBlobIfAbsent", move |client| {
+        self.enqueue_sized(ctx, "PutBlobIfAbsent", bytes, move |client| {
             let res = client.put_blob_if_absent(&ctx_clone, (*data).clone())?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
@@ -359,7 +968,8 @@ impl ReconnectingClient {
         let req = req.clone();
         let ctx_clone = ctx.clone();
         let result_clone = result.clone();
-        self.enqueue(ctx, "AppendTurnWithFs", move |client| {
+        let bytes = req.payload.len();
+        self.enqueue_sized(ctx, "AppendTurnWithFs", bytes, move |client| {
             let res = client.append_turn_with_fs(&ctx_clone, &req, fs_root_hash)?;
             *result_clone.lock().unwrap() = Some(res);
             Ok(())
@@ -368,7 +978,146 @@ impl ReconnectingClient {
         Ok(value)
     }
 
-    fn enqueue<F>(&self, ctx: &RequestContext, _desc: &str, op: F) -> Result<()>
+    /// `Client::watch_head` that survives reconnects: when the connection
+    /// drops, the watch is re-established on the new connection and resumes
+    /// from the last head it delivered, so a long-lived watcher does not go
+    /// quiet. Turns appended while disconnected arrive as one update for the
+    /// latest head. Returns an error only once reconnecting fails.
+    ///
+    /// Like the plain client, the connection is dedicated to the watch while
+    /// it runs; queued requests wait until it returns.
+    pub fn watch_head(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        on_update: impl FnMut(ContextHead, crate::turn::TurnRecord) -> bool + Send + 'static,
+    ) -> Result<()> {
+        let state = Arc::new(Mutex::new((None, on_update)));
+        loop {
+            let subscribed = Arc::new(AtomicBool::new(false));
+            let ctx_clone = ctx.clone();
+            let state_clone = state.clone();
+            let subscribed_clone = subscribed.clone();
+            let result = self.enqueue(ctx, "WatchHead", move |client| {
+                let mut state = state_clone.lock().unwrap();
+                let (last_seen, on_update) = &mut *state;
+                let result = client.watch_head_from(&ctx_clone, context_id, last_seen, on_update);
+                if last_seen.is_some() {
+                    subscribed_clone.store(true, Ordering::SeqCst);
+                }
+                result
+            });
+            match result {
+                // The watch was live and then lost its connection; the next
+                // attempt reconnects and resubscribes.
+                Err(err)
+                    if (self.inner.should_reconnect)(&err) && subscribed.load(Ordering::SeqCst) =>
+                {
+                    continue
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Writes `op` to the persistent queue journal, if there is one, and
+    /// returns its sequence number there.
+    fn journal_record(&self, op: impl FnOnce() -> JournalOp) -> Result<Option<u64>> {
+        match &self.inner.journal {
+            Some(journal) => journal.record(&op()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Marks a journaled call done unless its outcome means it may not have
+    /// reached the server.
+    fn journal_settle(&self, seq: Option<u64>, outcome: &Result<()>) {
+        let (Some(journal), Some(seq)) = (&self.inner.journal, seq) else {
+            return;
+        };
+        if journal_settled(outcome) {
+            // If the ack cannot be written the call is replayed on the next
+            // start, which duplicates an append without an idempotency key.
+            let _ = journal.ack(seq);
+        }
+    }
+
+    /// Sends the calls a previous process left in the journal, in order.
+    fn replay_journal(&self, pending: Vec<(u64, JournalOp)>) {
+        let ctx = RequestContext::background();
+        for (seq, op) in pending {
+            let ctx_clone = ctx.clone();
+            let outcome = match op {
+                JournalOp::AppendTurn(req) => {
+                    let bytes = req.payload.len();
+                    self.enqueue_sized(&ctx, "ReplayAppendTurn", bytes, move |client| {
+                        client.append_turn(&ctx_clone, &req).map(|_| ())
+                    })
+                }
+                JournalOp::PutBlob(data) => {
+                    let req = crate::fs::PutBlobRequest { data };
+                    let bytes = req.data.len();
+                    self.enqueue_sized(&ctx, "ReplayPutBlob", bytes, move |client| {
+                        client.put_blob(&ctx_clone, &req).map(|_| ())
+                    })
+                }
+            };
+            self.journal_settle(Some(seq), &outcome);
+            if !journal_settled(&outcome) {
+                break;
+            }
+        }
+    }
+
+    fn enqueue<F>(&self, ctx: &RequestContext, desc: &str, op: F) -> Result<()>
+    where
+        F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
+    {
+        self.enqueue_sized(ctx, desc, 0, op)
+    }
+
+    /// `enqueue` for a request that changes nothing on the server, which
+    /// any worker may run alongside others.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn enqueue_read<F>(&self, ctx: &RequestContext, desc: &str, op: F) -> Result<()>
+    where
+        F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
+    {
+        traced(trace_span!("cxdb.enqueue", op = desc), || {
+            self.enqueue_inner(ctx, 0, true, op)
+        })
+    }
+
+    /// `enqueue` for a request carrying `bytes` of payload, counted against
+    /// `max_queued_bytes`.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn enqueue_sized<F>(&self, ctx: &RequestContext, desc: &str, bytes: usize, op: F) -> Result<()>
+    where
+        F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
+    {
+        traced(trace_span!("cxdb.enqueue", op = desc), || {
+            self.enqueue_inner(ctx, bytes, false, op)
+        })
+    }
+
+    /// Takes `bytes` from the queue's byte budget, or fails with
+    /// `Error::QueueFull` if they do not fit.
+    fn reserve_bytes(&self, bytes: usize) -> Result<QueuedBytes> {
+        let counter = &self.inner.queued_bytes;
+        let max = self.inner.max_queued_bytes;
+        counter
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                let fits = max.is_none_or(|max| queued == 0 || queued.saturating_add(bytes) <= max);
+                fits.then(|| queued.saturating_add(bytes))
+            })
+            .map_err(|_| Error::QueueFull)?;
+        Ok(QueuedBytes {
+            counter: Some(counter.clone()),
+            bytes,
+        })
+    }
+
+    fn enqueue_inner<F>(&self, ctx: &RequestContext, bytes: usize, read: bool, op: F) -> Result<()>
     where
         F: Fn(&Client) -> Result<()> + Send + Sync + 'static,
     {
@@ -383,15 +1132,36 @@ impl ReconnectingClient {
                 return Err(Error::Timeout);
             }
         }
+        if let Some(limiter) = &self.inner.rate_limiter {
+            loop {
+                let wait = match limiter.lock() {
+                    Ok(mut bucket) => bucket.try_take(),
+                    Err(_) => return Err(Error::ClientClosed),
+                };
+                match wait {
+                    Ok(()) => break,
+                    Err(wait) => sleep_with_cancel(wait, ctx, &self.inner.closed)?,
+                }
+            }
+        }
 
+        let bytes = self.reserve_bytes(bytes)?;
         let (result_tx, result_rx) = bounded(1);
         let req = QueuedRequest {
             ctx: ctx.clone(),
             op: Arc::new(op),
             result_tx,
+            bytes,
+            pending: PendingGuard::new(&self.inner.pending),
         };
 
-        match self.inner.queue_tx.try_send(req) {
+        // With a single worker everything shares one queue, in order.
+        let queue = if read && self.inner.concurrent_reads {
+            &self.inner.read_queue_tx
+        } else {
+            &self.inner.queue_tx
+        };
+        match queue.try_send(req) {
             Ok(_) => {}
             Err(_) => return Err(Error::QueueFull),
         }
@@ -400,14 +1170,25 @@ impl ReconnectingClient {
     }
 }
 
-fn sender_loop(inner: Arc<Inner>) {
+/// Runs queued requests until shutdown. Only the `ordered` worker takes
+/// requests from the in-order queue; every worker takes reads.
+fn sender_loop(inner: Arc<Inner>, ordered: bool) {
+    let never = crossbeam_channel::never();
+    let queue_rx = if ordered { &inner.queue_rx } else { &never };
     loop {
         select! {
             recv(inner.shutdown_rx) -> _ => {
                 drain_queue(&inner, Error::ClientClosed);
                 break;
             }
-            recv(inner.queue_rx) -> msg => {
+            recv(queue_rx) -> msg => {
+                let req = match msg {
+                    Ok(req) => req,
+                    Err(_) => break,
+                };
+                process_request(&inner, req);
+            }
+            recv(inner.read_queue_rx) -> msg => {
                 let req = match msg {
                     Ok(req) => req,
                     Err(_) => break,
@@ -420,24 +1201,20 @@ fn sender_loop(inner: Arc<Inner>) {
 
 fn process_request(inner: &Arc<Inner>, req: QueuedRequest) {
     if req.ctx.is_cancelled() {
-        let _ = req.result_tx.send(Err(Error::Cancelled));
+        req.finish(Err(Error::Cancelled));
         return;
     }
     if let Some(deadline) = req.ctx.deadline() {
         if deadline <= Instant::now() {
-            let _ = req.result_tx.send(Err(Error::Timeout));
+            req.finish(Err(Error::Timeout));
             return;
         }
     }
 
-    let client = match inner.client.lock() {
-        Ok(guard) => guard.as_ref().cloned(),
-        Err(_) => None,
-    };
-    let client = match client {
+    let client = match current_client(inner) {
         Some(client) => client,
         None => {
-            let _ = req.result_tx.send(Err(Error::ClientClosed));
+            req.finish(Err(Error::ClientClosed));
             return;
         }
     };
@@ -445,28 +1222,80 @@ fn process_request(inner: &Arc<Inner>, req: QueuedRequest) {
     let op = req.op.clone();
     let mut err = (op)(&client);
     if let Err(ref e) = err {
-        if is_connection_error(e) {
-            if let Err(reconn_err) = reconnect(inner, &req.ctx) {
-                err = Err(reconn_err);
-            } else {
-                let client = inner.client.lock().ok().and_then(|c| c.as_ref().cloned());
-                if let Some(client) = client {
-                    err = (op)(&client);
-                }
+        if (inner.should_reconnect)(e) {
+            match recover(inner, &client, &req.ctx, Instant::now()) {
+                Ok(client) => err = (op)(&client),
+                Err(reconn_err) => err = Err(reconn_err),
             }
         }
     }
 
-    let _ = req.result_tx.send(err);
+    req.finish(err);
+}
+
+/// The live connection, waiting out a reconnect another worker has in
+/// progress.
+fn current_client(inner: &Arc<Inner>) -> Option<Arc<Client>> {
+    if let Some(client) = inner.client.lock().ok().and_then(|c| c.as_ref().cloned()) {
+        return Some(client);
+    }
+    let _reconnecting = inner.reconnect_lock.lock().ok()?;
+    inner.client.lock().ok().and_then(|c| c.as_ref().cloned())
 }
 
-fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
+/// Returns a connection to retry on after `failed` hit a connection error.
+/// The first worker to get here reconnects, which closes `failed` and so
+/// fails any other requests still in flight on it; those workers then find
+/// the new connection already in place.
+fn recover(
+    inner: &Arc<Inner>,
+    failed: &Arc<Client>,
+    ctx: &RequestContext,
+    disconnected_at: Instant,
+) -> Result<Arc<Client>> {
+    let _reconnecting = inner
+        .reconnect_lock
+        .lock()
+        .map_err(|_| Error::ClientClosed)?;
+    let current = inner.client.lock().ok().and_then(|c| c.as_ref().cloned());
+    if let Some(client) = current {
+        if !Arc::ptr_eq(&client, failed) {
+            return Ok(client);
+        }
+    }
+    reconnect(inner, ctx, disconnected_at)?;
+    inner
+        .client
+        .lock()
+        .ok()
+        .and_then(|c| c.as_ref().cloned())
+        .ok_or(Error::ClientClosed)
+}
+
+fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext, disconnected_at: Instant) -> Result<()> {
+    traced(trace_span!("cxdb.reconnect"), || {
+        reconnect_inner(inner, ctx, disconnected_at)
+    })
+}
+
+fn reconnect_inner(
+    inner: &Arc<Inner>,
+    ctx: &RequestContext,
+    disconnected_at: Instant,
+) -> Result<()> {
     let mut delay = inner.retry_delay;
     let mut last_err: Option<Error> = None;
 
     for attempt in 1..=inner.max_retries {
+        notify_backoff(
+            &inner.on_backoff,
+            attempt,
+            inner.max_retries,
+            delay,
+            last_err.as_ref(),
+        );
         if attempt > 1 {
-            sleep_with_cancel(delay, ctx, inner)?;
+            sleep_with_cancel(delay, ctx, &inner.closed)?;
             delay = cmp::min(delay * 2, inner.max_retry_delay);
         }
 
@@ -484,15 +1313,34 @@ fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
             Ok(client) => {
                 let client = Arc::new(client);
                 let session_id = client.session_id();
+                let prior = record_session(&inner.last_session, &client);
+                let resumed = prior != 0 && prior == session_id;
+                let downtime = disconnected_at.elapsed();
+                trace_event!(
+                    attempt,
+                    session_id,
+                    resumed,
+                    downtime_ms = downtime.as_millis() as u64,
+                    "reconnected"
+                );
                 if let Ok(mut guard) = inner.client.lock() {
                     *guard = Some(client);
                 }
                 if let Some(cb) = &inner.on_reconnect {
                     cb(session_id);
                 }
+                if let Some(cb) = &inner.on_reconnect_info {
+                    cb(&ReconnectInfo {
+                        session_id,
+                        resumed,
+                        attempts: attempt,
+                        downtime,
+                    });
+                }
                 return Ok(());
             }
             Err(err) => {
+                trace_event!(attempt, error = %err, "reconnect attempt failed");
                 last_err = Some(err);
             }
         }
@@ -501,11 +1349,34 @@ fn reconnect(inner: &Arc<Inner>, ctx: &RequestContext) -> Result<()> {
     Err(last_err.unwrap_or(Error::ClientClosed))
 }
 
-fn sleep_with_cancel(duration: Duration, ctx: &RequestContext, inner: &Arc<Inner>) -> Result<()> {
+/// Reports an upcoming attempt to a `with_backoff_observer` callback. `delay`
+/// is the backoff the loop sleeps before attempts after the first.
+pub(crate) fn notify_backoff(
+    observer: &Option<BackoffObserver>,
+    attempt: usize,
+    max_attempts: usize,
+    delay: Duration,
+    last_error: Option<&Error>,
+) {
+    if let Some(cb) = observer {
+        cb(&BackoffInfo {
+            attempt,
+            max_attempts,
+            delay: if attempt > 1 { delay } else { Duration::ZERO },
+            last_error,
+        });
+    }
+}
+
+pub(crate) fn sleep_with_cancel(
+    duration: Duration,
+    ctx: &RequestContext,
+    closed: &AtomicBool,
+) -> Result<()> {
     let start = Instant::now();
     let step = Duration::from_millis(50);
     while start.elapsed() < duration {
-        if inner.closed.load(Ordering::SeqCst) {
+        if closed.load(Ordering::SeqCst) {
             return Err(Error::ClientClosed);
         }
         if ctx.is_cancelled() {
@@ -525,7 +1396,10 @@ fn sleep_with_cancel(duration: Duration, ctx: &RequestContext, inner: &Arc<Inner
 
 fn drain_queue(inner: &Arc<Inner>, _err: Error) {
     while let Ok(req) = inner.queue_rx.try_recv() {
-        let _ = req.result_tx.send(Err(Error::ClientClosed));
+        req.finish(Err(Error::ClientClosed));
+    }
+    while let Ok(req) = inner.read_queue_rx.try_recv() {
+        req.finish(Err(Error::ClientClosed));
     }
 }
 
@@ -573,6 +1447,16 @@ fn wait_for_result(result_rx: &Receiver<Result<()>>, ctx: &RequestContext) -> Re
     }
 }
 
+/// Whether a journaled call's outcome shows the server answered it.
+fn journal_settled(outcome: &Result<()>) -> bool {
+    match outcome {
+        Ok(()) => true,
+        Err(Error::Server(_)) | Err(Error::PayloadTooLarge { .. }) => true,
+        Err(err @ Error::InvalidResponse(_)) => !is_connection_error(err),
+        Err(_) => false,
+    }
+}
+
 pub fn is_connection_error(err: &Error) -> bool {
     match err {
         Error::ClientClosed => false,
@@ -580,6 +1464,9 @@ pub fn is_connection_error(err: &Error) -> bool {
         Error::Timeout => false,
         Error::Cancelled => false,
         Error::QueueFull => false,
+        Error::PayloadTooLarge { .. } => false,
+        Error::NotReady { .. } => false,
+        Error::ReadOnly => false,
         Error::Io(io_err) => match io_err.kind() {
             std::io::ErrorKind::ConnectionReset
             | std::io::ErrorKind::ConnectionAborted
@@ -619,7 +1506,8 @@ fn contains_connection_pattern(msg: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::{read_frame, write_frame, MSG_HELLO};
+    use crate::protocol::{read_frame, write_frame};
+    use crate::test_util::{accept_hello, hello_response, mock_server};
     use byteorder::{LittleEndian, WriteBytesExt};
     use std::net::TcpListener;
     use std::sync::{
@@ -630,20 +1518,11 @@ mod tests {
     use std::time::Duration;
 
     fn start_hello_server() -> (String, mpsc::Sender<()>, thread::JoinHandle<()>) {
-        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-        let addr = listener.local_addr().unwrap();
         let (stop_tx, stop_rx) = mpsc::channel();
-        let handle = thread::spawn(move || {
-            let (mut stream, _) = listener.accept().unwrap();
-            let frame = read_frame(&mut stream).unwrap();
-            assert_eq!(frame.header.msg_type, MSG_HELLO);
-            let mut resp = Vec::new();
-            resp.write_u64::<LittleEndian>(1).unwrap();
-            resp.write_u16::<LittleEndian>(1).unwrap();
-            write_frame(&mut stream, MSG_HELLO, 0, frame.header.req_id, &resp).unwrap();
+        let (addr, handle) = mock_server(move |_| {
             let _ = stop_rx.recv();
         });
-        (addr.to_string(), stop_tx, handle)
+        (addr, stop_tx, handle)
     }
 
     #[test]
@@ -671,6 +1550,73 @@ mod tests {
         ))));
     }
 
+    #[test]
+    fn with_client_runs_against_current_connection() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let client = dial_reconnecting(&addr, Vec::<ReconnectOption>::new(), Vec::new()).unwrap();
+
+        assert_eq!(client.with_client(|c| c.session_id()), Some(1));
+        assert_eq!(
+            client.with_client(|c| c.peer_addr()).flatten(),
+            client.peer_addr()
+        );
+
+        client.close().unwrap();
+        assert_eq!(client.with_client(|c| c.session_id()), None);
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn max_queued_bytes_rejects_calls_over_budget() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let client = Arc::new(
+            dial_reconnecting(
+                &addr,
+                vec![with_max_queued_bytes(100)],
+                Vec::<ClientOption>::new(),
+            )
+            .unwrap(),
+        );
+
+        let start_barrier = Arc::new(Barrier::new(2));
+        let release_barrier = Arc::new(Barrier::new(2));
+        let client_clone = client.clone();
+        let start_barrier_clone = start_barrier.clone();
+        let release_barrier_clone = release_barrier.clone();
+        let first = thread::spawn(move || {
+            client_clone
+                .enqueue_sized(&RequestContext::background(), "block", 80, move |_| {
+                    start_barrier_clone.wait();
+                    release_barrier_clone.wait();
+                    Ok(())
+                })
+                .unwrap();
+        });
+
+        start_barrier.wait();
+        assert_eq!(client.queued_bytes(), 80);
+        let err = client
+            .enqueue_sized(&RequestContext::background(), "overflow", 30, |_| Ok(()))
+            .unwrap_err();
+        assert!(matches!(err, Error::QueueFull));
+        assert_eq!(client.queued_bytes(), 80);
+
+        release_barrier.wait();
+        first.join().unwrap();
+        assert_eq!(client.queued_bytes(), 0);
+
+        // Alone in the queue, a call larger than the whole budget still runs.
+        client
+            .enqueue_sized(&RequestContext::background(), "large", 500, |_| Ok(()))
+            .unwrap();
+        assert_eq!(client.queued_bytes(), 0);
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
     #[test]
     fn queue_full_returns_error() {
         let (addr, stop_tx, handle) = start_hello_server();
@@ -712,6 +1658,8 @@ mod tests {
             ctx: RequestContext::background(),
             op: Arc::new(|_| Ok(())),
             result_tx: queued_tx,
+            bytes: QueuedBytes::default(),
+            pending: PendingGuard::default(),
         };
         client.inner.queue_tx.try_send(queued_req).unwrap();
 
@@ -725,6 +1673,91 @@ mod tests {
         first.join().unwrap();
         let _ = queued_rx.recv();
         client.close().unwrap();
+        assert_eq!(client.peer_addr(), None);
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn flush_waits_for_running_requests() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let client =
+            Arc::new(dial_reconnecting(&addr, Vec::new(), Vec::<ClientOption>::new()).unwrap());
+        client.flush(Duration::from_millis(10)).unwrap();
+
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let release_rx = Mutex::new(release_rx);
+        let done = Arc::new(AtomicBool::new(false));
+        let client_clone = client.clone();
+        let done_clone = done.clone();
+        let call = thread::spawn(move || {
+            client_clone
+                .enqueue(&RequestContext::background(), "block", move |_| {
+                    started_tx.lock().unwrap().send(()).unwrap();
+                    release_rx.lock().unwrap().recv().unwrap();
+                    done_clone.store(true, AtomicOrdering::SeqCst);
+                    Ok(())
+                })
+                .unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        let err = client.flush(Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+
+        release_tx.send(()).unwrap();
+        client.flush(Duration::from_secs(5)).unwrap();
+        assert!(done.load(AtomicOrdering::SeqCst));
+        call.join().unwrap();
+
+        client.close().unwrap();
+        assert!(matches!(
+            client.flush(Duration::from_millis(10)),
+            Err(Error::ClientClosed)
+        ));
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn close_wakes_a_waiting_flush() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let client =
+            Arc::new(dial_reconnecting(&addr, Vec::new(), Vec::<ClientOption>::new()).unwrap());
+
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let release_rx = Mutex::new(release_rx);
+        let client_clone = client.clone();
+        let call = thread::spawn(move || {
+            let _ = client_clone.enqueue(&RequestContext::background(), "block", move |_| {
+                started_tx.lock().unwrap().send(()).unwrap();
+                release_rx.lock().unwrap().recv().unwrap();
+                Ok(())
+            });
+        });
+        started_rx.recv().unwrap();
+
+        let client_clone = client.clone();
+        let flush = thread::spawn(move || {
+            let started = Instant::now();
+            let result = client_clone.flush(Duration::from_secs(30));
+            (result, started.elapsed())
+        });
+        thread::sleep(Duration::from_millis(20));
+        // `close` waits for the blocked request, but `flush` returns first.
+        let client_clone = client.clone();
+        let close = thread::spawn(move || client_clone.close());
+        let (result, waited) = flush.join().unwrap();
+        assert!(matches!(result, Err(Error::ClientClosed)), "{result:?}");
+        assert!(waited < Duration::from_secs(5), "{waited:?}");
+
+        release_tx.send(()).unwrap();
+        close.join().unwrap().unwrap();
+        call.join().unwrap();
         let _ = stop_tx.send(());
         handle.join().unwrap();
     }
@@ -745,6 +1778,11 @@ mod tests {
             )
             .unwrap(),
         );
+        assert_eq!(
+            client.peer_addr().map(|a| a.to_string()),
+            Some(addr.clone())
+        );
+        assert!(client.local_addr().is_some());
 
         let started = Arc::new(Barrier::new(2));
         let release = Arc::new(Barrier::new(2));
@@ -769,6 +1807,8 @@ mod tests {
             ctx: RequestContext::background(),
             op: Arc::new(|_| Ok(())),
             result_tx: queued_tx,
+            bytes: QueuedBytes::default(),
+            pending: PendingGuard::default(),
         };
         client.inner.queue_tx.try_send(queued_req).unwrap();
         thread::sleep(Duration::from_millis(10));
@@ -782,6 +1822,197 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn should_reconnect_retries_selected_server_errors() {
+        use crate::error::is_server_error;
+        use crate::protocol::{MSG_ERROR, MSG_GET_HEAD};
+        use crate::test_util::head_payload;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // First connection: the session has expired.
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let req = read_frame(&mut stream).unwrap();
+            let msg = b"session expired";
+            let mut resp = Vec::new();
+            resp.write_u32::<LittleEndian>(440).unwrap();
+            resp.write_u32::<LittleEndian>(msg.len() as u32).unwrap();
+            resp.extend_from_slice(msg);
+            write_frame(&mut stream, MSG_ERROR, 0, req.header.req_id, &resp).unwrap();
+
+            // Second connection answers.
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+            let resp = head_payload(1, 10, 1);
+            write_frame(&mut stream, MSG_GET_HEAD, 0, req.header.req_id, &resp).unwrap();
+        });
+
+        let client = dial_reconnecting(
+            &addr,
+            vec![
+                with_retry_delay(Duration::from_millis(10)),
+                with_should_reconnect(|err| is_server_error(err, 440) || is_connection_error(err)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        let head = client
+            .get_head(&RequestContext::with_timeout(Duration::from_secs(5)), 1)
+            .unwrap();
+        assert_eq!(head.head_turn_id, 10);
+
+        client.close().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn worker_concurrency_pipelines_requests_and_shares_reconnect() {
+        use crate::protocol::MSG_GET_HEAD;
+        use crate::test_util::head_payload;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // First connection: both requests arrive before either is
+            // answered, then the connection drops.
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let _ = read_frame(&mut stream).unwrap();
+            let _ = read_frame(&mut stream).unwrap();
+            drop(stream);
+
+            // Second connection: answer both retries, newest first.
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let first = read_frame(&mut stream).unwrap();
+            let second = read_frame(&mut stream).unwrap();
+            for req in [second, first] {
+                assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+                let context_id = u64::from_le_bytes(req.payload[..8].try_into().unwrap());
+                let resp = head_payload(context_id, context_id * 10, 1);
+                write_frame(&mut stream, MSG_GET_HEAD, 0, req.header.req_id, &resp).unwrap();
+            }
+        });
+
+        let dials = Arc::new(AtomicUsize::new(0));
+        let dial_func: DialFunc = Arc::new({
+            let addr = addr.clone();
+            let dials = dials.clone();
+            move || {
+                dials.fetch_add(1, AtomicOrdering::SeqCst);
+                dial(&addr, Vec::<ClientOption>::new())
+            }
+        });
+        let client = Arc::new(
+            dial_reconnecting_inner(
+                &addr,
+                false,
+                vec![
+                    with_dial_func(dial_func),
+                    with_worker_concurrency(2),
+                    with_retry_delay(Duration::from_millis(10)),
+                ],
+                Vec::<ClientOption>::new(),
+            )
+            .unwrap(),
+        );
+
+        let calls: Vec<_> = [1u64, 2]
+            .into_iter()
+            .map(|context_id| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    let ctx = RequestContext::with_timeout(Duration::from_secs(5));
+                    client.get_head(&ctx, context_id).unwrap()
+                })
+            })
+            .collect();
+        for (call, context_id) in calls.into_iter().zip([1u64, 2]) {
+            let head = call.join().unwrap();
+            assert_eq!(head.context_id, context_id);
+            assert_eq!(head.head_turn_id, context_id * 10);
+        }
+        assert_eq!(dials.load(AtomicOrdering::SeqCst), 2);
+
+        server.join().unwrap();
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn worker_concurrency_keeps_writes_in_order() {
+        use crate::protocol::MSG_APPEND_TURN;
+        use crate::turn::AppendRequest;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let first = read_frame(&mut stream).unwrap();
+            // The second append must not be sent while the first is open.
+            stream
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            assert!(read_frame(&mut stream).is_err(), "writes were pipelined");
+            stream.set_read_timeout(None).unwrap();
+            let mut payloads = Vec::new();
+            let mut req = first;
+            for _ in 0..2 {
+                assert_eq!(req.header.msg_type, MSG_APPEND_TURN);
+                payloads.push(req.payload.clone());
+                write_frame(
+                    &mut stream,
+                    MSG_APPEND_TURN,
+                    0,
+                    req.header.req_id,
+                    &[0u8; 52],
+                )
+                .unwrap();
+                if payloads.len() < 2 {
+                    req = read_frame(&mut stream).unwrap();
+                }
+            }
+            payloads
+        });
+
+        let client = Arc::new(
+            dial_reconnecting(
+                &addr,
+                vec![with_worker_concurrency(4)],
+                Vec::<ClientOption>::new(),
+            )
+            .unwrap(),
+        );
+        let appends: Vec<_> = [b"first".to_vec(), b"second".to_vec()]
+            .into_iter()
+            .map(|payload| {
+                let client = client.clone();
+                let handle = thread::spawn(move || {
+                    let req = AppendRequest::new(7, "test.Turn", 1, payload);
+                    client
+                        .append_turn(&RequestContext::background(), &req)
+                        .unwrap();
+                });
+                thread::sleep(Duration::from_millis(50));
+                handle
+            })
+            .collect();
+        for append in appends {
+            append.join().unwrap();
+        }
+
+        let payloads = server.join().unwrap();
+        assert!(contains(&payloads[0], b"first"));
+        assert!(contains(&payloads[1], b"second"));
+        client.close().unwrap();
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
     #[test]
     fn concurrent_enqueues_succeed() {
         let (addr, stop_tx, handle) = start_hello_server();
@@ -821,6 +2052,36 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn rate_limit_delays_requests_past_burst() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let client = dial_reconnecting_inner(
+            &addr,
+            false,
+            vec![with_rate_limit(20.0, 2)],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        for _ in 0..4 {
+            client
+                .enqueue(&RequestContext::background(), "noop", |_| Ok(()))
+                .unwrap();
+        }
+        // Two go out at once; the next two wait ~50ms each for a token.
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        // A context that ends before the next token arrives gives up.
+        let ctx = RequestContext::with_timeout(Duration::from_millis(5));
+        let err = client.enqueue(&ctx, "noop", |_| Ok(())).unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
     #[test]
     fn enqueue_after_close_returns_client_closed() {
         let (addr, stop_tx, handle) = start_hello_server();
@@ -902,6 +2163,261 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn dial_func_is_called_on_each_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = accept_hello(&listener, streams.len() as u64 + 1);
+                streams.push(stream);
+            }
+            let _ = stop_rx.recv();
+        });
+
+        let dial_count = Arc::new(AtomicUsize::new(0));
+        let dial_func: DialFunc = Arc::new({
+            let addr = addr.clone();
+            let dial_count = dial_count.clone();
+            move || {
+                dial_count.fetch_add(1, AtomicOrdering::SeqCst);
+                dial(&addr, Vec::<ClientOption>::new())
+            }
+        });
+
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let infos_clone = infos.clone();
+        let client = dial_reconnecting(
+            "unused:0",
+            vec![
+                with_dial_func(dial_func),
+                with_reconnect_info(move |info| infos_clone.lock().unwrap().push(*info)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+        assert_eq!(dial_count.load(AtomicOrdering::SeqCst), 1);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        client
+            .enqueue(&RequestContext::background(), "reconnect-once", move |_| {
+                if calls_clone.fetch_add(1, AtomicOrdering::SeqCst) == 0 {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionReset,
+                        "reset",
+                    )));
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(dial_count.load(AtomicOrdering::SeqCst), 2);
+        assert_eq!(client.session_id(), 2);
+
+        let infos = infos.lock().unwrap().clone();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].session_id, 2);
+        assert_eq!(infos[0].attempts, 1);
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reconnect_resumes_session_and_rewatches_head() {
+        use crate::protocol::{MSG_GET_HEAD, MSG_GET_LAST, MSG_WATCH_HEAD, WATCH_FLAG_UPDATE};
+        use crate::test_util::{head_payload, page_payload, update_payload};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // First connection: fresh session, one update, then the link dies.
+            let (mut stream, hello) = accept_hello(&listener, 5);
+            let hello = hello.payload;
+            assert_eq!(hello.len(), 8, "first HELLO must not ask to resume");
+            let watch = read_frame(&mut stream).unwrap();
+            assert_eq!(watch.header.msg_type, MSG_WATCH_HEAD);
+            let req_id = watch.header.req_id;
+            write_frame(
+                &mut stream,
+                MSG_WATCH_HEAD,
+                0,
+                req_id,
+                &head_payload(7, 1, 0),
+            )
+            .unwrap();
+            let update = update_payload(7, 2, 1, b"first");
+            write_frame(
+                &mut stream,
+                MSG_WATCH_HEAD,
+                WATCH_FLAG_UPDATE,
+                req_id,
+                &update,
+            )
+            .unwrap();
+            drop(stream);
+
+            // Second connection presents session 5; the head moved to turn 3
+            // while disconnected, so the client catches up before resubscribing.
+            let (mut stream, hello) = accept_hello(&listener, 5);
+            let hello = hello.payload;
+            assert_eq!(hello[8..16], 5u64.to_le_bytes());
+            assert_eq!(
+                hello[16..],
+                hello_response(5)[11..],
+                "resume must present the token"
+            );
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+            let head = head_payload(7, 3, 2);
+            write_frame(&mut stream, MSG_GET_HEAD, 0, req.header.req_id, &head).unwrap();
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_LAST);
+            let page = page_payload(3, 2, b"second");
+            write_frame(&mut stream, MSG_GET_LAST, 0, req.header.req_id, &page).unwrap();
+            // Client closes once the callback stops.
+            let _ = read_frame(&mut stream);
+        });
+
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let infos_clone = infos.clone();
+        let client = dial_reconnecting(
+            &addr,
+            vec![with_reconnect_info(move |info| {
+                infos_clone.lock().unwrap().push(*info)
+            })],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+        assert_eq!(client.session_id(), 5);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        client
+            .watch_head(&RequestContext::background(), 7, move |head, turn| {
+                seen_clone
+                    .lock()
+                    .unwrap()
+                    .push((head.head_turn_id, turn.payload));
+                head.head_turn_id < 3
+            })
+            .unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(2, b"first".to_vec()), (3, b"second".to_vec())]
+        );
+        assert_eq!(client.session_id(), 5);
+        let infos = infos.lock().unwrap().clone();
+        assert_eq!(infos.len(), 1);
+        assert!(infos[0].resumed);
+
+        client.close().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn initial_dial_retry_waits_for_server() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let dial_func: DialFunc = Arc::new({
+            let attempts = attempts.clone();
+            move || {
+                if attempts.fetch_add(1, AtomicOrdering::SeqCst) < 2 {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "refused",
+                    )));
+                }
+                dial(&addr, Vec::<ClientOption>::new())
+            }
+        });
+
+        let err = dial_reconnecting(
+            "unused:0",
+            vec![with_dial_func(dial_func.clone())],
+            Vec::<ClientOption>::new(),
+        )
+        .err()
+        .expect("fail fast without initial retry");
+        assert!(is_connection_error(&err));
+        assert_eq!(attempts.load(AtomicOrdering::SeqCst), 1);
+
+        let client = dial_reconnecting(
+            "unused:0",
+            vec![
+                with_dial_func(dial_func),
+                with_initial_dial_retry(true),
+                with_retry_delay(Duration::from_millis(5)),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+        assert_eq!(attempts.load(AtomicOrdering::SeqCst), 3);
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn backoff_observer_reports_each_attempt() {
+        let (addr, stop_tx, handle) = start_hello_server();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let dial_func: DialFunc = Arc::new({
+            let attempts = attempts.clone();
+            move || {
+                if attempts.fetch_add(1, AtomicOrdering::SeqCst) < 2 {
+                    return Err(Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "refused",
+                    )));
+                }
+                dial(&addr, Vec::<ClientOption>::new())
+            }
+        });
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let client = dial_reconnecting(
+            "unused:0",
+            vec![
+                with_dial_func(dial_func),
+                with_initial_dial_retry(true),
+                with_max_retries(4),
+                with_retry_delay(Duration::from_millis(5)),
+                with_max_retry_delay(Duration::from_millis(8)),
+                with_backoff_observer(move |info| {
+                    seen_clone.lock().unwrap().push((
+                        info.attempt,
+                        info.max_attempts,
+                        info.delay,
+                        info.last_error.is_some(),
+                    ))
+                }),
+            ],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+
+        // The second delay doubles to 10ms and is capped at 8ms.
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (1, 4, Duration::ZERO, false),
+                (2, 4, Duration::from_millis(5), true),
+                (3, 4, Duration::from_millis(8), true),
+            ]
+        );
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        handle.join().unwrap();
+    }
+
     #[test]
     fn queue_full_returns_error_legacy() {
         let dial_func: DialFunc = Arc::new(|| Err(Error::ClientClosed));
@@ -913,4 +2429,93 @@ mod tests {
         );
         assert!(matches!(result, Err(Error::ClientClosed)));
     }
+
+    #[test]
+    fn persistent_queue_replays_unacknowledged_calls_on_dial() {
+        use crate::protocol::{MSG_APPEND_TURN, MSG_PUT_BLOB};
+        use crate::turn::AppendRequest;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let (journal, pending) = QueueJournal::open(dir.path()).unwrap();
+            assert!(pending.is_empty());
+            let mut req = AppendRequest::new(7, "test.Turn", 1, b"left over".to_vec());
+            req.idempotency_key = b"turn-1".to_vec();
+            journal.record(&JournalOp::AppendTurn(req)).unwrap();
+            let acked = journal
+                .record(&JournalOp::PutBlob(b"done".to_vec()))
+                .unwrap();
+            journal
+                .record(&JournalOp::PutBlob(b"blob".to_vec()))
+                .unwrap();
+            journal.ack(acked).unwrap();
+        }
+        // A record torn by a crash mid-write is dropped.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("queue.log"))
+            .unwrap()
+            .write_all(&[1, 9, 0, 0])
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let mut seen = Vec::new();
+            for _ in 0..3 {
+                let frame = read_frame(&mut stream).unwrap();
+                let resp = match frame.header.msg_type {
+                    MSG_APPEND_TURN => vec![0u8; 52],
+                    MSG_PUT_BLOB => vec![0u8; 33],
+                    other => panic!("unexpected msg_type {other}"),
+                };
+                write_frame(
+                    &mut stream,
+                    frame.header.msg_type,
+                    0,
+                    frame.header.req_id,
+                    &resp,
+                )
+                .unwrap();
+                seen.push((frame.header.msg_type, frame.payload));
+            }
+            let _ = stop_rx.recv();
+            seen
+        });
+
+        let client = dial_reconnecting(
+            &addr,
+            vec![with_persistent_queue(dir.path())],
+            Vec::<ClientOption>::new(),
+        )
+        .unwrap();
+        // Replay finished before dialing returned.
+        assert_eq!(
+            std::fs::metadata(dir.path().join("queue.log"))
+                .unwrap()
+                .len(),
+            0
+        );
+
+        let ctx = RequestContext::background();
+        let req = AppendRequest::new(7, "test.Turn", 1, b"new".to_vec());
+        client.append_turn(&ctx, &req).unwrap();
+        assert_eq!(
+            std::fs::metadata(dir.path().join("queue.log"))
+                .unwrap()
+                .len(),
+            0
+        );
+
+        client.close().unwrap();
+        let _ = stop_tx.send(());
+        let seen = handle.join().unwrap();
+        let types: Vec<u16> = seen.iter().map(|(msg_type, _)| *msg_type).collect();
+        assert_eq!(types, vec![MSG_APPEND_TURN, MSG_PUT_BLOB, MSG_APPEND_TURN]);
+        assert!(seen[0].1.ends_with(b"turn-1"));
+        assert!(seen[1].1.ends_with(b"blob"));
+    }
 }
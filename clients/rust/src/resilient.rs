@@ -0,0 +1,389 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shareable client that re-dials inline when its connection dies.
+//!
+//! `ResilientClient` is the lightweight alternative to `ReconnectingClient`:
+//! no background worker and no queue. Each call runs on the caller's thread;
+//! if it fails with a connection error, the caller re-dials (with the
+//! configured backoff and retry bound) and retries the call once. Threads
+//! that hit the same dead connection share a single re-dial.
+
+#![allow(clippy::type_complexity)]
+
+use std::cmp;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use crate::client::{Client, ClientOption, HealthStatus, RequestContext};
+use crate::context::{ContextHead, ContextSummary};
+use crate::error::{Error, Result};
+use crate::fs::{AttachFsRequest, AttachFsResult, PutBlobRequest, PutBlobResult, VerifyReport};
+use crate::reconnect::{
+    default_dial_func, initial_dial_with_retry, notify_backoff, record_session, sleep_with_cancel,
+    BackoffObserver, DialFunc, LastSession, ReconnectConfig, ReconnectInfo, ReconnectOption,
+    ReconnectPredicate,
+};
+use crate::turn::{AppendRequest, AppendResult, GetLastOptions, TurnPage, TurnRecord};
+use crate::types::ContextMetadata;
+
+pub struct ResilientClient {
+    client: RwLock<Arc<Client>>,
+    /// Held while re-dialing so concurrent callers dial once between them.
+    redial: Mutex<()>,
+    dial_func: DialFunc,
+    last_session: LastSession,
+
+    max_retries: usize,
+    retry_delay: std::time::Duration,
+    max_retry_delay: std::time::Duration,
+    on_reconnect: Option<Arc<dyn Fn(u64) + Send + Sync>>,
+    on_reconnect_info: Option<Arc<dyn Fn(&ReconnectInfo) + Send + Sync>>,
+    on_backoff: Option<BackoffObserver>,
+    should_reconnect: ReconnectPredicate,
+    closed: AtomicBool,
+}
+
+/// Dials `addr` and wraps the connection in a `ResilientClient`. Takes the
+/// same options as `dial_reconnecting`; the queue options
+/// (`with_queue_size`, `with_max_queued_bytes`, `with_persistent_queue`)
+/// have no effect.
+pub fn dial_resilient(
+    addr: &str,
+    reconnect_opts: impl IntoIterator<Item = ReconnectOption>,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<ResilientClient> {
+    dial_resilient_inner(addr, false, reconnect_opts, opts)
+}
+
+pub fn dial_tls_resilient(
+    addr: &str,
+    reconnect_opts: impl IntoIterator<Item = ReconnectOption>,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<ResilientClient> {
+    dial_resilient_inner(addr, true, reconnect_opts, opts)
+}
+
+fn dial_resilient_inner(
+    addr: &str,
+    use_tls: bool,
+    reconnect_opts: impl IntoIterator<Item = ReconnectOption>,
+    opts: impl IntoIterator<Item = ClientOption>,
+) -> Result<ResilientClient> {
+    let mut cfg = ReconnectConfig::default();
+    for opt in reconnect_opts {
+        opt(&mut cfg);
+    }
+
+    let last_session = LastSession::default();
+    let dial_func = cfg.dial_func.clone().unwrap_or_else(|| {
+        default_dial_func(
+            addr,
+            use_tls,
+            opts,
+            cfg.session_resume,
+            last_session.clone(),
+        )
+    });
+
+    let client = if cfg.initial_dial_retry {
+        initial_dial_with_retry(&dial_func, &cfg)?
+    } else {
+        dial_func()?
+    };
+    record_session(&last_session, &client);
+
+    Ok(ResilientClient {
+        client: RwLock::new(Arc::new(client)),
+        redial: Mutex::new(()),
+        dial_func,
+        last_session,
+        max_retries: cfg.max_retries,
+        retry_delay: cfg.retry_delay,
+        max_retry_delay: cfg.max_retry_delay,
+        should_reconnect: cfg.reconnect_predicate(),
+        on_reconnect: cfg.on_reconnect,
+        on_reconnect_info: cfg.on_reconnect_info,
+        on_backoff: cfg.on_backoff,
+        closed: AtomicBool::new(false),
+    })
+}
+
+impl ResilientClient {
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.current().close()
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.current().session_id()
+    }
+
+    pub fn client_tag(&self) -> String {
+        self.current().client_tag().to_string()
+    }
+
+    /// Server address of the current connection.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.current().peer_addr()
+    }
+
+    /// Runs `op` on the current connection. On a connection error (or
+    /// whatever `with_should_reconnect` selects) the connection is re-dialed
+    /// and `op` runs once more; any other error, or a second failure, is
+    /// returned as is.
+    ///
+    /// The retry makes non-idempotent operations (such as `append_turn`
+    /// without an idempotency key) at-least-once, as with
+    /// `ReconnectingClient`.
+    pub fn call<T>(&self, ctx: &RequestContext, op: impl Fn(&Client) -> Result<T>) -> Result<T> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::ClientClosed);
+        }
+        let client = self.current();
+        match op(&client) {
+            Err(err) if (self.should_reconnect)(&err) => {
+                let client = self.redial(ctx, &client, Instant::now())?;
+                op(&client)
+            }
+            other => other,
+        }
+    }
+
+    pub fn create_context(&self, ctx: &RequestContext, base_turn_id: u64) -> Result<ContextHead> {
+        self.call(ctx, |client| client.create_context(ctx, base_turn_id))
+    }
+
+    pub fn fork_context(&self, ctx: &RequestContext, base_turn_id: u64) -> Result<ContextHead> {
+        self.call(ctx, |client| client.fork_context(ctx, base_turn_id))
+    }
+
+    pub fn health(&self, ctx: &RequestContext) -> Result<HealthStatus> {
+        self.call(ctx, |client| client.health(ctx))
+    }
+
+    pub fn get_head(&self, ctx: &RequestContext, context_id: u64) -> Result<ContextHead> {
+        self.call(ctx, |client| client.get_head(ctx, context_id))
+    }
+
+    pub fn list_contexts(
+        &self,
+        ctx: &RequestContext,
+        limit: u32,
+        after: u64,
+    ) -> Result<Vec<ContextSummary>> {
+        self.call(ctx, |client| client.list_contexts(ctx, limit, after))
+    }
+
+    pub fn get_head_at(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        timestamp_ms: u64,
+    ) -> Result<ContextHead> {
+        self.call(ctx, |client| {
+            client.get_head_at(ctx, context_id, timestamp_ms)
+        })
+    }
+
+    pub fn get_metadata(&self, ctx: &RequestContext, context_id: u64) -> Result<ContextMetadata> {
+        self.call(ctx, |client| client.get_metadata(ctx, context_id))
+    }
+
+    pub fn append_turn(&self, ctx: &RequestContext, req: &AppendRequest) -> Result<AppendResult> {
+        self.call(ctx, |client| client.append_turn(ctx, req))
+    }
+
+    pub fn get_last(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<Vec<TurnRecord>> {
+        self.call(ctx, |client| client.get_last(ctx, context_id, opts.clone()))
+    }
+
+    pub fn get_last_page(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<TurnPage> {
+        self.call(ctx, |client| {
+            client.get_last_page(ctx, context_id, opts.clone())
+        })
+    }
+
+    pub fn attach_fs(&self, ctx: &RequestContext, req: &AttachFsRequest) -> Result<AttachFsResult> {
+        self.call(ctx, |client| client.attach_fs(ctx, req))
+    }
+
+    pub fn attach_fs_to_head(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        fs_root_hash: [u8; 32],
+    ) -> Result<AttachFsResult> {
+        self.call(ctx, |client| {
+            client.attach_fs_to_head(ctx, context_id, fs_root_hash)
+        })
+    }
+
+    pub fn verify_snapshot(
+        &self,
+        ctx: &RequestContext,
+        fs_root_hash: [u8; 32],
+    ) -> Result<VerifyReport> {
+        self.call(ctx, |client| client.verify_snapshot(ctx, fs_root_hash))
+    }
+
+    pub fn put_blob(&self, ctx: &RequestContext, req: &PutBlobRequest) -> Result<PutBlobResult> {
+        self.call(ctx, |client| client.put_blob(ctx, req))
+    }
+
+    pub fn put_blob_if_absent(
+        &self,
+        ctx: &RequestContext,
+        data: Vec<u8>,
+    ) -> Result<([u8; 32], bool)> {
+        self.call(ctx, |client| client.put_blob_if_absent(ctx, data.clone()))
+    }
+
+    pub fn append_turn_with_fs(
+        &self,
+        ctx: &RequestContext,
+        req: &AppendRequest,
+        fs_root_hash: Option<[u8; 32]>,
+    ) -> Result<AppendResult> {
+        self.call(ctx, |client| {
+            client.append_turn_with_fs(ctx, req, fs_root_hash)
+        })
+    }
+
+    fn current(&self) -> Arc<Client> {
+        self.client.read().unwrap().clone()
+    }
+
+    /// Replaces `failed` with a fresh connection, unless another caller
+    /// already has, in which case that connection is returned.
+    fn redial(
+        &self,
+        ctx: &RequestContext,
+        failed: &Arc<Client>,
+        disconnected_at: Instant,
+    ) -> Result<Arc<Client>> {
+        let _redial = self.redial.lock().unwrap();
+        let current = self.current();
+        if !Arc::ptr_eq(&current, failed) {
+            return Ok(current);
+        }
+        let _ = failed.close();
+
+        let mut delay = self.retry_delay;
+        let mut last_err: Option<Error> = None;
+        let max_attempts = cmp::max(self.max_retries, 1);
+        for attempt in 1..=max_attempts {
+            notify_backoff(
+                &self.on_backoff,
+                attempt,
+                max_attempts,
+                delay,
+                last_err.as_ref(),
+            );
+            if attempt > 1 {
+                sleep_with_cancel(delay, ctx, &self.closed)?;
+                delay = cmp::min(delay * 2, self.max_retry_delay);
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(Error::ClientClosed);
+            }
+
+            match (self.dial_func)() {
+                Ok(client) => {
+                    let client = Arc::new(client);
+                    let session_id = client.session_id();
+                    let prior = record_session(&self.last_session, &client);
+                    *self.client.write().unwrap() = client.clone();
+                    if let Some(cb) = &self.on_reconnect {
+                        cb(session_id);
+                    }
+                    if let Some(cb) = &self.on_reconnect_info {
+                        cb(&ReconnectInfo {
+                            session_id,
+                            resumed: prior != 0 && prior == session_id,
+                            attempts: attempt,
+                            downtime: disconnected_at.elapsed(),
+                        });
+                    }
+                    return Ok(client);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or(Error::ClientClosed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{read_frame, write_frame, MSG_GET_HEAD};
+    use crate::reconnect::{with_max_retries, with_on_reconnect, with_retry_delay};
+    use crate::test_util::{accept_hello, head_payload};
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn redials_inline_and_retries_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // First connection dies on its first request.
+            let (mut stream, _) = accept_hello(&listener, 1);
+            read_frame(&mut stream).unwrap();
+            drop(stream);
+
+            let (mut stream, _) = accept_hello(&listener, 1);
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.msg_type, MSG_GET_HEAD);
+            let head = head_payload(7, 3, 2);
+            write_frame(&mut stream, MSG_GET_HEAD, 0, req.header.req_id, &head).unwrap();
+            let _ = read_frame(&mut stream);
+        });
+
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let reconnects_clone = reconnects.clone();
+        let client = Arc::new(
+            dial_resilient(
+                &addr,
+                vec![
+                    with_max_retries(3),
+                    with_retry_delay(Duration::from_millis(10)),
+                    with_on_reconnect(move |_| {
+                        reconnects_clone.fetch_add(1, Ordering::SeqCst);
+                    }),
+                ],
+                Vec::<ClientOption>::new(),
+            )
+            .unwrap(),
+        );
+
+        let head = client.get_head(&RequestContext::background(), 7).unwrap();
+        assert_eq!(head.head_turn_id, 3);
+        assert_eq!(reconnects.load(Ordering::SeqCst), 1);
+
+        client.close().unwrap();
+        assert!(matches!(
+            client.get_head(&RequestContext::background(), 7),
+            Err(Error::ClientClosed)
+        ));
+        server.join().unwrap();
+    }
+}
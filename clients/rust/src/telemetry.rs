@@ -364,6 +364,8 @@ mod tests {
                 idempotency_key: vec![],
                 encoding: ENCODING_MSGPACK,
                 compression: 0,
+                dedup_by_item_id: false,
+                checkpoint: false,
             };
             assert!(sender.send(req), "should not overflow for item {i}");
         }
@@ -378,6 +380,8 @@ mod tests {
             idempotency_key: vec![],
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         assert!(!sender.send(req), "should overflow");
 
@@ -416,6 +420,8 @@ mod tests {
             idempotency_key: vec![],
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         assert!(!sender.send(req));
     }
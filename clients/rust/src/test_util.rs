@@ -1,8 +1,19 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(test)]
+use byteorder::{LittleEndian, WriteBytesExt};
 #[cfg(test)]
 use serde::Deserialize;
+#[cfg(test)]
+use std::io::{Read, Write};
+#[cfg(test)]
+use std::net::{TcpListener, TcpStream};
+#[cfg(test)]
+use std::thread::{self, JoinHandle};
+
+#[cfg(test)]
+use crate::protocol::{read_frame, write_frame, Frame, MSG_HELLO};
 
 #[cfg(test)]
 #[derive(Debug, Deserialize)]
@@ -32,3 +43,113 @@ pub fn load_fixture(name: &str) -> Fixture {
 pub fn decode_hex(hex_str: &str) -> Vec<u8> {
     hex::decode(hex_str).unwrap_or_else(|err| panic!("hex decode failed: {err}"))
 }
+
+#[cfg(test)]
+pub fn head_payload(context_id: u64, head_turn_id: u64, head_depth: u32) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.write_u64::<LittleEndian>(context_id).unwrap();
+    payload.write_u64::<LittleEndian>(head_turn_id).unwrap();
+    payload.write_u32::<LittleEndian>(head_depth).unwrap();
+    payload
+}
+
+#[cfg(test)]
+pub fn update_payload(context_id: u64, turn_id: u64, depth: u32, body: &[u8]) -> Vec<u8> {
+    let mut payload = head_payload(context_id, turn_id, depth);
+    payload.extend_from_slice(&page_payload(turn_id, depth, body));
+    payload
+}
+
+#[cfg(test)]
+/// GET_LAST response holding a single turn with its payload.
+pub fn page_payload(turn_id: u64, depth: u32, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.write_u32::<LittleEndian>(1).unwrap();
+    payload.write_u64::<LittleEndian>(turn_id).unwrap();
+    payload.write_u64::<LittleEndian>(turn_id - 1).unwrap();
+    payload.write_u32::<LittleEndian>(depth).unwrap();
+    payload.write_u32::<LittleEndian>(4).unwrap();
+    payload.extend_from_slice(b"test");
+    payload.write_u32::<LittleEndian>(1).unwrap();
+    payload.write_u32::<LittleEndian>(1).unwrap();
+    payload.write_u32::<LittleEndian>(0).unwrap();
+    payload
+        .write_u32::<LittleEndian>(body.len() as u32)
+        .unwrap();
+    payload.extend_from_slice(blake3::hash(body).as_bytes());
+    payload
+        .write_u32::<LittleEndian>(body.len() as u32)
+        .unwrap();
+    payload.extend_from_slice(body);
+    payload.extend_from_slice(&[1, 0]);
+    payload
+}
+
+#[cfg(test)]
+/// HELLO response for `session_id`: protocol 1, BLAKE3, and a resume token.
+pub fn hello_response(session_id: u64) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.write_u64::<LittleEndian>(session_id).unwrap();
+    payload.write_u16::<LittleEndian>(1).unwrap();
+    payload.push(0);
+    payload.extend_from_slice(&[0xA5; 16]);
+    payload
+}
+
+#[cfg(test)]
+/// Reads the client's HELLO from `stream` and answers it with `session_id`.
+/// Returns the HELLO so tests can check what the client sent.
+pub fn answer_hello<S: Read + Write>(stream: &mut S, session_id: u64) -> Frame {
+    let hello = read_frame(stream).unwrap();
+    assert_eq!(hello.header.msg_type, MSG_HELLO);
+    let resp = hello_response(session_id);
+    write_frame(stream, MSG_HELLO, 0, hello.header.req_id, &resp).unwrap();
+    hello
+}
+
+#[cfg(test)]
+/// Accepts a connection on `listener` and answers its HELLO with
+/// `session_id`, returning the stream and the HELLO the client sent.
+pub fn accept_hello(listener: &TcpListener, session_id: u64) -> (TcpStream, Frame) {
+    let (mut stream, _) = listener.accept().unwrap();
+    let hello = answer_hello(&mut stream, session_id);
+    (stream, hello)
+}
+
+#[cfg(test)]
+/// A fake server for one connection: accepts it, answers HELLO with session
+/// 1, then hands the stream to `serve`. Returns the address to dial and the
+/// server thread, which yields what `serve` returns.
+pub fn mock_server<T, F>(serve: F) -> (String, JoinHandle<T>)
+where
+    T: Send + 'static,
+    F: FnOnce(&mut TcpStream) -> T + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = accept_hello(&listener, 1);
+        serve(&mut stream)
+    });
+    (addr, handle)
+}
+
+#[cfg(test)]
+/// `mock_server` that answers each request with the message type and
+/// payload `respond` returns for it, echoing the request's id. Stops when
+/// the client disconnects or `respond` returns None.
+pub fn mock_responder<F>(mut respond: F) -> (String, JoinHandle<()>)
+where
+    F: FnMut(&Frame) -> Option<(u16, Vec<u8>)> + Send + 'static,
+{
+    mock_server(move |stream| {
+        while let Ok(req) = read_frame(stream) {
+            let Some((msg_type, payload)) = respond(&req) else {
+                break;
+            };
+            if write_frame(stream, msg_type, 0, req.header.req_id, &payload).is_err() {
+                break;
+            }
+        }
+    })
+}
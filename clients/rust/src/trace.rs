@@ -0,0 +1,76 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Optional `tracing` instrumentation.
+//!
+//! With the `tracing` feature enabled, requests, dials, reconnects and queued
+//! operations each run inside a debug-level span that ends with an event
+//! recording how long they took and whether they failed. Without the feature
+//! these macros expand to nothing, so call sites need no `cfg` of their own.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+use crate::error::Result;
+
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+#[cfg(not(feature = "tracing"))]
+pub(crate) type Span = ();
+
+/// A debug-level span; arguments follow `tracing::debug_span!`.
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        tracing::debug_span!($($arg)*)
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+/// A debug-level event; arguments follow `tracing::debug!`.
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        tracing::debug!($($arg)*)
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($($arg:tt)*) => {};
+}
+
+/// Fill in a field of the current span that was declared `Empty`.
+#[cfg(feature = "tracing")]
+macro_rules! trace_record {
+    ($field:literal, $value:expr) => {
+        tracing::Span::current().record($field, $value);
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_record {
+    ($field:literal, $value:expr) => {};
+}
+
+/// Run `f` inside `span`, then record its duration and outcome.
+#[cfg(feature = "tracing")]
+pub(crate) fn traced<T>(span: Span, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _entered = span.enter();
+    let start = Instant::now();
+    let result = f();
+    let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+    match &result {
+        Ok(_) => tracing::debug!(elapsed_ms, outcome = "ok"),
+        Err(err) => tracing::debug!(elapsed_ms, outcome = "error", error = %err),
+    }
+    result
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn traced<T>(_span: Span, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    f()
+}
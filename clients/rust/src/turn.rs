@@ -5,8 +5,14 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::Read;
 
 use crate::client::{Client, RequestContext};
-use crate::error::{Error, Result};
-use crate::protocol::{ENCODING_MSGPACK, MSG_APPEND_TURN, MSG_GET_LAST};
+use crate::encoding::decode_msgpack_into;
+use crate::error::{is_server_error, Error, Result};
+use crate::protocol::{
+    Frame, APPEND_FLAG_CHECKPOINT, APPEND_FLAG_DEDUP_ITEM_ID, COMPRESSION_NONE, ENCODING_MSGPACK,
+    GET_LAST_FLAG_ITEM_TYPES, GET_LAST_FLAG_MIN_HEAD, GET_LAST_FLAG_SUMMARY, MSG_APPEND_TURN,
+    MSG_GET_LAST,
+};
+use crate::types::{ConversationItem, TypeIDConversationItem, TypeIDConversationItemLegacy};
 
 #[derive(Debug, Clone)]
 pub struct AppendRequest {
@@ -18,6 +24,12 @@ pub struct AppendRequest {
     pub idempotency_key: Vec<u8>,
     pub encoding: u32,
     pub compression: u32,
+    /// Ask the server to return the existing turn instead of appending when a
+    /// turn on the target chain already carries the same `ConversationItem.id`.
+    pub dedup_by_item_id: bool,
+    /// Store the turn as a checkpoint: its payload is the caller's summary of
+    /// the chain so far, and summary-mode reads stop at it.
+    pub checkpoint: bool,
 }
 
 impl AppendRequest {
@@ -36,6 +48,8 @@ impl AppendRequest {
             idempotency_key: Vec::new(),
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         }
     }
 }
@@ -50,21 +64,119 @@ pub struct TurnRecord {
     pub encoding: u32,
     pub compression: u32,
     pub payload_hash: [u8; 32],
+    /// Uncompressed payload size in bytes, reported even when the payload
+    /// itself was not fetched.
+    pub payload_size: u32,
+    /// Empty unless the request set `include_payload`.
     pub payload: Vec<u8>,
+    /// False when the turn is no longer an ancestor of the context head (its
+    /// branch was abandoned). Servers that predate the flag report true.
+    pub on_active_chain: bool,
+    /// The turn was appended as a checkpoint (`AppendRequest::checkpoint`).
+    pub is_checkpoint: bool,
 }
 
+/// A turn as listed by `get_last_headers`: what a turn list needs to show,
+/// without the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnHeader {
+    pub turn_id: u64,
+    pub parent_id: u64,
+    pub depth: u32,
+    pub type_id: String,
+    pub type_version: u32,
+    pub payload_hash: [u8; 32],
+    /// Uncompressed payload size in bytes.
+    pub payload_size: u32,
+    /// The `ConversationItem.item_type`, or None for turns of other types
+    /// and from servers that predate item types in listings.
+    pub item_type: Option<String>,
+    pub on_active_chain: bool,
+    pub is_checkpoint: bool,
+}
+
+impl TurnRecord {
+    /// Decodes the payload of a `ConversationItem` turn, under either its
+    /// current or legacy type ID. Other types, encodings, and compressed
+    /// payloads are reported as `Error::InvalidResponse`.
+    pub fn decode_item(&self) -> Result<ConversationItem> {
+        if self.type_id != TypeIDConversationItem && self.type_id != TypeIDConversationItemLegacy {
+            return Err(Error::invalid_response(format!(
+                "turn {} has type {}, not a ConversationItem",
+                self.turn_id, self.type_id
+            )));
+        }
+        if self.encoding != ENCODING_MSGPACK || self.compression != COMPRESSION_NONE {
+            return Err(Error::invalid_response(format!(
+                "turn {}: unsupported encoding {} / compression {}",
+                self.turn_id, self.encoding, self.compression
+            )));
+        }
+        decode_msgpack_into(&self.payload).map_err(|err| match err {
+            Error::InvalidResponse(msg) => {
+                Error::invalid_response(format!("turn {}: {msg}", self.turn_id))
+            }
+            other => other,
+        })
+    }
+
+    /// `decode_item` for each record, failing on the first that does not
+    /// decode.
+    pub fn decode_items(records: &[TurnRecord]) -> Result<Vec<ConversationItem>> {
+        records.iter().map(TurnRecord::decode_item).collect()
+    }
+}
+
+/// A turn's payload exactly as the server stores it, for consumers that
+/// re-encode or re-import turns and must keep fields this crate does not
+/// model. `payload` is uncompressed and hashes to `payload_hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawTurn {
+    pub turn_id: u64,
+    pub type_id: String,
+    pub type_version: u32,
+    pub encoding: u32,
+    pub payload_hash: [u8; 32],
+    pub payload: Vec<u8>,
+}
+
+/// Server acknowledgement for an appended turn.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AppendResult {
+    /// Context the turn was appended to.
     pub context_id: u64,
+    /// Server-assigned id of the new turn.
     pub turn_id: u64,
+    /// Depth of the new turn (0 for the first turn in a chain).
     pub depth: u32,
+    /// BLAKE3-256 hash of the uncompressed payload.
     pub payload_hash: [u8; 32],
+    /// Context head after the append. Equals `turn_id` unless the server
+    /// moved the head elsewhere; older servers that omit it report `turn_id`.
+    pub head_turn_id: u64,
+    /// Server commit time in Unix milliseconds, or 0 if the server predates
+    /// this field.
+    pub committed_at_unix_ms: u64,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct GetLastOptions {
     pub limit: u32,
     pub include_payload: bool,
+    /// Turns to skip back from the head before collecting `limit` turns.
+    /// With `item_types` set, only matching turns are counted.
+    pub offset: u32,
+    /// Only return turns whose `ConversationItem.item_type` is in this set;
+    /// the server skips the rest without sending them. Empty means all types.
+    pub item_types: Vec<String>,
+    /// Stop at the most recent checkpoint turn, returning it as the oldest
+    /// turn instead of walking the chain before it.
+    pub summary: bool,
+    /// Read-your-writes: a turn id (typically from an `AppendResult`) the
+    /// server must already hold before answering, or `Error::NotReady` is
+    /// returned instead. 0 disables the check. Servers that predate it
+    /// ignore it.
+    pub min_head_turn_id: u64,
 }
 
 impl Default for GetLastOptions {
@@ -72,10 +184,23 @@ impl Default for GetLastOptions {
         Self {
             limit: 10,
             include_payload: false,
+            offset: 0,
+            item_types: Vec::new(),
+            summary: false,
+            min_head_turn_id: 0,
         }
     }
 }
 
+/// A window of turns returned by `get_last_page`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnPage {
+    /// Turns in the window, oldest first.
+    pub records: Vec<TurnRecord>,
+    /// Whether older turns exist before the window.
+    pub has_more: bool,
+}
+
 impl Client {
     pub fn append_turn(&self, ctx: &RequestContext, req: &AppendRequest) -> Result<AppendResult> {
         let encoding = if req.encoding == 0 {
@@ -107,7 +232,8 @@ impl Client {
             payload.extend_from_slice(&req.idempotency_key);
         }
 
-        let frame = self.send_request(ctx, MSG_APPEND_TURN, &payload)?;
+        let frame =
+            self.send_request_with_flags(ctx, MSG_APPEND_TURN, append_flags(req), &payload)?;
         parse_append_result(&frame.payload)
     }
 
@@ -117,18 +243,164 @@ impl Client {
         context_id: u64,
         opts: GetLastOptions,
     ) -> Result<Vec<TurnRecord>> {
+        Ok(self.get_last_page(ctx, context_id, opts)?.records)
+    }
+
+    /// Like `get_last`, but always fetches payloads and returns them as the
+    /// stored bytes, checked against their content hash and never decoded.
+    pub fn get_last_raw(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<Vec<RawTurn>> {
+        let opts = GetLastOptions {
+            include_payload: true,
+            ..opts
+        };
+        self.get_last(ctx, context_id, opts)?
+            .into_iter()
+            .map(|record| {
+                if blake3::hash(&record.payload).as_bytes() != &record.payload_hash {
+                    return Err(Error::invalid_response(format!(
+                        "turn {} payload does not match its hash",
+                        record.turn_id
+                    )));
+                }
+                Ok(RawTurn {
+                    turn_id: record.turn_id,
+                    type_id: record.type_id,
+                    type_version: record.type_version,
+                    encoding: record.encoding,
+                    payload_hash: record.payload_hash,
+                    payload: record.payload,
+                })
+            })
+            .collect()
+    }
+
+    /// Fetches a window of turns ending `opts.offset` turns before the head,
+    /// reporting whether older turns remain so callers can page backward.
+    pub fn get_last_page(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<TurnPage> {
+        let frame = self.send_get_last(ctx, context_id, &opts, 0)?;
+        Ok(split_turn_page(&frame.payload, opts.include_payload)?.0)
+    }
+
+    /// Lists turns like `get_last`, with each turn's payload size and item
+    /// type but never its payload (`opts.include_payload` is ignored), so a
+    /// turn list can be shown cheaply and bodies fetched on demand.
+    pub fn get_last_headers(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: GetLastOptions,
+    ) -> Result<Vec<TurnHeader>> {
+        let opts = GetLastOptions {
+            include_payload: false,
+            ..opts
+        };
+        let frame = self.send_get_last(ctx, context_id, &opts, GET_LAST_FLAG_ITEM_TYPES)?;
+        let (page, mut rest) = split_turn_page(&frame.payload, false)?;
+        // Servers that predate the flag send no item types.
+        let mut item_types = Vec::with_capacity(page.records.len());
+        if !rest.is_empty() {
+            for _ in &page.records {
+                let len = rest.read_u16::<LittleEndian>()? as usize;
+                let item_type = rest
+                    .get(..len)
+                    .ok_or_else(|| Error::invalid_response("item type truncated"))?;
+                let item_type = String::from_utf8(item_type.to_vec())
+                    .map_err(|_| Error::invalid_response("item_type not utf8"))?;
+                rest = &rest[len..];
+                item_types.push((!item_type.is_empty()).then_some(item_type));
+            }
+        }
+        item_types.resize(page.records.len(), None);
+
+        Ok(page
+            .records
+            .into_iter()
+            .zip(item_types)
+            .map(|(record, item_type)| TurnHeader {
+                turn_id: record.turn_id,
+                parent_id: record.parent_id,
+                depth: record.depth,
+                type_id: record.type_id,
+                type_version: record.type_version,
+                payload_hash: record.payload_hash,
+                payload_size: record.payload_size,
+                item_type,
+                on_active_chain: record.on_active_chain,
+                is_checkpoint: record.is_checkpoint,
+            })
+            .collect())
+    }
+
+    fn send_get_last(
+        &self,
+        ctx: &RequestContext,
+        context_id: u64,
+        opts: &GetLastOptions,
+        extra_flags: u16,
+    ) -> Result<Frame> {
         let limit = if opts.limit == 0 { 10 } else { opts.limit };
-        let mut payload = Vec::with_capacity(16);
+        let mut payload = Vec::with_capacity(20);
         payload.write_u64::<LittleEndian>(context_id)?;
         payload.write_u32::<LittleEndian>(limit)?;
         payload.write_u32::<LittleEndian>(if opts.include_payload { 1 } else { 0 })?;
+        // min_head_turn_id comes last, so it needs the optional fields before it.
+        let min_head = opts.min_head_turn_id != 0;
+        if opts.offset != 0 || !opts.item_types.is_empty() || min_head {
+            payload.write_u32::<LittleEndian>(opts.offset)?;
+        }
+        if !opts.item_types.is_empty() || min_head {
+            payload.write_u32::<LittleEndian>(opts.item_types.len() as u32)?;
+            for item_type in &opts.item_types {
+                payload.write_u16::<LittleEndian>(item_type.len() as u16)?;
+                payload.extend_from_slice(item_type.as_bytes());
+            }
+        }
+        if min_head {
+            payload.write_u64::<LittleEndian>(opts.min_head_turn_id)?;
+        }
 
-        let frame = self.send_request(ctx, MSG_GET_LAST, &payload)?;
-        parse_turn_records(&frame.payload)
+        let mut flags = extra_flags;
+        if opts.summary {
+            flags |= GET_LAST_FLAG_SUMMARY;
+        }
+        if min_head {
+            flags |= GET_LAST_FLAG_MIN_HEAD;
+        }
+        self.send_request_with_flags(ctx, MSG_GET_LAST, flags, &payload)
+            .map_err(|err| {
+                if is_server_error(&err, 425) {
+                    Error::NotReady {
+                        min_head_turn_id: opts.min_head_turn_id,
+                    }
+                } else {
+                    err
+                }
+            })
     }
 }
 
-fn parse_append_result(payload: &[u8]) -> Result<AppendResult> {
+pub(crate) fn append_flags(req: &AppendRequest) -> u16 {
+    let mut flags = 0;
+    if req.dedup_by_item_id {
+        flags |= APPEND_FLAG_DEDUP_ITEM_ID;
+    }
+    if req.checkpoint {
+        flags |= APPEND_FLAG_CHECKPOINT;
+    }
+    flags
+}
+
+pub(crate) fn parse_append_result(payload: &[u8]) -> Result<AppendResult> {
     if payload.len() < 52 {
         return Err(Error::invalid_response(format!(
             "append response too short ({} bytes)",
@@ -141,15 +413,35 @@ fn parse_append_result(payload: &[u8]) -> Result<AppendResult> {
     let depth = cursor.read_u32::<LittleEndian>()?;
     let mut hash = [0u8; 32];
     cursor.read_exact(&mut hash)?;
+
+    // Servers that predate the extended ack stop after the hash.
+    let (head_turn_id, committed_at_unix_ms) = if payload.len() >= 68 {
+        (
+            cursor.read_u64::<LittleEndian>()?,
+            cursor.read_u64::<LittleEndian>()?,
+        )
+    } else {
+        (turn_id, 0)
+    };
+
     Ok(AppendResult {
         context_id,
         turn_id,
         depth,
         payload_hash: hash,
+        head_turn_id,
+        committed_at_unix_ms,
     })
 }
 
-fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
+pub(crate) fn parse_turn_page(payload: &[u8]) -> Result<TurnPage> {
+    Ok(split_turn_page(payload, true)?.0)
+}
+
+/// Parses a turn page whose records carry payloads only if
+/// `include_payload` was requested, returning it with whatever follows the
+/// checkpoint flags.
+fn split_turn_page(payload: &[u8], include_payload: bool) -> Result<(TurnPage, &[u8])> {
     if payload.len() < 4 {
         return Err(Error::invalid_response("turn records too short"));
     }
@@ -173,13 +465,16 @@ fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
         let encoding = cursor.read_u32::<LittleEndian>()?;
         let compression = cursor.read_u32::<LittleEndian>()?;
 
-        let _uncompressed_len = cursor.read_u32::<LittleEndian>()?;
+        let payload_size = cursor.read_u32::<LittleEndian>()?;
         let mut payload_hash = [0u8; 32];
         cursor.read_exact(&mut payload_hash)?;
 
-        let payload_len = cursor.read_u32::<LittleEndian>()? as usize;
-        let mut payload_bytes = vec![0u8; payload_len];
-        cursor.read_exact(&mut payload_bytes)?;
+        let mut payload_bytes = Vec::new();
+        if include_payload {
+            let payload_len = cursor.read_u32::<LittleEndian>()? as usize;
+            payload_bytes.resize(payload_len, 0);
+            cursor.read_exact(&mut payload_bytes)?;
+        }
 
         records.push(TurnRecord {
             turn_id,
@@ -190,11 +485,33 @@ fn parse_turn_records(payload: &[u8]) -> Result<Vec<TurnRecord>> {
             encoding,
             compression,
             payload_hash,
+            payload_size,
             payload: payload_bytes,
+            on_active_chain: true,
+            is_checkpoint: false,
         });
     }
 
-    Ok(records)
+    // Optional trailer: one active-chain flag per record, a has-more byte,
+    // then one checkpoint flag per record.
+    let remaining = &payload[cursor.position() as usize..];
+    let mut has_more = false;
+    let mut rest: &[u8] = &[];
+    if remaining.len() >= records.len() {
+        for (record, flag) in records.iter_mut().zip(remaining) {
+            record.on_active_chain = *flag != 0;
+        }
+        has_more = remaining.get(records.len()).is_some_and(|b| *b != 0);
+        let checkpoints = &remaining[(records.len() + 1).min(remaining.len())..];
+        if checkpoints.len() >= records.len() {
+            for (record, flag) in records.iter_mut().zip(checkpoints) {
+                record.is_checkpoint = *flag != 0;
+            }
+            rest = &checkpoints[records.len()..];
+        }
+    }
+
+    Ok((TurnPage { records, has_more }, rest))
 }
 
 #[cfg(test)]
@@ -238,6 +555,41 @@ mod tests {
         payload
     }
 
+    #[test]
+    fn decode_item_accepts_current_and_legacy_type_ids() {
+        let mut item = crate::types::build_tool_result("call-1", "ok").build();
+        item.id = "item-1".to_string();
+        let mut record = TurnRecord {
+            turn_id: 9,
+            parent_id: 8,
+            depth: 2,
+            type_id: TypeIDConversationItem.to_string(),
+            type_version: 3,
+            encoding: ENCODING_MSGPACK,
+            compression: 0,
+            payload_hash: [0u8; 32],
+            payload_size: 0,
+            payload: crate::encoding::encode_msgpack(&item).unwrap(),
+            on_active_chain: true,
+            is_checkpoint: false,
+        };
+        assert_eq!(record.decode_item().unwrap().id, "item-1");
+
+        let mut legacy = record.clone();
+        legacy.type_id = TypeIDConversationItemLegacy.to_string();
+        let items = TurnRecord::decode_items(&[record.clone(), legacy]).unwrap();
+        assert_eq!(items.len(), 2);
+
+        record.type_id = "app.Other".to_string();
+        let err = record.decode_item().unwrap_err();
+        assert!(err.to_string().contains("app.Other"), "{err}");
+
+        record.type_id = TypeIDConversationItem.to_string();
+        record.payload = vec![0xc1];
+        let err = record.decode_item().unwrap_err();
+        assert!(err.to_string().contains("turn 9"), "{err}");
+    }
+
     #[test]
     fn append_payloads_match_fixtures() {
         let fixture = load_fixture("append_parent0");
@@ -252,6 +604,8 @@ mod tests {
             idempotency_key: Vec::new(),
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         assert_eq!(decode_hex(&fixture.payload_hex), build_append_payload(&req));
 
@@ -267,6 +621,8 @@ mod tests {
             idempotency_key: Vec::new(),
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         assert_eq!(decode_hex(&fixture.payload_hex), build_append_payload(&req));
 
@@ -282,10 +638,45 @@ mod tests {
             idempotency_key: b"idem-1".to_vec(),
             encoding: ENCODING_MSGPACK,
             compression: 0,
+            dedup_by_item_id: false,
+            checkpoint: false,
         };
         assert_eq!(decode_hex(&fixture.payload_hex), build_append_payload(&req));
     }
 
+    #[test]
+    fn append_flags_reflect_request_options() {
+        let mut req = AppendRequest::new(1, "cxdb.ConversationItem", 3, vec![0x80]);
+        assert_eq!(append_flags(&req), 0);
+        req.dedup_by_item_id = true;
+        assert_eq!(append_flags(&req), APPEND_FLAG_DEDUP_ITEM_ID);
+    }
+
+    #[test]
+    fn append_result_parses_extended_ack() {
+        let mut payload = Vec::new();
+        payload.write_u64::<LittleEndian>(3).unwrap();
+        payload.write_u64::<LittleEndian>(41).unwrap();
+        payload.write_u32::<LittleEndian>(2).unwrap();
+        payload.extend_from_slice(&[0xCC; 32]);
+
+        let legacy = parse_append_result(&payload).unwrap();
+        assert_eq!(legacy.turn_id, 41);
+        assert_eq!(legacy.head_turn_id, 41);
+        assert_eq!(legacy.committed_at_unix_ms, 0);
+
+        payload.write_u64::<LittleEndian>(41).unwrap();
+        payload
+            .write_u64::<LittleEndian>(1_700_000_000_000)
+            .unwrap();
+        let extended = parse_append_result(&payload).unwrap();
+        assert_eq!(extended.context_id, 3);
+        assert_eq!(extended.depth, 2);
+        assert_eq!(extended.payload_hash, [0xCC; 32]);
+        assert_eq!(extended.head_turn_id, 41);
+        assert_eq!(extended.committed_at_unix_ms, 1_700_000_000_000);
+    }
+
     #[test]
     fn get_last_payloads_match_fixtures() {
         let fixture = load_fixture("get_last_default");
@@ -304,4 +695,304 @@ mod tests {
         payload.write_u32::<LittleEndian>(1).unwrap();
         assert_eq!(decode_hex(&fixture.payload_hex), payload);
     }
+
+    #[test]
+    fn turn_records_read_active_chain_trailer() {
+        let mut payload = Vec::new();
+        payload.write_u32::<LittleEndian>(2).unwrap();
+        for turn_id in [1u64, 2] {
+            payload.write_u64::<LittleEndian>(turn_id).unwrap();
+            payload.write_u64::<LittleEndian>(turn_id - 1).unwrap();
+            payload
+                .write_u32::<LittleEndian>(turn_id as u32 - 1)
+                .unwrap();
+            payload.write_u32::<LittleEndian>(1).unwrap();
+            payload.extend_from_slice(b"t");
+            payload.write_u32::<LittleEndian>(1).unwrap();
+            payload.write_u32::<LittleEndian>(ENCODING_MSGPACK).unwrap();
+            payload.write_u32::<LittleEndian>(0).unwrap();
+            payload.write_u32::<LittleEndian>(1).unwrap();
+            payload.extend_from_slice(&[0u8; 32]);
+            payload.write_u32::<LittleEndian>(1).unwrap();
+            payload.push(0xC0);
+        }
+
+        let legacy = parse_turn_page(&payload).unwrap();
+        assert!(legacy.records.iter().all(|r| r.on_active_chain));
+        assert!(!legacy.has_more);
+
+        payload.extend_from_slice(&[1, 0]);
+        let records = parse_turn_page(&payload).unwrap().records;
+        assert_eq!(records.len(), 2);
+        assert!(records[0].on_active_chain);
+        assert!(!records[1].on_active_chain);
+
+        payload.push(1);
+        assert!(parse_turn_page(&payload).unwrap().has_more);
+
+        payload.extend_from_slice(&[1, 0]);
+        let records = parse_turn_page(&payload).unwrap().records;
+        assert!(records[0].is_checkpoint);
+        assert!(!records[1].is_checkpoint);
+    }
+
+    #[test]
+    fn get_last_headers_reports_sizes_and_item_types_without_payloads() {
+        use crate::protocol::{read_frame, write_frame};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = read_frame(&mut stream).unwrap();
+            write_frame(
+                &mut stream,
+                hello.header.msg_type,
+                0,
+                hello.header.req_id,
+                &[0; 10],
+            )
+            .unwrap();
+
+            let mut flags = Vec::new();
+            for with_item_types in [true, false] {
+                let req = read_frame(&mut stream).unwrap();
+                flags.push(req.header.flags);
+                assert_eq!(&req.payload[12..16], &0u32.to_le_bytes());
+
+                // Metadata-only records: no payload_len or payload bytes.
+                let mut resp = Vec::new();
+                resp.write_u32::<LittleEndian>(2).unwrap();
+                for (turn_id, size) in [(1u64, 5_000_000u32), (2, 12)] {
+                    resp.write_u64::<LittleEndian>(turn_id).unwrap();
+                    resp.write_u64::<LittleEndian>(turn_id - 1).unwrap();
+                    resp.write_u32::<LittleEndian>(turn_id as u32 - 1).unwrap();
+                    resp.write_u32::<LittleEndian>(1).unwrap();
+                    resp.extend_from_slice(b"t");
+                    resp.write_u32::<LittleEndian>(1).unwrap();
+                    resp.write_u32::<LittleEndian>(ENCODING_MSGPACK).unwrap();
+                    resp.write_u32::<LittleEndian>(0).unwrap();
+                    resp.write_u32::<LittleEndian>(size).unwrap();
+                    resp.extend_from_slice(&[turn_id as u8; 32]);
+                }
+                resp.extend_from_slice(&[1, 1, 0, 0, 1]);
+                if with_item_types {
+                    resp.write_u16::<LittleEndian>(11).unwrap();
+                    resp.extend_from_slice(b"tool_result");
+                    resp.write_u16::<LittleEndian>(0).unwrap();
+                }
+                write_frame(&mut stream, MSG_GET_LAST, 0, req.header.req_id, &resp).unwrap();
+            }
+            flags
+        });
+
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let opts = GetLastOptions {
+            include_payload: true,
+            ..GetLastOptions::default()
+        };
+        let headers = client.get_last_headers(&ctx, 1, opts.clone()).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].payload_size, 5_000_000);
+        assert_eq!(headers[0].item_type.as_deref(), Some("tool_result"));
+        assert_eq!(headers[1].payload_size, 12);
+        assert_eq!(headers[1].item_type, None);
+        assert!(headers[1].is_checkpoint);
+        assert_eq!(headers[1].payload_hash, [2u8; 32]);
+
+        // A server without item types still yields sizes.
+        let headers = client.get_last_headers(&ctx, 1, opts).unwrap();
+        assert_eq!(headers[0].payload_size, 5_000_000);
+        assert!(headers.iter().all(|h| h.item_type.is_none()));
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![GET_LAST_FLAG_ITEM_TYPES, GET_LAST_FLAG_ITEM_TYPES]
+        );
+    }
+
+    #[test]
+    fn get_last_offset_is_sent_only_when_set() {
+        use crate::protocol::{read_frame, write_frame};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = read_frame(&mut stream).unwrap();
+            write_frame(
+                &mut stream,
+                hello.header.msg_type,
+                0,
+                hello.header.req_id,
+                &[0; 10],
+            )
+            .unwrap();
+            let mut lens = Vec::new();
+            for _ in 0..4 {
+                let req = read_frame(&mut stream).unwrap();
+                lens.push((req.payload.len(), req.header.flags));
+                let mut resp = Vec::new();
+                resp.write_u32::<LittleEndian>(0).unwrap();
+                resp.push(1);
+                write_frame(&mut stream, MSG_GET_LAST, 0, req.header.req_id, &resp).unwrap();
+                if req.payload.len() == 20 {
+                    assert_eq!(&req.payload[16..], &200u32.to_le_bytes());
+                }
+                if req.payload.len() > 20 {
+                    // Item types force the offset field, then count + len-prefixed names.
+                    assert_eq!(&req.payload[16..20], &0u32.to_le_bytes());
+                    assert_eq!(&req.payload[20..24], &1u32.to_le_bytes());
+                    assert_eq!(&req.payload[24..26], &10u16.to_le_bytes());
+                    assert_eq!(&req.payload[26..], b"user_input");
+                }
+            }
+            lens
+        });
+
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let page = client
+            .get_last_page(&ctx, 1, GetLastOptions::default())
+            .unwrap();
+        assert!(page.records.is_empty());
+        assert!(page.has_more);
+        let opts = GetLastOptions {
+            offset: 200,
+            limit: 100,
+            ..GetLastOptions::default()
+        };
+        client.get_last_page(&ctx, 1, opts).unwrap();
+        let opts = GetLastOptions {
+            item_types: vec!["user_input".to_string()],
+            ..GetLastOptions::default()
+        };
+        client.get_last_page(&ctx, 1, opts).unwrap();
+        let opts = GetLastOptions {
+            summary: true,
+            ..GetLastOptions::default()
+        };
+        client.get_last_page(&ctx, 1, opts).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![(16, 0), (20, 0), (36, 0), (16, GET_LAST_FLAG_SUMMARY)]
+        );
+    }
+
+    #[test]
+    fn get_last_min_head_turn_id_is_sent_and_not_ready_is_typed() {
+        use crate::protocol::{read_frame, write_frame, MSG_ERROR};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = read_frame(&mut stream).unwrap();
+            write_frame(
+                &mut stream,
+                hello.header.msg_type,
+                0,
+                hello.header.req_id,
+                &[0; 10],
+            )
+            .unwrap();
+
+            // Offset and an empty type list are sent so the id can follow.
+            let req = read_frame(&mut stream).unwrap();
+            assert_eq!(req.header.flags, GET_LAST_FLAG_MIN_HEAD);
+            assert_eq!(req.payload.len(), 32);
+            assert_eq!(&req.payload[16..24], &[0; 8]);
+            assert_eq!(&req.payload[24..], &42u64.to_le_bytes());
+            let mut detail = Vec::new();
+            detail.write_u32::<LittleEndian>(425).unwrap();
+            let msg = b"turn 42 has not been appended here yet";
+            detail.write_u32::<LittleEndian>(msg.len() as u32).unwrap();
+            detail.extend_from_slice(msg);
+            write_frame(&mut stream, MSG_ERROR, 0, req.header.req_id, &detail).unwrap();
+
+            let req = read_frame(&mut stream).unwrap();
+            let mut resp = Vec::new();
+            resp.write_u32::<LittleEndian>(0).unwrap();
+            resp.push(0);
+            write_frame(&mut stream, MSG_GET_LAST, 0, req.header.req_id, &resp).unwrap();
+        });
+
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let opts = GetLastOptions {
+            min_head_turn_id: 42,
+            ..GetLastOptions::default()
+        };
+        let err = client.get_last(&ctx, 1, opts.clone()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::NotReady {
+                    min_head_turn_id: 42
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(client.get_last(&ctx, 1, opts).unwrap().is_empty());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_last_raw_returns_stored_bytes_and_checks_hash() {
+        use crate::protocol::{read_frame, write_frame};
+        use crate::test_util::page_payload;
+
+        // Field 99 is not part of ConversationItem; it must come back intact.
+        let item = rmpv::Value::Map(vec![
+            (rmpv::Value::from(1), rmpv::Value::from("user_input")),
+            (rmpv::Value::from(99), rmpv::Value::from("from the future")),
+        ]);
+        let mut body = Vec::new();
+        rmpv::encode::write_value(&mut body, &item).unwrap();
+        let expected = body.clone();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = read_frame(&mut stream).unwrap();
+            write_frame(
+                &mut stream,
+                hello.header.msg_type,
+                0,
+                hello.header.req_id,
+                &[0; 10],
+            )
+            .unwrap();
+            for tamper in [false, true] {
+                let req = read_frame(&mut stream).unwrap();
+                assert_eq!(&req.payload[12..16], &1u32.to_le_bytes());
+                let mut resp = page_payload(5, 4, &body);
+                if tamper {
+                    // Flip a payload byte, which sits before the 2-byte trailer.
+                    let at = resp.len() - 3;
+                    resp[at] ^= 0xFF;
+                }
+                write_frame(&mut stream, MSG_GET_LAST, 0, req.header.req_id, &resp).unwrap();
+            }
+        });
+
+        let client = crate::dial(&addr, Vec::new()).unwrap();
+        let ctx = RequestContext::background();
+        let turns = client
+            .get_last_raw(&ctx, 1, GetLastOptions::default())
+            .unwrap();
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].turn_id, 5);
+        assert_eq!(turns[0].type_id, "test");
+        assert_eq!(turns[0].payload, expected);
+
+        let err = client
+            .get_last_raw(&ctx, 1, GetLastOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+        server.join().unwrap();
+    }
 }
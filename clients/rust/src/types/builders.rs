@@ -25,6 +25,15 @@ impl ConversationItem {
         }
         self
     }
+
+    /// Sets extension `name` (see `ConversationItem::extensions`),
+    /// replacing any value already stored under it.
+    pub fn with_extension(&mut self, name: impl Into<String>, value: rmpv::Value) -> &mut Self {
+        self.extensions
+            .get_or_insert_with(Default::default)
+            .insert(name.into(), value);
+        self
+    }
 }
 
 pub fn new_user_input(text: impl Into<String>, files: Vec<String>) -> ConversationItem {
@@ -44,6 +53,7 @@ pub fn new_user_input(text: impl Into<String>, files: Vec<String>) -> Conversati
         tool_call: None,
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -70,6 +80,7 @@ pub fn new_assistant_turn(text: impl Into<String>) -> ConversationItem {
         tool_call: None,
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -162,6 +173,11 @@ impl AssistantTurnBuilder {
         self
     }
 
+    pub fn with_extension(&mut self, name: impl Into<String>, value: rmpv::Value) -> &mut Self {
+        self.item.with_extension(name, value);
+        self
+    }
+
     pub fn build(self) -> ConversationItem {
         self.item
     }
@@ -272,6 +288,7 @@ pub fn new_handoff(from_agent: impl Into<String>, to_agent: impl Into<String>) -
         tool_call: None,
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -347,6 +364,7 @@ fn new_system_message(kind: String, content: impl Into<String>) -> ConversationI
         tool_call: None,
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -400,6 +418,7 @@ pub fn new_assistant(text: impl Into<String>) -> ConversationItem {
         tool_call: None,
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -476,6 +495,7 @@ pub fn new_tool_call(
         }),
         tool_result: None,
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -535,8 +555,10 @@ pub fn new_tool_result(
             streaming_output: String::new(),
             output_truncated: false,
             duration_ms: 0,
+            content_blob: None,
         }),
         context_metadata: None,
+        extensions: None,
     }
 }
 
@@ -589,6 +611,14 @@ impl ToolResultBuilder {
         self
     }
 
+    /// References output already stored with `put_blob` instead of inlining it.
+    pub fn with_content_blob(&mut self, hash: [u8; 32]) -> &mut Self {
+        if let Some(result) = &mut self.item.tool_result {
+            result.content_blob = Some(hash);
+        }
+        self
+    }
+
     pub fn build(self) -> ConversationItem {
         self.item
     }
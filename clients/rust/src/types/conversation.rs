@@ -48,38 +48,51 @@ pub const SystemKindRewind: &str = "rewind";
 pub struct ConversationItem {
     #[serde(rename = "1")]
     pub item_type: ItemType,
-    #[serde(rename = "2", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "2", default, skip_serializing_if = "String::is_empty")]
     pub status: ItemStatus,
-    #[serde(rename = "3", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "3", default, skip_serializing_if = "is_zero_i64")]
     pub timestamp: i64,
-    #[serde(rename = "4", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "4", default, skip_serializing_if = "String::is_empty")]
     pub id: String,
 
-    #[serde(rename = "10")]
+    #[serde(rename = "10", skip_serializing_if = "Option::is_none")]
     pub user_input: Option<UserInput>,
-    #[serde(rename = "11")]
+    #[serde(rename = "11", skip_serializing_if = "Option::is_none")]
     pub turn: Option<AssistantTurn>,
-    #[serde(rename = "12")]
+    #[serde(rename = "12", skip_serializing_if = "Option::is_none")]
     pub system: Option<SystemMessage>,
-    #[serde(rename = "13")]
+    #[serde(rename = "13", skip_serializing_if = "Option::is_none")]
     pub handoff: Option<HandoffInfo>,
 
-    #[serde(rename = "20")]
+    #[serde(rename = "20", skip_serializing_if = "Option::is_none")]
     pub assistant: Option<Assistant>,
-    #[serde(rename = "21")]
+    #[serde(rename = "21", skip_serializing_if = "Option::is_none")]
     pub tool_call: Option<ToolCall>,
-    #[serde(rename = "22")]
+    #[serde(rename = "22", skip_serializing_if = "Option::is_none")]
     pub tool_result: Option<ToolResult>,
 
-    #[serde(rename = "30")]
+    #[serde(rename = "30", skip_serializing_if = "Option::is_none")]
     pub context_metadata: Option<ContextMetadata>,
+
+    /// Integrator-defined data, keyed by a name the integrator owns (e.g.
+    /// `"acme.review/v2"`, putting the schema version in the name). CXDB
+    /// does not interpret the values. Each is stored as its own msgpack
+    /// encoding in a binary field, so it decodes exactly as written: map
+    /// key order, integer keys and ext types included.
+    #[serde(
+        rename = "100",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "extension_values"
+    )]
+    pub extensions: Option<std::collections::HashMap<String, rmpv::Value>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UserInput {
     #[serde(rename = "1")]
     pub text: String,
-    #[serde(rename = "2", skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "2", default, skip_serializing_if = "Vec::is_empty")]
     pub files: Vec<String>,
 }
 
@@ -87,19 +100,19 @@ pub struct UserInput {
 pub struct AssistantTurn {
     #[serde(rename = "1")]
     pub text: String,
-    #[serde(rename = "2", skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "2", default, skip_serializing_if = "Vec::is_empty")]
     pub tool_calls: Vec<ToolCallItem>,
-    #[serde(rename = "3", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "3", default, skip_serializing_if = "String::is_empty")]
     pub reasoning: String,
-    #[serde(rename = "4")]
+    #[serde(rename = "4", skip_serializing_if = "Option::is_none")]
     pub metrics: Option<TurnMetrics>,
-    #[serde(rename = "5", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "5", default, skip_serializing_if = "String::is_empty")]
     pub agent: String,
-    #[serde(rename = "6", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "6", default, skip_serializing_if = "is_zero_i64")]
     pub turn_number: i64,
-    #[serde(rename = "7", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "7", default, skip_serializing_if = "is_zero_i64")]
     pub max_turns: i64,
-    #[serde(rename = "8", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "8", default, skip_serializing_if = "String::is_empty")]
     pub finish_reason: String,
 }
 
@@ -113,17 +126,17 @@ pub struct ToolCallItem {
     pub args: String,
     #[serde(rename = "4")]
     pub status: ToolCallStatus,
-    #[serde(rename = "5", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "5", default, skip_serializing_if = "String::is_empty")]
     pub description: String,
-    #[serde(rename = "6", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "6", default, skip_serializing_if = "String::is_empty")]
     pub streaming_output: String,
-    #[serde(rename = "7", skip_serializing_if = "is_false")]
+    #[serde(rename = "7", default, skip_serializing_if = "is_false")]
     pub streaming_output_truncated: bool,
-    #[serde(rename = "8")]
+    #[serde(rename = "8", skip_serializing_if = "Option::is_none")]
     pub result: Option<ToolCallResult>,
-    #[serde(rename = "9")]
+    #[serde(rename = "9", skip_serializing_if = "Option::is_none")]
     pub error: Option<ToolCallError>,
-    #[serde(rename = "10", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "10", default, skip_serializing_if = "is_zero_i64")]
     pub duration_ms: i64,
 }
 
@@ -131,21 +144,21 @@ pub struct ToolCallItem {
 pub struct ToolCallResult {
     #[serde(rename = "1")]
     pub content: String,
-    #[serde(rename = "2", skip_serializing_if = "is_false")]
+    #[serde(rename = "2", default, skip_serializing_if = "is_false")]
     pub content_truncated: bool,
     #[serde(rename = "3")]
     pub success: bool,
-    #[serde(rename = "4")]
+    #[serde(rename = "4", skip_serializing_if = "Option::is_none")]
     pub exit_code: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ToolCallError {
-    #[serde(rename = "1", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "1", default, skip_serializing_if = "String::is_empty")]
     pub code: String,
     #[serde(rename = "2")]
     pub message: String,
-    #[serde(rename = "3")]
+    #[serde(rename = "3", skip_serializing_if = "Option::is_none")]
     pub exit_code: Option<i64>,
 }
 
@@ -157,13 +170,13 @@ pub struct TurnMetrics {
     pub output_tokens: i64,
     #[serde(rename = "3")]
     pub total_tokens: i64,
-    #[serde(rename = "4")]
+    #[serde(rename = "4", skip_serializing_if = "Option::is_none")]
     pub cached_tokens: Option<i64>,
-    #[serde(rename = "5")]
+    #[serde(rename = "5", skip_serializing_if = "Option::is_none")]
     pub reasoning_tokens: Option<i64>,
-    #[serde(rename = "6")]
+    #[serde(rename = "6", skip_serializing_if = "Option::is_none")]
     pub duration_ms: Option<i64>,
-    #[serde(rename = "7", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "7", default, skip_serializing_if = "String::is_empty")]
     pub model: String,
 }
 
@@ -171,7 +184,7 @@ pub struct TurnMetrics {
 pub struct SystemMessage {
     #[serde(rename = "1")]
     pub kind: SystemKind,
-    #[serde(rename = "2", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "2", default, skip_serializing_if = "String::is_empty")]
     pub title: String,
     #[serde(rename = "3")]
     pub content: String,
@@ -183,11 +196,11 @@ pub struct HandoffInfo {
     pub from_agent: String,
     #[serde(rename = "2")]
     pub to_agent: String,
-    #[serde(rename = "3", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "3", default, skip_serializing_if = "String::is_empty")]
     pub tool_name: String,
-    #[serde(rename = "4", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "4", default, skip_serializing_if = "String::is_empty")]
     pub input: String,
-    #[serde(rename = "5", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "5", default, skip_serializing_if = "String::is_empty")]
     pub reason: String,
 }
 
@@ -195,15 +208,15 @@ pub struct HandoffInfo {
 pub struct Assistant {
     #[serde(rename = "1")]
     pub text: String,
-    #[serde(rename = "2", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "2", default, skip_serializing_if = "String::is_empty")]
     pub reasoning: String,
-    #[serde(rename = "3", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "3", default, skip_serializing_if = "String::is_empty")]
     pub model: String,
-    #[serde(rename = "4", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "4", default, skip_serializing_if = "is_zero_i64")]
     pub input_tokens: i64,
-    #[serde(rename = "5", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "5", default, skip_serializing_if = "is_zero_i64")]
     pub output_tokens: i64,
-    #[serde(rename = "6", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "6", default, skip_serializing_if = "String::is_empty")]
     pub stop_reason: String,
 }
 
@@ -215,7 +228,7 @@ pub struct ToolCall {
     pub name: String,
     #[serde(rename = "3")]
     pub args: String,
-    #[serde(rename = "4", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "4", default, skip_serializing_if = "String::is_empty")]
     pub description: String,
 }
 
@@ -227,17 +240,28 @@ pub struct ToolResult {
     pub content: String,
     #[serde(rename = "3")]
     pub is_error: bool,
-    #[serde(rename = "4")]
+    #[serde(rename = "4", skip_serializing_if = "Option::is_none")]
     pub exit_code: Option<i64>,
-    #[serde(rename = "5", skip_serializing_if = "String::is_empty")]
+    #[serde(rename = "5", default, skip_serializing_if = "String::is_empty")]
     pub streaming_output: String,
-    #[serde(rename = "6", skip_serializing_if = "is_false")]
+    #[serde(rename = "6", default, skip_serializing_if = "is_false")]
     pub output_truncated: bool,
-    #[serde(rename = "7", skip_serializing_if = "is_zero_i64")]
+    #[serde(rename = "7", default, skip_serializing_if = "is_zero_i64")]
     pub duration_ms: i64,
+    /// Hash of a blob (uploaded with `put_blob`) holding the full output,
+    /// for results too large to inline. `content` may then be empty or a
+    /// short preview; the server substitutes the blob when rendering.
+    #[serde(
+        rename = "8",
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bytes"
+    )]
+    pub content_blob: Option<[u8; 32]>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
 pub struct ContextMetadata {
     #[serde(rename = "1", skip_serializing_if = "String::is_empty")]
     pub client_tag: String,
@@ -247,10 +271,34 @@ pub struct ContextMetadata {
     pub labels: Vec<String>,
     #[serde(rename = "4", skip_serializing_if = "map_is_empty")]
     pub custom: std::collections::HashMap<String, String>,
-    #[serde(rename = "10")]
+    #[serde(rename = "10", skip_serializing_if = "Option::is_none")]
     pub provenance: Option<super::provenance::Provenance>,
 }
 
+impl ContextMetadata {
+    /// Metadata titled `title` whose provenance describes the current
+    /// process, as `capture_process_provenance` captures it. The variables
+    /// in `DefaultEnvAllowlist` are recorded unless `opts` includes its own
+    /// `with_env_vars`; `opts` are applied after that.
+    pub fn with_process_provenance(
+        title: impl Into<String>,
+        service_name: impl Into<String>,
+        service_version: impl Into<String>,
+        opts: impl IntoIterator<Item = super::provenance::ProvenanceOption>,
+    ) -> Self {
+        let opts = std::iter::once(super::provenance::with_env_vars(None)).chain(opts);
+        Self {
+            title: title.into(),
+            provenance: Some(super::provenance::capture_process_provenance(
+                service_name,
+                service_version,
+                opts,
+            )),
+            ..Self::default()
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 pub fn Now() -> i64 {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -260,6 +308,54 @@ pub fn Now() -> i64 {
         .unwrap_or(0)
 }
 
+/// Serde for `ConversationItem::extensions`: a map from name to the msgpack
+/// encoding of each value, as binary.
+mod extension_values {
+    use std::collections::HashMap;
+
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_bytes::ByteBuf;
+
+    pub(super) fn serialize<S: Serializer>(
+        extensions: &Option<HashMap<String, rmpv::Value>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let Some(extensions) = extensions else {
+            return serializer.serialize_none();
+        };
+        let mut encoded = HashMap::with_capacity(extensions.len());
+        for (name, value) in extensions {
+            let mut bytes = Vec::new();
+            rmpv::encode::write_value(&mut bytes, value).map_err(S::Error::custom)?;
+            encoded.insert(name.as_str(), ByteBuf::from(bytes));
+        }
+        encoded.serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<HashMap<String, rmpv::Value>>, D::Error> {
+        let Some(encoded) = Option::<HashMap<String, ByteBuf>>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        let mut extensions = HashMap::with_capacity(encoded.len());
+        for (name, bytes) in encoded {
+            let mut rest = bytes.as_slice();
+            let value = rmpv::decode::read_value(&mut rest)
+                .map_err(|err| D::Error::custom(format!("extension {name:?}: {err}")))?;
+            if !rest.is_empty() {
+                return Err(D::Error::custom(format!(
+                    "extension {name:?}: trailing bytes after value"
+                )));
+            }
+            extensions.insert(name, value);
+        }
+        Ok(Some(extensions))
+    }
+}
+
 fn is_zero_i64(value: &i64) -> bool {
     *value == 0
 }
@@ -8,12 +8,13 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
 pub struct Provenance {
-    #[serde(rename = "1")]
+    #[serde(rename = "1", skip_serializing_if = "Option::is_none")]
     pub parent_context_id: Option<u64>,
     #[serde(rename = "2", skip_serializing_if = "String::is_empty")]
     pub spawn_reason: String,
-    #[serde(rename = "3")]
+    #[serde(rename = "3", skip_serializing_if = "Option::is_none")]
     pub root_context_id: Option<u64>,
 
     #[serde(rename = "10", skip_serializing_if = "String::is_empty")]
@@ -57,7 +58,7 @@ pub struct Provenance {
     #[serde(rename = "51", skip_serializing_if = "is_zero_i64")]
     pub client_port: i64,
 
-    #[serde(rename = "60")]
+    #[serde(rename = "60", skip_serializing_if = "Option::is_none")]
     pub env_vars: Option<HashMap<String, String>>,
 
     #[serde(rename = "70", skip_serializing_if = "String::is_empty")]
@@ -38,10 +38,45 @@ fn fixture_conversation_item() -> ConversationItem {
 
 #[test]
 fn msgpack_conversation_item_matches_fixture() {
+    // The Go fixture carries explicit nils for absent fields; both forms
+    // must decode to the same item.
     let fixture = load_msgpack_fixture("msgpack_conversation_item");
     let item = fixture_conversation_item();
+    let from_go: ConversationItem = decode_msgpack_into(&decode_hex(&fixture.payload_hex)).unwrap();
+    assert_eq!(from_go, item);
+
+    let payload = encode_msgpack(&item).unwrap();
+    let roundtrip: ConversationItem = decode_msgpack_into(&payload).unwrap();
+    assert_eq!(roundtrip, item);
+}
+
+#[test]
+fn msgpack_omits_absent_optional_fields() {
+    fn assert_no_nil(value: &Value) {
+        match value {
+            Value::Nil => panic!("encoded payload contains nil"),
+            Value::Map(entries) => entries.iter().for_each(|(_, v)| assert_no_nil(v)),
+            Value::Array(items) => items.iter().for_each(assert_no_nil),
+            _ => {}
+        }
+    }
+
+    let mut item = fixture_conversation_item();
+    item.turn = new_assistant_turn("hi").turn;
     let payload = encode_msgpack(&item).unwrap();
-    assert_eq!(hex::encode(payload), fixture.payload_hex);
+    let value = rmpv::decode::read_value(&mut std::io::Cursor::new(&payload)).unwrap();
+    assert_no_nil(&value);
+    let keys: Vec<_> = value
+        .as_map()
+        .unwrap()
+        .iter()
+        .filter_map(|(k, _)| k.as_str())
+        .collect();
+    assert!(keys.contains(&"10") && keys.contains(&"11") && keys.contains(&"30"));
+    assert!(!keys.contains(&"12") && !keys.contains(&"21"));
+
+    let decoded: ConversationItem = decode_msgpack_into(&payload).unwrap();
+    assert_eq!(decoded, item);
 }
 
 #[test]
@@ -111,6 +146,89 @@ fn decode_msgpack_into_conversation_item() {
     );
 }
 
+fn sample_extension() -> Value {
+    Value::Map(vec![
+        (Value::from(1), Value::from("int key")),
+        (
+            Value::from("nested"),
+            Value::Array(vec![Value::Nil, Value::from(true)]),
+        ),
+        (Value::from("bin"), Value::Binary(vec![0, 159, 255])),
+        (Value::from("neg"), Value::from(-42)),
+        (Value::from("f32"), Value::F32(1.5)),
+        (Value::from("f64"), Value::F64(-0.25)),
+        (Value::from("ext"), Value::Ext(7, vec![1, 2, 3])),
+    ])
+}
+
+#[test]
+fn extensions_roundtrip_arbitrary_msgpack() {
+    let mut item = new_user_input("hi", Vec::new());
+    item.with_extension("acme.review/v2", sample_extension());
+    let payload = encode_msgpack(&item).unwrap();
+    let decoded: ConversationItem = decode_msgpack_into(&payload).unwrap();
+    assert_eq!(
+        decoded.extensions.unwrap()["acme.review/v2"],
+        sample_extension()
+    );
+
+    // Writers using integer field tags (e.g. the Go client) decode alike.
+    let mut encoded = Vec::new();
+    rmpv::encode::write_value(&mut encoded, &sample_extension()).unwrap();
+    let go_style = Value::Map(vec![
+        (Value::from(1), Value::from(ItemTypeUserInput)),
+        (
+            Value::from(10),
+            Value::Map(vec![(Value::from(1), Value::from("hi"))]),
+        ),
+        (
+            Value::from(100),
+            Value::Map(vec![(
+                Value::from("acme.review/v2"),
+                Value::Binary(encoded),
+            )]),
+        ),
+    ]);
+    let mut payload = Vec::new();
+    rmpv::encode::write_value(&mut payload, &go_style).unwrap();
+    let decoded: ConversationItem = decode_msgpack_into(&payload).unwrap();
+    assert_eq!(
+        decoded.extensions.unwrap()["acme.review/v2"],
+        sample_extension()
+    );
+}
+
+#[test]
+fn tool_result_content_blob_roundtrips_as_binary() {
+    let hash = *blake3::hash(b"large tool output").as_bytes();
+    let mut builder = build_tool_result("call-1", "");
+    builder.with_content_blob(hash);
+    let mut item = builder.build();
+    item.id = "item-1".to_string();
+    let payload = encode_msgpack(&item).unwrap();
+
+    let value = rmpv::decode::read_value(&mut std::io::Cursor::new(&payload)).unwrap();
+    let result = value
+        .as_map()
+        .and_then(|m| m.iter().find(|(k, _)| k.as_str() == Some("22")))
+        .map(|(_, v)| v.clone())
+        .expect("tool_result");
+    let blob = result
+        .as_map()
+        .and_then(|m| m.iter().find(|(k, _)| k.as_str() == Some("8")))
+        .map(|(_, v)| v.clone())
+        .expect("content_blob");
+    assert_eq!(blob, Value::Binary(hash.to_vec()));
+
+    let decoded: ConversationItem = decode_msgpack_into(&payload).unwrap();
+    assert_eq!(decoded.tool_result.unwrap().content_blob, Some(hash));
+
+    item.tool_result = build_tool_result("call-1", "ok").build().tool_result;
+    let inline = encode_msgpack(&item).unwrap();
+    let decoded: ConversationItem = decode_msgpack_into(&inline).unwrap();
+    assert_eq!(decoded.tool_result.unwrap().content_blob, None);
+}
+
 #[test]
 fn capture_process_provenance_populates_fields() {
     let p = capture_process_provenance("test-service", "1.0.0", Vec::<ProvenanceOption>::new());
@@ -122,6 +240,36 @@ fn capture_process_provenance_populates_fields() {
     assert!(p.captured_at > 0);
 }
 
+#[test]
+fn context_metadata_with_process_provenance() {
+    std::env::set_var("SERVICE_NAME", "from-env");
+    let meta = ContextMetadata::with_process_provenance(
+        "triage",
+        "test-service",
+        "1.0.0",
+        vec![with_correlation_id("req-1")],
+    );
+    assert_eq!(meta.title, "triage");
+    let p = meta.provenance.expect("provenance");
+    assert_eq!(p.service_name, "test-service");
+    assert_eq!(p.process_pid, std::process::id() as i64);
+    assert_eq!(p.correlation_id, "req-1");
+    assert_eq!(
+        p.env_vars.unwrap().get("SERVICE_NAME").map(String::as_str),
+        Some("from-env")
+    );
+
+    // A caller-supplied allowlist replaces the default.
+    let meta = ContextMetadata::with_process_provenance(
+        "triage",
+        "test-service",
+        "1.0.0",
+        vec![with_env_vars(Some(vec!["NONEXISTENT_VAR".to_string()]))],
+    );
+    assert_eq!(meta.provenance.unwrap().env_vars, None);
+    std::env::remove_var("SERVICE_NAME");
+}
+
 #[test]
 fn new_provenance_inherits_and_overrides() {
     let base = capture_process_provenance("test-service", "1.0.0", Vec::<ProvenanceOption>::new());
@@ -0,0 +1,66 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bloom filter over blob content hashes.
+//!
+//! Keys are already uniformly distributed cryptographic hashes, so bit positions
+//! are derived directly from the key bytes (Kirsch–Mitzenmacher double hashing
+//! over two 64-bit words) instead of re-hashing.
+
+const BITS_PER_ITEM: usize = 10;
+const NUM_HASHES: u64 = 7;
+const MIN_CAPACITY: usize = 1 << 16;
+
+pub struct BlobBloom {
+    bits: Vec<u64>,
+    num_bits: u64,
+    capacity: usize,
+    len: usize,
+}
+
+impl BlobBloom {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let num_bits = (capacity * BITS_PER_ITEM) as u64;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Number of keys the filter was sized for; past this the false-positive
+    /// rate climbs and the owner should rebuild with a larger capacity.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn insert(&mut self, hash: &[u8; 32]) {
+        for bit in self.positions(hash) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    /// False means the key was definitely never inserted.
+    pub fn may_contain(&self, hash: &[u8; 32]) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn positions(&self, hash: &[u8; 32]) -> impl Iterator<Item = u64> {
+        let mut a = [0u8; 8];
+        let mut b = [0u8; 8];
+        a.copy_from_slice(&hash[0..8]);
+        b.copy_from_slice(&hash[8..16]);
+        let h1 = u64::from_le_bytes(a);
+        let h2 = u64::from_le_bytes(b) | 1;
+        let num_bits = self.num_bits;
+        (0..NUM_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
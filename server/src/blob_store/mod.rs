@@ -1,6 +1,17 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
+//! Content-addressed blob storage.
+//!
+//! Blob records are appended to pack files and located through `blobs.idx`.
+//! By default everything goes to a single `blobs.pack`. With a pack target
+//! size set, a put that would grow the active pack past it starts a new
+//! segment instead: `blobs.1.pack`, `blobs.2.pack`, and so on. Segments
+//! other than the last are never written again, so backups can copy them
+//! once. Each index entry records its segment in the (formerly reserved)
+//! last two bytes; 0 is `blobs.pack`, so stores written before segments
+//! existed read unchanged.
+
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -9,11 +20,28 @@ use std::path::{Path, PathBuf};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher;
 
+use crate::check::{
+    ensure_trailing_only, truncate_tail, valid_prefix_len, CheckReport, RepairReport,
+};
 use crate::error::{Result, StoreError};
 
+mod bloom;
+mod upload;
+
+use bloom::BlobBloom;
+pub use upload::BlobUploads;
+
 const BLOB_MAGIC: u32 = 0x42534C42; // 'B''S''L''B'
 const BLOB_VERSION: u16 = 1;
 
+/// Size of one blobs.idx entry: hash(32) + offset(8) + raw_len(4) + stored_len(4) + codec(2) + segment(2).
+const INDEX_ENTRY_LEN: usize = 32 + 8 + 4 + 4 + 2 + 2;
+
+/// Size of a pack record header: magic, version, codec, raw_len, stored_len, hash.
+const PACK_HEADER_LEN: u64 = 4 + 2 + 2 + 4 + 4 + 32;
+
+const BLOBS_IDX: &str = "blobs/blobs.idx";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlobCodec {
     None = 0,
@@ -22,49 +50,76 @@ pub enum BlobCodec {
 
 #[derive(Debug, Clone)]
 pub struct BlobIndexEntry {
+    /// Pack segment holding the record (0 is `blobs.pack`).
+    pub segment: u16,
     pub offset: u64,
     pub raw_len: u32,
     pub stored_len: u32,
     pub codec: BlobCodec,
 }
 
+/// File name of pack segment `segment` within the blobs directory.
+pub fn pack_file_name(segment: u16) -> String {
+    if segment == 0 {
+        "blobs.pack".to_string()
+    } else {
+        format!("blobs.{segment}.pack")
+    }
+}
+
+/// Number of pack segments present in `dir`: `blobs.pack` plus each
+/// consecutively numbered `blobs.N.pack` after it.
+pub fn pack_segment_count(dir: &Path) -> u16 {
+    let mut count = 1;
+    while count < u16::MAX && dir.join(pack_file_name(count)).exists() {
+        count += 1;
+    }
+    count
+}
+
 pub struct BlobStore {
-    pack_path: PathBuf,
+    dir: PathBuf,
     idx_path: PathBuf,
-    pack_file: File,
+    /// Open pack segments, indexed by segment number. The last is the one
+    /// new blobs are appended to.
+    packs: Vec<File>,
+    /// Start a new segment rather than grow the active one past this size.
+    pack_target_bytes: Option<u64>,
     idx_file: File,
     index: HashMap<[u8; 32], BlobIndexEntry>,
+    /// Fast negative check in front of `index`, rebuilt when it outgrows its capacity.
+    bloom: BlobBloom,
 }
 
 impl BlobStore {
     pub fn open(dir: &Path) -> Result<Self> {
         std::fs::create_dir_all(dir)?;
-        let pack_path = dir.join("blobs.pack");
         let idx_path = dir.join("blobs.idx");
 
-        let pack_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .read(true)
-            .write(true)
-            .open(&pack_path)?;
+        let mut packs = Vec::new();
+        for segment in 0..pack_segment_count(dir) {
+            packs.push(open_pack(dir, segment)?);
+        }
 
         let idx_file = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&idx_path)?;
 
         let mut store = Self {
-            pack_path,
+            dir: dir.to_path_buf(),
             idx_path,
-            pack_file,
+            packs,
+            pack_target_bytes: None,
             idx_file,
             index: HashMap::new(),
+            bloom: BlobBloom::with_capacity(0),
         };
 
         store.load_index()?;
+        store.rebuild_bloom(store.index.len() * 2);
         Ok(store)
     }
 
@@ -73,83 +128,132 @@ impl BlobStore {
         let mut buf = Vec::new();
         self.idx_file.read_to_end(&mut buf)?;
 
-        // Each index entry is 52 bytes: hash(32) + offset(8) + raw_len(4) + stored_len(4) + codec(2) + reserved(2)
-        const ENTRY_SIZE: usize = 32 + 8 + 4 + 4 + 2 + 2;
-
-        let mut cursor = std::io::Cursor::new(&buf);
-        let mut valid_len: u64 = 0;
-
-        while (cursor.position() as usize) < buf.len() {
-            let entry_start = cursor.position();
+        let valid_len = valid_prefix_len(&buf, INDEX_ENTRY_LEN as u64, |entry| {
+            decode_index_entry(entry).is_some()
+        });
+        if valid_len < buf.len() as u64 {
+            ensure_trailing_only(
+                BLOBS_IDX,
+                buf.len() as u64,
+                valid_len,
+                INDEX_ENTRY_LEN as u64,
+            )?;
+            // Truncate any partial entry at the end
+            self.idx_file.set_len(valid_len)?;
+        }
 
-            // Check if we have enough bytes for a complete entry
-            let remaining = buf.len() - entry_start as usize;
-            if remaining < ENTRY_SIZE {
-                // Partial entry - truncate and stop
-                break;
-            }
-
-            let mut hash = [0u8; 32];
-            if cursor.read_exact(&mut hash).is_err() {
-                break;
+        for chunk in buf[..valid_len as usize].chunks_exact(INDEX_ENTRY_LEN) {
+            if let Some((hash, entry)) = decode_index_entry(chunk) {
+                if entry.segment as usize >= self.packs.len() {
+                    return Err(StoreError::Corrupt(format!(
+                        "{BLOBS_IDX}: blob {} is in missing pack segment {}",
+                        hex::encode(hash),
+                        pack_file_name(entry.segment)
+                    )));
+                }
+                self.index.insert(hash, entry);
             }
+        }
 
-            // These reads should not fail given the size check above, but handle gracefully
-            let offset = match cursor.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            let raw_len = match cursor.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            let stored_len = match cursor.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            let codec_raw = match cursor.read_u16::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            let _reserved = match cursor.read_u16::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => break,
-            };
+        Ok(())
+    }
 
-            let codec = match codec_raw {
-                0 => BlobCodec::None,
-                1 => BlobCodec::Zstd,
-                _ => return Err(StoreError::Corrupt("unknown blob codec".into())),
-            };
+    /// Roll over to a new pack segment once the active one would grow past
+    /// `target` bytes. None keeps a single, unbounded `blobs.pack`.
+    pub fn set_pack_target_bytes(&mut self, target: Option<u64>) {
+        self.pack_target_bytes = target;
+    }
 
-            self.index.insert(
-                hash,
-                BlobIndexEntry {
-                    offset,
-                    raw_len,
-                    stored_len,
-                    codec,
-                },
-            );
+    /// Pack segment new blobs are appended to.
+    pub fn active_segment(&self) -> u16 {
+        (self.packs.len() - 1) as u16
+    }
 
-            valid_len = cursor.position();
+    /// Return the segment a record of `record_len` bytes should go to,
+    /// starting a new one if the active segment is full.
+    fn segment_for(&mut self, record_len: u64) -> Result<u16> {
+        let active = self.active_segment();
+        let Some(target) = self.pack_target_bytes else {
+            return Ok(active);
+        };
+        let len = self.packs[active as usize].metadata()?.len();
+        // An empty segment takes the record even if it alone exceeds the target.
+        if len == 0 || len + record_len <= target {
+            return Ok(active);
         }
+        let next = active
+            .checked_add(1)
+            .filter(|next| *next < u16::MAX)
+            .ok_or_else(|| StoreError::InvalidInput("blob pack segment limit reached".into()))?;
+        // A sealed segment is never written again; sync it once here so
+        // `sync_handles` only needs the active one.
+        self.packs[active as usize].sync_data()?;
+        self.packs.push(open_pack(&self.dir, next)?);
+        Ok(next)
+    }
 
-        // Truncate any partial entry at the end
-        if valid_len < buf.len() as u64 {
-            self.idx_file.set_len(valid_len)?;
+    /// Truncate blobs.idx at its first bad entry and blobs.pack after the last
+    /// indexed record (bytes past it were never indexed, e.g. a put torn by a
+    /// crash), recording what was discarded.
+    pub fn repair(dir: &Path, report: &mut RepairReport) -> Result<()> {
+        let idx_path = dir.join("blobs.idx");
+        let idx = match std::fs::read(&idx_path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let valid_len = valid_prefix_len(&idx, INDEX_ENTRY_LEN as u64, |entry| {
+            decode_index_entry(entry).is_some()
+        });
+        truncate_tail(
+            &idx_path,
+            BLOBS_IDX,
+            valid_len,
+            Some(INDEX_ENTRY_LEN as u64),
+            report,
+        )?;
+
+        let segments = pack_segment_count(dir);
+        let mut pack_ends = vec![0u64; segments as usize];
+        for (_, entry) in idx[..valid_len as usize]
+            .chunks_exact(INDEX_ENTRY_LEN)
+            .filter_map(decode_index_entry)
+        {
+            if let Some(end) = pack_ends.get_mut(entry.segment as usize) {
+                *end = (*end).max(entry.offset + PACK_HEADER_LEN + entry.stored_len as u64 + 4);
+            }
+        }
+        for (segment, pack_end) in pack_ends.into_iter().enumerate() {
+            let name = pack_file_name(segment as u16);
+            truncate_tail(
+                &dir.join(&name),
+                format!("blobs/{name}"),
+                pack_end,
+                None,
+                report,
+            )?;
         }
-
         Ok(())
     }
 
+    fn rebuild_bloom(&mut self, capacity: usize) {
+        let mut bloom = BlobBloom::with_capacity(capacity);
+        for hash in self.index.keys() {
+            bloom.insert(hash);
+        }
+        self.bloom = bloom;
+    }
+
     pub fn contains(&self, hash: &[u8; 32]) -> bool {
-        self.index.contains_key(hash)
+        self.bloom.may_contain(hash) && self.index.contains_key(hash)
     }
 
     pub fn put_if_absent(&mut self, hash: [u8; 32], raw_bytes: &[u8]) -> Result<BlobIndexEntry> {
-        if let Some(entry) = self.index.get(&hash) {
-            return Ok(entry.clone());
+        // Bloom miss means the blob is definitely new; only probe the index on a hit.
+        if self.bloom.may_contain(&hash) {
+            if let Some(entry) = self.index.get(&hash) {
+                return Ok(entry.clone());
+            }
         }
 
         let mut stored_bytes = raw_bytes.to_vec();
@@ -164,7 +268,9 @@ impl BlobStore {
         let raw_len = raw_bytes.len() as u32;
         let stored_len = stored_bytes.len() as u32;
 
-        let offset = self.pack_file.seek(SeekFrom::End(0))?;
+        let segment = self.segment_for(PACK_HEADER_LEN + stored_len as u64 + 4)?;
+        let pack_file = &mut self.packs[segment as usize];
+        let offset = pack_file.seek(SeekFrom::End(0))?;
 
         let mut header = Vec::with_capacity(4 + 2 + 2 + 4 + 4 + 32);
         header.write_u32::<LittleEndian>(BLOB_MAGIC)?;
@@ -179,10 +285,10 @@ impl BlobStore {
         hasher.update(&stored_bytes);
         let crc = hasher.finalize();
 
-        self.pack_file.write_all(&header)?;
-        self.pack_file.write_all(&stored_bytes)?;
-        self.pack_file.write_u32::<LittleEndian>(crc)?;
-        self.pack_file.flush()?;
+        pack_file.write_all(&header)?;
+        pack_file.write_all(&stored_bytes)?;
+        pack_file.write_u32::<LittleEndian>(crc)?;
+        pack_file.flush()?;
 
         // append to index
         let mut idx_entry = Vec::with_capacity(32 + 8 + 4 + 4 + 2 + 2);
@@ -191,18 +297,24 @@ impl BlobStore {
         idx_entry.write_u32::<LittleEndian>(raw_len)?;
         idx_entry.write_u32::<LittleEndian>(stored_len)?;
         idx_entry.write_u16::<LittleEndian>(codec as u16)?;
-        idx_entry.write_u16::<LittleEndian>(0)?;
+        idx_entry.write_u16::<LittleEndian>(segment)?;
         self.idx_file.seek(SeekFrom::End(0))?;
         self.idx_file.write_all(&idx_entry)?;
         self.idx_file.flush()?;
 
         let entry = BlobIndexEntry {
+            segment,
             offset,
             raw_len,
             stored_len,
             codec,
         };
         self.index.insert(hash, entry.clone());
+        if self.bloom.len() >= self.bloom.capacity() {
+            self.rebuild_bloom(self.index.len() * 2);
+        } else {
+            self.bloom.insert(&hash);
+        }
         Ok(entry)
     }
 
@@ -213,69 +325,152 @@ impl BlobStore {
             .ok_or_else(|| StoreError::NotFound("blob".into()))?
             .clone();
 
-        self.pack_file.seek(SeekFrom::Start(entry.offset))?;
+        read_blob(&mut self.packs[entry.segment as usize], entry.offset, hash)
+    }
+
+    /// Like `get`, but through `&self` using positional reads, so several
+    /// threads can read blobs at once.
+    pub fn get_shared(&self, hash: &[u8; 32]) -> Result<Vec<u8>> {
+        let entry = self
+            .index
+            .get(hash)
+            .ok_or_else(|| StoreError::NotFound("blob".into()))?;
 
-        let magic = self.pack_file.read_u32::<LittleEndian>()?;
-        if magic != BLOB_MAGIC {
+        let mut reader = PositionedReader {
+            file: &self.packs[entry.segment as usize],
+            pos: 0,
+        };
+        read_blob(&mut reader, entry.offset, hash)
+    }
+
+    /// Read up to `len` bytes of a blob's content starting at `offset`,
+    /// without loading the rest of it. Fewer bytes come back when the blob
+    /// ends first. Only the bytes needed are read (and, for compressed blobs,
+    /// decompressed), so the record checksum is not verified; use `get` when
+    /// integrity matters.
+    pub fn get_range(&mut self, hash: &[u8; 32], offset: u64, len: usize) -> Result<Vec<u8>> {
+        let entry = self
+            .index
+            .get(hash)
+            .ok_or_else(|| StoreError::NotFound("blob".into()))?
+            .clone();
+        let pack = &mut self.packs[entry.segment as usize];
+        pack.seek(SeekFrom::Start(entry.offset))?;
+        let mut header = [0u8; PACK_HEADER_LEN as usize];
+        pack.read_exact(&mut header)?;
+        if header[0..4] != BLOB_MAGIC.to_le_bytes() {
             return Err(StoreError::Corrupt("invalid blob magic".into()));
         }
-        let version = self.pack_file.read_u16::<LittleEndian>()?;
-        if version != BLOB_VERSION {
-            return Err(StoreError::Corrupt("unsupported blob version".into()));
-        }
-        let codec_raw = self.pack_file.read_u16::<LittleEndian>()?;
-        let raw_len = self.pack_file.read_u32::<LittleEndian>()?;
-        let stored_len = self.pack_file.read_u32::<LittleEndian>()?;
-        let mut stored_hash = [0u8; 32];
-        self.pack_file.read_exact(&mut stored_hash)?;
-
-        if &stored_hash != hash {
+        if &header[16..48] != hash {
             return Err(StoreError::Corrupt("blob hash mismatch".into()));
         }
 
-        let mut stored_bytes = vec![0u8; stored_len as usize];
-        self.pack_file.read_exact(&mut stored_bytes)?;
-        let crc = self.pack_file.read_u32::<LittleEndian>()?;
-
-        let mut header = Vec::with_capacity(4 + 2 + 2 + 4 + 4 + 32);
-        header.write_u32::<LittleEndian>(magic)?;
-        header.write_u16::<LittleEndian>(version)?;
-        header.write_u16::<LittleEndian>(codec_raw)?;
-        header.write_u32::<LittleEndian>(raw_len)?;
-        header.write_u32::<LittleEndian>(stored_len)?;
-        header.extend_from_slice(&stored_hash);
-
-        let mut hasher = Hasher::new();
-        hasher.update(&header);
-        hasher.update(&stored_bytes);
-        let actual_crc = hasher.finalize();
-        if crc != actual_crc {
-            return Err(StoreError::Corrupt("blob crc mismatch".into()));
+        let start = offset.min(entry.raw_len as u64);
+        let end = offset.saturating_add(len as u64).min(entry.raw_len as u64);
+        let mut out = vec![0u8; (end - start) as usize];
+        match entry.codec {
+            BlobCodec::None => {
+                pack.seek(SeekFrom::Current(start as i64))?;
+                pack.read_exact(&mut out)?;
+            }
+            BlobCodec::Zstd => {
+                let stored = (&mut *pack).take(entry.stored_len as u64);
+                let mut decoder = zstd::stream::read::Decoder::new(stored)
+                    .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?;
+                std::io::copy(&mut (&mut decoder).take(start), &mut std::io::sink())
+                    .and_then(|_| decoder.read_exact(&mut out))
+                    .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?;
+            }
         }
+        Ok(out)
+    }
 
-        let codec = match codec_raw {
-            0 => BlobCodec::None,
-            1 => BlobCodec::Zstd,
-            _ => return Err(StoreError::Corrupt("unknown blob codec".into())),
-        };
-
-        let raw_bytes = match codec {
-            BlobCodec::None => stored_bytes,
-            BlobCodec::Zstd => zstd::decode_all(&stored_bytes[..])
-                .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?,
-        };
-
-        if raw_bytes.len() as u32 != raw_len {
-            return Err(StoreError::Corrupt("blob length mismatch".into()));
+    /// Re-read blobs.idx from disk and verify every entry against its pack
+    /// segment: the record must lie within the pack, pass its checksum, and
+    /// its bytes must satisfy `verify_hash` for the indexed hash.
+    pub fn check(
+        &self,
+        report: &mut CheckReport,
+        verify_hash: impl Fn(&[u8; 32], &[u8]) -> bool,
+    ) -> Result<()> {
+        const IDX: &str = BLOBS_IDX;
+
+        let idx = std::fs::read(&self.idx_path)?;
+        if !idx.len().is_multiple_of(INDEX_ENTRY_LEN) {
+            report.issue(
+                IDX,
+                None,
+                format!("length {} is not a whole number of entries", idx.len()),
+            );
+        }
+        let mut packs = Vec::new();
+        for segment in 0..pack_segment_count(&self.dir) {
+            packs.push(File::open(self.dir.join(pack_file_name(segment)))?);
+        }
+        for (i, entry) in idx.chunks_exact(INDEX_ENTRY_LEN).enumerate() {
+            let entry_offset = (i * INDEX_ENTRY_LEN) as u64;
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&entry[0..32]);
+            let offset = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let stored_len = u32::from_le_bytes(entry[44..48].try_into().unwrap()) as u64;
+            let segment = u16::from_le_bytes(entry[50..52].try_into().unwrap());
+            let pack_name = pack_file_name(segment);
+            let Some(pack) = packs.get_mut(segment as usize) else {
+                report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!("blob {} is in missing {pack_name}", hex::encode(hash)),
+                );
+                continue;
+            };
+            let pack_len = pack.metadata()?.len();
+            let record_end = offset + PACK_HEADER_LEN + stored_len + 4;
+            if record_end > pack_len {
+                report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!(
+                        "blob {} record ends at {record_end}, past the end of {pack_name} ({pack_len} bytes)",
+                        hex::encode(hash)
+                    ),
+                );
+                continue;
+            }
+            match read_blob(pack, offset, &hash) {
+                Ok(data) if verify_hash(&hash, &data) => {}
+                Ok(_) => report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!("blob {} content does not match its hash", hex::encode(hash)),
+                ),
+                Err(err) => report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!("blob {}: {err}", hex::encode(hash)),
+                ),
+            }
+            report.blobs_checked += 1;
         }
+        Ok(())
+    }
 
-        Ok(raw_bytes)
+    /// Duplicated handles to the active pack segment and blobs.idx, for
+    /// fsyncing without holding the store.
+    pub fn sync_handles(&self) -> Result<Vec<File>> {
+        let mut files = vec![self.idx_file.try_clone()?];
+        if let Some(pack) = self.packs.last() {
+            files.push(pack.try_clone()?);
+        }
+        Ok(files)
     }
 
     pub fn stats(&self) -> BlobStoreStats {
         BlobStoreStats {
             blobs_total: self.index.len(),
-            pack_bytes: file_len(&self.pack_path),
+            pack_bytes: (0..self.packs.len())
+                .map(|segment| file_len(&self.dir.join(pack_file_name(segment as u16))))
+                .sum(),
+            pack_segments: self.packs.len(),
             idx_bytes: file_len(&self.idx_path),
         }
     }
@@ -294,10 +489,260 @@ impl BlobStore {
 #[derive(Debug, Clone)]
 pub struct BlobStoreStats {
     pub blobs_total: usize,
+    /// Total size of all pack segments.
     pub pack_bytes: u64,
+    pub pack_segments: usize,
     pub idx_bytes: u64,
 }
 
+/// Read and verify the pack record at `offset`, returning the raw bytes.
+fn read_blob<R: Read + Seek>(reader: &mut R, offset: u64, hash: &[u8; 32]) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let magic = reader.read_u32::<LittleEndian>()?;
+    if magic != BLOB_MAGIC {
+        return Err(StoreError::Corrupt("invalid blob magic".into()));
+    }
+    let version = reader.read_u16::<LittleEndian>()?;
+    if version != BLOB_VERSION {
+        return Err(StoreError::Corrupt("unsupported blob version".into()));
+    }
+    let codec_raw = reader.read_u16::<LittleEndian>()?;
+    let raw_len = reader.read_u32::<LittleEndian>()?;
+    let stored_len = reader.read_u32::<LittleEndian>()?;
+    let mut stored_hash = [0u8; 32];
+    reader.read_exact(&mut stored_hash)?;
+
+    if &stored_hash != hash {
+        return Err(StoreError::Corrupt("blob hash mismatch".into()));
+    }
+
+    let mut stored_bytes = vec![0u8; stored_len as usize];
+    reader.read_exact(&mut stored_bytes)?;
+    let crc = reader.read_u32::<LittleEndian>()?;
+
+    let mut header = Vec::with_capacity(4 + 2 + 2 + 4 + 4 + 32);
+    header.write_u32::<LittleEndian>(magic)?;
+    header.write_u16::<LittleEndian>(version)?;
+    header.write_u16::<LittleEndian>(codec_raw)?;
+    header.write_u32::<LittleEndian>(raw_len)?;
+    header.write_u32::<LittleEndian>(stored_len)?;
+    header.extend_from_slice(&stored_hash);
+
+    let mut hasher = Hasher::new();
+    hasher.update(&header);
+    hasher.update(&stored_bytes);
+    let actual_crc = hasher.finalize();
+    if crc != actual_crc {
+        return Err(StoreError::Corrupt("blob crc mismatch".into()));
+    }
+
+    let codec = match codec_raw {
+        0 => BlobCodec::None,
+        1 => BlobCodec::Zstd,
+        _ => return Err(StoreError::Corrupt("unknown blob codec".into())),
+    };
+
+    let raw_bytes = match codec {
+        BlobCodec::None => stored_bytes,
+        BlobCodec::Zstd => zstd::decode_all(&stored_bytes[..])
+            .map_err(|e| StoreError::Corrupt(format!("zstd decode failed: {e}")))?,
+    };
+
+    if raw_bytes.len() as u32 != raw_len {
+        return Err(StoreError::Corrupt("blob length mismatch".into()));
+    }
+
+    Ok(raw_bytes)
+}
+
+/// Reads a shared file at its own position without moving the file's
+/// cursor, so it can be used concurrently with other readers.
+struct PositionedReader<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(self.file, buf, self.pos)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PositionedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start")
+            })?,
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "seek from end",
+                ))
+            }
+        };
+        Ok(self.pos)
+    }
+}
+
+fn open_pack(dir: &Path, segment: u16) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(dir.join(pack_file_name(segment)))?)
+}
+
+/// Decode one blobs.idx entry, or None if its codec is unknown.
+fn decode_index_entry(entry: &[u8]) -> Option<([u8; 32], BlobIndexEntry)> {
+    let hash: [u8; 32] = entry.get(0..32)?.try_into().ok()?;
+    let mut cursor = std::io::Cursor::new(entry.get(32..INDEX_ENTRY_LEN)?);
+    let offset = cursor.read_u64::<LittleEndian>().ok()?;
+    let raw_len = cursor.read_u32::<LittleEndian>().ok()?;
+    let stored_len = cursor.read_u32::<LittleEndian>().ok()?;
+    let codec = match cursor.read_u16::<LittleEndian>().ok()? {
+        0 => BlobCodec::None,
+        1 => BlobCodec::Zstd,
+        _ => return None,
+    };
+    let segment = cursor.read_u16::<LittleEndian>().ok()?;
+    Some((
+        hash,
+        BlobIndexEntry {
+            segment,
+            offset,
+            raw_len,
+            stored_len,
+            codec,
+        },
+    ))
+}
+
 fn file_len(path: &PathBuf) -> u64 {
     std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn bloom_tracks_blobs_across_rebuilds() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = BlobStore::open(tmpdir.path()).unwrap();
+        let initial_capacity = store.bloom.capacity();
+
+        let mut hashes = Vec::new();
+        for i in 0..(initial_capacity + 10) as u32 {
+            let data = i.to_le_bytes();
+            let hash = *blake3::hash(&data).as_bytes();
+            store.put_if_absent(hash, &data).unwrap();
+            hashes.push(hash);
+        }
+
+        assert!(store.bloom.capacity() > initial_capacity);
+        assert!(hashes.iter().all(|h| store.contains(h)));
+        assert!(!store.contains(blake3::hash(b"never stored").as_bytes()));
+        assert_eq!(store.get(&hashes[0]).unwrap(), 0u32.to_le_bytes());
+    }
+
+    #[test]
+    fn get_range_reads_part_of_plain_and_compressed_blobs() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = BlobStore::open(tmpdir.path()).unwrap();
+
+        let compressible: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+        let incompressible: Vec<u8> = (0..1000u32)
+            .map(|i| blake3::hash(&i.to_le_bytes()).as_bytes()[0])
+            .collect();
+        for data in [&compressible, &incompressible] {
+            let hash = *blake3::hash(data).as_bytes();
+            let entry = store.put_if_absent(hash, data).unwrap();
+            assert_eq!(
+                entry.codec,
+                if data.len() == 10_000 {
+                    BlobCodec::Zstd
+                } else {
+                    BlobCodec::None
+                }
+            );
+
+            assert_eq!(store.get_range(&hash, 0, 16).unwrap(), data[..16]);
+            assert_eq!(store.get_range(&hash, 500, 100).unwrap(), data[500..600]);
+            let tail = data.len() as u64 - 10;
+            assert_eq!(
+                store.get_range(&hash, tail, 100).unwrap(),
+                data[data.len() - 10..]
+            );
+            assert!(store
+                .get_range(&hash, data.len() as u64 + 5, 10)
+                .unwrap()
+                .is_empty());
+        }
+        assert!(matches!(
+            store.get_range(blake3::hash(b"missing").as_bytes(), 0, 1),
+            Err(StoreError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn pack_rolls_over_to_new_segments() {
+        let tmpdir = TempDir::new().unwrap();
+        let blobs: Vec<(Vec<u8>, [u8; 32])> = (0..4u8)
+            .map(|i| {
+                // Incompressible, so each record is 152 bytes: two per segment.
+                let data: Vec<u8> = (0..100u32)
+                    .map(|j| blake3::hash(&[i, j as u8]).as_bytes()[0])
+                    .collect();
+                let hash = *blake3::hash(&data).as_bytes();
+                (data, hash)
+            })
+            .collect();
+
+        {
+            let mut store = BlobStore::open(tmpdir.path()).unwrap();
+            store.set_pack_target_bytes(Some(400));
+            for (data, hash) in &blobs {
+                store.put_if_absent(*hash, data).unwrap();
+            }
+            assert_eq!(store.active_segment(), 1);
+            assert_eq!(store.stats().pack_segments, 2);
+        }
+        assert!(tmpdir.path().join("blobs.1.pack").exists());
+
+        let mut store = BlobStore::open(tmpdir.path()).unwrap();
+        for (data, hash) in &blobs {
+            assert_eq!(&store.get(hash).unwrap(), data);
+        }
+        let mut report = CheckReport::default();
+        store.check(&mut report, |_, _| true).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.blobs_checked, 4);
+
+        // Without a target, new blobs keep going to the last segment.
+        let data = b"after reopen".to_vec();
+        let hash = *blake3::hash(&data).as_bytes();
+        assert_eq!(store.put_if_absent(hash, &data).unwrap().segment, 1);
+
+        // Unindexed bytes at the end of a segment are repaired away.
+        drop(store);
+        let segment = tmpdir.path().join("blobs.1.pack");
+        let len = std::fs::metadata(&segment).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[0u8; 9]).unwrap();
+        let mut repair = RepairReport::default();
+        BlobStore::repair(tmpdir.path(), &mut repair).unwrap();
+        assert_eq!(repair.actions.len(), 1, "{:?}", repair.actions);
+        assert_eq!(repair.actions[0].file, "blobs/blobs.1.pack");
+        assert_eq!(std::fs::metadata(&segment).unwrap().len(), len);
+    }
+}
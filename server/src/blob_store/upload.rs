@@ -0,0 +1,177 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-progress chunked blob uploads (BEGIN_BLOB / BLOB_CHUNK / COMMIT_BLOB).
+//!
+//! Uploads are keyed by content hash as well as id, so a client that lost its
+//! connection can call BEGIN_BLOB again and continue from the bytes already
+//! received. Received bytes are written to a `.part` file in the uploads
+//! directory rather than held in memory; only COMMIT_BLOB reads a blob back
+//! in whole. At most `MAX_PENDING_UPLOADS` uploads are in progress at once,
+//! and uploads idle for longer than `UPLOAD_IDLE_TIMEOUT` are dropped along
+//! with their files. Upload ids do not survive a restart, so `open` clears
+//! files left by a previous run.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, StoreError};
+
+const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Uploads that may be in progress at once, across all connections.
+const MAX_PENDING_UPLOADS: usize = 256;
+
+struct PendingUpload {
+    hash: [u8; 32],
+    total_len: u64,
+    file: File,
+    received: u64,
+    last_activity: Instant,
+}
+
+pub struct BlobUploads {
+    dir: PathBuf,
+    next_id: u64,
+    uploads: HashMap<u64, PendingUpload>,
+}
+
+impl BlobUploads {
+    /// Use `dir` for the files of uploads in progress, removing any left
+    /// there by a previous run.
+    pub fn open(dir: &Path) -> Result<Self> {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            next_id: 0,
+            uploads: HashMap::new(),
+        })
+    }
+
+    /// Start (or resume) an upload. Returns the upload id and the number of
+    /// bytes already received, which is where the next chunk must start.
+    pub fn begin(&mut self, hash: [u8; 32], total_len: u64) -> Result<(u64, u64)> {
+        if total_len > u32::MAX as u64 {
+            return Err(StoreError::InvalidInput(format!(
+                "blob too large: {total_len} bytes"
+            )));
+        }
+
+        let now = Instant::now();
+        self.expire_idle(now);
+
+        if let Some((id, upload)) = self
+            .uploads
+            .iter_mut()
+            .find(|(_, upload)| upload.hash == hash && upload.total_len == total_len)
+        {
+            upload.last_activity = now;
+            return Ok((*id, upload.received));
+        }
+
+        if self.uploads.len() >= MAX_PENDING_UPLOADS {
+            return Err(StoreError::QuotaExceeded(format!(
+                "{MAX_PENDING_UPLOADS} blob uploads already in progress"
+            )));
+        }
+
+        self.next_id += 1;
+        let id = self.next_id;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(self.part_path(id))?;
+        self.uploads.insert(
+            id,
+            PendingUpload {
+                hash,
+                total_len,
+                file,
+                received: 0,
+                last_activity: now,
+            },
+        );
+        Ok((id, 0))
+    }
+
+    /// Append a chunk at `offset`, which must equal the bytes received so far.
+    /// Returns the new received length.
+    pub fn append(&mut self, upload_id: u64, offset: u64, chunk: &[u8]) -> Result<u64> {
+        let now = Instant::now();
+        self.expire_idle(now);
+        let upload = self
+            .uploads
+            .get_mut(&upload_id)
+            .ok_or_else(|| StoreError::NotFound(format!("blob upload {upload_id}")))?;
+        let received = upload.received;
+        if offset != received {
+            return Err(StoreError::InvalidInput(format!(
+                "blob chunk offset {offset} does not match received length {received}"
+            )));
+        }
+        if received + chunk.len() as u64 > upload.total_len {
+            return Err(StoreError::InvalidInput(format!(
+                "blob chunk overruns declared length {}",
+                upload.total_len
+            )));
+        }
+        upload.file.write_all(chunk)?;
+        upload.received += chunk.len() as u64;
+        upload.last_activity = now;
+        Ok(upload.received)
+    }
+
+    /// Remove a fully received upload, returning its declared hash and bytes.
+    /// Incomplete uploads are left in place so they can still be resumed.
+    pub fn take_complete(&mut self, upload_id: u64) -> Result<([u8; 32], Vec<u8>)> {
+        self.expire_idle(Instant::now());
+        let upload = self
+            .uploads
+            .get(&upload_id)
+            .ok_or_else(|| StoreError::NotFound(format!("blob upload {upload_id}")))?;
+        if upload.received < upload.total_len {
+            return Err(StoreError::InvalidInput(format!(
+                "blob upload incomplete: {} of {} bytes",
+                upload.received, upload.total_len
+            )));
+        }
+        let PendingUpload {
+            hash,
+            total_len,
+            file,
+            ..
+        } = self.uploads.remove(&upload_id).expect("checked above");
+        drop(file);
+        let path = self.part_path(upload_id);
+        let mut data = Vec::with_capacity(total_len as usize);
+        File::open(&path)?.read_to_end(&mut data)?;
+        std::fs::remove_file(&path)?;
+        Ok((hash, data))
+    }
+
+    /// Drop uploads idle for longer than `UPLOAD_IDLE_TIMEOUT`, and their files.
+    fn expire_idle(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .uploads
+            .iter()
+            .filter(|(_, upload)| now.duration_since(upload.last_activity) >= UPLOAD_IDLE_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.uploads.remove(&id);
+            let _ = std::fs::remove_file(self.part_path(id));
+        }
+    }
+
+    fn part_path(&self, upload_id: u64) -> PathBuf {
+        self.dir.join(format!("{upload_id}.part"))
+    }
+}
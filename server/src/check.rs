@@ -0,0 +1,170 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Consistency check and explicit repair of the on-disk store.
+//!
+//! `Store::check` re-reads every data file from disk (not the in-memory
+//! indexes built at open) and cross-checks them: record checksums, index
+//! offsets against file lengths, heads and fs roots against turns, and blob
+//! bytes against their content hashes. Nothing is modified; operators decide
+//! whether to repair or restore from backup based on the report.
+//!
+//! Opening a store drops a torn final record silently, since that is what a
+//! crash mid-append leaves behind. A bad record with more data after it fails
+//! the open instead: truncating there would also discard the records that
+//! follow. `Store::repair` performs that truncation explicitly and reports
+//! every byte it discards. The exception is `fs/roots.idx`, whose records
+//! carry their own length: a record that fails its crc is skipped at open
+//! (and counted) so the records after it survive.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use crate::error::{Result, StoreError};
+
+/// One inconsistency found by `Store::check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckIssue {
+    /// Path relative to the data directory, e.g. `turns/turns.log`.
+    pub file: Cow<'static, str>,
+    /// Byte offset of the offending record, when there is one.
+    pub offset: Option<u64>,
+    pub detail: String,
+}
+
+impl fmt::Display for CheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} @ {}: {}", self.file, offset, self.detail),
+            None => write!(f, "{}: {}", self.file, self.detail),
+        }
+    }
+}
+
+/// Outcome of a `Store::check` pass.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub issues: Vec<CheckIssue>,
+    pub turns_checked: usize,
+    pub heads_checked: usize,
+    pub blobs_checked: usize,
+    pub fs_roots_checked: usize,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub(crate) fn issue(
+        &mut self,
+        file: impl Into<Cow<'static, str>>,
+        offset: Option<u64>,
+        detail: impl Into<String>,
+    ) {
+        self.issues.push(CheckIssue {
+            file: file.into(),
+            offset,
+            detail: detail.into(),
+        });
+    }
+}
+
+/// One file truncated by `Store::repair`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairAction {
+    /// Path relative to the data directory, e.g. `turns/turns.log`.
+    pub file: Cow<'static, str>,
+    /// New length of the file; everything from here on was discarded.
+    pub truncated_at: u64,
+    pub bytes_discarded: u64,
+    /// Records (whole or torn) discarded, for files of fixed-size records.
+    /// None for variable-length files (turns.meta, roots.idx) and blob packs
+    /// (unindexed bytes).
+    pub records_discarded: Option<u64>,
+}
+
+impl fmt::Display for RepairAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: truncated at {}, discarded {} bytes",
+            self.file, self.truncated_at, self.bytes_discarded
+        )?;
+        if let Some(records) = self.records_discarded {
+            write!(f, " ({records} records)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a `Store::repair` pass.
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub actions: Vec<RepairAction>,
+}
+
+impl RepairReport {
+    /// True when no file needed truncating.
+    pub fn is_clean(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// Byte length of the leading run of whole fixed-size records that pass `valid`.
+pub(crate) fn valid_prefix_len(data: &[u8], record_len: u64, valid: impl Fn(&[u8]) -> bool) -> u64 {
+    data.chunks_exact(record_len as usize)
+        .take_while(|record| valid(record))
+        .count() as u64
+        * record_len
+}
+
+/// Called at load when a file has bytes past its valid prefix. Damage confined
+/// to the final record is a torn append and may be dropped; anything more
+/// needs an explicit `Store::repair`.
+pub(crate) fn ensure_trailing_only(
+    file: &'static str,
+    len: u64,
+    valid_len: u64,
+    record_len: u64,
+) -> Result<()> {
+    if len - valid_len <= record_len {
+        return Ok(());
+    }
+    Err(StoreError::Corrupt(format!(
+        "{file}: bad record at offset {valid_len} with {} more bytes after it; run Store::repair (CXDB_REPAIR=1) to truncate",
+        len - valid_len - record_len
+    )))
+}
+
+/// Truncate `path` to `valid_len` if it is longer, recording what was dropped.
+pub(crate) fn truncate_tail(
+    path: &Path,
+    file: impl Into<Cow<'static, str>>,
+    valid_len: u64,
+    record_len: Option<u64>,
+    report: &mut RepairReport,
+) -> Result<()> {
+    let len = match std::fs::metadata(path) {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if len <= valid_len {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(valid_len)?;
+    let bytes_discarded = len - valid_len;
+    report.actions.push(RepairAction {
+        file: file.into(),
+        truncated_at: valid_len,
+        bytes_discarded,
+        records_discarded: record_len.map(|record_len| bytes_discarded.div_ceil(record_len)),
+    });
+    Ok(())
+}
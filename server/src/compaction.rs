@@ -0,0 +1,113 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Background compaction of append-only index files.
+//!
+//! `heads.tbl` and `fs/roots.idx` are last-write-wins logs that grow with every
+//! head move or re-attach. The maintenance thread periodically checks their
+//! dead-record ratio and rewrites them in place when it crosses a threshold,
+//! holding the store lock only for the duration of the rewrite.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{parse_env, ConfigError};
+use crate::store::Store;
+
+#[derive(Debug, Clone)]
+pub struct CompactionConfig {
+    /// Time between compaction checks.
+    pub interval: Duration,
+    /// Minimum fraction of superseded records before a file is rewritten.
+    pub min_dead_ratio: f64,
+    /// Files smaller than this are never compacted.
+    pub min_file_bytes: u64,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(300),
+            min_dead_ratio: 0.5,
+            min_file_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl CompactionConfig {
+    /// Load from environment. Returns None unless `CXDB_COMPACTION_ENABLED`
+    /// is `1`/`true`; the other variables override the defaults. The
+    /// interval must be at least a second and the dead ratio in (0, 1], since
+    /// anything else would rewrite the files on every pass.
+    pub fn from_env() -> Result<Option<Self>, ConfigError> {
+        let enabled = std::env::var("CXDB_COMPACTION_ENABLED")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        if !enabled {
+            return Ok(None);
+        }
+
+        let defaults = Self::default();
+        let interval = match parse_env::<u64>("CXDB_COMPACTION_INTERVAL_SECS")? {
+            Some(0) => {
+                return Err(ConfigError(
+                    "CXDB_COMPACTION_INTERVAL_SECS must be at least 1".into(),
+                ))
+            }
+            Some(secs) => Duration::from_secs(secs),
+            None => defaults.interval,
+        };
+        let min_dead_ratio =
+            parse_env::<f64>("CXDB_COMPACTION_DEAD_RATIO")?.unwrap_or(defaults.min_dead_ratio);
+        if !(min_dead_ratio > 0.0 && min_dead_ratio <= 1.0) {
+            return Err(ConfigError(format!(
+                "CXDB_COMPACTION_DEAD_RATIO must be in (0, 1]: {min_dead_ratio}"
+            )));
+        }
+        let min_file_bytes =
+            parse_env("CXDB_COMPACTION_MIN_BYTES")?.unwrap_or(defaults.min_file_bytes);
+
+        Ok(Some(Self {
+            interval,
+            min_dead_ratio,
+            min_file_bytes,
+        }))
+    }
+}
+
+/// Spawn the maintenance thread. It exits once `shutdown` is set.
+pub fn start_compaction(
+    store: Arc<Mutex<Store>>,
+    config: CompactionConfig,
+    shutdown: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_run = Instant::now();
+        while !shutdown.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(200).min(config.interval));
+            if last_run.elapsed() < config.interval {
+                continue;
+            }
+            last_run = Instant::now();
+
+            let result = {
+                let mut store = store.lock().unwrap();
+                store.compact(config.min_dead_ratio, config.min_file_bytes)
+            };
+            match result {
+                Ok(report) if report.heads_compacted || report.roots_compacted => {
+                    eprintln!(
+                        "compaction: reclaimed {} bytes (heads.tbl {}, roots.idx {})",
+                        report.bytes_reclaimed(),
+                        report.heads_bytes_reclaimed,
+                        report.roots_bytes_reclaimed
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("compaction failed: {e}"),
+            }
+        }
+    })
+}
@@ -4,23 +4,90 @@
 use std::env;
 use std::path::PathBuf;
 
+use thiserror::Error;
+
+use crate::fs_store::HashAlgorithm;
+use crate::quota::ContextQuota;
+
+/// A configuration variable that is set to a value the server cannot use.
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct ConfigError(pub String);
+
+/// Parse the variable `name` if it is set.
+pub fn parse_env<T: std::str::FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfigError(format!("invalid {name}: {value}"))),
+        Err(_) => Ok(None),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub data_dir: PathBuf,
     pub bind_addr: String,
     pub http_bind_addr: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// Keyed-BLAKE3 namespace keys accepted for filesystem blobs
+    /// (`CXDB_HASH_KEYS`, comma-separated 64-digit hex).
+    pub hash_keys: Vec<[u8; 32]>,
+    /// Run `Store::repair` before opening the store (`CXDB_REPAIR=1`).
+    pub repair_on_start: bool,
+    /// Start a new blob pack segment instead of growing the active one past
+    /// this many bytes (`CXDB_BLOB_PACK_TARGET_BYTES`). Unset keeps a single pack.
+    pub blob_pack_target_bytes: Option<u64>,
+    /// Quota for contexts created without one of their own
+    /// (`CXDB_CONTEXT_MAX_TURNS`, `CXDB_CONTEXT_MAX_BYTES`). Unset is unlimited.
+    pub default_context_quota: ContextQuota,
 }
 
 impl Config {
-    pub fn from_env() -> Self {
+    /// Load from environment, failing on a variable set to a value the
+    /// server cannot use rather than silently falling back to its default.
+    pub fn from_env() -> Result<Self, ConfigError> {
         let data_dir = env::var("CXDB_DATA_DIR").unwrap_or_else(|_| "./data".to_string());
         let bind_addr = env::var("CXDB_BIND").unwrap_or_else(|_| "127.0.0.1:9009".to_string());
         let http_bind_addr =
             env::var("CXDB_HTTP_BIND").unwrap_or_else(|_| "127.0.0.1:9010".to_string());
-        Self {
+        let hash_algorithm = match env::var("CXDB_HASH_ALGORITHM") {
+            Ok(name) => HashAlgorithm::from_name(&name)
+                .ok_or_else(|| ConfigError(format!("unsupported CXDB_HASH_ALGORITHM: {name}")))?,
+            Err(_) => HashAlgorithm::Blake3,
+        };
+        let hash_keys = match env::var("CXDB_HASH_KEYS") {
+            Ok(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| {
+                    hex::decode(key)
+                        .ok()
+                        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                        .ok_or_else(|| ConfigError(format!("invalid CXDB_HASH_KEYS entry: {key}")))
+                })
+                .collect::<Result<_, _>>()?,
+            Err(_) => Vec::new(),
+        };
+        let repair_on_start = env::var("CXDB_REPAIR")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let blob_pack_target_bytes = parse_env("CXDB_BLOB_PACK_TARGET_BYTES")?;
+        let default_context_quota = ContextQuota {
+            max_turns: parse_env("CXDB_CONTEXT_MAX_TURNS")?,
+            max_bytes: parse_env("CXDB_CONTEXT_MAX_BYTES")?,
+        };
+        Ok(Self {
             data_dir: PathBuf::from(data_dir),
             bind_addr,
             http_bind_addr,
-        }
+            hash_algorithm,
+            hash_keys,
+            repair_on_start,
+            blob_pack_target_bytes,
+            default_context_quota,
+        })
     }
 }
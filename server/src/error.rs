@@ -13,6 +13,12 @@ pub enum StoreError {
     NotFound(String),
     #[error("invalid input: {0}")]
     InvalidInput(String),
+    #[error("invalid parent turn: {0}")]
+    InvalidParent(String),
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("not ready: {0}")]
+    NotReady(String),
 }
 
 pub type Result<T> = std::result::Result<T, StoreError>;
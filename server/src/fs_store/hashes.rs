@@ -0,0 +1,75 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed wrappers for the 32-byte hashes used by filesystem snapshots.
+//!
+//! Root, tree, and blob hashes share a representation but not a meaning:
+//! passing a file blob hash where a root tree is expected only fails later
+//! as a `Corrupt` tree parse. The wrappers make that a compile error. They
+//! are in-memory only; the wire and on-disk formats still carry raw bytes.
+
+use std::fmt;
+
+macro_rules! hash_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name([u8; 32]);
+
+        impl $name {
+            pub const fn new(bytes: [u8; 32]) -> Self {
+                Self(bytes)
+            }
+
+            pub fn as_bytes(&self) -> &[u8; 32] {
+                &self.0
+            }
+        }
+
+        impl From<[u8; 32]> for $name {
+            fn from(bytes: [u8; 32]) -> Self {
+                Self(bytes)
+            }
+        }
+
+        impl From<$name> for [u8; 32] {
+            fn from(hash: $name) -> Self {
+                hash.0
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), hex::encode(self.0))
+            }
+        }
+    };
+}
+
+hash_type!(
+    /// Root tree of a filesystem snapshot, as attached to a turn.
+    FsRootHash
+);
+
+hash_type!(
+    /// A directory tree object (msgpack array of `TreeEntry`).
+    TreeHash
+);
+
+hash_type!(
+    /// File content or symlink target.
+    BlobHash
+);
+
+impl From<FsRootHash> for TreeHash {
+    /// A snapshot root is the tree object for its top-level directory.
+    fn from(root: FsRootHash) -> Self {
+        TreeHash(root.0)
+    }
+}
@@ -9,11 +9,18 @@
 //!
 //! # Storage Format
 //!
-//! The roots index (`fs/roots.idx`) is an append-only file with fixed-size records:
+//! The roots index (`fs/roots.idx`) is an append-only file that starts with an
+//! 8-byte header (`CXRI` followed by format version 2 as a u32), then records:
 //! - turn_id: u64 (8 bytes)
 //! - fs_root_hash: [u8; 32] (32 bytes)
-//! - crc32: u32 (4 bytes)
-//! - Total: 44 bytes per record
+//! - meta_len: u16 (2 bytes; 0 when the client sent no capture metadata)
+//! - meta: captured_at_unix_ms, file_count, dir_count, total_bytes,
+//!   duration_ms, each u64 (`meta_len` bytes; readers ignore fields they
+//!   do not know)
+//! - crc32: u32 (4 bytes, over everything before it)
+//!
+//! Version 1 files have no header and fixed 44-byte records (turn_id,
+//! fs_root_hash, crc32). They are rewritten in the current format on open.
 //!
 //! Last-write-wins semantics per turn_id (like heads.tbl).
 //!
@@ -27,21 +34,60 @@
 //!     mode: u32,         // msgpack tag 3 (POSIX permissions)
 //!     size: u64,         // msgpack tag 4 (file size, 0 for dirs)
 //!     hash: [u8; 32],    // msgpack tag 5 (content hash)
+//!     hash_alg: u8,      // msgpack tag 6 (0=BLAKE3-256, 1=SHA-256, 2=keyed BLAKE3; omitted for BLAKE3)
+//!     inline: bytes,     // msgpack tag 7 (small file contents; no separate blob)
 //! }
 //! ```
+//!
+//! Entries are canonically sorted by name (byte-wise, as `str` orders them)
+//! before a tree is hashed. Trees from producers that did not sort are sorted
+//! when read, so listings and lookups never depend on the producer's order.
+//! Names must not be `.` or `..` or contain `/`, `\`, or NUL; a tree with
+//! such an entry is rejected as corrupt.
+//!
+//! Content hashes default to BLAKE3-256. Servers started with
+//! `CXDB_HASH_ALGORITHM=sha256` verify uploaded blobs with SHA-256 instead.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 use crc32fast::Hasher;
+use rmp::decode::ValueReadError;
+use rmp::Marker;
 use rmpv::Value;
+use sha2::Digest;
 
 use crate::blob_store::BlobStore;
+use crate::check::{ensure_trailing_only, truncate_tail, CheckReport, RepairReport};
 use crate::error::{Result, StoreError};
-use crate::turn_store::TurnStore;
+use crate::turn_store::{replace_file, TurnStore};
+
+mod hashes;
+mod sniff;
+
+pub use hashes::{BlobHash, FsRootHash, TreeHash};
+pub use sniff::{sniff, FileKind, SNIFF_LEN};
+
+/// Magic and format version at the start of roots.idx.
+const ROOTS_HEADER: [u8; 8] = *b"CXRI\x02\x00\x00\x00";
+
+/// Size of one legacy (headerless) roots.idx record: turn_id, fs_root_hash, crc.
+const LEGACY_ROOT_RECORD_LEN: u64 = 8 + 32 + 4;
+
+/// Size of a current-format record with no metadata: turn_id, fs_root_hash,
+/// meta_len, crc.
+const MIN_ROOT_RECORD_LEN: u64 = 8 + 32 + 2 + 4;
+
+/// Upper bound on a record's metadata, so a corrupt length is recognized as
+/// such rather than as a torn tail.
+const MAX_ROOT_META_LEN: usize = 1024;
+
+const ROOTS_IDX: &str = "fs/roots.idx";
 
 /// Entry kinds for filesystem tree entries.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -63,6 +109,46 @@ impl From<u8> for EntryKind {
     }
 }
 
+/// Content-addressing algorithm for filesystem blobs and tree objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3 = 0,
+    Sha256 = 1,
+}
+
+impl HashAlgorithm {
+    /// Parse a configuration name (`blake3` or `sha256`, case-insensitive).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "sha256" | "sha-256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Map a tree entry's `hash_alg` identifier to an algorithm.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(HashAlgorithm::Blake3),
+            1 => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn hash(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgorithm::Blake3 => *blake3::hash(data).as_bytes(),
+            HashAlgorithm::Sha256 => sha2::Sha256::digest(data).into(),
+        }
+    }
+}
+
 /// A single entry in a directory tree.
 #[derive(Debug, Clone)]
 pub struct TreeEntry {
@@ -78,8 +164,14 @@ pub struct TreeEntry {
     /// Size in bytes (files only, 0 for directories).
     pub size: u64,
 
-    /// BLAKE3-256 hash of content (file), subtree (dir), or target (symlink).
+    /// Hash of content (file), subtree (dir), or target (symlink).
     pub hash: Vec<u8>,
+
+    /// Identifier of the algorithm that produced `hash` (see `HashAlgorithm`).
+    pub hash_alg: u8,
+
+    /// File contents stored in the tree itself instead of as a blob.
+    pub inline_content: Option<Vec<u8>>,
 }
 
 impl TreeEntry {
@@ -102,15 +194,82 @@ impl TreeEntry {
     }
 }
 
+/// Capture metadata recorded alongside an attached filesystem snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotMeta {
+    /// When the snapshot was captured (Unix milliseconds).
+    pub captured_at_unix_ms: u64,
+    pub file_count: u64,
+    pub dir_count: u64,
+    pub total_bytes: u64,
+    /// How long the capture took, in milliseconds.
+    pub duration_ms: u64,
+}
+
+impl SnapshotMeta {
+    /// Encoded size: five little-endian u64 fields.
+    pub const ENCODED_LEN: usize = 40;
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        for field in [
+            self.captured_at_unix_ms,
+            self.file_count,
+            self.dir_count,
+            self.total_bytes,
+            self.duration_ms,
+        ] {
+            buf.extend_from_slice(&field.to_le_bytes());
+        }
+    }
+
+    /// Decode from at least `ENCODED_LEN` bytes; later bytes are ignored so
+    /// newer writers can append fields.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let field = |i: usize| -> Option<u64> {
+            Some(u64::from_le_bytes(
+                bytes.get(i * 8..i * 8 + 8)?.try_into().ok()?,
+            ))
+        };
+        Some(Self {
+            captured_at_unix_ms: field(0)?,
+            file_count: field(1)?,
+            dir_count: field(2)?,
+            total_bytes: field(3)?,
+            duration_ms: field(4)?,
+        })
+    }
+}
+
+/// Result of decoding the record at the start of a byte slice.
+enum ScannedRecord {
+    Root {
+        len: u64,
+        turn_id: u64,
+        fs_root_hash: FsRootHash,
+        meta: Option<SnapshotMeta>,
+    },
+    /// The slice ends before the record does.
+    Torn,
+    /// A complete record that fails validation. `len` is None when the
+    /// record's own length cannot be trusted, so scanning cannot resume.
+    Bad { len: Option<u64>, detail: String },
+}
+
 /// Sparse index mapping turn_id → fs_root_hash.
 pub struct FsRootsIndex {
     path: PathBuf,
     file: File,
-    roots: HashMap<u64, [u8; 32]>,
+    roots: HashMap<u64, FsRootHash>,
+    meta: HashMap<u64, SnapshotMeta>,
+    /// Records in the file, superseded ones included.
+    records: usize,
+    /// Corrupt records passed over by the last load.
+    skipped_records: usize,
 }
 
 impl FsRootsIndex {
-    /// Open or create the filesystem roots index.
+    /// Open or create the filesystem roots index, upgrading a legacy
+    /// (headerless, fixed-size record) file to the current format.
     pub fn open(dir: &Path) -> Result<Self> {
         std::fs::create_dir_all(dir)?;
         let path = dir.join("roots.idx");
@@ -126,104 +285,322 @@ impl FsRootsIndex {
             path,
             file,
             roots: HashMap::new(),
+            meta: HashMap::new(),
+            records: 0,
+            skipped_records: 0,
         };
 
-        index.load()?;
+        if !index.load()? {
+            index.compact()?;
+        }
         Ok(index)
     }
 
-    /// Load existing entries from disk.
-    fn load(&mut self) -> Result<()> {
+    /// Load existing entries from disk. Returns false if the file is not in
+    /// the current format and needs rewriting.
+    ///
+    /// A complete record that fails its crc is skipped, so one damaged
+    /// mapping does not cost every later one; see `skipped_records`. Only
+    /// data that cannot be parsed past (a torn tail, or a record whose
+    /// length is itself corrupt) is truncated.
+    fn load(&mut self) -> Result<bool> {
         self.roots.clear();
+        self.meta.clear();
+        self.records = 0;
+        self.skipped_records = 0;
+
+        let mut data = Vec::new();
         self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+        let current = Self::is_current(&data);
+
+        let mut offset = Self::header_len(&data);
+        while offset < data.len() as u64 {
+            match Self::scan_record(&data[offset as usize..], current) {
+                ScannedRecord::Root {
+                    len,
+                    turn_id,
+                    fs_root_hash,
+                    meta,
+                } => {
+                    self.insert(turn_id, fs_root_hash, meta);
+                    self.records += 1;
+                    offset += len;
+                }
+                ScannedRecord::Torn => break,
+                ScannedRecord::Bad { len: Some(len), .. } => {
+                    self.skipped_records += 1;
+                    offset += len;
+                }
+                ScannedRecord::Bad { len: None, .. } => {
+                    ensure_trailing_only(
+                        ROOTS_IDX,
+                        data.len() as u64,
+                        offset,
+                        MIN_ROOT_RECORD_LEN,
+                    )?;
+                    break;
+                }
+            }
+        }
+        if offset < data.len() as u64 {
+            self.file.set_len(offset)?;
+        }
 
-        loop {
-            let start = self.file.stream_position()?;
+        Ok(current)
+    }
 
-            // Read turn_id
-            let turn_id = match self.file.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(StoreError::Io(e)),
-            };
+    /// A duplicated handle to roots.idx, for fsyncing without holding the
+    /// store.
+    pub fn sync_handle(&self) -> Result<File> {
+        Ok(self.file.try_clone()?)
+    }
 
-            // Read fs_root_hash
-            let mut fs_root_hash = [0u8; 32];
-            if self.file.read_exact(&mut fs_root_hash).is_err() {
-                self.file.set_len(start)?;
-                break;
+    /// Corrupt records `open` skipped over. Their mappings are lost; the
+    /// records around them were kept. `Store::check` reports each one.
+    pub fn skipped_records(&self) -> usize {
+        self.skipped_records
+    }
+
+    /// True if `data` starts with the current format's header.
+    fn is_current(data: &[u8]) -> bool {
+        data.len() >= ROOTS_HEADER.len() && data[..ROOTS_HEADER.len()] == ROOTS_HEADER
+    }
+
+    fn header_len(data: &[u8]) -> u64 {
+        if Self::is_current(data) {
+            ROOTS_HEADER.len() as u64
+        } else {
+            0
+        }
+    }
+
+    /// Decode the record at the start of `data`, in the current format or
+    /// the legacy fixed-size one.
+    fn scan_record(data: &[u8], current: bool) -> ScannedRecord {
+        if !current {
+            let Some(record) = data.get(..LEGACY_ROOT_RECORD_LEN as usize) else {
+                return ScannedRecord::Torn;
+            };
+            let turn_id = u64::from_le_bytes(record[0..8].try_into().unwrap());
+            let fs_root_hash: [u8; 32] = record[8..40].try_into().unwrap();
+            let crc = u32::from_le_bytes(record[40..44].try_into().unwrap());
+            if crc != Self::compute_crc(&record[..40]) {
+                return ScannedRecord::Bad {
+                    len: Some(LEGACY_ROOT_RECORD_LEN),
+                    detail: "root crc mismatch".into(),
+                };
             }
+            return ScannedRecord::Root {
+                len: LEGACY_ROOT_RECORD_LEN,
+                turn_id,
+                fs_root_hash: fs_root_hash.into(),
+                meta: None,
+            };
+        }
 
-            // Read and verify CRC
-            let crc = match self.file.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.file.set_len(start)?;
-                    break;
-                }
+        if data.len() < 42 {
+            return ScannedRecord::Torn;
+        }
+        let meta_len = u16::from_le_bytes(data[40..42].try_into().unwrap()) as usize;
+        if meta_len > MAX_ROOT_META_LEN || (meta_len != 0 && meta_len < SnapshotMeta::ENCODED_LEN) {
+            return ScannedRecord::Bad {
+                len: None,
+                detail: format!("invalid metadata length {meta_len}"),
+            };
+        }
+        let len = 42 + meta_len + 4;
+        let Some(record) = data.get(..len) else {
+            return ScannedRecord::Torn;
+        };
+        let crc = u32::from_le_bytes(record[len - 4..].try_into().unwrap());
+        if crc != Self::compute_crc(&record[..len - 4]) {
+            return ScannedRecord::Bad {
+                len: Some(len as u64),
+                detail: "root crc mismatch".into(),
             };
+        }
+        let turn_id = u64::from_le_bytes(record[0..8].try_into().unwrap());
+        let fs_root_hash: [u8; 32] = record[8..40].try_into().unwrap();
+        ScannedRecord::Root {
+            len: len as u64,
+            turn_id,
+            fs_root_hash: fs_root_hash.into(),
+            meta: SnapshotMeta::decode(&record[42..42 + meta_len]),
+        }
+    }
 
-            let actual_crc = Self::compute_crc(turn_id, &fs_root_hash);
-            if crc != actual_crc {
-                self.file.set_len(start)?;
-                break;
+    /// Truncate roots.idx where it stops being readable, recording what was
+    /// discarded. Bad records that `open` can skip are left in place.
+    pub fn repair(dir: &Path, report: &mut RepairReport) -> Result<()> {
+        let path = dir.join("roots.idx");
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let current = Self::is_current(&data);
+        let mut valid_len = Self::header_len(&data);
+        while valid_len < data.len() as u64 {
+            match Self::scan_record(&data[valid_len as usize..], current) {
+                ScannedRecord::Root { len, .. } | ScannedRecord::Bad { len: Some(len), .. } => {
+                    valid_len += len
+                }
+                ScannedRecord::Torn | ScannedRecord::Bad { len: None, .. } => break,
             }
-
-            self.roots.insert(turn_id, fs_root_hash);
         }
+        let record_len = (!current).then_some(LEGACY_ROOT_RECORD_LEN);
+        truncate_tail(&path, ROOTS_IDX, valid_len, record_len, report)
+    }
 
-        Ok(())
+    /// Re-read roots.idx from disk, recording bad records in `report`, and
+    /// return the live (last-written) root for each turn.
+    pub fn check(&self, report: &mut CheckReport) -> Result<HashMap<u64, FsRootHash>> {
+        const ROOTS: &str = ROOTS_IDX;
+
+        let data = std::fs::read(&self.path)?;
+        let current = Self::is_current(&data);
+        let mut roots = HashMap::new();
+        let mut offset = Self::header_len(&data);
+        while offset < data.len() as u64 {
+            match Self::scan_record(&data[offset as usize..], current) {
+                ScannedRecord::Root {
+                    len,
+                    turn_id,
+                    fs_root_hash,
+                    ..
+                } => {
+                    roots.insert(turn_id, fs_root_hash);
+                    offset += len;
+                }
+                ScannedRecord::Torn => {
+                    let remaining = data.len() as u64 - offset;
+                    report.issue(ROOTS, Some(offset), format!("{remaining} trailing bytes"));
+                    break;
+                }
+                ScannedRecord::Bad { len, detail } => {
+                    report.issue(ROOTS, Some(offset), detail);
+                    match len {
+                        Some(len) => offset += len,
+                        None => break,
+                    }
+                }
+            }
+        }
+        Ok(roots)
     }
 
-    /// Compute CRC32 for a record.
-    fn compute_crc(turn_id: u64, fs_root_hash: &[u8; 32]) -> u32 {
-        let mut buf = Vec::with_capacity(40);
-        buf.write_u64::<LittleEndian>(turn_id).unwrap();
-        buf.extend_from_slice(fs_root_hash);
+    /// Compute CRC32 over a record's bytes up to (not including) its crc.
+    fn compute_crc(body: &[u8]) -> u32 {
         let mut hasher = Hasher::new();
-        hasher.update(&buf);
+        hasher.update(body);
         hasher.finalize()
     }
 
-    /// Attach a filesystem snapshot to a turn.
-    pub fn attach(&mut self, turn_id: u64, fs_root_hash: [u8; 32]) -> Result<()> {
-        // Write record to file
-        let mut buf = Vec::with_capacity(44);
+    /// Encode one record in the current format.
+    fn encode_record(
+        buf: &mut Vec<u8>,
+        turn_id: u64,
+        fs_root_hash: &FsRootHash,
+        meta: Option<&SnapshotMeta>,
+    ) -> Result<()> {
+        let start = buf.len();
         buf.write_u64::<LittleEndian>(turn_id)?;
-        buf.extend_from_slice(&fs_root_hash);
-        let crc = Self::compute_crc(turn_id, &fs_root_hash);
+        buf.extend_from_slice(fs_root_hash.as_bytes());
+        match meta {
+            Some(meta) => {
+                buf.write_u16::<LittleEndian>(SnapshotMeta::ENCODED_LEN as u16)?;
+                meta.encode(buf);
+            }
+            None => buf.write_u16::<LittleEndian>(0)?,
+        }
+        let crc = Self::compute_crc(&buf[start..]);
         buf.write_u32::<LittleEndian>(crc)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, turn_id: u64, fs_root_hash: FsRootHash, meta: Option<SnapshotMeta>) {
+        self.roots.insert(turn_id, fs_root_hash);
+        match meta {
+            Some(meta) => self.meta.insert(turn_id, meta),
+            None => self.meta.remove(&turn_id),
+        };
+    }
+
+    /// Attach a filesystem snapshot to a turn.
+    pub fn attach(&mut self, turn_id: u64, fs_root_hash: FsRootHash) -> Result<()> {
+        self.attach_with_meta(turn_id, fs_root_hash, None)
+    }
+
+    /// Attach a filesystem snapshot to a turn, recording its capture
+    /// metadata. A later attach to the same turn replaces both.
+    pub fn attach_with_meta(
+        &mut self,
+        turn_id: u64,
+        fs_root_hash: FsRootHash,
+        meta: Option<SnapshotMeta>,
+    ) -> Result<()> {
+        let mut buf = Vec::with_capacity(MIN_ROOT_RECORD_LEN as usize + SnapshotMeta::ENCODED_LEN);
+        Self::encode_record(&mut buf, turn_id, &fs_root_hash, meta.as_ref())?;
 
         self.file.seek(SeekFrom::End(0))?;
         self.file.write_all(&buf)?;
         self.file.flush()?;
 
         // Update in-memory index
-        self.roots.insert(turn_id, fs_root_hash);
+        self.insert(turn_id, fs_root_hash, meta);
+        self.records += 1;
 
         Ok(())
     }
 
     /// Get the fs_root_hash directly attached to a turn.
-    pub fn get(&self, turn_id: u64) -> Option<[u8; 32]> {
+    pub fn get(&self, turn_id: u64) -> Option<FsRootHash> {
         self.roots.get(&turn_id).copied()
     }
 
+    /// Turns the snapshot `hash` is directly attached to (the reverse of
+    /// `get`), in ascending order. Descendants that only inherit it are not
+    /// included.
+    pub fn turns_for_root(&self, hash: &[u8; 32]) -> Vec<u64> {
+        let mut turns: Vec<u64> = self
+            .roots
+            .iter()
+            .filter(|(_, root)| root.as_bytes() == hash)
+            .map(|(&turn_id, _)| turn_id)
+            .collect();
+        turns.sort_unstable();
+        turns
+    }
+
+    /// Get the capture metadata recorded with the snapshot directly attached
+    /// to a turn, if the client supplied any.
+    pub fn get_meta(&self, turn_id: u64) -> Option<SnapshotMeta> {
+        self.meta.get(&turn_id).copied()
+    }
+
     /// Get the fs_root_hash for a turn, walking parent chain if not directly attached.
-    pub fn get_inherited(&self, turn_id: u64, turn_store: &TurnStore) -> Option<[u8; 32]> {
+    pub fn get_inherited(&self, turn_id: u64, turn_store: &TurnStore) -> Option<FsRootHash> {
+        self.find_attached(turn_id, turn_store)
+            .and_then(|attached| self.roots.get(&attached).copied())
+    }
+
+    /// Find the turn whose snapshot `turn_id` sees: itself, or the nearest
+    /// ancestor with one attached.
+    pub fn find_attached(&self, turn_id: u64, turn_store: &TurnStore) -> Option<u64> {
         // First check direct attachment
-        if let Some(hash) = self.roots.get(&turn_id) {
-            return Some(*hash);
+        if self.roots.contains_key(&turn_id) {
+            return Some(turn_id);
         }
 
         // Walk parent chain
         let mut current = turn_id;
         while current != 0 {
             if let Ok(turn) = turn_store.get_turn(current) {
-                if let Some(hash) = self.roots.get(&turn.turn_id) {
-                    return Some(*hash);
+                if self.roots.contains_key(&turn.turn_id) {
+                    return Some(turn.turn_id);
                 }
-                current = turn.parent_turn_id;
+                current = turn_store.retained_parent(&turn);
             } else {
                 break;
             }
@@ -241,13 +618,38 @@ impl FsRootsIndex {
     pub fn stats(&self) -> FsRootsStats {
         FsRootsStats {
             entries_total: self.roots.len(),
+            skipped_records: self.skipped_records,
             file_bytes: std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0),
             content_bytes: 0, // Computed by Store::stats() which has blob_store access
         }
     }
 
+    /// Fraction of roots.idx records superseded by a later attach to the same turn.
+    pub fn dead_ratio(&self) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.records.saturating_sub(self.roots.len()) as f64 / self.records as f64
+    }
+
+    /// Rewrite roots.idx keeping only the latest record per turn. Returns bytes reclaimed.
+    pub fn compact(&mut self) -> Result<u64> {
+        let before = std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        let mut roots: Vec<(&u64, &FsRootHash)> = self.roots.iter().collect();
+        roots.sort_by_key(|(turn_id, _)| **turn_id);
+        let mut buf = Vec::with_capacity(ROOTS_HEADER.len() + roots.len() * 86);
+        buf.extend_from_slice(&ROOTS_HEADER);
+        for (turn_id, hash) in roots {
+            Self::encode_record(&mut buf, *turn_id, hash, self.meta.get(turn_id))?;
+        }
+
+        self.file = replace_file(&self.path, &buf)?;
+        self.records = self.roots.len();
+        Ok(before.saturating_sub(buf.len() as u64))
+    }
+
     /// Get all unique root hashes for computing content size.
-    pub fn unique_roots(&self) -> Vec<[u8; 32]> {
+    pub fn unique_roots(&self) -> Vec<FsRootHash> {
         let mut seen = std::collections::HashSet::new();
         let mut roots = Vec::new();
         for hash in self.roots.values() {
@@ -257,12 +659,74 @@ impl FsRootsIndex {
         }
         roots
     }
+
+    /// Total raw size of the trees and blobs reachable from all attached
+    /// roots, each counted once however many snapshots share it. Roots are
+    /// walked by up to `concurrency` threads that share one set of visited
+    /// hashes. Trees that cannot be read count only their own size.
+    pub fn content_bytes(&self, blob_store: &BlobStore, concurrency: usize) -> u64 {
+        let roots = self.unique_roots();
+        let next = AtomicUsize::new(0);
+        let visited = Mutex::new(HashSet::new());
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..concurrency.clamp(1, roots.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut total = 0;
+                        while let Some(root) = roots.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            total += shared_tree_size(blob_store, &(*root).into(), &visited);
+                        }
+                        total
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or(0))
+                .sum()
+        })
+    }
+}
+
+/// Size of a tree and everything below it not already in `visited`.
+fn shared_tree_size(
+    blob_store: &BlobStore,
+    tree_hash: &TreeHash,
+    visited: &Mutex<HashSet<[u8; 32]>>,
+) -> u64 {
+    let first_visit =
+        |hash: &[u8; 32]| visited.lock().map(|mut v| v.insert(*hash)).unwrap_or(false);
+    if !first_visit(tree_hash.as_bytes()) {
+        return 0;
+    }
+
+    let mut total = blob_store.raw_len(tree_hash.as_bytes()).unwrap_or(0) as u64;
+    let entries = match blob_store
+        .get_shared(tree_hash.as_bytes())
+        .and_then(|bytes| parse_tree_entries(&bytes))
+    {
+        Ok(entries) => entries,
+        Err(_) => return total,
+    };
+    for entry in entries {
+        let Ok(hash) = entry.hash_array() else {
+            continue;
+        };
+        if entry.kind_enum() == EntryKind::Directory {
+            total += shared_tree_size(blob_store, &hash.into(), visited);
+        } else if first_visit(&hash) {
+            total += blob_store.raw_len(&hash).unwrap_or(0) as u64;
+        }
+    }
+    total
 }
 
 /// Statistics about the filesystem roots index.
 #[derive(Debug, Clone)]
 pub struct FsRootsStats {
     pub entries_total: usize,
+    /// Corrupt records skipped when the index was opened.
+    pub skipped_records: usize,
     pub file_bytes: u64,
     /// Total size of all blobs referenced by filesystem snapshots (computed externally).
     pub content_bytes: u64,
@@ -271,40 +735,58 @@ pub struct FsRootsStats {
 /// Load and deserialize tree entries from the blob store.
 pub fn load_tree_entries(
     blob_store: &mut BlobStore,
-    tree_hash: &[u8; 32],
+    tree_hash: &TreeHash,
 ) -> Result<Vec<TreeEntry>> {
-    let bytes = blob_store.get(tree_hash)?;
+    let bytes = blob_store.get(tree_hash.as_bytes())?;
     parse_tree_entries(&bytes)
 }
 
-/// Parse tree entries from msgpack bytes.
-/// The format is an array of maps with numeric keys (1=name, 2=kind, 3=mode, 4=size, 5=hash).
+/// Parse tree entries from msgpack bytes, sorted by name.
+/// The format is an array of maps with numeric keys (1=name, 2=kind, 3=mode, 4=size, 5=hash,
+/// 6=hash_alg, 7=inline content).
+///
+/// Errors give the byte offset where decoding failed and, for entries of the
+/// wrong shape, the msgpack type found there.
 fn parse_tree_entries(bytes: &[u8]) -> Result<Vec<TreeEntry>> {
     let mut cursor = Cursor::new(bytes);
-    let value = rmpv::decode::read_value(&mut cursor)
-        .map_err(|e| StoreError::Corrupt(format!("invalid tree msgpack: {e}")))?;
-
-    let array = match &value {
-        Value::Array(arr) => arr,
-        _ => return Err(StoreError::Corrupt("tree is not an array".into())),
+    let count = match rmp::decode::read_array_len(&mut cursor) {
+        Ok(count) => count,
+        Err(ValueReadError::TypeMismatch(marker)) => {
+            return Err(corrupt_tree(
+                0,
+                format!("expected array, found {}", marker_type_name(marker)),
+            ))
+        }
+        Err(e) => return Err(corrupt_tree(cursor.position(), e.to_string())),
     };
 
-    let mut entries = Vec::with_capacity(array.len());
-    for item in array {
-        let entry = parse_tree_entry(item)?;
+    // The count is untrusted; every entry takes at least one byte.
+    let mut entries = Vec::with_capacity((count as usize).min(bytes.len()));
+    for index in 0..count {
+        let offset = cursor.position();
+        let value = rmpv::decode::read_value(&mut cursor)
+            .map_err(|e| corrupt_tree(cursor.position(), format!("entry {index}: {e}")))?;
+        let entry = parse_tree_entry(&value)
+            .map_err(|detail| corrupt_tree(offset, format!("entry {index}: {detail}")))?;
         entries.push(entry);
     }
+    // Stable, so duplicate names keep the order the producer wrote them in.
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
 
     Ok(entries)
 }
 
+fn corrupt_tree(offset: u64, detail: String) -> StoreError {
+    StoreError::Corrupt(format!("invalid tree msgpack at byte {offset}: {detail}"))
+}
+
 /// Parse a single TreeEntry from a msgpack Value.
 /// Supports both integer keys (1, 2, 3...) and string keys ("1", "2", "3"...)
 /// since Go's msgpack encoder uses string keys for struct tags like `msgpack:"1"`.
-fn parse_tree_entry(value: &Value) -> Result<TreeEntry> {
+fn parse_tree_entry(value: &Value) -> std::result::Result<TreeEntry, String> {
     let map = match value {
         Value::Map(m) => m,
-        _ => return Err(StoreError::Corrupt("tree entry is not a map".into())),
+        other => return Err(format!("expected map, found {}", value_type_name(other))),
     };
 
     let mut name = String::new();
@@ -312,6 +794,8 @@ fn parse_tree_entry(value: &Value) -> Result<TreeEntry> {
     let mut mode: u32 = 0;
     let mut size: u64 = 0;
     let mut hash: Vec<u8> = Vec::new();
+    let mut hash_alg: u8 = 0;
+    let mut inline_content = None;
 
     for (k, v) in map {
         // Support both integer keys and string keys (Go uses string keys like "1", "2")
@@ -352,28 +836,112 @@ fn parse_tree_entry(value: &Value) -> Result<TreeEntry> {
                     hash = b.clone();
                 }
             }
+            6 => {
+                // hash_alg
+                if let Value::Integer(i) = v {
+                    hash_alg = i
+                        .as_u64()
+                        .and_then(|id| u8::try_from(id).ok())
+                        .ok_or_else(|| format!("hash_alg {i} out of range"))?;
+                }
+            }
+            7 => {
+                // inline_content
+                if let Value::Binary(b) = v {
+                    inline_content = Some(b.clone());
+                }
+            }
             _ => {}
         }
     }
 
+    // Names that could escape a directory the tree is written into.
+    if name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(format!("unsafe entry name {name:?}"));
+    }
+
     Ok(TreeEntry {
         name,
         kind,
         mode,
         size,
         hash,
+        hash_alg,
+        inline_content,
     })
 }
 
+/// msgpack type family of a decoded value, for error messages.
+fn value_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "bool",
+        Value::Integer(_) => "integer",
+        Value::F32(_) | Value::F64(_) => "float",
+        Value::String(_) => "string",
+        Value::Binary(_) => "binary",
+        Value::Array(_) => "array",
+        Value::Map(_) => "map",
+        Value::Ext(..) => "ext",
+    }
+}
+
+/// msgpack type family of a marker byte, for error messages.
+fn marker_type_name(marker: Marker) -> &'static str {
+    match marker {
+        Marker::Null => "nil",
+        Marker::True | Marker::False => "bool",
+        Marker::FixPos(_)
+        | Marker::FixNeg(_)
+        | Marker::U8
+        | Marker::U16
+        | Marker::U32
+        | Marker::U64
+        | Marker::I8
+        | Marker::I16
+        | Marker::I32
+        | Marker::I64 => "integer",
+        Marker::F32 | Marker::F64 => "float",
+        Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32 => "string",
+        Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => "binary",
+        Marker::FixArray(_) | Marker::Array16 | Marker::Array32 => "array",
+        Marker::FixMap(_) | Marker::Map16 | Marker::Map32 => "map",
+        Marker::FixExt1
+        | Marker::FixExt2
+        | Marker::FixExt4
+        | Marker::FixExt8
+        | Marker::FixExt16
+        | Marker::Ext8
+        | Marker::Ext16
+        | Marker::Ext32 => "ext",
+        Marker::Reserved => "reserved marker 0xc1",
+    }
+}
+
+/// What a snapshot path resolves to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolvedPath {
+    /// A directory, identified by its tree object.
+    Tree(TreeHash),
+    /// A file or symlink, identified by its content blob.
+    Blob(BlobHash),
+}
+
+/// Look up `name` in entries sorted by `parse_tree_entries`.
+fn find_entry<'a>(entries: &'a [TreeEntry], name: &str) -> Option<&'a TreeEntry> {
+    let idx = entries.partition_point(|e| e.name.as_str() < name);
+    entries.get(idx).filter(|e| e.name == name)
+}
+
 /// Resolve a path to its tree hash (for directories) or blob hash (for files).
-/// Returns (hash, is_directory).
 pub fn resolve_path(
     blob_store: &mut BlobStore,
-    root_hash: &[u8; 32],
+    root_hash: &FsRootHash,
     path: &str,
-) -> Result<([u8; 32], bool)> {
+) -> Result<ResolvedPath> {
+    let root = ResolvedPath::Tree((*root_hash).into());
     if path.is_empty() || path == "/" {
-        return Ok((*root_hash, true));
+        return Ok(root);
     }
 
     let parts: Vec<&str> = path
@@ -383,24 +951,26 @@ pub fn resolve_path(
         .collect();
 
     if parts.is_empty() {
-        return Ok((*root_hash, true));
+        return Ok(root);
     }
 
-    let mut current_hash = *root_hash;
+    let mut current_hash: TreeHash = (*root_hash).into();
 
     for (i, part) in parts.iter().enumerate() {
         let entries = load_tree_entries(blob_store, &current_hash)?;
 
-        let entry = entries
-            .iter()
-            .find(|e| e.name == *part)
+        let entry = find_entry(&entries, part)
             .ok_or_else(|| StoreError::NotFound(format!("path component not found: {part}")))?;
 
         let entry_hash = entry.hash_array()?;
         let is_last = i == parts.len() - 1;
 
         if is_last {
-            return Ok((entry_hash, entry.kind_enum() == EntryKind::Directory));
+            return Ok(if entry.kind_enum() == EntryKind::Directory {
+                ResolvedPath::Tree(entry_hash.into())
+            } else {
+                ResolvedPath::Blob(entry_hash.into())
+            });
         }
 
         // Must be a directory to continue
@@ -408,18 +978,132 @@ pub fn resolve_path(
             return Err(StoreError::InvalidInput(format!("not a directory: {part}")));
         }
 
-        current_hash = entry_hash;
+        current_hash = entry_hash.into();
     }
 
     unreachable!()
 }
 
-/// Get a file's content by path from a filesystem snapshot.
-pub fn get_file_at_path(
+/// Resolve a directory path to its tree hash and the tree object exactly as
+/// stored, for copying trees to another store without re-encoding them
+/// (which could change their bytes and so their hash).
+pub fn get_tree_bytes(
     blob_store: &mut BlobStore,
-    root_hash: &[u8; 32],
+    root_hash: &FsRootHash,
     path: &str,
-) -> Result<(Vec<u8>, TreeEntry)> {
+) -> Result<([u8; 32], Vec<u8>)> {
+    match resolve_path(blob_store, root_hash, path)? {
+        ResolvedPath::Tree(tree_hash) => {
+            let bytes = blob_store.get(tree_hash.as_bytes())?;
+            Ok((tree_hash.into(), bytes))
+        }
+        ResolvedPath::Blob(_) => Err(StoreError::InvalidInput(format!("not a directory: {path}"))),
+    }
+}
+
+/// Most paths a `SnapshotVerifyReport` lists; further problems are only
+/// counted.
+pub const VERIFY_MAX_PATHS: usize = 1000;
+
+/// What `verify_snapshot` found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotVerifyReport {
+    /// Trees read and parsed.
+    pub trees_checked: u64,
+    /// File and symlink blobs checked for presence.
+    pub blobs_checked: u64,
+    /// Entries whose tree or blob is missing, or whose tree is unreadable.
+    pub missing: u64,
+    /// Paths of the first `VERIFY_MAX_PATHS` of those, in walk order ("" is
+    /// the root).
+    pub missing_paths: Vec<String>,
+}
+
+/// Checks that every object a snapshot references is stored: each tree is
+/// read and parsed, and each file and symlink blob must be present (its
+/// content is not re-read). Subtrees shared within the snapshot and found
+/// intact are walked once.
+pub fn verify_snapshot(
+    blob_store: &mut BlobStore,
+    root_hash: &FsRootHash,
+) -> Result<SnapshotVerifyReport> {
+    let mut report = SnapshotVerifyReport::default();
+    let mut intact = HashSet::new();
+    verify_tree(
+        blob_store,
+        (*root_hash).into(),
+        "",
+        &mut intact,
+        &mut report,
+    );
+    Ok(report)
+}
+
+/// Verifies the tree at `path`, returning whether it and everything below
+/// it are present.
+fn verify_tree(
+    blob_store: &mut BlobStore,
+    tree_hash: TreeHash,
+    path: &str,
+    intact: &mut HashSet<[u8; 32]>,
+    report: &mut SnapshotVerifyReport,
+) -> bool {
+    fn record(report: &mut SnapshotVerifyReport, path: String) {
+        report.missing += 1;
+        if report.missing_paths.len() < VERIFY_MAX_PATHS {
+            report.missing_paths.push(path);
+        }
+    }
+
+    if intact.contains(tree_hash.as_bytes()) {
+        return true;
+    }
+    report.trees_checked += 1;
+    let entries = match load_tree_entries(blob_store, &tree_hash) {
+        Ok(entries) => entries,
+        Err(_) => {
+            record(report, path.to_string());
+            return false;
+        }
+    };
+
+    let mut ok = true;
+    for entry in entries {
+        let child = if path.is_empty() {
+            entry.name.clone()
+        } else {
+            format!("{path}/{}", entry.name)
+        };
+        if entry.inline_content.is_some() {
+            continue;
+        }
+        let Ok(hash) = entry.hash_array() else {
+            record(report, child);
+            ok = false;
+            continue;
+        };
+        if entry.kind_enum() == EntryKind::Directory {
+            ok &= verify_tree(blob_store, hash.into(), &child, intact, report);
+        } else {
+            report.blobs_checked += 1;
+            if !blob_store.contains(&hash) {
+                record(report, child);
+                ok = false;
+            }
+        }
+    }
+    if ok {
+        intact.insert(*tree_hash.as_bytes());
+    }
+    ok
+}
+
+/// Find the entry a path names in a filesystem snapshot.
+fn find_path_entry(
+    blob_store: &mut BlobStore,
+    root_hash: &FsRootHash,
+    path: &str,
+) -> Result<TreeEntry> {
     let parts: Vec<&str> = path
         .trim_matches('/')
         .split('/')
@@ -430,37 +1114,16 @@ pub fn get_file_at_path(
         return Err(StoreError::InvalidInput("empty path".into()));
     }
 
-    let mut current_hash = *root_hash;
+    let mut current_hash: TreeHash = (*root_hash).into();
 
     for (i, part) in parts.iter().enumerate() {
         let entries = load_tree_entries(blob_store, &current_hash)?;
 
-        let entry = entries
-            .iter()
-            .find(|e| e.name == *part)
+        let entry = find_entry(&entries, part)
             .ok_or_else(|| StoreError::NotFound(format!("path component not found: {part}")))?;
 
-        let entry_hash = entry.hash_array()?;
-        let is_last = i == parts.len() - 1;
-
-        if is_last {
-            // Return file content
-            match entry.kind_enum() {
-                EntryKind::File => {
-                    let content = blob_store.get(&entry_hash)?;
-                    return Ok((content, entry.clone()));
-                }
-                EntryKind::Symlink => {
-                    // For symlinks, return the target path as content
-                    let content = blob_store.get(&entry_hash)?;
-                    return Ok((content, entry.clone()));
-                }
-                EntryKind::Directory => {
-                    return Err(StoreError::InvalidInput(format!(
-                        "path is a directory: {path}"
-                    )));
-                }
-            }
+        if i == parts.len() - 1 {
+            return Ok(entry.clone());
         }
 
         // Must be a directory to continue
@@ -468,12 +1131,60 @@ pub fn get_file_at_path(
             return Err(StoreError::InvalidInput(format!("not a directory: {part}")));
         }
 
-        current_hash = entry_hash;
+        current_hash = entry.hash_array()?.into();
     }
 
     unreachable!()
 }
 
+/// Get a file's content by path from a filesystem snapshot.
+pub fn get_file_at_path(
+    blob_store: &mut BlobStore,
+    root_hash: &FsRootHash,
+    path: &str,
+) -> Result<(Vec<u8>, TreeEntry)> {
+    let entry = find_path_entry(blob_store, root_hash, path)?;
+    let entry_hash = entry.hash_array()?;
+    match entry.kind_enum() {
+        EntryKind::File => {
+            let content = match &entry.inline_content {
+                Some(inline) => inline.clone(),
+                None => blob_store.get(&entry_hash)?,
+            };
+            Ok((content, entry))
+        }
+        EntryKind::Symlink => {
+            // For symlinks, return the target path as content
+            let content = blob_store.get(&entry_hash)?;
+            Ok((content, entry))
+        }
+        EntryKind::Directory => Err(StoreError::InvalidInput(format!(
+            "path is a directory: {path}"
+        ))),
+    }
+}
+
+/// Classify the file at a path from its first `SNIFF_LEN` bytes, reading
+/// only that much of its blob. Symlinks are classified by their target path.
+pub fn file_kind_at_path(
+    blob_store: &mut BlobStore,
+    root_hash: &FsRootHash,
+    path: &str,
+) -> Result<FileKind> {
+    let entry = find_path_entry(blob_store, root_hash, path)?;
+    if entry.kind_enum() == EntryKind::Directory {
+        return Err(StoreError::InvalidInput(format!(
+            "path is a directory: {path}"
+        )));
+    }
+    let head = match &entry.inline_content {
+        Some(inline) => inline[..inline.len().min(SNIFF_LEN)].to_vec(),
+        None => blob_store.get_range(&entry.hash_array()?, 0, SNIFF_LEN)?,
+    };
+    let truncated = entry.size > head.len() as u64;
+    Ok(sniff(&head, truncated))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -488,7 +1199,7 @@ mod tests {
         assert!(index.get(1).is_none());
 
         // Attach
-        let hash = [0xabu8; 32];
+        let hash = FsRootHash::new([0xabu8; 32]);
         index.attach(1, hash).unwrap();
 
         // Should be retrievable
@@ -505,8 +1216,8 @@ mod tests {
         let tmpdir = TempDir::new().unwrap();
         let mut index = FsRootsIndex::open(tmpdir.path()).unwrap();
 
-        let hash1 = [0x11u8; 32];
-        let hash2 = [0x22u8; 32];
+        let hash1 = FsRootHash::new([0x11u8; 32]);
+        let hash2 = FsRootHash::new([0x22u8; 32]);
 
         index.attach(1, hash1).unwrap();
         index.attach(1, hash2).unwrap();
@@ -514,4 +1225,152 @@ mod tests {
         // Last write wins
         assert_eq!(index.get(1), Some(hash2));
     }
+
+    #[test]
+    fn test_fs_roots_turns_for_root() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut index = FsRootsIndex::open(tmpdir.path()).unwrap();
+
+        index.attach(3, [0x11u8; 32].into()).unwrap();
+        index.attach(1, [0x11u8; 32].into()).unwrap();
+        index.attach(2, [0x22u8; 32].into()).unwrap();
+        // Re-attaching a turn drops its old mapping.
+        index.attach(4, [0x11u8; 32].into()).unwrap();
+        index.attach(4, [0x22u8; 32].into()).unwrap();
+
+        assert_eq!(index.turns_for_root(&[0x11u8; 32]), vec![1, 3]);
+        assert_eq!(index.turns_for_root(&[0x22u8; 32]), vec![2, 4]);
+        assert!(index.turns_for_root(&[0x33u8; 32]).is_empty());
+    }
+
+    #[test]
+    fn test_fs_roots_compact() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut index = FsRootsIndex::open(tmpdir.path()).unwrap();
+
+        index.attach(1, [0x11u8; 32].into()).unwrap();
+        index.attach(1, [0x22u8; 32].into()).unwrap();
+        index.attach(2, [0x33u8; 32].into()).unwrap();
+        index.attach(1, [0x44u8; 32].into()).unwrap();
+        assert_eq!(index.dead_ratio(), 0.5);
+
+        let reclaimed = index.compact().unwrap();
+        assert_eq!(reclaimed, 2 * MIN_ROOT_RECORD_LEN);
+        assert_eq!(index.dead_ratio(), 0.0);
+
+        // Writes after compaction append to the new file.
+        index.attach(3, [0x55u8; 32].into()).unwrap();
+        drop(index);
+        let index2 = FsRootsIndex::open(tmpdir.path()).unwrap();
+        assert_eq!(index2.get(1), Some([0x44u8; 32].into()));
+        assert_eq!(index2.get(2), Some([0x33u8; 32].into()));
+        assert_eq!(index2.get(3), Some([0x55u8; 32].into()));
+    }
+
+    #[test]
+    fn test_fs_roots_skip_corrupt_mid_file_record() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut index = FsRootsIndex::open(tmpdir.path()).unwrap();
+        for turn_id in 1..=3u64 {
+            index.attach(turn_id, [turn_id as u8; 32].into()).unwrap();
+        }
+        drop(index);
+
+        // Flip a hash byte in the second record.
+        let path = tmpdir.path().join("roots.idx");
+        let mut data = std::fs::read(&path).unwrap();
+        let second = ROOTS_HEADER.len() + MIN_ROOT_RECORD_LEN as usize;
+        data[second + 8] ^= 0xFF;
+        std::fs::write(&path, &data).unwrap();
+
+        let mut index = FsRootsIndex::open(tmpdir.path()).unwrap();
+        assert_eq!(index.skipped_records(), 1);
+        assert_eq!(index.stats().skipped_records, 1);
+        assert_eq!(index.get(1), Some([1u8; 32].into()));
+        assert_eq!(index.get(2), None);
+        assert_eq!(index.get(3), Some([3u8; 32].into()));
+        let mut report = CheckReport::default();
+        index.check(&mut report).unwrap();
+        assert_eq!(report.issues.len(), 1, "{:?}", report.issues);
+        assert_eq!(report.issues[0].offset, Some(second as u64));
+
+        // Appends still land after the damaged record, and repair keeps it.
+        index.attach(4, [4u8; 32].into()).unwrap();
+        drop(index);
+        let mut repair = RepairReport::default();
+        FsRootsIndex::repair(tmpdir.path(), &mut repair).unwrap();
+        assert!(repair.is_clean(), "{:?}", repair.actions);
+        let index = FsRootsIndex::open(tmpdir.path()).unwrap();
+        assert_eq!(index.skipped_records(), 1);
+        assert_eq!(index.get(3), Some([3u8; 32].into()));
+        assert_eq!(index.get(4), Some([4u8; 32].into()));
+    }
+
+    #[test]
+    fn test_tree_entries_sorted_on_read() {
+        let entry = |name: &str| {
+            Value::Map(vec![
+                (Value::from(1), Value::from(name)),
+                (Value::from(2), Value::from(0)),
+                (Value::from(5), Value::Binary(vec![0u8; 32])),
+            ])
+        };
+        let tree = Value::Array(vec![entry("zeta"), entry("Beta"), entry("alpha")]);
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &tree).unwrap();
+
+        let entries = parse_tree_entries(&bytes).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Beta", "alpha", "zeta"]);
+        assert_eq!(
+            find_entry(&entries, "zeta").map(|e| e.name.as_str()),
+            Some("zeta")
+        );
+        assert!(find_entry(&entries, "gamma").is_none());
+    }
+
+    #[test]
+    fn test_tree_decode_errors_report_offset_and_type() {
+        let detail = |bytes: &[u8]| match parse_tree_entries(bytes) {
+            Err(StoreError::Corrupt(msg)) => msg,
+            other => panic!("expected corrupt tree, got {other:?}"),
+        };
+
+        let mut not_array = Vec::new();
+        rmpv::encode::write_value(&mut not_array, &Value::from("tree")).unwrap();
+        assert_eq!(
+            detail(&not_array),
+            "invalid tree msgpack at byte 0: expected array, found string"
+        );
+
+        // The second entry (after the 1-byte array header and a 1-byte
+        // empty map) is an integer.
+        let tree = Value::Array(vec![Value::Map(vec![]), Value::from(7)]);
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &tree).unwrap();
+        assert_eq!(
+            detail(&bytes),
+            "invalid tree msgpack at byte 2: entry 1: expected map, found integer"
+        );
+
+        // Truncated mid-entry: decoding stops at the end of the input.
+        bytes.truncate(2);
+        assert!(detail(&bytes).starts_with("invalid tree msgpack at byte 2: entry 1:"));
+    }
+
+    #[test]
+    fn test_tree_rejects_path_traversal_names() {
+        for name in [".", "..", "../../etc", "a/b", "a\\b", "a\0b"] {
+            let entry = Value::Map(vec![(Value::from(1), Value::from(name))]);
+            let mut bytes = Vec::new();
+            rmpv::encode::write_value(&mut bytes, &Value::Array(vec![entry])).unwrap();
+            match parse_tree_entries(&bytes) {
+                Err(StoreError::Corrupt(msg)) => assert!(
+                    msg.starts_with("invalid tree msgpack at byte 1: entry 0: unsafe entry name"),
+                    "{msg}"
+                ),
+                other => panic!("expected corrupt tree for {name:?}, got {other:?}"),
+            }
+        }
+    }
 }
@@ -0,0 +1,114 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content-type sniffing for snapshot files.
+//!
+//! Classification looks only at a file's leading bytes, so a browser can
+//! decide how to render a file without downloading it.
+
+/// How many leading bytes of a file are examined.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Offset of the `ustar` magic in a tar header.
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// What a file's leading bytes say about its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// Valid UTF-8 without NUL bytes (including empty files).
+    Text,
+    /// Anything else that is not a recognised image or archive.
+    Binary,
+    /// An image, with its format (e.g. "png").
+    Image(&'static str),
+    /// A compressed file or archive, with its format (e.g. "zip").
+    Archive(&'static str),
+}
+
+const IMAGE_MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpeg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"II*\0", "tiff"),
+    (b"MM\0*", "tiff"),
+    (b"\0\0\x01\0", "ico"),
+];
+
+const ARCHIVE_MAGIC: &[(&[u8], &str)] = &[
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip"),
+    (b"\x1f\x8b", "gzip"),
+    (b"BZh", "bzip2"),
+    (b"\xfd7zXZ\0", "xz"),
+    (b"\x28\xb5\x2f\xfd", "zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+];
+
+/// Classify a file from its first bytes. `truncated` says whether `head` is
+/// only a prefix of the file, in which case a multi-byte UTF-8 sequence cut
+/// off at the end does not make it binary.
+pub fn sniff(head: &[u8], truncated: bool) -> FileKind {
+    if head.len() >= 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return FileKind::Image("webp");
+    }
+    if let Some((_, format)) = IMAGE_MAGIC
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+    {
+        return FileKind::Image(format);
+    }
+    if let Some((_, format)) = ARCHIVE_MAGIC
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+    {
+        return FileKind::Archive(format);
+    }
+    if head
+        .get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 5)
+        .is_some_and(|magic| magic == b"ustar")
+    {
+        return FileKind::Archive("tar");
+    }
+
+    if head.contains(&0) {
+        return FileKind::Binary;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => FileKind::Text,
+        // error_len() is None when the input ends mid-sequence.
+        Err(err) if truncated && err.error_len().is_none() => FileKind::Text,
+        Err(_) => FileKind::Binary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_classifies_text_binary_and_magic() {
+        assert_eq!(sniff(b"", false), FileKind::Text);
+        assert_eq!(sniff("héllo\n".as_bytes(), false), FileKind::Text);
+        assert_eq!(
+            sniff(b"\x89PNG\r\n\x1a\n\0\0", false),
+            FileKind::Image("png")
+        );
+        assert_eq!(
+            sniff(b"RIFF\0\0\0\0WEBPVP8 ", false),
+            FileKind::Image("webp")
+        );
+        assert_eq!(sniff(b"PK\x03\x04rest", false), FileKind::Archive("zip"));
+        assert_eq!(sniff(b"\x1f\x8b\x08", false), FileKind::Archive("gzip"));
+        let mut tar = vec![b'a'; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar, false), FileKind::Archive("tar"));
+        assert_eq!(sniff(b"ab\0cd", false), FileKind::Binary);
+        assert_eq!(sniff(b"\xff\xfe\xfa", false), FileKind::Binary);
+
+        // A prefix may end partway through a character.
+        let cut = &"añ".as_bytes()[..2];
+        assert_eq!(sniff(cut, true), FileKind::Text);
+        assert_eq!(sniff(cut, false), FileKind::Binary);
+    }
+}
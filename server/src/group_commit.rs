@@ -0,0 +1,283 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Opt-in group commit for durable writes.
+//!
+//! Without it, write requests are acknowledged once their bytes reach the
+//! OS, and a crash of the machine can lose them. With it, each write
+//! request waits for an fsync before it is acknowledged. A single syncer
+//! thread collects the writes that arrive within `max_delay` of each other
+//! (or until `max_batch` are waiting), syncs the store's files once, and
+//! wakes every waiter in the batch. Durable append throughput then scales
+//! with concurrency instead of being bounded by one fsync per append.
+//!
+//! The store lock is held only while the file handles are duplicated, not
+//! during the fsync, so reads and further writes proceed meanwhile.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, StoreError};
+use crate::store::Store;
+
+#[derive(Debug, Clone)]
+pub struct GroupCommitConfig {
+    /// Longest a write waits for others to share its fsync.
+    pub max_delay: Duration,
+    /// Sync as soon as this many writes are waiting.
+    pub max_batch: usize,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        Self {
+            max_delay: Duration::from_millis(2),
+            max_batch: 64,
+        }
+    }
+}
+
+impl GroupCommitConfig {
+    /// Load from environment. Returns None unless
+    /// `CXDB_GROUP_COMMIT_DELAY_MS` is set; `CXDB_GROUP_COMMIT_MAX_BATCH`
+    /// overrides the batch size.
+    pub fn from_env() -> Option<Self> {
+        let delay_ms: u64 = std::env::var("CXDB_GROUP_COMMIT_DELAY_MS")
+            .ok()?
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid CXDB_GROUP_COMMIT_DELAY_MS"));
+        let max_batch = std::env::var("CXDB_GROUP_COMMIT_MAX_BATCH")
+            .ok()
+            .map(|v| {
+                v.parse()
+                    .unwrap_or_else(|_| panic!("invalid CXDB_GROUP_COMMIT_MAX_BATCH: {v}"))
+            })
+            .unwrap_or(Self::default().max_batch);
+        Some(Self {
+            max_delay: Duration::from_millis(delay_ms),
+            max_batch,
+        })
+    }
+}
+
+pub struct GroupCommit {
+    state: Mutex<CommitState>,
+    /// Signalled when a write is registered.
+    registered: Condvar,
+    /// Signalled when a batch has been synced (or failed to).
+    completed: Condvar,
+    config: GroupCommitConfig,
+}
+
+#[derive(Default)]
+struct CommitState {
+    /// Writes registered so far; each waiter holds its position.
+    registered: u64,
+    /// Writes whose batch has been through an fsync attempt.
+    completed: u64,
+    /// When the oldest write not yet in a batch was registered.
+    oldest_waiting: Option<Instant>,
+    /// Failed batches whose waiters have not all seen the failure yet.
+    failed: Vec<FailedBatch>,
+    batches: u64,
+}
+
+struct FailedBatch {
+    /// Positions after `after` up to and including `through`.
+    after: u64,
+    through: u64,
+    /// Waiters in the batch that have not yet returned.
+    unreported: u64,
+    error: String,
+}
+
+impl GroupCommit {
+    /// Start the syncer thread for `store`. It runs for the life of the
+    /// process.
+    pub fn start(store: Arc<Mutex<Store>>, config: GroupCommitConfig) -> Arc<Self> {
+        Self::start_with(move || sync_store(&store), config)
+    }
+
+    /// Start the syncer thread with `sync` standing in for the store fsync.
+    fn start_with(
+        sync: impl Fn() -> Result<()> + Send + 'static,
+        config: GroupCommitConfig,
+    ) -> Arc<Self> {
+        let commit = Arc::new(Self {
+            state: Mutex::new(CommitState::default()),
+            registered: Condvar::new(),
+            completed: Condvar::new(),
+            config,
+        });
+        let syncer = Arc::clone(&commit);
+        thread::spawn(move || syncer.run(sync));
+        commit
+    }
+
+    /// Block until every write made before the call is on disk. Called after
+    /// a write request's store update and before its response is sent.
+    pub fn sync(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.registered += 1;
+        let ticket = state.registered;
+        state.oldest_waiting.get_or_insert_with(Instant::now);
+        self.registered.notify_one();
+
+        while state.completed < ticket {
+            state = self.completed.wait(state).unwrap();
+        }
+        let Some(index) = state
+            .failed
+            .iter()
+            .position(|batch| ticket > batch.after && ticket <= batch.through)
+        else {
+            return Ok(());
+        };
+        let batch = &mut state.failed[index];
+        batch.unreported -= 1;
+        let err = StoreError::Io(std::io::Error::other(format!(
+            "group commit fsync failed: {}",
+            batch.error
+        )));
+        if batch.unreported == 0 {
+            state.failed.swap_remove(index);
+        }
+        Err(err)
+    }
+
+    /// Batches synced so far.
+    pub fn batches(&self) -> u64 {
+        self.state.lock().unwrap().batches
+    }
+
+    fn run(&self, sync: impl Fn() -> Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            while state.registered == state.completed {
+                state = self.registered.wait(state).unwrap();
+            }
+            // Give other writers until the oldest waiter's deadline to join.
+            let deadline =
+                state.oldest_waiting.unwrap_or_else(Instant::now) + self.config.max_delay;
+            loop {
+                let waiting = (state.registered - state.completed) as usize;
+                let now = Instant::now();
+                if waiting >= self.config.max_batch || now >= deadline {
+                    break;
+                }
+                state = self
+                    .registered
+                    .wait_timeout(state, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+
+            let batch_end = state.registered;
+            drop(state);
+            let result = sync();
+            state = self.state.lock().unwrap();
+
+            if let Err(err) = result {
+                let after = state.completed;
+                state.failed.push(FailedBatch {
+                    after,
+                    through: batch_end,
+                    unreported: batch_end - after,
+                    error: err.to_string(),
+                });
+            }
+            state.completed = batch_end;
+            state.oldest_waiting = (state.registered > batch_end).then(Instant::now);
+            state.batches += 1;
+            self.completed.notify_all();
+        }
+    }
+}
+
+fn sync_store(store: &Mutex<Store>) -> Result<()> {
+    let files = store.lock().unwrap().sync_handles()?;
+    for file in files {
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn concurrent_writes_share_fsyncs() {
+        let dir = TempDir::new().unwrap();
+        let store = Arc::new(Mutex::new(Store::open(dir.path()).unwrap()));
+        let commit = GroupCommit::start(
+            Arc::clone(&store),
+            GroupCommitConfig {
+                max_delay: Duration::from_millis(50),
+                max_batch: 4,
+            },
+        );
+
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let commit = Arc::clone(&commit);
+                thread::spawn(move || {
+                    store.lock().unwrap().create_context(0).unwrap();
+                    commit.sync().unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let batches = commit.batches();
+        assert!((1..8).contains(&batches), "{batches} batches");
+
+        // A lone write still completes once the delay passes.
+        store.lock().unwrap().create_context(0).unwrap();
+        commit.sync().unwrap();
+        assert_eq!(commit.batches(), batches + 1);
+    }
+
+    #[test]
+    fn every_waiter_of_a_failed_batch_sees_the_error() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let failing = Arc::new(AtomicBool::new(true));
+        let sync_failing = Arc::clone(&failing);
+        let commit = GroupCommit::start_with(
+            move || {
+                if sync_failing.load(Ordering::SeqCst) {
+                    Err(StoreError::Io(std::io::Error::other("disk gone")))
+                } else {
+                    Ok(())
+                }
+            },
+            GroupCommitConfig {
+                max_delay: Duration::from_millis(1),
+                max_batch: 1,
+            },
+        );
+
+        // One write per batch, so later failed batches complete while
+        // earlier waiters may still be waking up.
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let commit = Arc::clone(&commit);
+                thread::spawn(move || commit.sync())
+            })
+            .collect();
+        for writer in writers {
+            let err = writer.join().unwrap().unwrap_err();
+            assert!(err.to_string().contains("disk gone"), "{err}");
+        }
+        assert!(commit.state.lock().unwrap().failed.is_empty());
+
+        failing.store(false, Ordering::SeqCst);
+        commit.sync().expect("sync after the disk recovers");
+    }
+}
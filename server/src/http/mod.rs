@@ -14,7 +14,7 @@ use url::Url;
 
 use crate::error::{Result, StoreError};
 use crate::events::EventBus;
-use crate::fs_store::EntryKind;
+use crate::fs_store::{EntryKind, SnapshotMeta};
 use crate::metrics::{Metrics, SessionTracker};
 use crate::projection::{BytesRender, EnumRender, RenderOptions, TimeRender, U64Format};
 use crate::registry::{PutOutcome, Registry, RegistryBundle, RendererSpec, TypeVersionSpec};
@@ -208,9 +208,24 @@ fn handle_request(
                     .get("include_provenance")
                     .map(|v| v == "1")
                     .unwrap_or(false);
+                // Repeated `label=` params; `label_match=any` switches from AND to OR.
+                let labels = query_values(url.query().unwrap_or(""), "label");
+                let match_all = match params.get("label_match").map(String::as_str) {
+                    None | Some("all") => true,
+                    Some("any") => false,
+                    Some(other) => {
+                        return Err(StoreError::InvalidInput(format!(
+                            "invalid label_match: {other} (expected all or any)"
+                        )))
+                    }
+                };
 
                 let mut store = store.lock().unwrap();
-                let contexts = store.list_recent_contexts(limit);
+                let contexts = if labels.is_empty() {
+                    store.list_recent_contexts(limit)
+                } else {
+                    store.list_contexts_with_labels(&labels, match_all, limit)
+                };
 
                 let contexts_json: Vec<JsonValue> = contexts
                     .iter()
@@ -546,6 +561,13 @@ fn handle_request(
                 };
                 metrics.record_get_last(t0.elapsed());
 
+                let min_depth = turns
+                    .iter()
+                    .map(|item| item.record.depth)
+                    .min()
+                    .unwrap_or(0);
+                let active_chain = store.turn_store.active_chain_from(context_id, min_depth);
+
                 let registry = registry.lock().unwrap();
                 let mut out_turns = Vec::new();
                 for item in turns.iter() {
@@ -581,6 +603,10 @@ fn handle_request(
                         JsonValue::String(item.record.parent_turn_id.to_string()),
                     );
                     turn_obj.insert("depth".into(), JsonValue::Number(item.record.depth.into()));
+                    turn_obj.insert(
+                        "on_active_chain".into(),
+                        JsonValue::Bool(active_chain.contains(&item.record.turn_id)),
+                    );
                     turn_obj.insert(
                         "declared_type".into(),
                         json!({
@@ -597,6 +623,8 @@ fn handle_request(
                             .payload
                             .as_ref()
                             .ok_or_else(|| StoreError::InvalidInput("payload not loaded".into()))?;
+                        let resolved = store.resolve_tool_result_blob(payload)?;
+                        let payload = resolved.as_deref().unwrap_or(payload);
                         let projected =
                             crate::projection::project_msgpack(payload, desc, &registry, &options)?;
                         turn_obj.insert(
@@ -740,6 +768,7 @@ fn handle_request(
                     "turn_id": turn_id.to_string(),
                     "path": path,
                     "fs_root_hash": hex::encode(fs_root),
+                    "snapshot": snapshot_meta_to_json(store.get_fs_meta(turn_id)),
                     "entries": entries_json,
                 });
 
@@ -870,6 +899,7 @@ fn handle_request(
                             "turn_id": turn_id.to_string(),
                             "path": path,
                             "fs_root_hash": hex::encode(fs_root),
+                            "snapshot": snapshot_meta_to_json(store.get_fs_meta(turn_id)),
                             "entries": entries_json,
                         });
 
@@ -1023,6 +1053,14 @@ fn parse_query(query: &str) -> HashMap<String, String> {
         .collect()
 }
 
+/// All values of a query parameter that may be repeated (`parse_query` keeps only the last).
+fn query_values(query: &str, key: &str) -> Vec<String> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .filter(|(k, _)| k == key)
+        .map(|(_, v)| v.into_owned())
+        .collect()
+}
+
 fn map_error(err: &StoreError) -> (u16, String) {
     match err {
         StoreError::NotFound(msg) => {
@@ -1033,6 +1071,9 @@ fn map_error(err: &StoreError) -> (u16, String) {
             }
         }
         StoreError::InvalidInput(msg) => (422, msg.clone()),
+        StoreError::InvalidParent(msg) => (422, msg.clone()),
+        StoreError::QuotaExceeded(msg) => (507, msg.clone()),
+        StoreError::NotReady(msg) => (425, msg.clone()),
         StoreError::Corrupt(msg) => (500, msg.clone()),
         StoreError::Io(msg) => (500, msg.to_string()),
     }
@@ -1088,6 +1129,21 @@ fn type_version_to_json(spec: &TypeVersionSpec) -> JsonValue {
 }
 
 /// Guess content type from file extension.
+/// Capture metadata recorded with a filesystem snapshot, or null for
+/// snapshots attached without any.
+fn snapshot_meta_to_json(meta: Option<SnapshotMeta>) -> JsonValue {
+    match meta {
+        Some(meta) => json!({
+            "captured_at_unix_ms": meta.captured_at_unix_ms,
+            "file_count": meta.file_count,
+            "dir_count": meta.dir_count,
+            "total_bytes": meta.total_bytes,
+            "duration_ms": meta.duration_ms,
+        }),
+        None => JsonValue::Null,
+    }
+}
+
 fn guess_content_type(path: &str) -> &'static str {
     let ext = path.rsplit('.').next().unwrap_or("");
     match ext.to_lowercase().as_str() {
@@ -4,15 +4,19 @@
 //! Library crate for the AI Context Store service.
 
 pub mod blob_store;
+pub mod check;
+pub mod compaction;
 pub mod config;
 pub mod cql;
 pub mod error;
 pub mod events;
 pub mod fs_store;
+pub mod group_commit;
 pub mod http;
 pub mod metrics;
 pub mod projection;
 pub mod protocol;
+pub mod quota;
 pub mod registry;
 pub mod s3_sync;
 pub mod store;
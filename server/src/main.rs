@@ -9,28 +9,49 @@ use std::thread;
 use std::time::Duration;
 
 use byteorder::WriteBytesExt;
+use cxdb_server::compaction::{start_compaction, CompactionConfig};
 use cxdb_server::config::Config;
 use cxdb_server::error::{Result, StoreError};
-use cxdb_server::events::{EventBus, StoreEvent};
+use cxdb_server::events::{EventBus, EventSubscriber, StoreEvent};
+use cxdb_server::group_commit::{GroupCommit, GroupCommitConfig};
 use cxdb_server::http::start_http;
 use cxdb_server::metrics::Metrics;
 use cxdb_server::metrics::SessionTracker;
 use cxdb_server::protocol::{
-    encode_append_ack, encode_attach_fs_resp, encode_ctx_create_resp, encode_error,
-    encode_hello_resp, encode_put_blob_resp, parse_append_turn, parse_attach_fs, parse_ctx_create,
-    parse_ctx_fork, parse_get_blob, parse_get_head, parse_get_last, parse_hello, parse_put_blob,
-    read_frame, write_frame, MsgType,
+    encode_append_ack, encode_attach_fs_resp, encode_blob_upload_resp, encode_ctx_create_resp,
+    encode_error, encode_find_snapshot_refs_resp, encode_has_blobs_resp, encode_health_resp,
+    encode_hello_resp, encode_item_types, encode_list_contexts_resp, encode_put_blob_resp,
+    encode_trim_context_resp, encode_verify_snapshot_resp, parse_append_turn, parse_attach_fs,
+    parse_attach_fs_head, parse_begin_blob, parse_blob_chunk, parse_commit_blob,
+    parse_ctx_create_request, parse_find_snapshot_refs, parse_get_blob, parse_get_head,
+    parse_get_head_at, parse_get_last, parse_has_blobs, parse_hello, parse_list_contexts,
+    parse_put_blob, parse_trim_context, parse_verify_snapshot, read_frame, write_frame, MsgType,
+    WATCH_FLAG_STOP, WATCH_FLAG_UPDATE,
 };
 use cxdb_server::registry::Registry;
 use cxdb_server::s3_sync::{S3Sync, S3SyncConfig, S3SyncHandle};
-use cxdb_server::store::Store;
+use cxdb_server::store::{Store, TurnWithMeta};
+use cxdb_server::turn_store::{ContextHead, TURN_FLAG_CHECKPOINT};
 
 fn main() -> Result<()> {
     // Create tokio runtime for async S3 operations
     let rt =
         tokio::runtime::Runtime::new().map_err(|e| StoreError::Io(std::io::Error::other(e)))?;
 
-    let config = Config::from_env();
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("cxdb: {err}");
+            std::process::exit(2);
+        }
+    };
+    let compaction_config = match CompactionConfig::from_env() {
+        Ok(compaction_config) => compaction_config,
+        Err(err) => {
+            eprintln!("cxdb: {err}");
+            std::process::exit(2);
+        }
+    };
     std::fs::create_dir_all(&config.data_dir)?;
 
     // S3 sync: restore from S3 if local data is empty
@@ -67,7 +88,29 @@ fn main() -> Result<()> {
         None
     };
 
-    let store = Arc::new(Mutex::new(Store::open(&config.data_dir)?));
+    if config.repair_on_start {
+        let report = Store::repair(&config.data_dir)?;
+        if report.is_clean() {
+            eprintln!("repair: no corruption found");
+        }
+        for action in &report.actions {
+            eprintln!("[repair] {action}");
+        }
+    }
+
+    let mut store = Store::open_with_hash_algorithm(&config.data_dir, config.hash_algorithm)?;
+    store
+        .blob_store
+        .set_pack_target_bytes(config.blob_pack_target_bytes);
+    store.set_default_quota(config.default_context_quota);
+    store.set_hash_keys(config.hash_keys.clone());
+    let skipped = store.fs_roots.skipped_records();
+    if skipped > 0 {
+        eprintln!(
+            "fs/roots.idx: skipped {skipped} corrupt record(s); their snapshot mappings are lost"
+        );
+    }
+    let store = Arc::new(Mutex::new(store));
     let registry = Arc::new(Mutex::new(Registry::open(
         &config.data_dir.join("registry"),
     )?));
@@ -93,6 +136,32 @@ fn main() -> Result<()> {
     })
     .expect("Error setting signal handler");
 
+    let compaction_handle = match compaction_config {
+        Some(compaction_config) => Some(start_compaction(
+            Arc::clone(&store),
+            compaction_config,
+            Arc::clone(&shutdown),
+        )),
+        None => {
+            eprintln!("background compaction disabled");
+            None
+        }
+    };
+
+    let group_commit = match GroupCommitConfig::from_env() {
+        Some(commit_config) => {
+            eprintln!(
+                "group commit enabled (max delay {:?}, max batch {})",
+                commit_config.max_delay, commit_config.max_batch
+            );
+            Some(GroupCommit::start(Arc::clone(&store), commit_config))
+        }
+        None => {
+            eprintln!("group commit disabled");
+            None
+        }
+    };
+
     let listener = TcpListener::bind(&config.bind_addr)?;
     listener
         .set_nonblocking(true)
@@ -112,6 +181,7 @@ fn main() -> Result<()> {
                 let metrics = Arc::clone(&metrics);
                 let session_tracker = Arc::clone(&session_tracker);
                 let event_bus = Arc::clone(&event_bus);
+                let group_commit = group_commit.clone();
                 let peer_addr_str = peer_addr.to_string();
                 thread::spawn(move || {
                     if let Err(err) = handle_client(
@@ -120,6 +190,7 @@ fn main() -> Result<()> {
                         metrics,
                         session_tracker,
                         event_bus,
+                        group_commit,
                         peer_addr_str,
                     ) {
                         eprintln!("connection error: {err}");
@@ -138,6 +209,10 @@ fn main() -> Result<()> {
 
     eprintln!("Shutting down...");
 
+    if let Some(handle) = compaction_handle {
+        let _ = handle.join();
+    }
+
     // Graceful S3 sync shutdown (performs final sync)
     if let Some(handle) = s3_sync_handle {
         rt.block_on(async {
@@ -155,13 +230,17 @@ fn handle_client(
     metrics: Arc<Metrics>,
     session_tracker: Arc<SessionTracker>,
     event_bus: Arc<EventBus>,
+    group_commit: Option<Arc<GroupCommit>>,
     peer_addr: String,
 ) -> Result<()> {
     let session = metrics.register_session();
-    let session_id = session.session_id();
+    // Replaced by the client's previous id if HELLO resumes a session.
+    let mut session_id = session.session_id();
     // Client tag will be set when HELLO is received
     let mut client_tag_received = false;
     let mut client_tag = String::new();
+    // Sent in the HELLO response; lets this client resume the session.
+    let mut resume_token = [0u8; 16];
 
     loop {
         let (header, payload) = match read_frame(&mut stream) {
@@ -170,7 +249,7 @@ fn handle_client(
             Err(e) => return Err(e),
         };
 
-        metrics.record_session_activity(session_id);
+        metrics.record_session_activity(session.session_id());
         session_tracker.record_activity(session_id);
         let msg_type = header.msg_type;
         let req_id = header.req_id;
@@ -182,11 +261,29 @@ fn handle_client(
                 // Register session with client tag and peer address
                 if !client_tag_received {
                     client_tag = hello.client_tag.clone();
-                    session_tracker.register(
-                        session_id,
-                        hello.client_tag.clone(),
-                        Some(peer_addr.clone()),
-                    );
+                    let resumed = hello.resume_session_id.and_then(|prior| {
+                        session_tracker
+                            .resume(
+                                prior,
+                                &hello.resume_token,
+                                &hello.client_tag,
+                                Some(peer_addr.clone()),
+                            )
+                            .map(|token| (prior, token))
+                    });
+                    match resumed {
+                        Some((prior, token)) => {
+                            session_id = prior;
+                            resume_token = token;
+                        }
+                        None => {
+                            resume_token = session_tracker.register(
+                                session_id,
+                                hello.client_tag.clone(),
+                                Some(peer_addr.clone()),
+                            )
+                        }
+                    }
                     client_tag_received = true;
 
                     // Publish ClientConnected event
@@ -195,18 +292,23 @@ fn handle_client(
                         client_tag: hello.client_tag.clone(),
                     });
                 }
-                let resp = encode_hello_resp(session_id, 1)?; // protocol version 1
+                let hash_algorithm = store.lock().unwrap().hash_algorithm;
+                let resp = encode_hello_resp(session_id, 1, hash_algorithm.id(), &resume_token)?; // protocol version 1
                 Ok((MsgType::Hello as u16, resp))
             }
             x if x == MsgType::CtxCreate as u16 => {
                 // If no HELLO was sent, register with empty tag
                 if !client_tag_received {
-                    session_tracker.register(session_id, String::new(), Some(peer_addr.clone()));
+                    resume_token = session_tracker.register(
+                        session_id,
+                        String::new(),
+                        Some(peer_addr.clone()),
+                    );
                     client_tag_received = true;
                 }
-                let base_turn_id = parse_ctx_create(&payload)?;
+                let req = parse_ctx_create_request(&payload)?;
                 let mut store = store.lock().unwrap();
-                let head = store.create_context(base_turn_id)?;
+                let head = store.create_context_with_quota(req.base_turn_id, req.quota)?;
                 // Associate context with this session
                 session_tracker.add_context(session_id, head.context_id);
 
@@ -225,12 +327,16 @@ fn handle_client(
             x if x == MsgType::CtxFork as u16 => {
                 // If no HELLO was sent, register with empty tag
                 if !client_tag_received {
-                    session_tracker.register(session_id, String::new(), Some(peer_addr.clone()));
+                    resume_token = session_tracker.register(
+                        session_id,
+                        String::new(),
+                        Some(peer_addr.clone()),
+                    );
                     client_tag_received = true;
                 }
-                let base_turn_id = parse_ctx_fork(&payload)?;
+                let req = parse_ctx_create_request(&payload)?;
                 let mut store = store.lock().unwrap();
-                let head = store.fork_context(base_turn_id)?;
+                let head = store.fork_context_with_quota(req.base_turn_id, req.quota)?;
                 // Associate forked context with this session
                 session_tracker.add_context(session_id, head.context_id);
 
@@ -254,70 +360,157 @@ fn handle_client(
                     encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
                 Ok((MsgType::GetHead as u16, resp))
             }
+            x if x == MsgType::GetHeadAt as u16 => {
+                let (context_id, timestamp_ms) = parse_get_head_at(&payload)?;
+                let store = store.lock().unwrap();
+                let head = store.get_head_at(context_id, timestamp_ms)?;
+                let resp =
+                    encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+                Ok((MsgType::GetHeadAt as u16, resp))
+            }
+            x if x == MsgType::ListContexts as u16 => {
+                let (after, limit) = parse_list_contexts(&payload)?;
+                let store = store.lock().unwrap();
+                let (heads, has_more) = store.list_contexts_after(after, limit);
+                let resp = encode_list_contexts_resp(&heads, has_more)?;
+                Ok((MsgType::ListContexts as u16, resp))
+            }
+            x if x == MsgType::TrimContext as u16 => {
+                let (context_id, before_unix_ms) = parse_trim_context(&payload)?;
+                let mut store = store.lock().unwrap();
+                let report = store.trim_context_before(context_id, before_unix_ms)?;
+                let resp = encode_trim_context_resp(
+                    report.turns_trimmed,
+                    report.gc_candidates.len() as u32,
+                )?;
+                Ok((MsgType::TrimContext as u16, resp))
+            }
+            x if x == MsgType::FindSnapshotRefs as u16 => {
+                let fs_root_hash = parse_find_snapshot_refs(&payload)?;
+                let store = store.lock().unwrap();
+                let turn_ids = store.find_snapshot_refs(&fs_root_hash);
+                let resp = encode_find_snapshot_refs_resp(&turn_ids)?;
+                Ok((MsgType::FindSnapshotRefs as u16, resp))
+            }
+            x if x == MsgType::VerifySnapshot as u16 => {
+                let fs_root_hash = parse_verify_snapshot(&payload)?;
+                let report = store.lock().unwrap().verify_snapshot(&fs_root_hash)?;
+                let resp = encode_verify_snapshot_resp(&report)?;
+                Ok((MsgType::VerifySnapshot as u16, resp))
+            }
+            x if x == MsgType::Health as u16 => {
+                let resp = encode_health_resp(
+                    metrics.uptime().as_millis() as u64,
+                    metrics.data_dir_writable(),
+                    env!("CARGO_PKG_VERSION"),
+                )?;
+                Ok((MsgType::Health as u16, resp))
+            }
             x if x == MsgType::AppendTurn as u16 => {
                 let req = parse_append_turn(&payload, header.flags)?;
                 let declared_type_id_clone = req.declared_type_id.clone();
                 let declared_type_version = req.declared_type_version;
                 let mut store = store.lock().unwrap();
-                let (record, metadata) = store.append_turn(
-                    req.context_id,
-                    req.parent_turn_id,
-                    req.declared_type_id,
-                    req.declared_type_version,
-                    req.encoding,
-                    req.compression,
-                    req.uncompressed_len,
-                    req.content_hash,
-                    &req.payload_bytes,
-                )?;
-                // If fs_root_hash was provided, attach it to this turn
-                if let Some(fs_root_hash) = req.fs_root_hash {
-                    store.attach_fs(record.turn_id, fs_root_hash)?;
-                }
-                metrics.record_append(op_start.elapsed());
-
-                // Publish TurnAppended event
-                event_bus.publish(StoreEvent::TurnAppended {
-                    context_id: req.context_id.to_string(),
-                    turn_id: record.turn_id.to_string(),
-                    parent_turn_id: record.parent_turn_id.to_string(),
-                    depth: record.depth,
-                    declared_type_id: Some(declared_type_id_clone),
-                    declared_type_version: Some(declared_type_version),
-                });
+                let duplicate = if req.dedup_by_item_id {
+                    store.find_duplicate_item(
+                        req.context_id,
+                        req.parent_turn_id,
+                        req.compression,
+                        &req.payload_bytes,
+                    )?
+                } else {
+                    None
+                };
+                if let Some(existing) = duplicate {
+                    // Same item id already on the chain: ack the existing turn
+                    // without appending or publishing events.
+                    let head = store.get_head(req.context_id)?;
+                    let resp = encode_append_ack(
+                        req.context_id,
+                        existing.turn_id,
+                        existing.depth,
+                        &existing.payload_hash,
+                        head.head_turn_id,
+                        existing.created_at_unix_ms,
+                    )?;
+                    Ok((MsgType::AppendTurn as u16, resp))
+                } else {
+                    let flags = if req.checkpoint {
+                        TURN_FLAG_CHECKPOINT
+                    } else {
+                        0
+                    };
+                    let (record, metadata) = store.append_turn_with_flags(
+                        req.context_id,
+                        req.parent_turn_id,
+                        req.declared_type_id,
+                        req.declared_type_version,
+                        req.encoding,
+                        req.compression,
+                        req.uncompressed_len,
+                        req.content_hash,
+                        &req.payload_bytes,
+                        flags,
+                    )?;
+                    // If fs_root_hash was provided, attach it to this turn
+                    if let Some(fs_root_hash) = req.fs_root_hash {
+                        store.attach_fs(record.turn_id, fs_root_hash.into())?;
+                    }
+                    metrics.record_append(op_start.elapsed());
 
-                // If metadata was extracted (first turn), publish ContextMetadataUpdated
-                if let Some(meta) = metadata {
-                    event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                    // Publish TurnAppended event
+                    event_bus.publish(StoreEvent::TurnAppended {
                         context_id: req.context_id.to_string(),
-                        client_tag: meta.client_tag,
-                        title: meta.title,
-                        labels: meta.labels,
-                        has_provenance: meta.provenance.is_some(),
+                        turn_id: record.turn_id.to_string(),
+                        parent_turn_id: record.parent_turn_id.to_string(),
+                        depth: record.depth,
+                        declared_type_id: Some(declared_type_id_clone),
+                        declared_type_version: Some(declared_type_version),
                     });
-                }
 
-                let resp = encode_append_ack(
-                    req.context_id,
-                    record.turn_id,
-                    record.depth,
-                    &record.payload_hash,
-                )?;
-                Ok((MsgType::AppendTurn as u16, resp))
+                    // If metadata was extracted (first turn), publish ContextMetadataUpdated
+                    if let Some(meta) = metadata {
+                        event_bus.publish(StoreEvent::ContextMetadataUpdated {
+                            context_id: req.context_id.to_string(),
+                            client_tag: meta.client_tag,
+                            title: meta.title,
+                            labels: meta.labels,
+                            has_provenance: meta.provenance.is_some(),
+                        });
+                    }
+
+                    let head = store.get_head(req.context_id)?;
+                    let resp = encode_append_ack(
+                        req.context_id,
+                        record.turn_id,
+                        record.depth,
+                        &record.payload_hash,
+                        head.head_turn_id,
+                        record.created_at_unix_ms,
+                    )?;
+                    Ok((MsgType::AppendTurn as u16, resp))
+                }
             }
             x if x == MsgType::AttachFs as u16 => {
                 let req = parse_attach_fs(&payload)?;
                 let mut store = store.lock().unwrap();
-                store.attach_fs(req.turn_id, req.fs_root_hash)?;
+                store.attach_fs_with_meta(req.turn_id, req.fs_root_hash.into(), req.meta)?;
                 let resp = encode_attach_fs_resp(req.turn_id, &req.fs_root_hash)?;
                 Ok((MsgType::AttachFs as u16, resp))
             }
+            x if x == MsgType::AttachFsHead as u16 => {
+                let req = parse_attach_fs_head(&payload)?;
+                let mut store = store.lock().unwrap();
+                let turn_id =
+                    store.attach_fs_to_head(req.context_id, req.fs_root_hash.into(), req.meta)?;
+                let resp = encode_attach_fs_resp(turn_id, &req.fs_root_hash)?;
+                Ok((MsgType::AttachFsHead as u16, resp))
+            }
             x if x == MsgType::PutBlob as u16 => {
                 let req = parse_put_blob(&payload)?;
                 let mut store = store.lock().unwrap();
                 // Verify hash matches
-                let actual_hash = blake3::hash(&req.data);
-                if actual_hash.as_bytes() != &req.hash {
+                if !store.blob_hash_matches(&req.hash, &req.data) {
                     return Err(StoreError::InvalidInput("blob hash mismatch".into()));
                 }
                 let was_new = !store.blob_store.contains(&req.hash);
@@ -325,44 +518,82 @@ fn handle_client(
                 let resp = encode_put_blob_resp(&req.hash, was_new)?;
                 Ok((MsgType::PutBlob as u16, resp))
             }
+            x if x == MsgType::BeginBlob as u16 => {
+                let req = parse_begin_blob(&payload)?;
+                let mut store = store.lock().unwrap();
+                // upload_id 0 means the blob is already stored; nothing to send.
+                let (upload_id, received) = store
+                    .begin_blob_upload(req.hash, req.total_len)?
+                    .unwrap_or((0, req.total_len));
+                let resp = encode_blob_upload_resp(upload_id, received)?;
+                Ok((MsgType::BeginBlob as u16, resp))
+            }
+            x if x == MsgType::BlobChunk as u16 => {
+                let req = parse_blob_chunk(&payload)?;
+                let mut store = store.lock().unwrap();
+                let received = store
+                    .blob_uploads
+                    .append(req.upload_id, req.offset, &req.data)?;
+                let resp = encode_blob_upload_resp(req.upload_id, received)?;
+                Ok((MsgType::BlobChunk as u16, resp))
+            }
+            x if x == MsgType::CommitBlob as u16 => {
+                let upload_id = parse_commit_blob(&payload)?;
+                let mut store = store.lock().unwrap();
+                let (hash, was_new) = store.commit_blob_upload(upload_id)?;
+                let resp = encode_put_blob_resp(&hash, was_new)?;
+                Ok((MsgType::CommitBlob as u16, resp))
+            }
+            x if x == MsgType::HasBlobs as u16 => {
+                let hashes = parse_has_blobs(&payload)?;
+                let store = store.lock().unwrap();
+                let present: Vec<bool> = hashes
+                    .iter()
+                    .map(|hash| store.blob_store.contains(hash))
+                    .collect();
+                let resp = encode_has_blobs_resp(&present)?;
+                Ok((MsgType::HasBlobs as u16, resp))
+            }
             x if x == MsgType::GetLast as u16 => {
-                let req = parse_get_last(&payload)?;
+                let req = parse_get_last(&payload, header.flags)?;
                 let mut store = store.lock().unwrap();
-                let items = store.get_last(req.context_id, req.limit, req.include_payload != 0)?;
+                if req.min_head_turn_id != 0 {
+                    store.require_turn(req.context_id, req.min_head_turn_id)?;
+                }
+                let (items, has_more) = store.get_last_window_of_types(
+                    req.context_id,
+                    req.offset,
+                    req.limit,
+                    req.include_payload != 0,
+                    &req.item_types,
+                    req.summary,
+                )?;
+                let item_types = if req.with_item_types {
+                    Some(store.item_types_of(&items)?)
+                } else {
+                    None
+                };
                 metrics.record_get_last(op_start.elapsed());
-                let mut resp = Vec::new();
-                resp.write_u32::<byteorder::LittleEndian>(items.len() as u32)?;
-                for item in items {
-                    resp.write_u64::<byteorder::LittleEndian>(item.record.turn_id)?;
-                    resp.write_u64::<byteorder::LittleEndian>(item.record.parent_turn_id)?;
-                    resp.write_u32::<byteorder::LittleEndian>(item.record.depth)?;
-                    resp.write_u32::<byteorder::LittleEndian>(
-                        item.meta.declared_type_id.len() as u32
-                    )?;
-                    resp.extend_from_slice(item.meta.declared_type_id.as_bytes());
-                    resp.write_u32::<byteorder::LittleEndian>(item.meta.declared_type_version)?;
-                    resp.write_u32::<byteorder::LittleEndian>(item.meta.encoding)?;
-                    // always return raw payload when included
-                    let compression = if item.payload.is_some() {
-                        0
-                    } else {
-                        item.meta.compression
-                    };
-                    resp.write_u32::<byteorder::LittleEndian>(compression)?;
-                    let uncompressed_len = item
-                        .payload
-                        .as_ref()
-                        .map(|p| p.len() as u32)
-                        .unwrap_or(item.meta.uncompressed_len);
-                    resp.write_u32::<byteorder::LittleEndian>(uncompressed_len)?;
-                    resp.extend_from_slice(&item.record.payload_hash);
-                    if let Some(payload) = item.payload {
-                        resp.write_u32::<byteorder::LittleEndian>(payload.len() as u32)?;
-                        resp.extend_from_slice(&payload);
-                    }
+                let mut resp = encode_turn_page(&store, req.context_id, items, has_more)?;
+                if let Some(item_types) = item_types {
+                    resp.extend(encode_item_types(&item_types)?);
                 }
                 Ok((MsgType::GetLast as u16, resp))
             }
+            x if x == MsgType::WatchHead as u16 => {
+                let context_id = parse_get_head(&payload)?;
+                // Subscribe before reading the head so no append slips between.
+                let subscriber = event_bus.subscribe();
+                let head = store.lock().unwrap().get_head(context_id)?;
+                let resp =
+                    encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+                write_frame(&mut stream, MsgType::WatchHead as u16, 0, req_id, &resp)?;
+                stream.flush()?;
+                if !watch_head(&mut stream, &store, &subscriber, &head, req_id)? {
+                    break;
+                }
+                continue;
+            }
             x if x == MsgType::GetBlob as u16 => {
                 let hash = parse_get_blob(&payload)?;
                 let mut store = store.lock().unwrap();
@@ -376,6 +607,14 @@ fn handle_client(
             _ => Err(StoreError::InvalidInput("unknown msg_type".into())),
         };
 
+        // With group commit, a write is acknowledged only once it is on disk.
+        let response = match &group_commit {
+            Some(commit) if is_write_msg(msg_type) => {
+                response.and_then(|ok| commit.sync().map(|()| ok))
+            }
+            _ => response,
+        };
+
         match response {
             Ok((resp_type, resp_payload)) => {
                 write_frame(&mut stream, resp_type, 0, req_id, &resp_payload)?;
@@ -402,6 +641,161 @@ fn handle_client(
     Ok(())
 }
 
+/// Message types that change the store and so wait for group commit.
+fn is_write_msg(msg_type: u16) -> bool {
+    [
+        MsgType::CtxCreate,
+        MsgType::CtxFork,
+        MsgType::AppendTurn,
+        MsgType::AttachFs,
+        MsgType::PutBlob,
+        MsgType::CommitBlob,
+        MsgType::AttachFsHead,
+        MsgType::TrimContext,
+    ]
+    .iter()
+    .any(|t| *t as u16 == msg_type)
+}
+
+/// How long a watching connection waits for a client frame before checking
+/// for new events.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Push a WATCH_HEAD update each time the context head advances, until the
+/// client sends a stop frame. Returns false if the client disconnected.
+fn watch_head(
+    stream: &mut TcpStream,
+    store: &Mutex<Store>,
+    subscriber: &EventSubscriber,
+    head: &ContextHead,
+    req_id: u64,
+) -> Result<bool> {
+    let context_id = head.context_id;
+    let context_key = context_id.to_string();
+    let mut last_head_turn_id = head.head_turn_id;
+
+    stream.set_read_timeout(Some(WATCH_POLL_INTERVAL))?;
+    let mut probe = [0u8; 1];
+    loop {
+        let mut advanced = false;
+        while let Some(event) = subscriber.try_recv() {
+            if let StoreEvent::TurnAppended { context_id, .. } = event {
+                advanced |= context_id == context_key;
+            }
+        }
+        if advanced {
+            let mut store = store.lock().unwrap();
+            let head = store.get_head(context_id)?;
+            if head.head_turn_id != last_head_turn_id {
+                last_head_turn_id = head.head_turn_id;
+                let (items, _) = store.get_last_window(context_id, 0, 1, true)?;
+                let mut resp =
+                    encode_ctx_create_resp(head.context_id, head.head_turn_id, head.head_depth)?;
+                resp.extend(encode_turn_page(&store, context_id, items, false)?);
+                drop(store);
+                write_frame(
+                    stream,
+                    MsgType::WatchHead as u16,
+                    WATCH_FLAG_UPDATE,
+                    req_id,
+                    &resp,
+                )?;
+                stream.flush()?;
+            }
+        }
+
+        match stream.peek(&mut probe) {
+            Ok(0) => return Ok(false),
+            Ok(_) => {}
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                continue;
+            }
+            Err(err) => return Err(StoreError::Io(err)),
+        }
+
+        stream.set_read_timeout(None)?;
+        let (header, _) = read_frame(stream)?;
+        if header.msg_type == MsgType::WatchHead as u16 && header.flags & WATCH_FLAG_STOP != 0 {
+            write_frame(
+                stream,
+                MsgType::WatchHead as u16,
+                WATCH_FLAG_STOP,
+                header.req_id,
+                &[],
+            )?;
+            stream.flush()?;
+            return Ok(true);
+        }
+        let payload = encode_error(409, "watch in progress; send WATCH_HEAD stop first")?;
+        write_frame(stream, MsgType::Error as u16, 0, header.req_id, &payload)?;
+        stream.flush()?;
+        stream.set_read_timeout(Some(WATCH_POLL_INTERVAL))?;
+    }
+}
+
+/// Encode turns in the GET_LAST response layout, including the trailer of
+/// active-chain flags and `has_more`.
+fn encode_turn_page(
+    store: &Store,
+    context_id: u64,
+    items: Vec<TurnWithMeta>,
+    has_more: bool,
+) -> Result<Vec<u8>> {
+    let min_depth = items
+        .iter()
+        .map(|item| item.record.depth)
+        .min()
+        .unwrap_or(0);
+    let active_chain = store.turn_store.active_chain_from(context_id, min_depth);
+    let chain_flags: Vec<u8> = items
+        .iter()
+        .map(|item| active_chain.contains(&item.record.turn_id) as u8)
+        .collect();
+    let checkpoint_flags: Vec<u8> = items
+        .iter()
+        .map(|item| item.record.is_checkpoint() as u8)
+        .collect();
+    let mut resp = Vec::new();
+    resp.write_u32::<byteorder::LittleEndian>(items.len() as u32)?;
+    for item in items {
+        resp.write_u64::<byteorder::LittleEndian>(item.record.turn_id)?;
+        resp.write_u64::<byteorder::LittleEndian>(item.record.parent_turn_id)?;
+        resp.write_u32::<byteorder::LittleEndian>(item.record.depth)?;
+        resp.write_u32::<byteorder::LittleEndian>(item.meta.declared_type_id.len() as u32)?;
+        resp.extend_from_slice(item.meta.declared_type_id.as_bytes());
+        resp.write_u32::<byteorder::LittleEndian>(item.meta.declared_type_version)?;
+        resp.write_u32::<byteorder::LittleEndian>(item.meta.encoding)?;
+        // always return raw payload when included
+        let compression = if item.payload.is_some() {
+            0
+        } else {
+            item.meta.compression
+        };
+        resp.write_u32::<byteorder::LittleEndian>(compression)?;
+        let uncompressed_len = item
+            .payload
+            .as_ref()
+            .map(|p| p.len() as u32)
+            .unwrap_or(item.meta.uncompressed_len);
+        resp.write_u32::<byteorder::LittleEndian>(uncompressed_len)?;
+        resp.extend_from_slice(&item.record.payload_hash);
+        if let Some(payload) = item.payload {
+            resp.write_u32::<byteorder::LittleEndian>(payload.len() as u32)?;
+            resp.extend_from_slice(&payload);
+        }
+    }
+    // Trailer: one active-chain flag per item, whether older turns remain,
+    // then one checkpoint flag per item. Older clients stop reading after
+    // the items, or after has_more.
+    resp.extend_from_slice(&chain_flags);
+    resp.push(has_more as u8);
+    resp.extend_from_slice(&checkpoint_flags);
+    Ok(resp)
+}
+
 /// Get current time in milliseconds since Unix epoch.
 fn unix_ms() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -415,6 +809,9 @@ fn map_error(err: &StoreError) -> (u32, String) {
     match err {
         StoreError::NotFound(msg) => (404, msg.clone()),
         StoreError::InvalidInput(msg) => (422, msg.clone()),
+        StoreError::InvalidParent(msg) => (422, msg.clone()),
+        StoreError::QuotaExceeded(msg) => (507, msg.clone()),
+        StoreError::NotReady(msg) => (425, msg.clone()),
         StoreError::Corrupt(msg) => (500, msg.clone()),
         StoreError::Io(msg) => (500, msg.to_string()),
     }
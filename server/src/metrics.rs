@@ -23,13 +23,22 @@ pub struct ClientSession {
     pub connected_at: u64,         // unix_ms
     pub last_activity_at: u64,     // unix_ms
     pub contexts_created: Vec<u64>, // context IDs created by this session
+    /// Secret a reconnecting client must present to resume this session.
+    #[serde(skip)]
+    resume_token: [u8; 16],
 }
 
+/// How long a disconnected session can still be resumed by a reconnecting
+/// client presenting its id and resume token in HELLO.
+pub const SESSION_RESUME_WINDOW: Duration = Duration::from_secs(5 * 60);
+
 /// Tracks connected client sessions and their metadata.
 #[derive(Default)]
 pub struct SessionTracker {
     sessions: RwLock<HashMap<u64, ClientSession>>,
     context_to_session: RwLock<HashMap<u64, u64>>,
+    /// Recently disconnected sessions, kept for `SESSION_RESUME_WINDOW`.
+    detached: Mutex<HashMap<u64, (ClientSession, Instant)>>,
 }
 
 impl SessionTracker {
@@ -37,12 +46,20 @@ impl SessionTracker {
         Self {
             sessions: RwLock::new(HashMap::new()),
             context_to_session: RwLock::new(HashMap::new()),
+            detached: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Register a new session with the given client tag and optional peer address.
-    pub fn register(&self, session_id: u64, client_tag: String, peer_addr: Option<String>) {
+    /// Register a new session with the given client tag and optional peer
+    /// address. Returns the token that resumes it after a disconnect.
+    pub fn register(
+        &self,
+        session_id: u64,
+        client_tag: String,
+        peer_addr: Option<String>,
+    ) -> [u8; 16] {
         let now_ms = unix_ms();
+        let resume_token = new_resume_token();
         let session = ClientSession {
             session_id,
             client_tag,
@@ -50,8 +67,10 @@ impl SessionTracker {
             connected_at: now_ms,
             last_activity_at: now_ms,
             contexts_created: Vec::new(),
+            resume_token,
         };
         self.sessions.write().unwrap().insert(session_id, session);
+        resume_token
     }
 
     /// Get the peer address for a session.
@@ -84,7 +103,8 @@ impl SessionTracker {
         }
     }
 
-    /// Unregister a session and return its orphaned contexts.
+    /// Unregister a session and return its orphaned contexts. The session
+    /// stays resumable for `SESSION_RESUME_WINDOW`.
     pub fn unregister(&self, session_id: u64) -> Vec<u64> {
         let session = self.sessions.write().unwrap().remove(&session_id);
         if let Some(session) = session {
@@ -92,12 +112,59 @@ impl SessionTracker {
             for ctx_id in &session.contexts_created {
                 ctx_map.remove(ctx_id);
             }
-            session.contexts_created
+            let contexts = session.contexts_created.clone();
+            let mut detached = self.detached.lock().unwrap();
+            detached.retain(|_, (_, at)| at.elapsed() < SESSION_RESUME_WINDOW);
+            detached.insert(session_id, (session, Instant::now()));
+            contexts
         } else {
             Vec::new()
         }
     }
 
+    /// Re-register a recently disconnected session under its old id, with the
+    /// contexts it created live again, and return its new resume token. Returns
+    /// None if the session is unknown, still connected, or past
+    /// `SESSION_RESUME_WINDOW`, or if `resume_token` or `client_tag` do not
+    /// match the session's; the caller then registers a fresh session instead.
+    pub fn resume(
+        &self,
+        session_id: u64,
+        resume_token: &[u8; 16],
+        client_tag: &str,
+        peer_addr: Option<String>,
+    ) -> Option<[u8; 16]> {
+        let mut detached = self.detached.lock().unwrap();
+        let (session, detached_at) = detached.get(&session_id)?;
+        // A wrong token leaves the session for its rightful owner.
+        if !tokens_match(&session.resume_token, resume_token) || session.client_tag != client_tag {
+            return None;
+        }
+        if detached_at.elapsed() >= SESSION_RESUME_WINDOW {
+            detached.remove(&session_id);
+            return None;
+        }
+        let (mut session, _) = detached.remove(&session_id)?;
+        drop(detached);
+        // Same lock order as get_session_for_context.
+        let mut ctx_map = self.context_to_session.write().unwrap();
+        let mut sessions = self.sessions.write().unwrap();
+        if sessions.contains_key(&session_id) {
+            return None;
+        }
+        for ctx_id in &session.contexts_created {
+            ctx_map.entry(*ctx_id).or_insert(session_id);
+        }
+        let now_ms = unix_ms();
+        let resume_token = new_resume_token();
+        session.peer_addr = peer_addr;
+        session.connected_at = now_ms;
+        session.last_activity_at = now_ms;
+        session.resume_token = resume_token;
+        sessions.insert(session_id, session);
+        Some(resume_token)
+    }
+
     /// Get session info for a context (if session is still connected).
     pub fn get_session_for_context(&self, context_id: u64) -> Option<ClientSession> {
         let ctx_map = self.context_to_session.read().unwrap();
@@ -235,6 +302,19 @@ impl Metrics {
         }
     }
 
+    pub fn uptime(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Whether a file can be created in the data directory, by writing and
+    /// removing a probe file.
+    pub fn data_dir_writable(&self) -> bool {
+        let probe = self.data_dir.join(".health-probe");
+        let writable = std::fs::write(&probe, b"ok").is_ok();
+        let _ = std::fs::remove_file(&probe);
+        writable
+    }
+
     pub fn register_session(self: &Arc<Self>) -> SessionGuard {
         let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
         self.sessions_total.fetch_add(1, Ordering::Relaxed);
@@ -359,6 +439,7 @@ impl Metrics {
         let store_stats = store.stats();
         let filesystem = FilesystemMetrics {
             snapshots_total: store_stats.fs_roots_total,
+            corrupt_records_skipped: store_stats.fs_roots_skipped_records,
             index_bytes: store_stats.fs_roots_bytes,
             content_bytes: store_stats.fs_content_bytes,
         };
@@ -612,6 +693,8 @@ pub struct ErrorMetrics {
 #[derive(Debug, Clone, Serialize)]
 pub struct FilesystemMetrics {
     pub snapshots_total: usize,
+    /// roots.idx records skipped as corrupt when the store was opened.
+    pub corrupt_records_skipped: usize,
     pub index_bytes: u64,
     pub content_bytes: u64,
 }
@@ -805,6 +888,15 @@ fn duration_to_ms(duration: Duration) -> f64 {
     duration.as_secs_f64() * 1000.0
 }
 
+fn new_resume_token() -> [u8; 16] {
+    *uuid::Uuid::new_v4().as_bytes()
+}
+
+/// Compare tokens in time independent of where they differ.
+fn tokens_match(a: &[u8; 16], b: &[u8; 16]) -> bool {
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 fn unix_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -853,3 +945,29 @@ fn disk_space_for_path(path: &Path) -> (u64, u64) {
         (0, 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_requires_the_sessions_token_and_tag() {
+        let tracker = SessionTracker::new();
+        let token = tracker.register(7, "agent".into(), None);
+        tracker.add_context(7, 42);
+        tracker.unregister(7);
+
+        // Guessing the id is not enough, nor is the token under another tag.
+        assert_eq!(tracker.resume(7, &[0; 16], "agent", None), None);
+        assert_eq!(tracker.resume(7, &token, "other", None), None);
+
+        let renewed = tracker.resume(7, &token, "agent", None).expect("resume");
+        assert_ne!(renewed, token);
+        assert_eq!(
+            tracker.get_session_for_context(42).map(|s| s.session_id),
+            Some(7)
+        );
+        // Resuming again while connected fails.
+        assert_eq!(tracker.resume(7, &renewed, "agent", None), None);
+    }
+}
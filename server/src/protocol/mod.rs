@@ -8,6 +8,13 @@ use std::io::{Read, Write};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::error::{Result, StoreError};
+use crate::fs_store::{SnapshotMeta, SnapshotVerifyReport};
+use crate::quota::ContextQuota;
+use crate::turn_store::ContextHead;
+
+/// Most contexts returned by one LIST_CONTEXTS request; also the page size
+/// when the request's limit is 0.
+pub const LIST_CONTEXTS_MAX: u32 = 1000;
 
 /// Maximum frame payload size (64 MB). Frames larger than this are rejected
 /// to prevent memory exhaustion from malicious or corrupted clients.
@@ -27,9 +34,48 @@ pub enum MsgType {
     GetBlob = 9,
     AttachFs = 10,
     PutBlob = 11,
+    WatchHead = 12,
+    BeginBlob = 13,
+    BlobChunk = 14,
+    CommitBlob = 15,
+    HasBlobs = 16,
+    GetHeadAt = 17,
+    ListContexts = 18,
+    AttachFsHead = 19,
+    TrimContext = 20,
+    FindSnapshotRefs = 21,
+    Health = 22,
+    VerifySnapshot = 23,
     Error = 255,
 }
 
+/// APPEND_TURN flag: an fs_root_hash trails the request payload.
+pub const APPEND_FLAG_FS_ROOT: u16 = 1 << 0;
+/// APPEND_TURN flag: return the existing turn if one on the target chain has
+/// the same ConversationItem id (field 4).
+pub const APPEND_FLAG_DEDUP_ITEM_ID: u16 = 1 << 1;
+/// APPEND_TURN flag: the turn is a checkpoint summarizing the chain before
+/// it (see `TURN_FLAG_CHECKPOINT`).
+pub const APPEND_FLAG_CHECKPOINT: u16 = 1 << 2;
+
+/// GET_LAST flag: summary mode, stop walking back at the most recent
+/// checkpoint turn.
+pub const GET_LAST_FLAG_SUMMARY: u16 = 1 << 0;
+
+/// GET_LAST flag: the payload ends with `min_head_turn_id`, a turn the
+/// response must reflect (offset and item_type_count are then always sent).
+pub const GET_LAST_FLAG_MIN_HEAD: u16 = 1 << 1;
+
+/// GET_LAST flag: append each turn's ConversationItem type to the response,
+/// so listings without payloads can still show what each turn is.
+pub const GET_LAST_FLAG_ITEM_TYPES: u16 = 1 << 2;
+
+/// WATCH_HEAD flag (server push): the payload carries a new head and its turn.
+pub const WATCH_FLAG_UPDATE: u16 = 1 << 0;
+/// WATCH_HEAD flag: client asks to end the watch; the server echoes it once
+/// no further updates will follow.
+pub const WATCH_FLAG_STOP: u16 = 1 << 1;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameHeader {
     pub len: u32,
@@ -53,6 +99,20 @@ pub struct AppendTurnRequest {
     /// Optional filesystem snapshot root hash to attach to this turn.
     /// Present if flags bit 0 is set.
     pub fs_root_hash: Option<[u8; 32]>,
+    /// Deduplicate against existing turns by ConversationItem id.
+    /// Set if flags bit 1 is set.
+    pub dedup_by_item_id: bool,
+    /// Store the turn as a checkpoint. Set if flags bit 2 is set.
+    pub checkpoint: bool,
+}
+
+/// CTX_CREATE / CTX_FORK request.
+#[derive(Debug, Clone, Copy)]
+pub struct CtxCreateRequest {
+    pub base_turn_id: u64,
+    /// Optional quota for the new context, present when the payload carries
+    /// the trailing max_turns/max_bytes fields.
+    pub quota: Option<ContextQuota>,
 }
 
 /// Request to attach a filesystem snapshot to an existing turn.
@@ -60,6 +120,18 @@ pub struct AppendTurnRequest {
 pub struct AttachFsRequest {
     pub turn_id: u64,
     pub fs_root_hash: [u8; 32],
+    /// Optional capture metadata, present when the payload carries the
+    /// trailing 40-byte block.
+    pub meta: Option<SnapshotMeta>,
+}
+
+/// Request to attach a filesystem snapshot to whatever turn is a context's
+/// head when the server handles it.
+#[derive(Debug, Clone)]
+pub struct AttachFsHeadRequest {
+    pub context_id: u64,
+    pub fs_root_hash: [u8; 32],
+    pub meta: Option<SnapshotMeta>,
 }
 
 /// Request to store a blob (for filesystem tree objects or file content).
@@ -69,11 +141,37 @@ pub struct PutBlobRequest {
     pub data: Vec<u8>,
 }
 
+/// Request to start (or resume) a chunked blob upload.
 #[derive(Debug, Clone, Copy)]
+pub struct BeginBlobRequest {
+    pub hash: [u8; 32],
+    pub total_len: u64,
+}
+
+/// One chunk of a chunked blob upload.
+#[derive(Debug, Clone)]
+pub struct BlobChunkRequest {
+    pub upload_id: u64,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
 pub struct GetLastRequest {
     pub context_id: u64,
     pub limit: u32,
     pub include_payload: u32,
+    /// Turns to skip back from the head before collecting (optional, 0 if absent).
+    pub offset: u32,
+    /// ConversationItem types to return (optional, empty = all).
+    pub item_types: Vec<String>,
+    /// Stop at the most recent checkpoint turn (`GET_LAST_FLAG_SUMMARY`).
+    pub summary: bool,
+    /// Turn the server must have stored before answering
+    /// (`GET_LAST_FLAG_MIN_HEAD`, 0 if absent).
+    pub min_head_turn_id: u64,
+    /// Report each turn's item type (`GET_LAST_FLAG_ITEM_TYPES`).
+    pub with_item_types: bool,
 }
 
 pub fn read_frame<R: Read>(reader: &mut R) -> Result<(FrameHeader, Vec<u8>)> {
@@ -130,16 +228,168 @@ pub fn parse_ctx_fork(payload: &[u8]) -> Result<u64> {
     parse_ctx_create(payload)
 }
 
+/// Parse CTX_CREATE / CTX_FORK: base_turn_id (u64), optionally followed by
+/// max_turns (u64) + max_bytes (u64), where 0 means no limit.
+pub fn parse_ctx_create_request(payload: &[u8]) -> Result<CtxCreateRequest> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let base_turn_id = cursor.read_u64::<LittleEndian>()?;
+    let quota = match payload.len() {
+        8 => None,
+        24 => {
+            let max_turns = cursor.read_u64::<LittleEndian>()?;
+            let max_bytes = cursor.read_u64::<LittleEndian>()?;
+            Some(ContextQuota::from_raw(max_turns, max_bytes))
+        }
+        _ => {
+            return Err(StoreError::InvalidInput(
+                "ctx_create quota fields truncated".into(),
+            ))
+        }
+    };
+    Ok(CtxCreateRequest {
+        base_turn_id,
+        quota,
+    })
+}
+
 pub fn parse_get_head(payload: &[u8]) -> Result<u64> {
     parse_ctx_create(payload)
 }
 
-pub fn parse_get_last(payload: &[u8]) -> Result<GetLastRequest> {
+/// Parse GET_HEAD_AT request: context_id (u64) + timestamp_ms (u64).
+pub fn parse_get_head_at(payload: &[u8]) -> Result<(u64, u64)> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let context_id = cursor.read_u64::<LittleEndian>()?;
+    let timestamp_ms = cursor.read_u64::<LittleEndian>()?;
+    Ok((context_id, timestamp_ms))
+}
+
+/// Parse TRIM_CONTEXT request: context_id (u64) + before_unix_ms (u64).
+pub fn parse_trim_context(payload: &[u8]) -> Result<(u64, u64)> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let context_id = cursor.read_u64::<LittleEndian>()?;
+    let before_unix_ms = cursor.read_u64::<LittleEndian>()?;
+    Ok((context_id, before_unix_ms))
+}
+
+/// Encode TRIM_CONTEXT response: turns_trimmed (u64) + gc_candidates (u32),
+/// the number of payload blobs the trim left unreferenced.
+pub fn encode_trim_context_resp(turns_trimmed: u64, gc_candidates: u32) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(12);
+    buf.write_u64::<LittleEndian>(turns_trimmed)?;
+    buf.write_u32::<LittleEndian>(gc_candidates)?;
+    Ok(buf)
+}
+
+/// Parse FIND_SNAPSHOT_REFS request: fs_root_hash (32 bytes).
+pub fn parse_find_snapshot_refs(payload: &[u8]) -> Result<[u8; 32]> {
+    payload.try_into().map_err(|_| {
+        StoreError::InvalidInput(format!(
+            "find_snapshot_refs payload must be 32 bytes, got {}",
+            payload.len()
+        ))
+    })
+}
+
+/// Encode FIND_SNAPSHOT_REFS response: count (u32) + count turn ids (u64).
+pub fn encode_find_snapshot_refs_resp(turn_ids: &[u64]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + turn_ids.len() * 8);
+    buf.write_u32::<LittleEndian>(turn_ids.len() as u32)?;
+    for &turn_id in turn_ids {
+        buf.write_u64::<LittleEndian>(turn_id)?;
+    }
+    Ok(buf)
+}
+
+/// Parse VERIFY_SNAPSHOT request: fs_root_hash (32 bytes).
+pub fn parse_verify_snapshot(payload: &[u8]) -> Result<[u8; 32]> {
+    payload.try_into().map_err(|_| {
+        StoreError::InvalidInput(format!(
+            "verify_snapshot payload must be 32 bytes, got {}",
+            payload.len()
+        ))
+    })
+}
+
+/// Encode VERIFY_SNAPSHOT response: trees_checked (u64) + blobs_checked
+/// (u64) + missing (u64) + path_count (u32) + per path, len (u32) + utf8.
+pub fn encode_verify_snapshot_resp(report: &SnapshotVerifyReport) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.write_u64::<LittleEndian>(report.trees_checked)?;
+    buf.write_u64::<LittleEndian>(report.blobs_checked)?;
+    buf.write_u64::<LittleEndian>(report.missing)?;
+    buf.write_u32::<LittleEndian>(report.missing_paths.len() as u32)?;
+    for path in &report.missing_paths {
+        buf.write_u32::<LittleEndian>(path.len() as u32)?;
+        buf.extend_from_slice(path.as_bytes());
+    }
+    Ok(buf)
+}
+
+/// Encode HEALTH response: uptime_ms (u64) + writable (u8) + version_len
+/// (u32) + version.
+pub fn encode_health_resp(uptime_ms: u64, writable: bool, version: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(13 + version.len());
+    buf.write_u64::<LittleEndian>(uptime_ms)?;
+    buf.write_u8(writable as u8)?;
+    buf.write_u32::<LittleEndian>(version.len() as u32)?;
+    buf.extend_from_slice(version.as_bytes());
+    Ok(buf)
+}
+
+/// Parse LIST_CONTEXTS: after_context_id (u64) + limit (u32). Returns the
+/// cursor and the limit, with 0 and oversized limits replaced by
+/// `LIST_CONTEXTS_MAX`.
+pub fn parse_list_contexts(payload: &[u8]) -> Result<(u64, u32)> {
     let mut cursor = std::io::Cursor::new(payload);
+    let after = cursor.read_u64::<LittleEndian>()?;
+    let limit = cursor.read_u32::<LittleEndian>()?;
+    let limit = if limit == 0 {
+        LIST_CONTEXTS_MAX
+    } else {
+        limit.min(LIST_CONTEXTS_MAX)
+    };
+    Ok((after, limit))
+}
+
+pub fn parse_get_last(payload: &[u8], flags: u16) -> Result<GetLastRequest> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let context_id = cursor.read_u64::<LittleEndian>()?;
+    let limit = cursor.read_u32::<LittleEndian>()?;
+    let include_payload = cursor.read_u32::<LittleEndian>()?;
+    let offset = if payload.len() >= 20 {
+        cursor.read_u32::<LittleEndian>()?
+    } else {
+        0
+    };
+    // item_type_count(u32) + [len(u16) + bytes] per type
+    let mut item_types = Vec::new();
+    if payload.len() >= 24 {
+        let count = cursor.read_u32::<LittleEndian>()?;
+        for _ in 0..count {
+            let len = cursor.read_u16::<LittleEndian>()? as usize;
+            let mut bytes = vec![0u8; len];
+            cursor.read_exact(&mut bytes)?;
+            item_types.push(
+                String::from_utf8(bytes)
+                    .map_err(|_| StoreError::InvalidInput("item_type not utf8".into()))?,
+            );
+        }
+    }
+    let min_head_turn_id = if flags & GET_LAST_FLAG_MIN_HEAD != 0 {
+        cursor.read_u64::<LittleEndian>()?
+    } else {
+        0
+    };
     Ok(GetLastRequest {
-        context_id: cursor.read_u64::<LittleEndian>()?,
-        limit: cursor.read_u32::<LittleEndian>()?,
-        include_payload: cursor.read_u32::<LittleEndian>()?,
+        context_id,
+        limit,
+        include_payload,
+        offset,
+        item_types,
+        summary: flags & GET_LAST_FLAG_SUMMARY != 0,
+        min_head_turn_id,
+        with_item_types: flags & GET_LAST_FLAG_ITEM_TYPES != 0,
     })
 }
 
@@ -181,7 +431,7 @@ pub fn parse_append_turn(payload: &[u8], flags: u16) -> Result<AppendTurnRequest
     }
 
     // Check for optional fs_root_hash (flags bit 0)
-    let fs_root_hash = if flags & 1 != 0 {
+    let fs_root_hash = if flags & APPEND_FLAG_FS_ROOT != 0 {
         let mut hash = [0u8; 32];
         cursor.read_exact(&mut hash)?;
         Some(hash)
@@ -201,24 +451,54 @@ pub fn parse_append_turn(payload: &[u8], flags: u16) -> Result<AppendTurnRequest
         payload_bytes,
         idempotency_key,
         fs_root_hash,
+        dedup_by_item_id: flags & APPEND_FLAG_DEDUP_ITEM_ID != 0,
+        checkpoint: flags & APPEND_FLAG_CHECKPOINT != 0,
     })
 }
 
-/// Parse ATTACH_FS request: turn_id (u64) + fs_root_hash (32 bytes)
+/// Parse ATTACH_FS request: turn_id (u64) + fs_root_hash (32 bytes), optionally
+/// followed by capture metadata (see `SnapshotMeta`).
 pub fn parse_attach_fs(payload: &[u8]) -> Result<AttachFsRequest> {
+    let (turn_id, fs_root_hash, meta) = parse_attach_payload(payload, "attach_fs")?;
+    Ok(AttachFsRequest {
+        turn_id,
+        fs_root_hash,
+        meta,
+    })
+}
+
+/// Parse ATTACH_FS_HEAD request: laid out like ATTACH_FS, with a context_id
+/// in place of the turn_id.
+pub fn parse_attach_fs_head(payload: &[u8]) -> Result<AttachFsHeadRequest> {
+    let (context_id, fs_root_hash, meta) = parse_attach_payload(payload, "attach_fs_head")?;
+    Ok(AttachFsHeadRequest {
+        context_id,
+        fs_root_hash,
+        meta,
+    })
+}
+
+fn parse_attach_payload(
+    payload: &[u8],
+    what: &str,
+) -> Result<(u64, [u8; 32], Option<SnapshotMeta>)> {
     if payload.len() < 40 {
-        return Err(StoreError::InvalidInput(
-            "attach_fs payload too short".into(),
-        ));
+        return Err(StoreError::InvalidInput(format!(
+            "{what} payload too short"
+        )));
     }
     let mut cursor = std::io::Cursor::new(payload);
-    let turn_id = cursor.read_u64::<LittleEndian>()?;
+    let id = cursor.read_u64::<LittleEndian>()?;
     let mut fs_root_hash = [0u8; 32];
     cursor.read_exact(&mut fs_root_hash)?;
-    Ok(AttachFsRequest {
-        turn_id,
-        fs_root_hash,
-    })
+    let meta = match &payload[40..] {
+        [] => None,
+        rest => Some(
+            SnapshotMeta::decode(rest)
+                .ok_or_else(|| StoreError::InvalidInput(format!("{what} metadata truncated")))?,
+        ),
+    };
+    Ok((id, fs_root_hash, meta))
 }
 
 /// Encode ATTACH_FS response: turn_id (u64) + fs_root_hash (32 bytes)
@@ -245,6 +525,86 @@ pub fn parse_put_blob(payload: &[u8]) -> Result<PutBlobRequest> {
     Ok(PutBlobRequest { hash, data })
 }
 
+pub fn parse_begin_blob(payload: &[u8]) -> Result<BeginBlobRequest> {
+    if payload.len() < 40 {
+        return Err(StoreError::InvalidInput(
+            "begin_blob payload too short".into(),
+        ));
+    }
+    let mut cursor = std::io::Cursor::new(payload);
+    let mut hash = [0u8; 32];
+    cursor.read_exact(&mut hash)?;
+    let total_len = cursor.read_u64::<LittleEndian>()?;
+    Ok(BeginBlobRequest { hash, total_len })
+}
+
+pub fn parse_blob_chunk(payload: &[u8]) -> Result<BlobChunkRequest> {
+    if payload.len() < 20 {
+        return Err(StoreError::InvalidInput(
+            "blob_chunk payload too short".into(),
+        ));
+    }
+    let mut cursor = std::io::Cursor::new(payload);
+    let upload_id = cursor.read_u64::<LittleEndian>()?;
+    let offset = cursor.read_u64::<LittleEndian>()?;
+    let data_len = cursor.read_u32::<LittleEndian>()? as usize;
+    let mut data = vec![0u8; data_len];
+    cursor.read_exact(&mut data)?;
+    Ok(BlobChunkRequest {
+        upload_id,
+        offset,
+        data,
+    })
+}
+
+pub fn parse_commit_blob(payload: &[u8]) -> Result<u64> {
+    parse_ctx_create(payload)
+}
+
+/// Parse HAS_BLOBS request: count (u32) + count hashes (32 bytes each).
+pub fn parse_has_blobs(payload: &[u8]) -> Result<Vec<[u8; 32]>> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    if payload.len() - 4 != count * 32 {
+        return Err(StoreError::InvalidInput(
+            "has_blobs payload length does not match count".into(),
+        ));
+    }
+    Ok(payload[4..]
+        .chunks_exact(32)
+        .map(|chunk| chunk.try_into().unwrap())
+        .collect())
+}
+
+/// Encode the GET_LAST item-type trailer: per turn, len (u16) + utf8 bytes,
+/// with an empty string for turns that are not ConversationItems.
+pub fn encode_item_types(item_types: &[Option<String>]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    for item_type in item_types {
+        let item_type = item_type.as_deref().unwrap_or("");
+        buf.write_u16::<LittleEndian>(item_type.len() as u16)?;
+        buf.extend_from_slice(item_type.as_bytes());
+    }
+    Ok(buf)
+}
+
+/// Encode HAS_BLOBS response: count (u32) + one byte per requested hash
+/// (1=stored, 0=missing), in request order.
+pub fn encode_has_blobs_resp(present: &[bool]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + present.len());
+    buf.write_u32::<LittleEndian>(present.len() as u32)?;
+    buf.extend(present.iter().map(|&p| p as u8));
+    Ok(buf)
+}
+
+/// Encode BEGIN_BLOB / BLOB_CHUNK response: upload_id (u64) + received (u64).
+pub fn encode_blob_upload_resp(upload_id: u64, received: u64) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(16);
+    buf.write_u64::<LittleEndian>(upload_id)?;
+    buf.write_u64::<LittleEndian>(received)?;
+    Ok(buf)
+}
+
 /// Encode PUT_BLOB response: hash (32 bytes) + stored (u8: 1=new, 0=exists)
 pub fn encode_put_blob_resp(hash: &[u8; 32], was_new: bool) -> Result<Vec<u8>> {
     let mut buf = Vec::with_capacity(33);
@@ -253,6 +613,22 @@ pub fn encode_put_blob_resp(hash: &[u8; 32], was_new: bool) -> Result<Vec<u8>> {
     Ok(buf)
 }
 
+/// Encode LIST_CONTEXTS response: count (u32), then per context
+/// context_id, head_turn_id, turn_count and updated_at_unix_ms (u64 each),
+/// then has_more (u8).
+pub fn encode_list_contexts_resp(heads: &[ContextHead], has_more: bool) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + heads.len() * 32 + 1);
+    buf.write_u32::<LittleEndian>(heads.len() as u32)?;
+    for head in heads {
+        buf.write_u64::<LittleEndian>(head.context_id)?;
+        buf.write_u64::<LittleEndian>(head.head_turn_id)?;
+        buf.write_u64::<LittleEndian>(head.turn_count())?;
+        buf.write_u64::<LittleEndian>(head.created_at_unix_ms)?;
+    }
+    buf.push(has_more as u8);
+    Ok(buf)
+}
+
 pub fn encode_ctx_create_resp(
     context_id: u64,
     head_turn_id: u64,
@@ -265,17 +641,23 @@ pub fn encode_ctx_create_resp(
     Ok(buf)
 }
 
+/// Encode APPEND_TURN ack. The trailing head_turn_id and committed_at_unix_ms
+/// fields were added after the original 52-byte layout; older clients ignore them.
 pub fn encode_append_ack(
     context_id: u64,
     new_turn_id: u64,
     new_depth: u32,
     hash: &[u8; 32],
+    head_turn_id: u64,
+    committed_at_unix_ms: u64,
 ) -> Result<Vec<u8>> {
-    let mut buf = Vec::with_capacity(8 + 8 + 4 + 32);
+    let mut buf = Vec::with_capacity(8 + 8 + 4 + 32 + 8 + 8);
     buf.write_u64::<LittleEndian>(context_id)?;
     buf.write_u64::<LittleEndian>(new_turn_id)?;
     buf.write_u32::<LittleEndian>(new_depth)?;
     buf.extend_from_slice(hash);
+    buf.write_u64::<LittleEndian>(head_turn_id)?;
+    buf.write_u64::<LittleEndian>(committed_at_unix_ms)?;
     Ok(buf)
 }
 
@@ -293,6 +675,10 @@ pub struct HelloRequest {
     pub protocol_version: u16,
     pub client_tag: String,
     pub client_meta_json: Option<String>,
+    /// Session the client held before reconnecting, if it asked to resume it.
+    pub resume_session_id: Option<u64>,
+    /// Token the server issued with that session; all zeros if not sent.
+    pub resume_token: [u8; 16],
 }
 
 /// Parse HELLO payload. Supports both old (empty) and new (with metadata) formats.
@@ -303,6 +689,7 @@ pub fn parse_hello(payload: &[u8]) -> Result<HelloRequest> {
     }
 
     // New format: protocol_version(u16) + client_tag_len(u16) + client_tag + meta_json_len(u32) + meta_json
+    // [+ resume_session_id(u64) + resume_token([u8; 16])]
     if payload.len() < 4 {
         return Err(StoreError::InvalidInput("hello payload too short".into()));
     }
@@ -334,17 +721,39 @@ pub fn parse_hello(payload: &[u8]) -> Result<HelloRequest> {
         None
     };
 
+    // Older clients end the payload here; zero also means "no session to resume".
+    let resume_session_id = if payload.len() as u64 - cursor.position() >= 8 {
+        Some(cursor.read_u64::<LittleEndian>()?).filter(|&id| id != 0)
+    } else {
+        None
+    };
+    let mut resume_token = [0u8; 16];
+    if payload.len() as u64 - cursor.position() >= 16 {
+        cursor.read_exact(&mut resume_token)?;
+    }
+
     Ok(HelloRequest {
         protocol_version,
         client_tag,
         client_meta_json,
+        resume_session_id,
+        resume_token,
     })
 }
 
-/// Encode HELLO response with session_id and protocol_version.
-pub fn encode_hello_resp(session_id: u64, protocol_version: u16) -> Result<Vec<u8>> {
-    let mut buf = Vec::with_capacity(10);
+/// Encode HELLO response with session_id, protocol_version, the id of the
+/// algorithm filesystem blobs are hashed with, and the token a reconnecting
+/// client must present to resume the session.
+pub fn encode_hello_resp(
+    session_id: u64,
+    protocol_version: u16,
+    hash_algorithm: u8,
+    resume_token: &[u8; 16],
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(27);
     buf.write_u64::<LittleEndian>(session_id)?;
     buf.write_u16::<LittleEndian>(protocol_version)?;
+    buf.write_u8(hash_algorithm)?;
+    buf.extend_from_slice(resume_token);
     Ok(buf)
 }
@@ -0,0 +1,171 @@
+// Copyright 2025 StrongDM Inc
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-context storage quotas.
+//!
+//! A context's usage is what its head can reach: the turns on its chain
+//! (including any inherited from the turn it was forked from), their payload
+//! bytes, and the content of filesystem snapshots attached directly to those
+//! turns. Appends and attaches that would take a context past its quota fail
+//! with `StoreError::QuotaExceeded`.
+//!
+//! Quotas set at context creation are kept in `turns/quotas.tbl`, an
+//! append-only file of fixed-size records (last write wins per context):
+//! - context_id: u64
+//! - max_turns: u64 (0 = unset)
+//! - max_bytes: u64 (0 = unset)
+//! - crc32: u32
+//!
+//! Contexts without a record, and limits left unset, fall back to the
+//! server-wide default. `ContextQuota::UNLIMITED` opts a context out of a
+//! default limit.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::check::{ensure_trailing_only, truncate_tail, valid_prefix_len, RepairReport};
+use crate::error::Result;
+
+/// Size of one quotas.tbl record: context_id, max_turns, max_bytes, crc.
+const QUOTA_RECORD_LEN: u64 = 8 + 8 + 8 + 4;
+
+const QUOTAS_TBL: &str = "turns/quotas.tbl";
+
+/// Storage limits for a context. `None` fields are unset: a context's own
+/// quota falls back to the default for them, and the default leaves them
+/// unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContextQuota {
+    pub max_turns: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl ContextQuota {
+    /// A limit that is never reached, for opting out of the default.
+    pub const UNLIMITED: u64 = u64::MAX;
+
+    /// Build from wire/disk values, where 0 means unset.
+    pub fn from_raw(max_turns: u64, max_bytes: u64) -> Self {
+        Self {
+            max_turns: (max_turns != 0).then_some(max_turns),
+            max_bytes: (max_bytes != 0).then_some(max_bytes),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_turns.is_none_or(|max| max == Self::UNLIMITED)
+            && self.max_bytes.is_none_or(|max| max == Self::UNLIMITED)
+    }
+
+    /// Whether the byte limit can be reached.
+    pub fn limits_bytes(&self) -> bool {
+        self.max_bytes.is_some_and(|max| max != Self::UNLIMITED)
+    }
+
+    /// Limits from `self`, with unset fields taken from `default`.
+    pub fn or(self, default: ContextQuota) -> ContextQuota {
+        ContextQuota {
+            max_turns: self.max_turns.or(default.max_turns),
+            max_bytes: self.max_bytes.or(default.max_bytes),
+        }
+    }
+}
+
+/// What a context's head can reach; see the module docs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContextUsage {
+    pub turns: u64,
+    pub bytes: u64,
+}
+
+/// Quotas assigned to individual contexts at creation.
+pub struct QuotaTable {
+    file: File,
+    quotas: HashMap<u64, ContextQuota>,
+}
+
+impl QuotaTable {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("quotas.tbl"))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let valid_len =
+            valid_prefix_len(&data, QUOTA_RECORD_LEN, |rec| decode_record(rec).is_some());
+        if valid_len < data.len() as u64 {
+            ensure_trailing_only(QUOTAS_TBL, data.len() as u64, valid_len, QUOTA_RECORD_LEN)?;
+            file.set_len(valid_len)?;
+        }
+
+        let mut quotas = HashMap::new();
+        for chunk in data[..valid_len as usize].chunks_exact(QUOTA_RECORD_LEN as usize) {
+            if let Some((context_id, quota)) = decode_record(chunk) {
+                quotas.insert(context_id, quota);
+            }
+        }
+        Ok(Self { file, quotas })
+    }
+
+    /// Truncate quotas.tbl at its first bad record, recording what was discarded.
+    pub fn repair(dir: &Path, report: &mut RepairReport) -> Result<()> {
+        let path = dir.join("quotas.tbl");
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let valid_len =
+            valid_prefix_len(&data, QUOTA_RECORD_LEN, |rec| decode_record(rec).is_some());
+        truncate_tail(&path, QUOTAS_TBL, valid_len, Some(QUOTA_RECORD_LEN), report)
+    }
+
+    pub fn set(&mut self, context_id: u64, quota: ContextQuota) -> Result<()> {
+        let mut buf = Vec::with_capacity(QUOTA_RECORD_LEN as usize);
+        buf.write_u64::<LittleEndian>(context_id)?;
+        buf.write_u64::<LittleEndian>(quota.max_turns.unwrap_or(0))?;
+        buf.write_u64::<LittleEndian>(quota.max_bytes.unwrap_or(0))?;
+        let crc = crc32fast::hash(&buf);
+        buf.write_u32::<LittleEndian>(crc)?;
+
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+        self.quotas.insert(context_id, quota);
+        Ok(())
+    }
+
+    /// A duplicated handle to quotas.tbl, for fsyncing without holding the
+    /// store.
+    pub fn sync_handle(&self) -> Result<File> {
+        Ok(self.file.try_clone()?)
+    }
+
+    pub fn get(&self, context_id: u64) -> Option<ContextQuota> {
+        self.quotas.get(&context_id).copied()
+    }
+
+    /// Contexts with a quota of their own.
+    pub fn context_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.quotas.keys().copied()
+    }
+}
+
+/// Decode one record, or None if its crc does not match.
+fn decode_record(record: &[u8]) -> Option<(u64, ContextQuota)> {
+    let field = |i: usize| u64::from_le_bytes(record[i * 8..i * 8 + 8].try_into().unwrap());
+    let crc = u32::from_le_bytes(record.get(24..28)?.try_into().ok()?);
+    if crc32fast::hash(&record[..24]) != crc {
+        return None;
+    }
+    Some((field(0), ContextQuota::from_raw(field(1), field(2))))
+}
@@ -20,6 +20,7 @@
 //! ```text
 //! s3://{bucket}/{prefix}/
 //!   blobs/blobs.pack
+//!   blobs/blobs.{N}.pack  # pack segments, when rollover is enabled
 //!   blobs/blobs.idx
 //!   turns/turns.log
 //!   turns/turns.idx
@@ -133,6 +134,19 @@ const SYNC_FILES: &[&str] = &[
     "turns/heads.tbl",
 ];
 
+/// Files to sync: `SYNC_FILES` plus any blob pack segments past the first.
+fn sync_files(data_dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = SYNC_FILES.iter().map(|f| f.to_string()).collect();
+    let segments = crate::blob_store::pack_segment_count(&data_dir.join("blobs"));
+    for segment in 1..segments {
+        files.push(format!(
+            "blobs/{}",
+            crate::blob_store::pack_file_name(segment)
+        ));
+    }
+    files
+}
+
 /// S3 sync manager
 pub struct S3Sync {
     config: S3SyncConfig,
@@ -274,7 +288,7 @@ impl S3Sync {
         let mut bytes_synced = 0u64;
 
         // Sync each tracked file
-        for relative_path in SYNC_FILES {
+        for relative_path in &sync_files(&self.data_dir) {
             let local_path = self.data_dir.join(relative_path);
 
             if !local_path.exists() {
@@ -282,7 +296,7 @@ impl S3Sync {
             }
 
             let current_size = fs::metadata(&local_path)?.len();
-            let last_size = state.file_sizes.get(*relative_path).copied().unwrap_or(0);
+            let last_size = state.file_sizes.get(relative_path).copied().unwrap_or(0);
 
             if current_size > last_size {
                 match self.upload_file(&local_path, relative_path).await {
@@ -1,16 +1,22 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
 use std::path::Path;
 
 use blake3::Hasher;
 use rmpv::Value;
 
-use crate::blob_store::BlobStore;
+use crate::blob_store::{BlobStore, BlobUploads};
+use crate::check::{CheckReport, RepairReport};
 use crate::cql::{self, CqlError, CqlQuery, IndexStats, SecondaryIndexes};
 use crate::error::{Result, StoreError};
-use crate::fs_store::{FsRootsIndex, TreeEntry};
+use crate::fs_store::{
+    FileKind, FsRootHash, FsRootsIndex, HashAlgorithm, ResolvedPath, SnapshotMeta,
+    SnapshotVerifyReport, TreeEntry, TreeHash,
+};
+use crate::quota::{ContextQuota, ContextUsage, QuotaTable};
 use crate::turn_store::{ContextHead, TurnMeta, TurnRecord, TurnStore};
 
 #[derive(Debug, Clone)]
@@ -86,6 +92,12 @@ pub struct SearchResult {
     pub elapsed_ms: u64,
 }
 
+/// Turns `find_duplicate_item` searches back along a chain. Dedup guards
+/// against retried appends, which land near the head, so the walk is
+/// bounded rather than taking time proportional to the context's depth on
+/// every deduplicated append.
+pub const ITEM_DEDUP_WINDOW: usize = 1024;
+
 pub struct Store {
     pub blob_store: BlobStore,
     pub turn_store: TurnStore,
@@ -95,16 +107,48 @@ pub struct Store {
     pub context_metadata_cache: HashMap<u64, Option<ContextMetadata>>,
     /// Secondary indexes for CQL queries.
     secondary_indexes: SecondaryIndexes,
+    /// ConversationItem ids (field 4) of turns `find_duplicate_item` has
+    /// read, per context, populated lazily and holding at most
+    /// `ITEM_DEDUP_WINDOW` turns each.
+    turn_item_ids: HashMap<u64, ItemIdCache>,
+    /// Algorithm used to verify filesystem blobs uploaded via PUT_BLOB.
+    pub hash_algorithm: HashAlgorithm,
+    /// Keys for clients hashing with keyed BLAKE3 (one per tenant namespace).
+    hash_keys: Vec<[u8; 32]>,
+    /// Chunked uploads in progress (BEGIN_BLOB / BLOB_CHUNK / COMMIT_BLOB).
+    pub blob_uploads: BlobUploads,
+    /// Quotas assigned at context creation.
+    quotas: QuotaTable,
+    /// Quota for contexts without one of their own.
+    default_quota: ContextQuota,
+    /// Usage of each context's head, computed on first use and then kept
+    /// current by appends, attaches and trims.
+    context_usage: HashMap<u64, ContextUsage>,
+    /// Content size of each attached snapshot root, populated lazily.
+    fs_root_sizes: HashMap<[u8; 32], u64>,
 }
 
 impl Store {
     pub fn open(dir: &Path) -> Result<Self> {
+        Self::open_with_hash_algorithm(dir, HashAlgorithm::Blake3)
+    }
+
+    /// Open the store, verifying filesystem blobs with `hash_algorithm`.
+    pub fn open_with_hash_algorithm(dir: &Path, hash_algorithm: HashAlgorithm) -> Result<Self> {
         let mut store = Self {
             blob_store: BlobStore::open(&dir.join("blobs"))?,
             turn_store: TurnStore::open(&dir.join("turns"))?,
             fs_roots: FsRootsIndex::open(&dir.join("fs"))?,
             context_metadata_cache: HashMap::new(),
             secondary_indexes: SecondaryIndexes::new(),
+            turn_item_ids: HashMap::new(),
+            hash_algorithm,
+            hash_keys: Vec::new(),
+            blob_uploads: BlobUploads::open(&dir.join("blobs").join("uploads"))?,
+            quotas: QuotaTable::open(&dir.join("turns"))?,
+            default_quota: ContextQuota::default(),
+            context_usage: HashMap::new(),
+            fs_root_sizes: HashMap::new(),
         };
 
         // Pre-populate metadata cache and build secondary indexes
@@ -176,10 +220,64 @@ impl Store {
         self.turn_store.fork_context(base_turn_id)
     }
 
+    /// Create a context with its own quota, overriding the default for the
+    /// limits it sets. `None` leaves the context on the default quota.
+    pub fn create_context_with_quota(
+        &mut self,
+        base_turn_id: u64,
+        quota: Option<ContextQuota>,
+    ) -> Result<ContextHead> {
+        let head = self.turn_store.create_context(base_turn_id)?;
+        if let Some(quota) = quota {
+            self.quotas.set(head.context_id, quota)?;
+        }
+        Ok(head)
+    }
+
+    /// Fork a context with its own quota. Turns inherited from the base
+    /// chain count towards it.
+    pub fn fork_context_with_quota(
+        &mut self,
+        base_turn_id: u64,
+        quota: Option<ContextQuota>,
+    ) -> Result<ContextHead> {
+        self.create_context_with_quota(base_turn_id, quota)
+    }
+
+    /// Set the quota applied to contexts created without one.
+    pub fn set_default_quota(&mut self, quota: ContextQuota) {
+        self.default_quota = quota;
+    }
+
+    /// The quota in force for a context: its own limits, falling back to the
+    /// default for any it leaves unset.
+    pub fn context_quota(&self, context_id: u64) -> ContextQuota {
+        self.quotas
+            .get(context_id)
+            .unwrap_or_default()
+            .or(self.default_quota)
+    }
+
+    /// Turns and bytes reachable from a context's head. See `crate::quota`.
+    pub fn context_usage(&mut self, context_id: u64) -> Result<ContextUsage> {
+        if let Some(usage) = self.context_usage.get(&context_id) {
+            return Ok(*usage);
+        }
+        let head = self.turn_store.get_head(context_id)?;
+        let usage = self.usage_at(context_id, head.head_turn_id)?;
+        self.context_usage.insert(context_id, usage);
+        Ok(usage)
+    }
+
     pub fn get_head(&self, context_id: u64) -> Result<ContextHead> {
         self.turn_store.get_head(context_id)
     }
 
+    /// The head a context had at `timestamp_ms` (see `TurnStore::head_at`).
+    pub fn get_head_at(&self, context_id: u64, timestamp_ms: u64) -> Result<ContextHead> {
+        self.turn_store.head_at(context_id, timestamp_ms)
+    }
+
     /// Append a turn to a context.
     ///
     /// Returns the turn record and, if this is the first turn (depth=0), the extracted metadata.
@@ -196,16 +294,37 @@ impl Store {
         content_hash: [u8; 32],
         payload_bytes: &[u8],
     ) -> Result<(TurnRecord, Option<ContextMetadata>)> {
-        let raw_bytes = match compression {
-            0 => payload_bytes.to_vec(),
-            1 => zstd::decode_all(payload_bytes)
-                .map_err(|e| StoreError::InvalidInput(format!("zstd decode failed: {e}")))?,
-            other => {
-                return Err(StoreError::InvalidInput(format!(
-                    "unsupported compression: {other}"
-                )))
-            }
-        };
+        self.append_turn_with_flags(
+            context_id,
+            parent_turn_id,
+            declared_type_id,
+            declared_type_version,
+            encoding,
+            compression,
+            uncompressed_len,
+            content_hash,
+            payload_bytes,
+            0,
+        )
+    }
+
+    /// `append_turn` with `TurnRecord` flags, e.g. `TURN_FLAG_CHECKPOINT` for
+    /// a caller-supplied summary of the chain so far.
+    #[allow(clippy::too_many_arguments)]
+    pub fn append_turn_with_flags(
+        &mut self,
+        context_id: u64,
+        parent_turn_id: u64,
+        declared_type_id: String,
+        declared_type_version: u32,
+        encoding: u32,
+        compression: u32,
+        uncompressed_len: u32,
+        content_hash: [u8; 32],
+        payload_bytes: &[u8],
+        flags: u32,
+    ) -> Result<(TurnRecord, Option<ContextMetadata>)> {
+        let raw_bytes = decompress_payload(compression, payload_bytes)?;
 
         if raw_bytes.len() as u32 != uncompressed_len {
             return Err(StoreError::InvalidInput(
@@ -220,8 +339,18 @@ impl Store {
             return Err(StoreError::InvalidInput("content hash mismatch".into()));
         }
 
+        if let Some(blob_hash) = extract_tool_result_blob(&raw_bytes) {
+            if !self.blob_store.contains(&blob_hash) {
+                return Err(StoreError::NotFound("tool_result content blob".into()));
+            }
+        }
+
+        self.check_append_quota(context_id, parent_turn_id, uncompressed_len)?;
+
         self.blob_store.put_if_absent(content_hash, &raw_bytes)?;
 
+        let previous_head = self.turn_store.get_head(context_id)?.head_turn_id;
+
         let record = self.turn_store.append_turn(
             context_id,
             parent_turn_id,
@@ -231,8 +360,20 @@ impl Store {
             declared_type_version,
             compression,
             uncompressed_len,
+            flags,
         )?;
 
+        // Appending to the head extends its usage; a branch starts a new
+        // chain, counted afresh when next needed.
+        if record.parent_turn_id == previous_head {
+            if let Some(usage) = self.context_usage.get_mut(&context_id) {
+                usage.turns += 1;
+                usage.bytes += uncompressed_len as u64;
+            }
+        } else {
+            self.context_usage.remove(&context_id);
+        }
+
         // Cache metadata if this is the first turn, and return it for event publishing
         let metadata = self.maybe_cache_metadata(context_id, record.depth, &raw_bytes);
 
@@ -250,13 +391,93 @@ impl Store {
         Ok((record, metadata))
     }
 
+    /// Find an existing turn carrying the same ConversationItem id as `payload_bytes`.
+    ///
+    /// Only the chain the new turn would be appended to is searched: from
+    /// `parent_turn_id`, or from the context head when it is 0, and at most
+    /// `ITEM_DEDUP_WINDOW` turns back. Returns None when the payload has no
+    /// id or no turn in that window matches.
+    pub fn find_duplicate_item(
+        &mut self,
+        context_id: u64,
+        parent_turn_id: u64,
+        compression: u32,
+        payload_bytes: &[u8],
+    ) -> Result<Option<TurnRecord>> {
+        let raw_bytes = decompress_payload(compression, payload_bytes)?;
+        let item_id = match extract_item_id(&raw_bytes) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let mut current = if parent_turn_id != 0 {
+            self.turn_store
+                .validate_parent(context_id, parent_turn_id)?;
+            parent_turn_id
+        } else {
+            self.turn_store.get_head(context_id)?.head_turn_id
+        };
+        for _ in 0..ITEM_DEDUP_WINDOW {
+            if current == 0 {
+                break;
+            }
+            let record = self.turn_store.get_turn(current)?;
+            if self.turn_item_id(context_id, &record).as_deref() == Some(item_id.as_str()) {
+                return Ok(Some(record));
+            }
+            current = self.turn_store.live_parent(context_id, &record);
+        }
+        Ok(None)
+    }
+
+    fn turn_item_id(&mut self, context_id: u64, record: &TurnRecord) -> Option<String> {
+        let cache = self.turn_item_ids.entry(context_id).or_default();
+        if let Some(cached) = cache.ids.get(&record.turn_id) {
+            return cached.clone();
+        }
+        let item_id = self
+            .blob_store
+            .get(&record.payload_hash)
+            .ok()
+            .and_then(|payload| extract_item_id(&payload));
+        cache.insert(record.turn_id, item_id.clone());
+        item_id
+    }
+
     pub fn get_last(
         &mut self,
         context_id: u64,
         limit: u32,
         include_payload: bool,
     ) -> Result<Vec<TurnWithMeta>> {
-        let turns = self.turn_store.get_last(context_id, limit)?;
+        Ok(self
+            .get_last_window(context_id, 0, limit, include_payload)?
+            .0)
+    }
+
+    /// Page backward through a context: skip `offset` turns from the head, then
+    /// return up to `limit` turns plus whether older turns remain.
+    pub fn get_last_window(
+        &mut self,
+        context_id: u64,
+        offset: u32,
+        limit: u32,
+        include_payload: bool,
+    ) -> Result<(Vec<TurnWithMeta>, bool)> {
+        self.get_last_window_from(context_id, offset, limit, include_payload, false)
+    }
+
+    fn get_last_window_from(
+        &mut self,
+        context_id: u64,
+        offset: u32,
+        limit: u32,
+        include_payload: bool,
+        summary: bool,
+    ) -> Result<(Vec<TurnWithMeta>, bool)> {
+        let (turns, has_more) = self
+            .turn_store
+            .get_last_window(context_id, offset, limit, summary)?;
         let mut out = Vec::with_capacity(turns.len());
         for record in turns {
             let meta = self.turn_store.get_turn_meta(record.turn_id)?;
@@ -271,7 +492,96 @@ impl Store {
                 payload,
             });
         }
-        Ok(out)
+        Ok((out, has_more))
+    }
+
+    /// Checks that turn `turn_id` is on the current chain of `context_id`,
+    /// so a read can promise to reflect a write the caller already saw
+    /// acknowledged. Turn ids are allocated under the store lock and only
+    /// acknowledged after the append, so an id this server never allocated
+    /// can never show up here and is reported as `StoreError::NotFound`, as
+    /// is a turn from another context or from a branch the head moved off.
+    pub fn require_turn(&self, context_id: u64, turn_id: u64) -> Result<()> {
+        if !self.turn_store.is_allocated(turn_id) {
+            return Err(StoreError::NotFound(format!(
+                "turn {turn_id} was never allocated"
+            )));
+        }
+        if !self.turn_store.is_on_active_chain(context_id, turn_id) {
+            return Err(StoreError::NotFound(format!(
+                "turn {turn_id} is not on the chain of context {context_id}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// `get_last_window` restricted to turns whose ConversationItem type is in
+    /// `item_types` (all turns when empty). `offset` and `limit` count matching
+    /// turns only. `has_more` reports whether older turns remain at all, so a
+    /// further page may come back empty.
+    ///
+    /// In `summary` mode the walk stops at the most recent checkpoint turn,
+    /// which is returned whatever its type: it stands in for everything
+    /// before it.
+    pub fn get_last_window_of_types(
+        &mut self,
+        context_id: u64,
+        offset: u32,
+        limit: u32,
+        include_payload: bool,
+        item_types: &[String],
+        summary: bool,
+    ) -> Result<(Vec<TurnWithMeta>, bool)> {
+        if item_types.is_empty() {
+            return self.get_last_window_from(context_id, offset, limit, include_payload, summary);
+        }
+
+        let mut current = self.turn_store.get_head(context_id)?.head_turn_id;
+        let mut skipped = 0;
+        let mut out = Vec::new();
+        while current != 0 && out.len() < limit as usize {
+            let record = self.turn_store.get_turn(current)?;
+            let checkpoint = summary && record.is_checkpoint();
+            current = if checkpoint {
+                0
+            } else {
+                self.turn_store.live_parent(context_id, &record)
+            };
+            let payload = self.blob_store.get(&record.payload_hash)?;
+            let matches = checkpoint
+                || extract_item_type(&payload)
+                    .is_some_and(|item_type| item_types.contains(&item_type));
+            if !matches {
+                continue;
+            }
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            let meta = self.turn_store.get_turn_meta(record.turn_id)?;
+            out.push(TurnWithMeta {
+                record,
+                meta,
+                payload: include_payload.then_some(payload),
+            });
+        }
+        out.reverse();
+        Ok((out, current != 0))
+    }
+
+    /// The ConversationItem type of each turn in `items`, read from its
+    /// payload (loaded from the blob store when `items` came without one).
+    /// None for turns that are not ConversationItems.
+    pub fn item_types_of(&mut self, items: &[TurnWithMeta]) -> Result<Vec<Option<String>>> {
+        items
+            .iter()
+            .map(|item| match &item.payload {
+                Some(payload) => Ok(extract_item_type(payload)),
+                None => Ok(extract_item_type(
+                    &self.blob_store.get(&item.record.payload_hash)?,
+                )),
+            })
+            .collect()
     }
 
     pub fn get_before(
@@ -301,6 +611,74 @@ impl Store {
         Ok(out)
     }
 
+    /// Inline a ToolResult's referenced content blob into its `content`
+    /// field for display. Returns None when the payload has no reference.
+    ///
+    /// The rewritten bytes no longer match the turn's content hash, so this
+    /// is only for decoded views, never for raw payloads.
+    pub fn resolve_tool_result_blob(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(blob_hash) = extract_tool_result_blob(payload) else {
+            return Ok(None);
+        };
+        let content = self.blob_store.get(&blob_hash)?;
+
+        let mut value = rmpv::decode::read_value(&mut std::io::Cursor::new(payload))
+            .map_err(|e| StoreError::Corrupt(format!("invalid msgpack: {e}")))?;
+        if let Some(Value::Map(result)) = map_field_mut(&mut value, TOOL_RESULT_KEY) {
+            let content = Value::from(String::from_utf8_lossy(&content).into_owned());
+            match result
+                .iter_mut()
+                .find(|(k, _)| numeric_key(k) == Some(TOOL_RESULT_CONTENT_KEY))
+            {
+                Some((_, v)) => *v = content,
+                None => result.push((Value::from(TOOL_RESULT_CONTENT_KEY), content)),
+            }
+        }
+
+        let mut out = Vec::with_capacity(payload.len() + 64);
+        rmpv::encode::write_value(&mut out, &value)
+            .map_err(|e| StoreError::Corrupt(format!("msgpack encode failed: {e}")))?;
+        Ok(Some(out))
+    }
+
+    /// Begin or resume a chunked blob upload, returning the upload id and the
+    /// offset to continue from. Returns None if the blob is already stored.
+    pub fn begin_blob_upload(
+        &mut self,
+        hash: [u8; 32],
+        total_len: u64,
+    ) -> Result<Option<(u64, u64)>> {
+        if self.blob_store.contains(&hash) {
+            return Ok(None);
+        }
+        self.blob_uploads.begin(hash, total_len).map(Some)
+    }
+
+    /// Verify a fully received chunked upload against its declared hash and
+    /// store it. Returns the hash and whether the blob was new.
+    pub fn commit_blob_upload(&mut self, upload_id: u64) -> Result<([u8; 32], bool)> {
+        let (hash, data) = self.blob_uploads.take_complete(upload_id)?;
+        if !self.blob_hash_matches(&hash, &data) {
+            return Err(StoreError::InvalidInput("blob hash mismatch".into()));
+        }
+        let was_new = !self.blob_store.contains(&hash);
+        self.blob_store.put_if_absent(hash, &data)?;
+        Ok((hash, was_new))
+    }
+
+    /// Accept filesystem blobs hashed with keyed BLAKE3 under any of `keys`,
+    /// in addition to the configured algorithm. Each key is a tenant's hash
+    /// namespace; blobs under different keys never dedup against each other.
+    pub fn set_hash_keys(&mut self, keys: Vec<[u8; 32]>) {
+        self.hash_keys = keys;
+    }
+
+    /// Whether `hash` is the address of `data` under the configured
+    /// algorithm or one of the namespace keys.
+    pub fn blob_hash_matches(&self, hash: &[u8; 32], data: &[u8]) -> bool {
+        blob_hash_matches(self.hash_algorithm, &self.hash_keys, hash, data)
+    }
+
     pub fn get_blob(&mut self, hash: &[u8; 32]) -> Result<Vec<u8>> {
         self.blob_store.get(hash)
     }
@@ -309,6 +687,44 @@ impl Store {
         self.turn_store.list_recent_contexts(limit)
     }
 
+    /// Pages through every context in id order: up to `limit` heads with
+    /// ids above `after`, and whether more follow.
+    pub fn list_contexts_after(&self, after: u64, limit: u32) -> (Vec<ContextHead>, bool) {
+        self.turn_store.list_contexts_after(after, limit)
+    }
+
+    /// Most recent contexts whose metadata carries the given labels: all of
+    /// them when `match_all`, otherwise any one. Scans every head, which is
+    /// fine at current context counts; an index can replace it later.
+    pub fn list_contexts_with_labels(
+        &mut self,
+        labels: &[String],
+        match_all: bool,
+        limit: u32,
+    ) -> Vec<ContextHead> {
+        let mut matched = Vec::new();
+        for head in self.turn_store.list_recent_contexts(u32::MAX) {
+            if matched.len() >= limit as usize {
+                break;
+            }
+            let metadata = self.get_context_metadata(head.context_id);
+            let have = metadata
+                .as_ref()
+                .and_then(|m| m.labels.as_deref())
+                .unwrap_or(&[]);
+            let hit = |label: &String| have.contains(label);
+            let is_match = if match_all {
+                labels.iter().all(hit)
+            } else {
+                labels.iter().any(hit)
+            };
+            if is_match {
+                matched.push(head);
+            }
+        }
+        matched
+    }
+
     // =========================================================================
     // CQL Search Methods
     // =========================================================================
@@ -383,31 +799,159 @@ impl Store {
         self.secondary_indexes.stats()
     }
 
+    // =========================================================================
+    // Retention
+    // =========================================================================
+
+    /// Trim a context's turns created before `before_unix_ms`: they stop
+    /// being returned for this context by `get_last`, `get_before` and the
+    /// HTTP turn listing, and stop counting towards its quota. The head is
+    /// always kept, other contexts keep reading the turns they share, and
+    /// the trim is persisted so it survives restarts (see
+    /// `TurnStore::trim_before`).
+    ///
+    /// Payload blobs are not deleted here, since the blob store is shared and
+    /// has no delete path yet; they stay readable by hash. The report lists
+    /// those no readable turn references any more, as does `gc_candidates`.
+    pub fn trim_context_before(
+        &mut self,
+        context_id: u64,
+        before_unix_ms: u64,
+    ) -> Result<TrimReport> {
+        let trimmed = self.turn_store.trim_before(context_id, before_unix_ms)?;
+        if trimmed.is_empty() {
+            return Ok(TrimReport::default());
+        }
+        self.context_usage.remove(&context_id);
+        self.turn_item_ids.remove(&context_id);
+
+        let orphaned = self.turn_store.trimmed_payloads();
+        let mut gc_candidates: Vec<[u8; 32]> = trimmed
+            .iter()
+            .map(|record| record.payload_hash)
+            .filter(|hash| orphaned.contains(hash))
+            .collect();
+        gc_candidates.sort_unstable();
+        gc_candidates.dedup();
+        Ok(TrimReport {
+            turns_trimmed: trimmed.len() as u64,
+            gc_candidates,
+        })
+    }
+
+    /// Payload blobs referenced only by trimmed turns, for a future blob GC.
+    pub fn gc_candidates(&self) -> Vec<[u8; 32]> {
+        let mut hashes: Vec<[u8; 32]> = self.turn_store.trimmed_payloads().into_iter().collect();
+        hashes.sort_unstable();
+        hashes
+    }
+
     // =========================================================================
     // Filesystem Snapshot Methods
     // =========================================================================
 
     /// Attach a filesystem snapshot to a turn.
     /// The tree objects and file blobs must already exist in the blob store.
-    pub fn attach_fs(&mut self, turn_id: u64, fs_root_hash: [u8; 32]) -> Result<()> {
+    pub fn attach_fs(&mut self, turn_id: u64, fs_root_hash: FsRootHash) -> Result<()> {
+        self.attach_fs_with_meta(turn_id, fs_root_hash, None)
+    }
+
+    /// Attach a filesystem snapshot to a turn along with the client's
+    /// capture metadata (when and how large the snapshot was).
+    pub fn attach_fs_with_meta(
+        &mut self,
+        turn_id: u64,
+        fs_root_hash: FsRootHash,
+        meta: Option<SnapshotMeta>,
+    ) -> Result<()> {
         // Verify the turn exists
         let _ = self.turn_store.get_turn(turn_id)?;
 
         // Verify the root tree exists in blob store
-        if !self.blob_store.contains(&fs_root_hash) {
+        if !self.blob_store.contains(fs_root_hash.as_bytes()) {
             return Err(StoreError::NotFound("fs root tree blob".into()));
         }
 
-        self.fs_roots.attach(turn_id, fs_root_hash)
+        // Contexts whose usage is cached or limited need adjusting; the rest
+        // count the snapshot when their usage is first computed.
+        let reaching: Vec<u64> = if self.context_usage.is_empty() && !self.has_byte_quotas() {
+            Vec::new()
+        } else {
+            self.turn_store
+                .contexts_reaching(turn_id)
+                .into_iter()
+                .filter(|id| {
+                    self.context_usage.contains_key(id) || self.context_quota(*id).limits_bytes()
+                })
+                .collect()
+        };
+        let (replaced, added) = if reaching.is_empty() {
+            (0, 0)
+        } else {
+            let replaced = match self.fs_roots.get(turn_id) {
+                Some(existing) => self.fs_root_size(&existing),
+                None => 0,
+            };
+            (replaced, self.fs_root_size(&fs_root_hash))
+        };
+        self.check_attach_quota(&reaching, replaced, added)?;
+
+        self.fs_roots
+            .attach_with_meta(turn_id, fs_root_hash, meta)?;
+        for context_id in reaching {
+            if let Some(usage) = self.context_usage.get_mut(&context_id) {
+                usage.bytes = usage.bytes.saturating_sub(replaced) + added;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach a filesystem snapshot to a context's current head turn and
+    /// return that turn's id. Fails with `InvalidInput` if the context has
+    /// no turns yet.
+    pub fn attach_fs_to_head(
+        &mut self,
+        context_id: u64,
+        fs_root_hash: FsRootHash,
+        meta: Option<SnapshotMeta>,
+    ) -> Result<u64> {
+        let head = self.turn_store.get_head(context_id)?;
+        if head.head_turn_id == 0 {
+            return Err(StoreError::InvalidInput(format!(
+                "context {context_id} has no turns to attach to"
+            )));
+        }
+        self.attach_fs_with_meta(head.head_turn_id, fs_root_hash, meta)?;
+        Ok(head.head_turn_id)
     }
 
     /// Get the filesystem root hash for a turn (direct or inherited).
-    pub fn get_fs_root(&self, turn_id: u64) -> Option<[u8; 32]> {
+    pub fn get_fs_root(&self, turn_id: u64) -> Option<FsRootHash> {
         self.fs_roots.get_inherited(turn_id, &self.turn_store)
     }
 
+    /// Get the capture metadata of the filesystem snapshot a turn sees
+    /// (direct or inherited), if it was attached with any.
+    pub fn get_fs_meta(&self, turn_id: u64) -> Option<SnapshotMeta> {
+        let attached = self.fs_roots.find_attached(turn_id, &self.turn_store)?;
+        self.fs_roots.get_meta(attached)
+    }
+
+    /// Turns the snapshot `fs_root_hash` is directly attached to, in
+    /// ascending order: what would lose their snapshot if it were deleted,
+    /// along with any descendants that inherit it from them.
+    pub fn find_snapshot_refs(&self, fs_root_hash: &[u8; 32]) -> Vec<u64> {
+        self.fs_roots.turns_for_root(fs_root_hash)
+    }
+
+    /// Checks that every tree and blob the snapshot `fs_root_hash`
+    /// references is stored; see `fs_store::verify_snapshot`.
+    pub fn verify_snapshot(&mut self, fs_root_hash: &[u8; 32]) -> Result<SnapshotVerifyReport> {
+        crate::fs_store::verify_snapshot(&mut self.blob_store, &(*fs_root_hash).into())
+    }
+
     /// Get the filesystem root hash directly attached to a turn (no inheritance).
-    pub fn get_fs_root_direct(&self, turn_id: u64) -> Option<[u8; 32]> {
+    pub fn get_fs_root_direct(&self, turn_id: u64) -> Option<FsRootHash> {
         self.fs_roots.get(turn_id)
     }
 
@@ -418,14 +962,13 @@ impl Store {
             .get_inherited(turn_id, &self.turn_store)
             .ok_or_else(|| StoreError::NotFound("no fs snapshot for turn".into()))?;
 
-        let (tree_hash, is_dir) =
-            crate::fs_store::resolve_path(&mut self.blob_store, &fs_root, path)?;
-
-        if !is_dir {
+        let ResolvedPath::Tree(tree_hash) =
+            crate::fs_store::resolve_path(&mut self.blob_store, &fs_root, path)?
+        else {
             return Err(StoreError::InvalidInput(format!(
                 "path is not a directory: {path}"
             )));
-        }
+        };
 
         crate::fs_store::load_tree_entries(&mut self.blob_store, &tree_hash)
     }
@@ -440,6 +983,138 @@ impl Store {
         crate::fs_store::get_file_at_path(&mut self.blob_store, &fs_root, path)
     }
 
+    /// Classify the file at a path in the filesystem snapshot for a turn
+    /// without reading all of its content.
+    pub fn fs_file_kind(&mut self, turn_id: u64, path: &str) -> Result<FileKind> {
+        let fs_root = self
+            .fs_roots
+            .get_inherited(turn_id, &self.turn_store)
+            .ok_or_else(|| StoreError::NotFound("no fs snapshot for turn".into()))?;
+
+        crate::fs_store::file_kind_at_path(&mut self.blob_store, &fs_root, path)
+    }
+
+    /// Duplicated handles to every file a write request appends to. Syncing
+    /// them makes all writes made before the call durable; see
+    /// `group_commit`.
+    pub fn sync_handles(&self) -> Result<Vec<File>> {
+        let mut files = self.turn_store.sync_handles()?;
+        files.extend(self.blob_store.sync_handles()?);
+        files.push(self.fs_roots.sync_handle()?);
+        files.push(self.quotas.sync_handle()?);
+        Ok(files)
+    }
+
+    /// Compact last-write-wins index files whose dead-record ratio is at least
+    /// `min_dead_ratio` and whose size is at least `min_file_bytes`.
+    ///
+    /// Blob packs are append-only with no delete path, so they never carry
+    /// dead bytes and are not compacted here.
+    pub fn compact(
+        &mut self,
+        min_dead_ratio: f64,
+        min_file_bytes: u64,
+    ) -> Result<CompactionReport> {
+        let mut report = CompactionReport::default();
+        let turn_stats = self.turn_store.stats();
+        if turn_stats.heads_table_bytes >= min_file_bytes
+            && self.turn_store.heads_dead_ratio() >= min_dead_ratio
+        {
+            report.heads_bytes_reclaimed = self.turn_store.compact_heads()?;
+            report.heads_compacted = true;
+        }
+        let roots_stats = self.fs_roots.stats();
+        if roots_stats.file_bytes >= min_file_bytes && self.fs_roots.dead_ratio() >= min_dead_ratio
+        {
+            report.roots_bytes_reclaimed = self.fs_roots.compact()?;
+            report.roots_compacted = true;
+        }
+        Ok(report)
+    }
+
+    /// Truncate every append-only file at its first bad record, listing each
+    /// truncation in the returned report. `open` only does this silently for
+    /// a torn final record and refuses mid-file corruption, since truncating
+    /// there also discards the intact records after it. Run against a closed
+    /// store, before `open`.
+    pub fn repair(dir: &Path) -> Result<RepairReport> {
+        let mut report = RepairReport::default();
+        TurnStore::repair(&dir.join("turns"), &mut report)?;
+        BlobStore::repair(&dir.join("blobs"), &mut report)?;
+        FsRootsIndex::repair(&dir.join("fs"), &mut report)?;
+        QuotaTable::repair(&dir.join("turns"), &mut report)?;
+        Ok(report)
+    }
+
+    /// Verify the on-disk files without modifying them. See `crate::check`.
+    ///
+    /// Errors are returned only when a file cannot be read at all; everything
+    /// else ends up in the report.
+    pub fn check(&mut self) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        let turn_ids = self.turn_store.check(&mut report)?;
+
+        let hash_algorithm = self.hash_algorithm;
+        let hash_keys = self.hash_keys.clone();
+        // Turn payloads are always BLAKE3; filesystem blobs use the configured
+        // algorithm or one of the namespace keys.
+        self.blob_store.check(&mut report, |hash, data| {
+            blake3::hash(data).as_bytes() == hash
+                || blob_hash_matches(hash_algorithm, &hash_keys, hash, data)
+        })?;
+
+        let roots = self.fs_roots.check(&mut report)?;
+        let mut visited = HashSet::new();
+        let mut sorted: Vec<_> = roots.into_iter().collect();
+        sorted.sort_by_key(|(turn_id, _)| *turn_id);
+        for (turn_id, root) in sorted {
+            if !turn_ids.contains(&turn_id) {
+                report.issue(
+                    "fs/roots.idx",
+                    None,
+                    format!("fs root attached to missing turn {turn_id}"),
+                );
+            }
+            if let Err(err) = self.check_tree(&root.into(), &mut visited) {
+                report.issue(
+                    "fs/roots.idx",
+                    None,
+                    format!(
+                        "fs root {} of turn {turn_id} does not resolve: {err}",
+                        hex::encode(root)
+                    ),
+                );
+            }
+            report.fs_roots_checked += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Walk a tree, failing on the first missing or unparseable object.
+    fn check_tree(&mut self, tree_hash: &TreeHash, visited: &mut HashSet<[u8; 32]>) -> Result<()> {
+        if !visited.insert(*tree_hash.as_bytes()) {
+            return Ok(());
+        }
+        let entries = crate::fs_store::load_tree_entries(&mut self.blob_store, tree_hash)?;
+        for entry in entries {
+            if entry.inline_content.is_some() {
+                continue;
+            }
+            let hash = entry.hash_array()?;
+            if entry.kind == 1 {
+                self.check_tree(&hash.into(), visited)?;
+            } else if !self.blob_store.contains(&hash) {
+                return Err(StoreError::NotFound(format!(
+                    "blob {} for {}",
+                    hex::encode(hash),
+                    entry.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn stats(&mut self) -> StoreStats {
         let blob_stats = self.blob_store.stats();
         let turn_stats = self.turn_store.stats();
@@ -457,44 +1132,124 @@ impl Store {
             blobs_pack_bytes: blob_stats.pack_bytes,
             blobs_index_bytes: blob_stats.idx_bytes,
             fs_roots_total: fs_stats.entries_total,
+            fs_roots_skipped_records: fs_stats.skipped_records,
             fs_roots_bytes: fs_stats.file_bytes,
             fs_content_bytes,
         }
     }
 
-    /// Compute the total size of all blobs referenced by filesystem snapshots.
-    /// This traverses all unique filesystem root trees and sums the raw blob sizes.
+    /// Compute the total size of all blobs referenced by filesystem snapshots,
+    /// walking roots on one thread per available core.
     fn compute_fs_content_bytes(&mut self) -> u64 {
-        use std::collections::HashSet;
+        let concurrency = std::thread::available_parallelism().map_or(1, |n| n.get());
+        self.fs_roots.content_bytes(&self.blob_store, concurrency)
+    }
 
-        let unique_roots = self.fs_roots.unique_roots();
-        if unique_roots.is_empty() {
-            return 0;
+    /// Reject an append that would take the context past its quota.
+    fn check_append_quota(
+        &mut self,
+        context_id: u64,
+        parent_turn_id: u64,
+        payload_len: u32,
+    ) -> Result<()> {
+        let quota = self.context_quota(context_id);
+        if quota.is_unlimited() {
+            return Ok(());
+        }
+        let parent = if parent_turn_id != 0 {
+            self.turn_store
+                .validate_parent(context_id, parent_turn_id)?;
+            parent_turn_id
+        } else {
+            self.turn_store.get_head(context_id)?.head_turn_id
+        };
+        let head = self.turn_store.get_head(context_id)?.head_turn_id;
+        let usage = if parent == head {
+            self.context_usage(context_id)?
+        } else {
+            self.usage_at(context_id, parent)?
+        };
+        check_quota(
+            context_id,
+            quota,
+            ContextUsage {
+                turns: usage.turns + 1,
+                bytes: usage.bytes + payload_len as u64,
+            },
+        )
+    }
+
+    /// Reject an attach that replaces `replaced` snapshot bytes with `added`
+    /// if it would take any of the `reaching` contexts past its byte quota.
+    fn check_attach_quota(&mut self, reaching: &[u64], replaced: u64, added: u64) -> Result<()> {
+        for &context_id in reaching {
+            let quota = self.context_quota(context_id);
+            if !quota.limits_bytes() {
+                continue;
+            }
+            let usage = self.context_usage(context_id)?;
+            check_quota(
+                context_id,
+                quota,
+                ContextUsage {
+                    turns: usage.turns,
+                    bytes: usage.bytes.saturating_sub(replaced) + added,
+                },
+            )?;
         }
+        Ok(())
+    }
 
-        let mut visited: HashSet<[u8; 32]> = HashSet::new();
-        let mut total_bytes: u64 = 0;
+    /// Whether any context may have a byte limit to enforce.
+    fn has_byte_quotas(&self) -> bool {
+        self.default_quota.limits_bytes()
+            || self
+                .quotas
+                .context_ids()
+                .any(|id| self.context_quota(id).limits_bytes())
+    }
 
-        for root_hash in unique_roots {
-            total_bytes += self.compute_tree_size(&root_hash, &mut visited);
+    /// Usage of the chain ending at `turn_id`, as read from `context_id`
+    /// (0 for an empty chain).
+    fn usage_at(&mut self, context_id: u64, turn_id: u64) -> Result<ContextUsage> {
+        let mut usage = ContextUsage::default();
+        let mut current = turn_id;
+        while current != 0 {
+            let record = self.turn_store.get_turn(current)?;
+            let meta = self.turn_store.get_turn_meta(current)?;
+            let snapshot = match self.fs_roots.get(current) {
+                Some(root) => self.fs_root_size(&root),
+                None => 0,
+            };
+            usage.turns += 1;
+            usage.bytes += meta.uncompressed_len as u64 + snapshot;
+            current = self.turn_store.live_parent(context_id, &record);
         }
+        Ok(usage)
+    }
 
-        total_bytes
+    fn fs_root_size(&mut self, root: &FsRootHash) -> u64 {
+        if let Some(size) = self.fs_root_sizes.get(root.as_bytes()) {
+            return *size;
+        }
+        let size = self.compute_tree_size(&(*root).into(), &mut HashSet::new());
+        self.fs_root_sizes.insert(*root.as_bytes(), size);
+        size
     }
 
     /// Recursively compute the size of all blobs in a tree.
     fn compute_tree_size(
         &mut self,
-        tree_hash: &[u8; 32],
+        tree_hash: &TreeHash,
         visited: &mut std::collections::HashSet<[u8; 32]>,
     ) -> u64 {
         // Skip if already visited (deduplication)
-        if !visited.insert(*tree_hash) {
+        if !visited.insert(*tree_hash.as_bytes()) {
             return 0;
         }
 
         // Add the tree blob's own size
-        let tree_size = self.blob_store.raw_len(tree_hash).unwrap_or(0) as u64;
+        let tree_size = self.blob_store.raw_len(tree_hash.as_bytes()).unwrap_or(0) as u64;
 
         // Try to load and traverse tree entries
         let entries = match crate::fs_store::load_tree_entries(&mut self.blob_store, tree_hash) {
@@ -508,7 +1263,7 @@ impl Store {
             if let Ok(hash) = entry.hash_array() {
                 if entry.kind == 1 {
                     // Directory - recurse
-                    total += self.compute_tree_size(&hash, visited);
+                    total += self.compute_tree_size(&hash.into(), visited);
                 } else {
                     // File or symlink - add blob size if not visited
                     if visited.insert(hash) {
@@ -522,6 +1277,50 @@ impl Store {
     }
 }
 
+/// Outcome of a `Store::trim_context_before` call.
+/// Item ids of up to `ITEM_DEDUP_WINDOW` turns, evicting the least recently
+/// added. None value means the payload carries no id.
+#[derive(Default)]
+struct ItemIdCache {
+    ids: HashMap<u64, Option<String>>,
+    order: VecDeque<u64>,
+}
+
+impl ItemIdCache {
+    fn insert(&mut self, turn_id: u64, item_id: Option<String>) {
+        if self.ids.insert(turn_id, item_id).is_none() {
+            self.order.push_back(turn_id);
+        }
+        while self.order.len() > ITEM_DEDUP_WINDOW {
+            if let Some(evicted) = self.order.pop_front() {
+                self.ids.remove(&evicted);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrimReport {
+    pub turns_trimmed: u64,
+    /// Payload blobs this trim left unreferenced by any readable turn.
+    pub gc_candidates: Vec<[u8; 32]>,
+}
+
+/// Outcome of a `Store::compact` pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub heads_compacted: bool,
+    pub heads_bytes_reclaimed: u64,
+    pub roots_compacted: bool,
+    pub roots_bytes_reclaimed: u64,
+}
+
+impl CompactionReport {
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.heads_bytes_reclaimed + self.roots_bytes_reclaimed
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StoreStats {
     pub turns_total: usize,
@@ -535,10 +1334,108 @@ pub struct StoreStats {
     pub blobs_pack_bytes: u64,
     pub blobs_index_bytes: u64,
     pub fs_roots_total: usize,
+    /// Corrupt roots.idx records skipped at open.
+    pub fs_roots_skipped_records: usize,
     pub fs_roots_bytes: u64,
     pub fs_content_bytes: u64,
 }
 
+/// Fail with `QuotaExceeded` if `usage` is over either of the quota's limits.
+fn check_quota(context_id: u64, quota: ContextQuota, usage: ContextUsage) -> Result<()> {
+    if let Some(max) = quota.max_turns.filter(|max| usage.turns > *max) {
+        return Err(StoreError::QuotaExceeded(format!(
+            "context {context_id} would hold {} turns (limit {max})",
+            usage.turns
+        )));
+    }
+    if let Some(max) = quota.max_bytes.filter(|max| usage.bytes > *max) {
+        return Err(StoreError::QuotaExceeded(format!(
+            "context {context_id} would hold {} bytes (limit {max})",
+            usage.bytes
+        )));
+    }
+    Ok(())
+}
+
+fn decompress_payload(compression: u32, payload_bytes: &[u8]) -> Result<Vec<u8>> {
+    match compression {
+        0 => Ok(payload_bytes.to_vec()),
+        1 => zstd::decode_all(payload_bytes)
+            .map_err(|e| StoreError::InvalidInput(format!("zstd decode failed: {e}"))),
+        other => Err(StoreError::InvalidInput(format!(
+            "unsupported compression: {other}"
+        ))),
+    }
+}
+
+/// Extract the ConversationItem type (key 1) from a msgpack payload.
+fn extract_item_type(payload: &[u8]) -> Option<String> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let value = rmpv::decode::read_value(&mut cursor).ok()?;
+    let map = match &value {
+        Value::Map(m) => m,
+        _ => return None,
+    };
+    map.iter()
+        .find(|(k, _)| numeric_key(k) == Some(1))
+        .and_then(|(_, v)| extract_string(v))
+}
+
+/// Extract the ConversationItem id (key 4) from a msgpack payload.
+/// Returns None for non-map payloads and empty ids.
+fn extract_item_id(payload: &[u8]) -> Option<String> {
+    let mut cursor = std::io::Cursor::new(payload);
+    let value = rmpv::decode::read_value(&mut cursor).ok()?;
+    let map = match &value {
+        Value::Map(m) => m,
+        _ => return None,
+    };
+    map.iter()
+        .find(|(k, _)| numeric_key(k) == Some(4))
+        .and_then(|(_, v)| extract_string(v))
+        .filter(|id| !id.is_empty())
+}
+
+/// ConversationItem key holding the ToolResult map.
+const TOOL_RESULT_KEY: u64 = 22;
+/// ToolResult keys: inline content and the blob hash that replaces it.
+const TOOL_RESULT_CONTENT_KEY: u64 = 2;
+const TOOL_RESULT_CONTENT_BLOB_KEY: u64 = 8;
+
+/// Extract a ToolResult's content blob reference (key 22 → key 8), if any.
+fn extract_tool_result_blob(payload: &[u8]) -> Option<[u8; 32]> {
+    let mut value = rmpv::decode::read_value(&mut std::io::Cursor::new(payload)).ok()?;
+    let Value::Map(result) = map_field_mut(&mut value, TOOL_RESULT_KEY)? else {
+        return None;
+    };
+    let (_, blob) = result
+        .iter()
+        .find(|(k, _)| numeric_key(k) == Some(TOOL_RESULT_CONTENT_BLOB_KEY))?;
+    match blob {
+        Value::Binary(b) => b.as_slice().try_into().ok(),
+        _ => None,
+    }
+}
+
+fn map_field_mut(value: &mut Value, key: u64) -> Option<&mut Value> {
+    match value {
+        Value::Map(m) => m
+            .iter_mut()
+            .find(|(k, _)| numeric_key(k) == Some(key))
+            .map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// Msgpack map keys may be integers or digit strings depending on the encoder.
+fn numeric_key(k: &Value) -> Option<u64> {
+    match k {
+        Value::Integer(i) => i.as_u64(),
+        Value::String(s) => s.as_str().and_then(|s| s.parse().ok()),
+        _ => None,
+    }
+}
+
 /// Extract context metadata from a msgpack-encoded ConversationItem payload.
 ///
 /// The payload is expected to be a msgpack map with numeric keys.
@@ -739,3 +1636,15 @@ fn extract_string_map(v: &Value) -> Option<HashMap<String, String>> {
         None
     }
 }
+
+fn blob_hash_matches(
+    hash_algorithm: HashAlgorithm,
+    hash_keys: &[[u8; 32]],
+    hash: &[u8; 32],
+    data: &[u8],
+) -> bool {
+    hash_algorithm.hash(data) == *hash
+        || hash_keys
+            .iter()
+            .any(|key| blake3::keyed_hash(key, data).as_bytes() == hash)
+}
@@ -1,7 +1,7 @@
 // Copyright 2025 StrongDM Inc
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
@@ -10,8 +10,36 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher;
 
+use crate::check::{
+    ensure_trailing_only, truncate_tail, valid_prefix_len, CheckReport, RepairReport,
+};
 use crate::error::{Result, StoreError};
 
+/// Size of one heads.tbl record: context_id, head_turn_id, depth, flags, created_at, crc.
+const HEAD_RECORD_LEN: u64 = 8 + 8 + 4 + 4 + 8 + 4;
+
+/// Size of one turns.log record (see `encode_turn_record`).
+const TURN_RECORD_LEN: u64 = 8 + 8 + 4 + 4 + 8 + 32 + 4 + 8 + 4;
+
+/// Size of one turns.idx record: turn_id, offset.
+const TURN_INDEX_RECORD_LEN: u64 = 8 + 8;
+
+/// Size of one trims.tbl record: context_id, min_depth, trimmed_at, crc.
+const TRIM_RECORD_LEN: u64 = 8 + 4 + 8 + 4;
+
+/// Size of one owners.tbl record: turn_id, context_id, crc.
+const OWNER_RECORD_LEN: u64 = 8 + 8 + 4;
+
+/// TurnRecord flag: the turn is a checkpoint whose payload summarizes the
+/// chain before it, so summary-mode reads need not walk further back.
+pub const TURN_FLAG_CHECKPOINT: u32 = 1 << 0;
+
+const TURNS_LOG: &str = "turns/turns.log";
+const TURNS_META: &str = "turns/turns.meta";
+const HEADS_TBL: &str = "turns/heads.tbl";
+const TRIMS_TBL: &str = "turns/trims.tbl";
+const OWNERS_TBL: &str = "turns/owners.tbl";
+
 #[derive(Debug, Clone)]
 pub struct TurnRecord {
     pub turn_id: u64,
@@ -24,6 +52,12 @@ pub struct TurnRecord {
     pub created_at_unix_ms: u64,
 }
 
+impl TurnRecord {
+    pub fn is_checkpoint(&self) -> bool {
+        self.flags & TURN_FLAG_CHECKPOINT != 0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TurnMeta {
     pub declared_type_id: String,
@@ -42,21 +76,45 @@ pub struct ContextHead {
     pub flags: u32,
 }
 
+impl ContextHead {
+    /// Turns on the chain ending at the head, inherited ones included.
+    pub fn turn_count(&self) -> u64 {
+        if self.head_turn_id == 0 {
+            0
+        } else {
+            self.head_depth as u64 + 1
+        }
+    }
+}
+
 pub struct TurnStore {
     turns_log_path: std::path::PathBuf,
     turns_idx_path: std::path::PathBuf,
     turns_meta_path: std::path::PathBuf,
     heads_tbl_path: std::path::PathBuf,
+    trims_tbl_path: std::path::PathBuf,
+    owners_tbl_path: std::path::PathBuf,
 
     turns_log: File,
     turns_idx: File,
     turns_meta: File,
     heads_tbl: File,
+    trims_tbl: File,
+    owners_tbl: File,
 
     turns: HashMap<u64, TurnRecord>,
     turn_index: HashMap<u64, u64>,
     turn_meta: HashMap<u64, TurnMeta>,
     heads: HashMap<u64, ContextHead>,
+    /// Per context, the depth below which retention trims hid its turns
+    /// (see `trim_before`).
+    trim_depths: HashMap<u64, u32>,
+    /// Turns trims hid from every context that read them; their payloads
+    /// are garbage collection candidates.
+    trimmed: HashSet<u64>,
+    /// The context each turn was appended to, from owners.tbl. Turns
+    /// appended before that table existed have no entry.
+    owners: HashMap<u64, u64>,
 
     next_turn_id: u64,
     next_context_id: u64,
@@ -69,45 +127,66 @@ impl TurnStore {
         let turns_idx_path = dir.join("turns.idx");
         let turns_meta_path = dir.join("turns.meta");
         let heads_tbl_path = dir.join("heads.tbl");
+        let trims_tbl_path = dir.join("trims.tbl");
+        let owners_tbl_path = dir.join("owners.tbl");
 
         let turns_log = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_log_path)?;
         let turns_idx = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_idx_path)?;
         let turns_meta = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&turns_meta_path)?;
         let heads_tbl = OpenOptions::new()
             .create(true)
-            .truncate(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&heads_tbl_path)?;
+        let trims_tbl = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&trims_tbl_path)?;
+        let owners_tbl = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&owners_tbl_path)?;
 
         let mut store = Self {
             turns_log_path,
             turns_idx_path,
             turns_meta_path,
             heads_tbl_path,
+            trims_tbl_path,
+            owners_tbl_path,
             turns_log,
             turns_idx,
             turns_meta,
             heads_tbl,
+            trims_tbl,
+            owners_tbl,
             turns: HashMap::new(),
             turn_index: HashMap::new(),
             turn_meta: HashMap::new(),
             heads: HashMap::new(),
+            trim_depths: HashMap::new(),
+            trimmed: HashSet::new(),
+            owners: HashMap::new(),
             next_turn_id: 1,
             next_context_id: 1,
         };
@@ -115,12 +194,26 @@ impl TurnStore {
         store.load_turns()?;
         store.load_meta()?;
         store.load_heads()?;
+        store.load_trims()?;
+        store.load_owners()?;
         store.rebuild_index()?;
         store.update_counters();
 
         Ok(store)
     }
 
+    /// Duplicated handles to the files appends write to, for fsyncing
+    /// without holding the store. trims.tbl is synced as it is written.
+    pub fn sync_handles(&self) -> Result<Vec<File>> {
+        Ok(vec![
+            self.turns_log.try_clone()?,
+            self.turns_idx.try_clone()?,
+            self.turns_meta.try_clone()?,
+            self.heads_tbl.try_clone()?,
+            self.owners_tbl.try_clone()?,
+        ])
+    }
+
     pub fn stats(&self) -> TurnStoreStats {
         TurnStoreStats {
             turns_total: self.turns.len(),
@@ -137,28 +230,24 @@ impl TurnStore {
         self.turns.clear();
         self.turn_index.clear();
 
+        let mut data = Vec::new();
         self.turns_log.seek(SeekFrom::Start(0))?;
-        let mut offset = 0u64;
-        loop {
-            let start = self.turns_log.stream_position()?;
-            let record = match read_turn_record(&mut self.turns_log) {
-                Ok(rec) => rec,
-                Err(StoreError::Corrupt(_)) => {
-                    // truncate partial/corrupt tail
-                    self.turns_log.set_len(start)?;
-                    break;
-                }
-                Err(StoreError::Io(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    // Truncate partial record to allow future appends to work correctly
-                    self.turns_log.set_len(start)?;
-                    break;
-                }
-                Err(e) => return Err(e),
-            };
+        self.turns_log.read_to_end(&mut data)?;
+        let valid_len = valid_prefix_len(&data, TURN_RECORD_LEN, turn_record_ok);
+        if valid_len < data.len() as u64 {
+            ensure_trailing_only(TURNS_LOG, data.len() as u64, valid_len, TURN_RECORD_LEN)?;
+            // Truncate partial/corrupt tail to allow future appends to work correctly
+            self.turns_log.set_len(valid_len)?;
+        }
 
-            self.turns.insert(record.turn_id, record.clone());
-            self.turn_index.insert(record.turn_id, offset);
-            offset = self.turns_log.stream_position()?;
+        for (i, chunk) in data[..valid_len as usize]
+            .chunks_exact(TURN_RECORD_LEN as usize)
+            .enumerate()
+        {
+            let record = read_turn_record(&mut std::io::Cursor::new(chunk))?;
+            self.turn_index
+                .insert(record.turn_id, i as u64 * TURN_RECORD_LEN);
+            self.turns.insert(record.turn_id, record);
         }
         Ok(())
     }
@@ -234,75 +323,148 @@ impl TurnStore {
 
     fn load_heads(&mut self) -> Result<()> {
         self.heads.clear();
+
+        let mut data = Vec::new();
         self.heads_tbl.seek(SeekFrom::Start(0))?;
-        loop {
-            let start = self.heads_tbl.stream_position()?;
-            let context_id = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(StoreError::Io(e)),
-            };
-            let head_turn_id = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
-                    break;
-                }
-            };
-            let head_depth = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
-                    break;
-                }
-            };
-            let flags = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
-                    break;
-                }
-            };
-            let created_at_unix_ms = match self.heads_tbl.read_u64::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
-                    break;
-                }
+        self.heads_tbl.read_to_end(&mut data)?;
+        let valid_len = valid_prefix_len(&data, HEAD_RECORD_LEN, |rec| decode_head(rec).is_some());
+        if valid_len < data.len() as u64 {
+            ensure_trailing_only(HEADS_TBL, data.len() as u64, valid_len, HEAD_RECORD_LEN)?;
+            self.heads_tbl.set_len(valid_len)?;
+        }
+
+        for chunk in data[..valid_len as usize].chunks_exact(HEAD_RECORD_LEN as usize) {
+            if let Some(head) = decode_head(chunk) {
+                self.heads.insert(head.context_id, head);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_owners(&mut self) -> Result<()> {
+        self.owners.clear();
+
+        let mut data = Vec::new();
+        self.owners_tbl.seek(SeekFrom::Start(0))?;
+        self.owners_tbl.read_to_end(&mut data)?;
+        let valid_len =
+            valid_prefix_len(&data, OWNER_RECORD_LEN, |rec| decode_owner(rec).is_some());
+        if valid_len < data.len() as u64 {
+            ensure_trailing_only(OWNERS_TBL, data.len() as u64, valid_len, OWNER_RECORD_LEN)?;
+            self.owners_tbl.set_len(valid_len)?;
+        }
+
+        for chunk in data[..valid_len as usize].chunks_exact(OWNER_RECORD_LEN as usize) {
+            if let Some((turn_id, context_id)) = decode_owner(chunk) {
+                self.owners.insert(turn_id, context_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_trims(&mut self) -> Result<()> {
+        self.trim_depths.clear();
+        self.trimmed.clear();
+
+        let mut data = Vec::new();
+        self.trims_tbl.seek(SeekFrom::Start(0))?;
+        self.trims_tbl.read_to_end(&mut data)?;
+        let valid_len = valid_prefix_len(&data, TRIM_RECORD_LEN, |rec| decode_trim(rec).is_some());
+        if valid_len < data.len() as u64 {
+            ensure_trailing_only(TRIMS_TBL, data.len() as u64, valid_len, TRIM_RECORD_LEN)?;
+            self.trims_tbl.set_len(valid_len)?;
+        }
+
+        for (context_id, min_depth) in data[..valid_len as usize]
+            .chunks_exact(TRIM_RECORD_LEN as usize)
+            .filter_map(decode_trim)
+        {
+            let depth = self.trim_depths.entry(context_id).or_default();
+            *depth = (*depth).max(min_depth);
+        }
+
+        // Turns hidden from a trimmed context that no context reads.
+        let readable = self.readable_turns();
+        let mut hidden = HashSet::new();
+        for (&context_id, &min_depth) in &self.trim_depths {
+            let Some(head) = self.heads.get(&context_id) else {
+                continue;
             };
-            let crc = match self.heads_tbl.read_u32::<LittleEndian>() {
-                Ok(v) => v,
-                Err(_) => {
-                    self.heads_tbl.set_len(start)?;
+            let mut current = head.head_turn_id;
+            while let Some(record) = self.turns.get(&current) {
+                if record.depth < min_depth && !hidden.insert(current) {
                     break;
                 }
-            };
-
-            let mut buf = Vec::with_capacity(8 + 8 + 4 + 4 + 8);
-            buf.write_u64::<LittleEndian>(context_id)?;
-            buf.write_u64::<LittleEndian>(head_turn_id)?;
-            buf.write_u32::<LittleEndian>(head_depth)?;
-            buf.write_u32::<LittleEndian>(flags)?;
-            buf.write_u64::<LittleEndian>(created_at_unix_ms)?;
-            let mut hasher = Hasher::new();
-            hasher.update(&buf);
-            let actual_crc = hasher.finalize();
-            if crc != actual_crc {
-                self.heads_tbl.set_len(start)?;
-                break;
+                current = record.parent_turn_id;
             }
-
-            self.heads.insert(
-                context_id,
-                ContextHead {
-                    context_id,
-                    head_turn_id,
-                    head_depth,
-                    created_at_unix_ms,
-                    flags,
-                },
-            );
         }
+        self.trimmed = hidden
+            .into_iter()
+            .filter(|id| !readable.contains(id))
+            .collect();
+        Ok(())
+    }
+
+    /// Truncate each turn file at its first bad record, as `open` does for a
+    /// torn final record, recording what was discarded. turns.idx is rebuilt
+    /// on open and is not touched.
+    pub fn repair(dir: &Path, report: &mut RepairReport) -> Result<()> {
+        let read = |name: &str| match std::fs::read(dir.join(name)) {
+            Ok(data) => Ok(data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        };
+
+        let log = read("turns.log")?;
+        let valid_len = valid_prefix_len(&log, TURN_RECORD_LEN, turn_record_ok);
+        truncate_tail(
+            &dir.join("turns.log"),
+            TURNS_LOG,
+            valid_len,
+            Some(TURN_RECORD_LEN),
+            report,
+        )?;
+
+        let meta = read("turns.meta")?;
+        // Meta records are variable-length and unchecksummed; only a torn tail is detectable.
+        truncate_tail(
+            &dir.join("turns.meta"),
+            TURNS_META,
+            meta_valid_len(&meta),
+            None,
+            report,
+        )?;
+
+        let heads = read("heads.tbl")?;
+        let valid_len = valid_prefix_len(&heads, HEAD_RECORD_LEN, |rec| decode_head(rec).is_some());
+        truncate_tail(
+            &dir.join("heads.tbl"),
+            HEADS_TBL,
+            valid_len,
+            Some(HEAD_RECORD_LEN),
+            report,
+        )?;
+
+        let trims = read("trims.tbl")?;
+        let valid_len = valid_prefix_len(&trims, TRIM_RECORD_LEN, |rec| decode_trim(rec).is_some());
+        truncate_tail(
+            &dir.join("trims.tbl"),
+            TRIMS_TBL,
+            valid_len,
+            Some(TRIM_RECORD_LEN),
+            report,
+        )?;
+
+        let owners = read("owners.tbl")?;
+        let valid_len =
+            valid_prefix_len(&owners, OWNER_RECORD_LEN, |rec| decode_owner(rec).is_some());
+        truncate_tail(
+            &dir.join("owners.tbl"),
+            OWNERS_TBL,
+            valid_len,
+            Some(OWNER_RECORD_LEN),
+            report,
+        )?;
         Ok(())
     }
 
@@ -341,6 +503,9 @@ impl TurnStore {
                 .turns
                 .get(&base_turn_id)
                 .ok_or_else(|| StoreError::NotFound("base turn".into()))?;
+            if self.trimmed.contains(&base_turn_id) {
+                return Err(StoreError::NotFound("base turn".into()));
+            }
             (turn.turn_id, turn.depth)
         };
 
@@ -371,6 +536,171 @@ impl TurnStore {
             .ok_or_else(|| StoreError::NotFound("context".into()))
     }
 
+    /// The context's head as of `timestamp_ms`: pointing at the latest turn on
+    /// its current chain created at or before then, or at no turn if there is
+    /// none. The chain includes turns a fork inherited from its base.
+    pub fn head_at(&self, context_id: u64, timestamp_ms: u64) -> Result<ContextHead> {
+        let head = self.get_head(context_id)?;
+        let mut turn_id = head.head_turn_id;
+        while turn_id != 0 {
+            let turn = self
+                .turns
+                .get(&turn_id)
+                .ok_or_else(|| StoreError::NotFound("turn".into()))?;
+            if !self.visible(context_id, turn) {
+                break;
+            }
+            if turn.created_at_unix_ms <= timestamp_ms {
+                return Ok(ContextHead {
+                    context_id,
+                    head_turn_id: turn.turn_id,
+                    head_depth: turn.depth,
+                    created_at_unix_ms: turn.created_at_unix_ms,
+                    flags: head.flags,
+                });
+            }
+            turn_id = self.live_parent(context_id, turn);
+        }
+        Ok(ContextHead {
+            context_id,
+            head_turn_id: 0,
+            head_depth: 0,
+            created_at_unix_ms: 0,
+            flags: head.flags,
+        })
+    }
+
+    /// Checks that an explicit `parent_turn_id` may be appended to in `context_id`.
+    ///
+    /// The parent must already exist (so it cannot point forward to, or be a
+    /// descendant of, the turn being created) and must belong to the context:
+    /// either lie on its current chain, or have been appended to it, such as
+    /// the tip of a branch the head has since moved off. Turns from unrelated
+    /// contexts are rejected so ancestry walks stay within one context's tree.
+    pub fn validate_parent(&self, context_id: u64, parent_turn_id: u64) -> Result<()> {
+        if parent_turn_id >= self.next_turn_id {
+            return Err(StoreError::InvalidParent(format!(
+                "turn {parent_turn_id} does not exist yet"
+            )));
+        }
+        let parent = self
+            .turns
+            .get(&parent_turn_id)
+            .ok_or_else(|| StoreError::NotFound("parent turn".into()))?;
+        let head = self
+            .heads
+            .get(&context_id)
+            .ok_or_else(|| StoreError::NotFound("context".into()))?;
+
+        if self.chain_contains(context_id, head.head_turn_id, parent) {
+            return Ok(());
+        }
+        if self.owners.get(&parent_turn_id) == Some(&context_id) && self.visible(context_id, parent)
+        {
+            return Ok(());
+        }
+
+        Err(StoreError::InvalidParent(format!(
+            "turn {parent_turn_id} does not belong to context {context_id}"
+        )))
+    }
+
+    /// Whether `turn_id` has been handed out by `append_turn`. Ids at or
+    /// above the next id to allocate cannot name a turn yet.
+    pub fn is_allocated(&self, turn_id: u64) -> bool {
+        turn_id != 0 && turn_id < self.next_turn_id
+    }
+
+    /// Whether `turn_id` is the context's head or one of its ancestors.
+    ///
+    /// Turns left behind when a context's head moves to another branch are
+    /// still retrievable but report false here.
+    pub fn is_on_active_chain(&self, context_id: u64, turn_id: u64) -> bool {
+        match (self.heads.get(&context_id), self.turns.get(&turn_id)) {
+            (Some(head), Some(turn)) => self.chain_contains(context_id, head.head_turn_id, turn),
+            _ => false,
+        }
+    }
+
+    /// Contexts whose active chain holds `turn_id`. Walks from each head
+    /// stop at the turn's depth and reuse the turns earlier walks visited,
+    /// so they cover only the chains below the turn once.
+    pub fn contexts_reaching(&self, turn_id: u64) -> Vec<u64> {
+        let Some(target) = self.turns.get(&turn_id) else {
+            return Vec::new();
+        };
+        let mut known: HashMap<u64, bool> = HashMap::new();
+        let mut contexts = Vec::new();
+        for head in self.heads.values() {
+            // Between a visible target and the head, every turn is deeper and
+            // so visible too, which is what lets walks share `known`.
+            if head.head_depth < target.depth || !self.visible(head.context_id, target) {
+                continue;
+            }
+            let mut walked = Vec::new();
+            let mut current = self.live_turn(head.context_id, head.head_turn_id);
+            let reaches = loop {
+                if current == turn_id {
+                    break true;
+                }
+                if let Some(known) = known.get(&current) {
+                    break *known;
+                }
+                match self.turns.get(&current) {
+                    Some(record) if record.depth > target.depth => {
+                        walked.push(current);
+                        current = self.live_parent(head.context_id, record);
+                    }
+                    _ => break false,
+                }
+            };
+            for id in walked {
+                known.insert(id, reaches);
+            }
+            if reaches {
+                contexts.push(head.context_id);
+            }
+        }
+        contexts
+    }
+
+    /// The turns on the context's active chain at depth `min_depth` or
+    /// deeper, for checking many turns against the chain with one walk.
+    pub fn active_chain_from(&self, context_id: u64, min_depth: u32) -> HashSet<u64> {
+        let mut chain = HashSet::new();
+        let Some(head) = self.heads.get(&context_id) else {
+            return chain;
+        };
+        let mut current = self.live_turn(context_id, head.head_turn_id);
+        while let Some(record) = self.turns.get(&current) {
+            if record.depth < min_depth {
+                break;
+            }
+            chain.insert(current);
+            current = self.live_parent(context_id, record);
+        }
+        chain
+    }
+
+    fn chain_contains(&self, context_id: u64, head_turn_id: u64, target: &TurnRecord) -> bool {
+        let mut current = self.live_turn(context_id, head_turn_id);
+        while current != 0 {
+            if current == target.turn_id {
+                return true;
+            }
+            let record = match self.turns.get(&current) {
+                Some(record) => record,
+                None => return false,
+            };
+            // Depth strictly decreases towards the root, so stop once we pass it.
+            if record.depth <= target.depth {
+                return false;
+            }
+            current = self.live_parent(context_id, record);
+        }
+        false
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn append_turn(
         &mut self,
@@ -382,12 +712,11 @@ impl TurnStore {
         declared_type_version: u32,
         compression: u32,
         uncompressed_len: u32,
+        flags: u32,
     ) -> Result<TurnRecord> {
         let (parent_id, depth) = if parent_turn_id != 0 {
-            let parent = self
-                .turns
-                .get(&parent_turn_id)
-                .ok_or_else(|| StoreError::NotFound("parent turn".into()))?;
+            self.validate_parent(context_id, parent_turn_id)?;
+            let parent = &self.turns[&parent_turn_id];
             (parent.turn_id, parent.depth + 1)
         } else {
             let head = self
@@ -415,7 +744,7 @@ impl TurnStore {
             codec: encoding,
             type_tag: 0,
             payload_hash,
-            flags: 0,
+            flags,
             created_at_unix_ms: Self::now_unix_ms(),
         };
 
@@ -452,8 +781,18 @@ impl TurnStore {
                 uncompressed_len,
             },
         );
+        let mut owner_bytes = Vec::with_capacity(OWNER_RECORD_LEN as usize);
+        owner_bytes.write_u64::<LittleEndian>(turn_id)?;
+        owner_bytes.write_u64::<LittleEndian>(context_id)?;
+        let crc = crc32fast::hash(&owner_bytes);
+        owner_bytes.write_u32::<LittleEndian>(crc)?;
+        self.owners_tbl.seek(SeekFrom::End(0))?;
+        self.owners_tbl.write_all(&owner_bytes)?;
+        self.owners_tbl.flush()?;
+
         self.turns.insert(turn_id, record.clone());
         self.turn_index.insert(turn_id, offset);
+        self.owners.insert(turn_id, context_id);
 
         // update head
         let head = ContextHead {
@@ -470,22 +809,39 @@ impl TurnStore {
     }
 
     fn write_head(&mut self, head: &ContextHead) -> Result<()> {
-        let mut buf = Vec::with_capacity(8 + 8 + 4 + 4 + 8 + 4);
-        buf.write_u64::<LittleEndian>(head.context_id)?;
-        buf.write_u64::<LittleEndian>(head.head_turn_id)?;
-        buf.write_u32::<LittleEndian>(head.head_depth)?;
-        buf.write_u32::<LittleEndian>(head.flags)?;
-        buf.write_u64::<LittleEndian>(head.created_at_unix_ms)?;
-        let mut hasher = Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
-        buf.write_u32::<LittleEndian>(crc)?;
+        let buf = encode_head(head)?;
         self.heads_tbl.seek(SeekFrom::End(0))?;
         self.heads_tbl.write_all(&buf)?;
         self.heads_tbl.flush()?;
         Ok(())
     }
 
+    /// Fraction of heads.tbl records superseded by a later write for the same context.
+    pub fn heads_dead_ratio(&self) -> f64 {
+        dead_ratio(
+            file_len(&self.heads_tbl_path),
+            HEAD_RECORD_LEN,
+            self.heads.len(),
+        )
+    }
+
+    /// Rewrite heads.tbl keeping only the latest record per context.
+    ///
+    /// The new table is written to a sibling file and renamed into place, so a
+    /// crash mid-compaction leaves the original intact. Returns bytes reclaimed.
+    pub fn compact_heads(&mut self) -> Result<u64> {
+        let before = file_len(&self.heads_tbl_path);
+        let mut heads: Vec<&ContextHead> = self.heads.values().collect();
+        heads.sort_by_key(|h| h.context_id);
+        let mut buf = Vec::with_capacity(heads.len() * HEAD_RECORD_LEN as usize);
+        for head in heads {
+            buf.extend_from_slice(&encode_head(head)?);
+        }
+
+        self.heads_tbl = replace_file(&self.heads_tbl_path, &buf)?;
+        Ok(before.saturating_sub(buf.len() as u64))
+    }
+
     pub fn get_turn(&self, turn_id: u64) -> Result<TurnRecord> {
         self.turns
             .get(&turn_id)
@@ -501,24 +857,59 @@ impl TurnStore {
     }
 
     pub fn get_last(&self, context_id: u64, limit: u32) -> Result<Vec<TurnRecord>> {
+        Ok(self.get_last_window(context_id, 0, limit, false)?.0)
+    }
+
+    /// Like `get_last`, but first skips `offset` turns back from the head.
+    ///
+    /// Also returns whether older turns exist before the returned window.
+    /// With `stop_at_checkpoint`, the walk ends at the most recent checkpoint
+    /// turn, which is returned as the oldest turn, and nothing before it is
+    /// reported as remaining.
+    pub fn get_last_window(
+        &self,
+        context_id: u64,
+        offset: u32,
+        limit: u32,
+        stop_at_checkpoint: bool,
+    ) -> Result<(Vec<TurnRecord>, bool)> {
         let head = self
             .heads
             .get(&context_id)
             .ok_or_else(|| StoreError::NotFound("context".into()))?;
 
+        let mut current = self.live_turn(context_id, head.head_turn_id);
+        for _ in 0..offset {
+            if current == 0 {
+                break;
+            }
+            let rec = self
+                .turns
+                .get(&current)
+                .ok_or_else(|| StoreError::NotFound("turn".into()))?;
+            current = if stop_at_checkpoint && rec.is_checkpoint() {
+                0
+            } else {
+                self.live_parent(context_id, rec)
+            };
+        }
+
         let mut results = Vec::new();
-        let mut current = head.head_turn_id;
         while current != 0 && results.len() < limit as usize {
             let rec = self
                 .turns
                 .get(&current)
                 .ok_or_else(|| StoreError::NotFound("turn".into()))?
                 .clone();
-            results.push(rec.clone());
-            current = rec.parent_turn_id;
+            current = if stop_at_checkpoint && rec.is_checkpoint() {
+                0
+            } else {
+                self.live_parent(context_id, &rec)
+            };
+            results.push(rec);
         }
         results.reverse();
-        Ok(results)
+        Ok((results, current != 0))
     }
 
     pub fn get_before(
@@ -540,7 +931,12 @@ impl TurnStore {
             .turns
             .get(&before_turn_id)
             .ok_or_else(|| StoreError::NotFound("before turn".into()))?;
-        let mut current = before.parent_turn_id;
+        // Nothing before a trimmed turn is readable from this context, even
+        // where its ancestors are still live for another one.
+        if !self.visible(context_id, before) {
+            return Ok(Vec::new());
+        }
+        let mut current = self.live_parent(context_id, before);
         let mut results = Vec::new();
         while current != 0 && results.len() < limit as usize {
             let rec = self
@@ -548,14 +944,15 @@ impl TurnStore {
                 .get(&current)
                 .ok_or_else(|| StoreError::NotFound("turn".into()))?
                 .clone();
-            results.push(rec.clone());
-            current = rec.parent_turn_id;
+            current = self.live_parent(context_id, &rec);
+            results.push(rec);
         }
         results.reverse();
         Ok(results)
     }
 
-    /// Get the first turn (depth=0) of a context, if it exists.
+    /// Get the first turn (depth=0) of a context, if it exists and has not
+    /// been trimmed.
     pub fn get_first_turn(&self, context_id: u64) -> Result<TurnRecord> {
         let head = self
             .heads
@@ -563,7 +960,7 @@ impl TurnStore {
             .ok_or_else(|| StoreError::NotFound("context".into()))?;
 
         // Walk back from head to find the turn with depth=0
-        let mut current = head.head_turn_id;
+        let mut current = self.live_turn(context_id, head.head_turn_id);
         while current != 0 {
             let rec = self
                 .turns
@@ -572,12 +969,410 @@ impl TurnStore {
             if rec.depth == 0 {
                 return Ok(rec.clone());
             }
-            current = rec.parent_turn_id;
+            current = self.live_parent(context_id, rec);
         }
 
         Err(StoreError::NotFound("first turn".into()))
     }
 
+    /// `record`'s parent, or 0 if a trim hid the parent from `context_id`,
+    /// which ends the chain for that context's reads.
+    pub fn live_parent(&self, context_id: u64, record: &TurnRecord) -> u64 {
+        self.live_turn(context_id, record.parent_turn_id)
+    }
+
+    /// `record`'s parent, or 0 if trims hid the parent from every context,
+    /// for walks that do not belong to one context.
+    pub fn retained_parent(&self, record: &TurnRecord) -> u64 {
+        if self.trimmed.contains(&record.parent_turn_id) {
+            0
+        } else {
+            record.parent_turn_id
+        }
+    }
+
+    /// `turn_id`, or 0 if a trim hid it from `context_id`, for starting a
+    /// chain walk.
+    fn live_turn(&self, context_id: u64, turn_id: u64) -> u64 {
+        match self.turns.get(&turn_id) {
+            Some(record) if self.visible(context_id, record) => turn_id,
+            _ => 0,
+        }
+    }
+
+    /// Whether `turn` is readable from `context_id`: no trim has hidden it
+    /// from every context, and it is not below the context's own trim depth.
+    fn visible(&self, context_id: u64, turn: &TurnRecord) -> bool {
+        !self.trimmed.contains(&turn.turn_id)
+            && turn.depth >= self.trim_depths.get(&context_id).copied().unwrap_or(0)
+    }
+
+    /// Whether retention trims hid `turn_id` from every context reading it.
+    pub fn is_trimmed(&self, turn_id: u64) -> bool {
+        self.trimmed.contains(&turn_id)
+    }
+
+    /// Payload hashes referenced only by trimmed turns: blobs nothing
+    /// readable points at any more, for garbage collection.
+    pub fn trimmed_payloads(&self) -> HashSet<[u8; 32]> {
+        let mut payloads: HashSet<[u8; 32]> = self
+            .trimmed
+            .iter()
+            .filter_map(|id| self.turns.get(id))
+            .map(|rec| rec.payload_hash)
+            .collect();
+        for (turn_id, rec) in &self.turns {
+            if !self.trimmed.contains(turn_id) {
+                payloads.remove(&rec.payload_hash);
+            }
+        }
+        payloads
+    }
+
+    /// Trim the context's chain before `cutoff_ms`: the newest turn created
+    /// earlier than the cutoff and all of its ancestors stop being readable
+    /// from this context. The head itself is never trimmed. Returns the
+    /// turns this trim hid.
+    ///
+    /// The trim is recorded in trims.tbl as the context's new minimum depth;
+    /// turn records stay in turns.log so the DAG and its checks are
+    /// unchanged. Other contexts are unaffected: a fork keeps reading the
+    /// ancestors it shares with this context. Turns no context reads any
+    /// more are added to the trimmed set, which takes a walk of every
+    /// context's readable chain.
+    pub fn trim_before(&mut self, context_id: u64, cutoff_ms: u64) -> Result<Vec<TurnRecord>> {
+        let head = self.get_head(context_id)?;
+        let mut current = match self.turns.get(&head.head_turn_id) {
+            Some(record) => self.live_parent(context_id, record),
+            None => 0,
+        };
+        while current != 0 {
+            let record = self
+                .turns
+                .get(&current)
+                .ok_or_else(|| StoreError::NotFound("turn".into()))?;
+            if record.created_at_unix_ms < cutoff_ms {
+                break;
+            }
+            current = self.live_parent(context_id, record);
+        }
+        let Some(boundary) = self.turns.get(&current) else {
+            return Ok(Vec::new());
+        };
+        let min_depth = boundary.depth + 1;
+
+        let mut buf = Vec::with_capacity(TRIM_RECORD_LEN as usize);
+        buf.write_u64::<LittleEndian>(context_id)?;
+        buf.write_u32::<LittleEndian>(min_depth)?;
+        buf.write_u64::<LittleEndian>(Self::now_unix_ms())?;
+        let crc = crc32fast::hash(&buf);
+        buf.write_u32::<LittleEndian>(crc)?;
+        self.trims_tbl.seek(SeekFrom::End(0))?;
+        self.trims_tbl.write_all(&buf)?;
+        self.trims_tbl.sync_data()?;
+
+        // The turns this trim hides: from the boundary back to the previous
+        // trim depth, or to the first turn no context reads.
+        let mut hidden = Vec::new();
+        while current != 0 {
+            let Some(record) = self.turns.get(&current) else {
+                break;
+            };
+            if !self.visible(context_id, record) {
+                break;
+            }
+            hidden.push(record.clone());
+            current = record.parent_turn_id;
+        }
+        self.trim_depths.insert(context_id, min_depth);
+
+        let readable = self.readable_turns();
+        for record in &hidden {
+            if !readable.contains(&record.turn_id) {
+                self.trimmed.insert(record.turn_id);
+            }
+        }
+        Ok(hidden)
+    }
+
+    /// Turns on any context's readable chain.
+    fn readable_turns(&self) -> HashSet<u64> {
+        let mut readable = HashSet::new();
+        for head in self.heads.values() {
+            let mut current = self.live_turn(head.context_id, head.head_turn_id);
+            while current != 0 && readable.insert(current) {
+                current = self
+                    .turns
+                    .get(&current)
+                    .map_or(0, |rec| self.live_parent(head.context_id, rec));
+            }
+        }
+        readable
+    }
+
+    /// Re-read turns.log, turns.idx, turns.meta and heads.tbl from disk and
+    /// record any inconsistencies in `report`. Returns the ids of turns whose
+    /// records verified, for cross-checks against other files.
+    pub fn check(&self, report: &mut CheckReport) -> Result<HashSet<u64>> {
+        const LOG: &str = TURNS_LOG;
+        const IDX: &str = "turns/turns.idx";
+        const META: &str = TURNS_META;
+        const HEADS: &str = HEADS_TBL;
+
+        // turns.log: fixed-size records, so a bad checksum does not stop the scan.
+        let log = std::fs::read(&self.turns_log_path)?;
+        let mut turns: HashMap<u64, (u64, TurnRecord)> = HashMap::new();
+        let mut offset = 0u64;
+        while offset < log.len() as u64 {
+            let remaining = log.len() as u64 - offset;
+            if remaining < TURN_RECORD_LEN {
+                report.issue(LOG, Some(offset), format!("{remaining} trailing bytes"));
+                break;
+            }
+            let mut cursor = std::io::Cursor::new(&log[offset as usize..]);
+            match read_turn_record(&mut cursor) {
+                Ok(record) => match turns.entry(record.turn_id) {
+                    std::collections::hash_map::Entry::Occupied(_) => report.issue(
+                        LOG,
+                        Some(offset),
+                        format!("duplicate turn {}", record.turn_id),
+                    ),
+                    std::collections::hash_map::Entry::Vacant(slot) => {
+                        slot.insert((offset, record));
+                    }
+                },
+                Err(err) => report.issue(LOG, Some(offset), err.to_string()),
+            }
+            offset += TURN_RECORD_LEN;
+        }
+        for (offset, record) in turns.values() {
+            if record.parent_turn_id == 0 {
+                continue;
+            }
+            match turns.get(&record.parent_turn_id) {
+                None => report.issue(
+                    LOG,
+                    Some(*offset),
+                    format!(
+                        "turn {} has missing parent {}",
+                        record.turn_id, record.parent_turn_id
+                    ),
+                ),
+                Some((_, parent)) if parent.depth + 1 != record.depth => report.issue(
+                    LOG,
+                    Some(*offset),
+                    format!(
+                        "turn {} depth {} does not follow parent depth {}",
+                        record.turn_id, record.depth, parent.depth
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+        report.turns_checked += turns.len();
+
+        // turns.idx: every entry must point at the record for its turn.
+        let idx = std::fs::read(&self.turns_idx_path)?;
+        if !(idx.len() as u64).is_multiple_of(TURN_INDEX_RECORD_LEN) {
+            report.issue(
+                IDX,
+                None,
+                format!("length {} is not a whole number of entries", idx.len()),
+            );
+        }
+        for (i, entry) in idx.chunks_exact(TURN_INDEX_RECORD_LEN as usize).enumerate() {
+            let entry_offset = i as u64 * TURN_INDEX_RECORD_LEN;
+            let turn_id = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            let log_offset = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+            if log_offset + TURN_RECORD_LEN > log.len() as u64 {
+                report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!(
+                        "turn {turn_id} offset {log_offset} is past the end of turns.log ({} bytes)",
+                        log.len()
+                    ),
+                );
+                continue;
+            }
+            match turns.get(&turn_id) {
+                Some((actual, _)) if *actual == log_offset => {}
+                Some((actual, _)) => report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!("turn {turn_id} indexed at {log_offset} but stored at {actual}"),
+                ),
+                None => report.issue(
+                    IDX,
+                    Some(entry_offset),
+                    format!("turn {turn_id} is not in turns.log"),
+                ),
+            }
+        }
+
+        // turns.meta: variable-length records, so stop at the first torn one.
+        let meta = std::fs::read(&self.turns_meta_path)?;
+        let mut cursor = std::io::Cursor::new(&meta[..]);
+        let mut with_meta = HashSet::new();
+        while (cursor.position() as usize) < meta.len() {
+            let start = cursor.position();
+            let parsed = (|| -> std::io::Result<u64> {
+                let turn_id = cursor.read_u64::<LittleEndian>()?;
+                let len = cursor.read_u32::<LittleEndian>()? as u64;
+                // type id, then version, encoding, compression, uncompressed_len
+                let end = cursor.position() + len + 16;
+                if end > meta.len() as u64 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                cursor.set_position(end);
+                Ok(turn_id)
+            })();
+            match parsed {
+                Ok(turn_id) => {
+                    if !turns.contains_key(&turn_id) {
+                        report.issue(
+                            META,
+                            Some(start),
+                            format!("metadata for unknown turn {turn_id}"),
+                        );
+                    }
+                    with_meta.insert(turn_id);
+                }
+                Err(_) => {
+                    report.issue(
+                        META,
+                        Some(start),
+                        format!("{} trailing bytes", meta.len() as u64 - start),
+                    );
+                    break;
+                }
+            }
+        }
+        for (offset, record) in turns.values() {
+            if !with_meta.contains(&record.turn_id) {
+                report.issue(
+                    LOG,
+                    Some(*offset),
+                    format!("turn {} has no entry in turns.meta", record.turn_id),
+                );
+            }
+        }
+
+        // heads.tbl: last write wins; only the live head of each context must resolve.
+        let heads = std::fs::read(&self.heads_tbl_path)?;
+        let mut live: HashMap<u64, (u64, u64, u32)> = HashMap::new();
+        let mut offset = 0u64;
+        while offset < heads.len() as u64 {
+            let remaining = heads.len() as u64 - offset;
+            if remaining < HEAD_RECORD_LEN {
+                report.issue(HEADS, Some(offset), format!("{remaining} trailing bytes"));
+                break;
+            }
+            let record = &heads[offset as usize..(offset + HEAD_RECORD_LEN) as usize];
+            match decode_head(record) {
+                Some(head) => {
+                    live.insert(
+                        head.context_id,
+                        (offset, head.head_turn_id, head.head_depth),
+                    );
+                }
+                None => report.issue(HEADS, Some(offset), "head crc mismatch"),
+            }
+            offset += HEAD_RECORD_LEN;
+        }
+        for (context_id, (offset, head_turn_id, head_depth)) in &live {
+            if *head_turn_id == 0 {
+                continue;
+            }
+            match turns.get(head_turn_id) {
+                None => report.issue(
+                    HEADS,
+                    Some(*offset),
+                    format!("context {context_id} head points at missing turn {head_turn_id}"),
+                ),
+                Some((_, turn)) if turn.depth != *head_depth => report.issue(
+                    HEADS,
+                    Some(*offset),
+                    format!(
+                        "context {context_id} head depth {head_depth} does not match turn {head_turn_id} depth {}",
+                        turn.depth
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+        report.heads_checked += live.len();
+
+        // trims.tbl: every trim must name a known context.
+        let trims = std::fs::read(&self.trims_tbl_path)?;
+        let mut offset = 0u64;
+        while offset < trims.len() as u64 {
+            let remaining = trims.len() as u64 - offset;
+            if remaining < TRIM_RECORD_LEN {
+                report.issue(
+                    TRIMS_TBL,
+                    Some(offset),
+                    format!("{remaining} trailing bytes"),
+                );
+                break;
+            }
+            let record = &trims[offset as usize..(offset + TRIM_RECORD_LEN) as usize];
+            match decode_trim(record) {
+                Some((context_id, _)) if !live.contains_key(&context_id) => report.issue(
+                    TRIMS_TBL,
+                    Some(offset),
+                    format!("trim of unknown context {context_id}"),
+                ),
+                Some(_) => {}
+                None => report.issue(TRIMS_TBL, Some(offset), "trim crc mismatch"),
+            }
+            offset += TRIM_RECORD_LEN;
+        }
+
+        // owners.tbl: every record must name a known turn.
+        let owners = std::fs::read(&self.owners_tbl_path)?;
+        let mut offset = 0u64;
+        while offset < owners.len() as u64 {
+            let remaining = owners.len() as u64 - offset;
+            if remaining < OWNER_RECORD_LEN {
+                report.issue(
+                    OWNERS_TBL,
+                    Some(offset),
+                    format!("{remaining} trailing bytes"),
+                );
+                break;
+            }
+            let record = &owners[offset as usize..(offset + OWNER_RECORD_LEN) as usize];
+            match decode_owner(record) {
+                Some((turn_id, _)) if !turns.contains_key(&turn_id) => report.issue(
+                    OWNERS_TBL,
+                    Some(offset),
+                    format!("owner of unknown turn {turn_id}"),
+                ),
+                Some(_) => {}
+                None => report.issue(OWNERS_TBL, Some(offset), "owner crc mismatch"),
+            }
+            offset += OWNER_RECORD_LEN;
+        }
+
+        Ok(turns.into_keys().collect())
+    }
+
+    /// Up to `limit` heads with context ids above `after`, in id order, and
+    /// whether more follow.
+    pub fn list_contexts_after(&self, after: u64, limit: u32) -> (Vec<ContextHead>, bool) {
+        let mut contexts: Vec<ContextHead> = self
+            .heads
+            .values()
+            .filter(|head| head.context_id > after)
+            .cloned()
+            .collect();
+        contexts.sort_by_key(|head| head.context_id);
+        let has_more = contexts.len() > limit as usize;
+        contexts.truncate(limit as usize);
+        (contexts, has_more)
+    }
+
     pub fn list_recent_contexts(&self, limit: u32) -> Vec<ContextHead> {
         let mut contexts: Vec<ContextHead> = self.heads.values().cloned().collect();
         // Sort by created_at descending (most recent first)
@@ -619,7 +1414,7 @@ fn encode_turn_record(record: &TurnRecord) -> Result<Vec<u8>> {
     Ok(buf)
 }
 
-fn read_turn_record(reader: &mut File) -> Result<TurnRecord> {
+fn read_turn_record<R: Read>(reader: &mut R) -> Result<TurnRecord> {
     let turn_id = reader.read_u64::<LittleEndian>()?;
     let parent_turn_id = reader.read_u64::<LittleEndian>()?;
     let depth = reader.read_u32::<LittleEndian>()?;
@@ -659,3 +1454,107 @@ fn read_turn_record(reader: &mut File) -> Result<TurnRecord> {
         created_at_unix_ms,
     })
 }
+
+fn turn_record_ok(record: &[u8]) -> bool {
+    read_turn_record(&mut std::io::Cursor::new(record)).is_ok()
+}
+
+/// Decode one heads.tbl record, or None if its crc does not match.
+fn decode_head(record: &[u8]) -> Option<ContextHead> {
+    let body_len = HEAD_RECORD_LEN as usize - 4;
+    let crc = u32::from_le_bytes(record[body_len..].try_into().ok()?);
+    if crc32fast::hash(&record[..body_len]) != crc {
+        return None;
+    }
+    let mut cursor = std::io::Cursor::new(record);
+    Some(ContextHead {
+        context_id: cursor.read_u64::<LittleEndian>().ok()?,
+        head_turn_id: cursor.read_u64::<LittleEndian>().ok()?,
+        head_depth: cursor.read_u32::<LittleEndian>().ok()?,
+        flags: cursor.read_u32::<LittleEndian>().ok()?,
+        created_at_unix_ms: cursor.read_u64::<LittleEndian>().ok()?,
+    })
+}
+
+/// Decode one trims.tbl record to its context id and minimum depth, or None
+/// if its crc does not match.
+fn decode_trim(record: &[u8]) -> Option<(u64, u32)> {
+    let body_len = TRIM_RECORD_LEN as usize - 4;
+    let crc = u32::from_le_bytes(record.get(body_len..)?.try_into().ok()?);
+    if crc32fast::hash(&record[..body_len]) != crc {
+        return None;
+    }
+    Some((
+        u64::from_le_bytes(record[..8].try_into().ok()?),
+        u32::from_le_bytes(record[8..12].try_into().ok()?),
+    ))
+}
+
+/// Decode one owners.tbl record to its turn and context ids, or None if its
+/// crc does not match.
+fn decode_owner(record: &[u8]) -> Option<(u64, u64)> {
+    let body_len = OWNER_RECORD_LEN as usize - 4;
+    let crc = u32::from_le_bytes(record.get(body_len..)?.try_into().ok()?);
+    if crc32fast::hash(&record[..body_len]) != crc {
+        return None;
+    }
+    Some((
+        u64::from_le_bytes(record[..8].try_into().ok()?),
+        u64::from_le_bytes(record[8..16].try_into().ok()?),
+    ))
+}
+
+/// Byte length of the whole records at the start of turns.meta.
+fn meta_valid_len(data: &[u8]) -> u64 {
+    let mut offset = 0usize;
+    // turn_id, type_id_len, type_id, then version, encoding, compression, uncompressed_len
+    while let Some(len_bytes) = data.get(offset + 8..offset + 12) {
+        let type_id_len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+        let end = offset + 12 + type_id_len + 16;
+        if end > data.len() {
+            break;
+        }
+        offset = end;
+    }
+    offset as u64
+}
+
+fn encode_head(head: &ContextHead) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(HEAD_RECORD_LEN as usize);
+    buf.write_u64::<LittleEndian>(head.context_id)?;
+    buf.write_u64::<LittleEndian>(head.head_turn_id)?;
+    buf.write_u32::<LittleEndian>(head.head_depth)?;
+    buf.write_u32::<LittleEndian>(head.flags)?;
+    buf.write_u64::<LittleEndian>(head.created_at_unix_ms)?;
+    let mut hasher = Hasher::new();
+    hasher.update(&buf);
+    let crc = hasher.finalize();
+    buf.write_u32::<LittleEndian>(crc)?;
+    Ok(buf)
+}
+
+/// Fraction of fixed-size records in a last-write-wins file that are no longer live.
+pub(crate) fn dead_ratio(file_bytes: u64, record_len: u64, live: usize) -> f64 {
+    let total = file_bytes / record_len;
+    if total == 0 {
+        return 0.0;
+    }
+    total.saturating_sub(live as u64) as f64 / total as f64
+}
+
+/// Atomically replace `path` with `contents` and return a read/write handle to it.
+pub(crate) fn replace_file(path: &Path, contents: &[u8]) -> Result<File> {
+    let tmp_path = path.with_extension("compact");
+    {
+        let mut tmp = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+        tmp.write_all(contents)?;
+        tmp.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    Ok(file)
+}
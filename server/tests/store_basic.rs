@@ -2,7 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use blake3::Hasher;
+use cxdb_server::error::StoreError;
+use cxdb_server::fs_store::{FileKind, SnapshotMeta};
+use cxdb_server::quota::ContextQuota;
 use cxdb_server::store::Store;
+use cxdb_server::turn_store::TURN_FLAG_CHECKPOINT;
 use tempfile::tempdir;
 
 #[test]
@@ -59,3 +63,1493 @@ fn append_and_fork() {
     assert_eq!(last.len(), 2);
     assert_eq!(last[0].record.turn_id, first.turn_id);
 }
+
+fn item_payload(item_id: &str) -> Vec<u8> {
+    let value = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from("user_input")),
+        (rmpv::Value::from(4), rmpv::Value::from(item_id)),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode payload");
+    buf
+}
+
+fn append_payload(store: &mut Store, context_id: u64, payload: &[u8]) -> u64 {
+    try_append_payload(store, context_id, payload).expect("append")
+}
+
+fn try_append_payload(
+    store: &mut Store,
+    context_id: u64,
+    payload: &[u8],
+) -> Result<u64, StoreError> {
+    let hash = blake3::hash(payload);
+    let (record, _metadata) = store.append_turn(
+        context_id,
+        0,
+        "cxdb.ConversationItem".to_string(),
+        3,
+        1,
+        0,
+        payload.len() as u32,
+        *hash.as_bytes(),
+        payload,
+    )?;
+    Ok(record.turn_id)
+}
+
+#[test]
+fn list_contexts_after_pages_in_id_order() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ids: Vec<u64> = (0..5)
+        .map(|_| store.create_context(0).expect("create context").context_id)
+        .collect();
+    append_payload(&mut store, ids[1], &item_payload("a"));
+    append_payload(&mut store, ids[1], &item_payload("b"));
+
+    let (page, has_more) = store.list_contexts_after(0, 2);
+    assert_eq!(
+        page.iter().map(|h| h.context_id).collect::<Vec<_>>(),
+        ids[..2]
+    );
+    assert!(has_more);
+    assert_eq!(page[0].turn_count(), 0);
+    assert_eq!(page[1].turn_count(), 2);
+
+    let (page, has_more) = store.list_contexts_after(ids[1], 10);
+    assert_eq!(
+        page.iter().map(|h| h.context_id).collect::<Vec<_>>(),
+        ids[2..]
+    );
+    assert!(!has_more);
+}
+
+#[test]
+fn find_duplicate_item_searches_target_chain() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let first = append_payload(&mut store, ctx.context_id, &item_payload("item-1"));
+    let second = append_payload(&mut store, ctx.context_id, &item_payload("item-2"));
+
+    let dup = store
+        .find_duplicate_item(ctx.context_id, 0, 0, &item_payload("item-1"))
+        .expect("find duplicate");
+    assert_eq!(dup.map(|r| r.turn_id), Some(first));
+
+    let fresh = store
+        .find_duplicate_item(ctx.context_id, 0, 0, &item_payload("item-3"))
+        .expect("find duplicate");
+    assert!(fresh.is_none());
+
+    // A fork from the first turn does not see item-2 on its chain.
+    let fork = store.fork_context(first).expect("fork");
+    let on_fork = store
+        .find_duplicate_item(fork.context_id, 0, 0, &item_payload("item-2"))
+        .expect("find duplicate");
+    assert!(on_fork.is_none());
+    let on_main = store
+        .find_duplicate_item(ctx.context_id, 0, 0, &item_payload("item-2"))
+        .expect("find duplicate");
+    assert_eq!(on_main.map(|r| r.turn_id), Some(second));
+}
+
+#[test]
+fn find_duplicate_item_looks_back_a_bounded_window() {
+    use cxdb_server::store::ITEM_DEDUP_WINDOW;
+
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+    let old = append_payload(&mut store, ctx, &item_payload("old"));
+    let recent = append_payload(&mut store, ctx, &item_payload("recent"));
+    for i in 0..ITEM_DEDUP_WINDOW - 1 {
+        append_payload(&mut store, ctx, &item_payload(&format!("filler-{i}")));
+    }
+
+    // `recent` is the oldest turn still in the window; `old` is just past it.
+    let found = store
+        .find_duplicate_item(ctx, 0, 0, &item_payload("recent"))
+        .expect("find duplicate");
+    assert_eq!(found.map(|r| r.turn_id), Some(recent));
+    let past = store
+        .find_duplicate_item(ctx, 0, 0, &item_payload("old"))
+        .expect("find duplicate");
+    assert!(past.is_none(), "turn {old} is outside the window");
+}
+
+#[test]
+fn tree_entries_record_hash_algorithm() {
+    use cxdb_server::fs_store::{load_tree_entries, HashAlgorithm};
+
+    let dir = tempdir().expect("tempdir");
+    let mut store =
+        Store::open_with_hash_algorithm(dir.path(), HashAlgorithm::Sha256).expect("open store");
+    assert_eq!(store.hash_algorithm, HashAlgorithm::Sha256);
+
+    let content_hash = HashAlgorithm::Sha256.hash(b"hello");
+    assert_eq!(
+        hex::encode(content_hash),
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    );
+
+    let entry = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from("hello.txt")),
+        (rmpv::Value::from(2), rmpv::Value::from(0)),
+        (rmpv::Value::from(3), rmpv::Value::from(0o644)),
+        (rmpv::Value::from(4), rmpv::Value::from(5)),
+        (
+            rmpv::Value::from(5),
+            rmpv::Value::Binary(content_hash.to_vec()),
+        ),
+        (
+            rmpv::Value::from(6),
+            rmpv::Value::from(HashAlgorithm::Sha256.id()),
+        ),
+    ]);
+    let mut tree = Vec::new();
+    rmpv::encode::write_value(&mut tree, &rmpv::Value::Array(vec![entry])).unwrap();
+    let tree_hash = HashAlgorithm::Sha256.hash(&tree);
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+
+    let entries = load_tree_entries(&mut store.blob_store, &tree_hash.into()).expect("load tree");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].hash_alg, HashAlgorithm::Sha256.id());
+    assert_eq!(
+        HashAlgorithm::from_id(entries[0].hash_alg),
+        Some(HashAlgorithm::Sha256)
+    );
+    assert_eq!(
+        HashAlgorithm::from_name("SHA256"),
+        Some(HashAlgorithm::Sha256)
+    );
+}
+
+#[test]
+fn append_rejects_parent_outside_context() {
+    use cxdb_server::error::StoreError;
+
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let payload = item_payload("item");
+    let hash = *blake3::hash(&payload).as_bytes();
+    let append = |store: &mut Store, context_id: u64, parent_turn_id: u64| {
+        store
+            .append_turn(
+                context_id,
+                parent_turn_id,
+                "cxdb.ConversationItem".to_string(),
+                3,
+                1,
+                0,
+                payload.len() as u32,
+                hash,
+                &payload,
+            )
+            .map(|(record, _)| record.turn_id)
+    };
+
+    let main = store.create_context(0).expect("create context");
+    let first = append(&mut store, main.context_id, 0).expect("append first");
+    let second = append(&mut store, main.context_id, 0).expect("append second");
+    let other = store.create_context(0).expect("create other");
+    let foreign = append(&mut store, other.context_id, 0).expect("append foreign");
+
+    // Branching from an ancestor of the head is allowed.
+    let branch = append(&mut store, main.context_id, first).expect("branch from ancestor");
+
+    // A turn from an unrelated context is rejected.
+    let err = append(&mut store, main.context_id, foreign).unwrap_err();
+    assert!(matches!(err, StoreError::InvalidParent(_)), "{err:?}");
+
+    // The head moved to the branch, but the old tip still belongs to the
+    // context and can be built on.
+    append(&mut store, main.context_id, second).expect("append on earlier branch tip");
+
+    // Another context cannot build on them.
+    let err = append(&mut store, other.context_id, second).unwrap_err();
+    assert!(matches!(err, StoreError::InvalidParent(_)), "{err:?}");
+
+    // Forward pointers to turns that do not exist yet are rejected.
+    let err = append(&mut store, main.context_id, branch + 100).unwrap_err();
+    assert!(matches!(err, StoreError::InvalidParent(_)), "{err:?}");
+
+    // Forks may append onto their base turn.
+    let fork = store.fork_context(first).expect("fork");
+    append(&mut store, fork.context_id, first).expect("append on fork base");
+
+    // Which context a turn belongs to survives a reopen.
+    drop(store);
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    append(&mut store, main.context_id, branch).expect("append on branch after reopen");
+    let err = append(&mut store, other.context_id, branch).unwrap_err();
+    assert!(matches!(err, StoreError::InvalidParent(_)), "{err:?}");
+}
+
+#[test]
+fn compact_rewrites_superseded_head_records() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    for i in 0..4 {
+        append_payload(
+            &mut store,
+            ctx.context_id,
+            &item_payload(&format!("item-{i}")),
+        );
+    }
+    let head = store.turn_store.get_head(ctx.context_id).expect("head");
+    assert!(store.turn_store.heads_dead_ratio() > 0.5);
+
+    // Thresholds that are not met leave files alone.
+    let skipped = store.compact(0.5, u64::MAX).expect("compact");
+    assert_eq!(skipped.bytes_reclaimed(), 0);
+    assert!(!skipped.heads_compacted);
+
+    let report = store.compact(0.5, 0).expect("compact");
+    assert!(report.heads_compacted);
+    assert!(report.bytes_reclaimed() > 0);
+    assert_eq!(store.turn_store.heads_dead_ratio(), 0.0);
+    assert_eq!(
+        store
+            .turn_store
+            .get_head(ctx.context_id)
+            .expect("head")
+            .head_turn_id,
+        head.head_turn_id
+    );
+
+    // Appends keep working against the rewritten table.
+    let next = append_payload(&mut store, ctx.context_id, &item_payload("after"));
+    assert_eq!(
+        store
+            .turn_store
+            .get_head(ctx.context_id)
+            .expect("head")
+            .head_turn_id,
+        next
+    );
+}
+
+#[test]
+fn abandoned_branch_is_off_active_chain() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let first = append_payload(&mut store, ctx.context_id, &item_payload("a"));
+    let second = append_payload(&mut store, ctx.context_id, &item_payload("b"));
+    assert!(store.turn_store.is_on_active_chain(ctx.context_id, second));
+
+    // Branch from the first turn; the old tip is left behind.
+    let payload = item_payload("c");
+    let (branch, _) = store
+        .append_turn(
+            ctx.context_id,
+            first,
+            "cxdb.ConversationItem".to_string(),
+            3,
+            1,
+            0,
+            payload.len() as u32,
+            *blake3::hash(&payload).as_bytes(),
+            &payload,
+        )
+        .expect("branch");
+
+    assert!(store.turn_store.is_on_active_chain(ctx.context_id, first));
+    assert!(store
+        .turn_store
+        .is_on_active_chain(ctx.context_id, branch.turn_id));
+    assert!(!store.turn_store.is_on_active_chain(ctx.context_id, second));
+
+    let chain = store.turn_store.active_chain_from(ctx.context_id, 0);
+    assert_eq!(chain, [first, branch.turn_id].into_iter().collect());
+    let chain = store.turn_store.active_chain_from(ctx.context_id, 1);
+    assert_eq!(chain, [branch.turn_id].into_iter().collect());
+}
+
+#[test]
+fn get_last_window_pages_backward() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let ids: Vec<u64> = (0..5)
+        .map(|i| append_payload(&mut store, ctx.context_id, &item_payload(&format!("t{i}"))))
+        .collect();
+
+    let window_ids = |turns: &[cxdb_server::store::TurnWithMeta]| -> Vec<u64> {
+        turns.iter().map(|t| t.record.turn_id).collect()
+    };
+
+    let (page, has_more) = store
+        .get_last_window(ctx.context_id, 0, 2, false)
+        .expect("first page");
+    assert_eq!(window_ids(&page), ids[3..5].to_vec());
+    assert!(has_more);
+
+    let (page, has_more) = store
+        .get_last_window(ctx.context_id, 2, 2, false)
+        .expect("second page");
+    assert_eq!(window_ids(&page), ids[1..3].to_vec());
+    assert!(has_more);
+
+    let (page, has_more) = store
+        .get_last_window(ctx.context_id, 4, 2, false)
+        .expect("last page");
+    assert_eq!(window_ids(&page), ids[0..1].to_vec());
+    assert!(!has_more);
+
+    let (page, has_more) = store
+        .get_last_window(ctx.context_id, 10, 2, false)
+        .expect("past the root");
+    assert!(page.is_empty());
+    assert!(!has_more);
+}
+
+#[test]
+fn require_turn_checks_the_context_chain() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let first = append_payload(&mut store, ctx.context_id, &item_payload("a"));
+    store
+        .require_turn(ctx.context_id, first)
+        .expect("appended turn is visible");
+
+    let err = store.require_turn(ctx.context_id, first + 1).unwrap_err();
+    assert!(matches!(err, StoreError::NotFound(_)), "{err}");
+
+    let other = store.create_context(0).expect("create context");
+    let foreign = append_payload(&mut store, other.context_id, &item_payload("x"));
+    let err = store.require_turn(ctx.context_id, foreign).unwrap_err();
+    assert!(matches!(err, StoreError::NotFound(_)), "{err}");
+
+    let second = append_payload(&mut store, ctx.context_id, &item_payload("b"));
+    store
+        .require_turn(ctx.context_id, second)
+        .expect("now visible");
+    store
+        .require_turn(ctx.context_id, first)
+        .expect("ancestors stay visible");
+}
+
+#[test]
+fn get_last_window_filters_item_types() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let typed = |item_type: &str, id: &str| {
+        let value = rmpv::Value::Map(vec![
+            (rmpv::Value::from(1), rmpv::Value::from(item_type)),
+            (rmpv::Value::from(4), rmpv::Value::from(id)),
+        ]);
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &value).expect("encode payload");
+        buf
+    };
+    let ids: Vec<u64> = [
+        ("user_input", "u1"),
+        ("tool_call", "c1"),
+        ("tool_result", "r1"),
+        ("assistant_turn", "a1"),
+        ("user_input", "u2"),
+        ("tool_call", "c2"),
+    ]
+    .iter()
+    .map(|(item_type, id)| append_payload(&mut store, ctx.context_id, &typed(item_type, id)))
+    .collect();
+
+    let wanted = vec!["user_input".to_string(), "assistant_turn".to_string()];
+    let (page, has_more) = store
+        .get_last_window_of_types(ctx.context_id, 0, 2, true, &wanted, false)
+        .expect("first page");
+    let page_ids: Vec<u64> = page.iter().map(|t| t.record.turn_id).collect();
+    assert_eq!(page_ids, vec![ids[3], ids[4]]);
+    assert!(page.iter().all(|t| t.payload.is_some()));
+    assert!(has_more);
+
+    // Offset counts matching turns only.
+    let (page, _) = store
+        .get_last_window_of_types(ctx.context_id, 2, 10, false, &wanted, false)
+        .expect("second page");
+    let page_ids: Vec<u64> = page.iter().map(|t| t.record.turn_id).collect();
+    assert_eq!(page_ids, vec![ids[0]]);
+
+    let (page, _) = store
+        .get_last_window_of_types(ctx.context_id, 0, 10, false, &[], false)
+        .expect("unfiltered");
+    assert_eq!(page.len(), ids.len());
+
+    // Item types and sizes are available without fetching payloads.
+    let item_types = store.item_types_of(&page[..3]).expect("item types");
+    assert_eq!(
+        item_types,
+        vec![
+            Some("user_input".to_string()),
+            Some("tool_call".to_string()),
+            Some("tool_result".to_string()),
+        ]
+    );
+    assert_eq!(
+        page[0].meta.uncompressed_len as usize,
+        typed("user_input", "u1").len()
+    );
+}
+
+#[test]
+fn summary_mode_stops_at_latest_checkpoint() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let ctx = store.create_context(0).expect("create context");
+    let append_checkpoint = |store: &mut Store, id: &str| {
+        let payload = item_payload(id);
+        let hash = blake3::hash(&payload);
+        let (record, _) = store
+            .append_turn_with_flags(
+                ctx.context_id,
+                0,
+                "cxdb.ConversationItem".to_string(),
+                3,
+                1,
+                0,
+                payload.len() as u32,
+                *hash.as_bytes(),
+                &payload,
+                TURN_FLAG_CHECKPOINT,
+            )
+            .expect("append checkpoint");
+        record.turn_id
+    };
+    append_payload(&mut store, ctx.context_id, &item_payload("t0"));
+    append_checkpoint(&mut store, "summary-1");
+    append_payload(&mut store, ctx.context_id, &item_payload("t1"));
+    let latest = append_checkpoint(&mut store, "summary-2");
+    let after = append_payload(&mut store, ctx.context_id, &item_payload("t2"));
+
+    let (page, has_more) = store
+        .get_last_window_of_types(ctx.context_id, 0, 10, false, &[], true)
+        .expect("summary");
+    let ids: Vec<u64> = page.iter().map(|t| t.record.turn_id).collect();
+    assert_eq!(ids, vec![latest, after]);
+    assert!(page[0].record.is_checkpoint());
+    assert!(!has_more);
+
+    // Paging past the checkpoint finds nothing more.
+    let (page, has_more) = store
+        .get_last_window_of_types(ctx.context_id, 2, 10, false, &[], true)
+        .expect("past checkpoint");
+    assert!(page.is_empty());
+    assert!(!has_more);
+
+    // A type filter still returns the checkpoint that ends the walk.
+    let wanted = vec!["assistant_turn".to_string()];
+    let (page, _) = store
+        .get_last_window_of_types(ctx.context_id, 0, 10, false, &wanted, true)
+        .expect("filtered summary");
+    let ids: Vec<u64> = page.iter().map(|t| t.record.turn_id).collect();
+    assert_eq!(ids, vec![latest]);
+
+    // Without summary mode checkpoints are ordinary turns.
+    let (page, _) = store
+        .get_last_window_of_types(ctx.context_id, 0, 10, false, &[], false)
+        .expect("full chain");
+    assert_eq!(page.len(), 5);
+
+    // The marker survives a reopen.
+    drop(store);
+    let store = Store::open(dir.path()).expect("reopen");
+    assert!(store.turn_store.get_turn(latest).unwrap().is_checkpoint());
+}
+
+#[test]
+fn tool_result_content_blob_is_checked_and_resolved() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context");
+
+    let output = b"very large tool output".to_vec();
+    let blob_hash = *blake3::hash(&output).as_bytes();
+    let value = rmpv::Value::Map(vec![
+        (rmpv::Value::from("1"), rmpv::Value::from("tool_result")),
+        (
+            rmpv::Value::from("22"),
+            rmpv::Value::Map(vec![
+                (rmpv::Value::from("1"), rmpv::Value::from("call-1")),
+                (rmpv::Value::from("2"), rmpv::Value::from("")),
+                (
+                    rmpv::Value::from("8"),
+                    rmpv::Value::Binary(blob_hash.to_vec()),
+                ),
+            ]),
+        ),
+    ]);
+    let mut payload = Vec::new();
+    rmpv::encode::write_value(&mut payload, &value).expect("encode payload");
+    let hash = *blake3::hash(&payload).as_bytes();
+    let append = |store: &mut Store| {
+        store.append_turn(
+            ctx.context_id,
+            0,
+            "cxdb.ConversationItem".to_string(),
+            3,
+            1,
+            0,
+            payload.len() as u32,
+            hash,
+            &payload,
+        )
+    };
+
+    let err = append(&mut store).expect_err("missing blob must be rejected");
+    assert!(matches!(err, cxdb_server::error::StoreError::NotFound(_)));
+
+    store
+        .blob_store
+        .put_if_absent(blob_hash, &output)
+        .expect("put blob");
+    append(&mut store).expect("append with blob reference");
+
+    let resolved = store
+        .resolve_tool_result_blob(&payload)
+        .expect("resolve")
+        .expect("payload has a blob reference");
+    let value = rmpv::decode::read_value(&mut std::io::Cursor::new(&resolved)).expect("decode");
+    let result = value.as_map().unwrap()[1].1.as_map().unwrap().clone();
+    assert_eq!(result[1].1.as_str(), Some("very large tool output"));
+    assert_eq!(result[2].1, rmpv::Value::Binary(blob_hash.to_vec()));
+
+    assert!(store
+        .resolve_tool_result_blob(&item_payload("plain"))
+        .expect("resolve")
+        .is_none());
+}
+
+#[test]
+fn get_head_at_returns_head_as_of_timestamp() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context").context_id;
+
+    let first = append_payload(&mut store, ctx, &item_payload("a"));
+    let first_at = store.get_head(ctx).unwrap().created_at_unix_ms;
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let second = append_payload(&mut store, ctx, &item_payload("b"));
+    let second_at = store.get_head(ctx).unwrap().created_at_unix_ms;
+    assert!(second_at > first_at);
+
+    let head = store.get_head_at(ctx, first_at).unwrap();
+    assert_eq!((head.head_turn_id, head.head_depth), (first, 0));
+    let head = store.get_head_at(ctx, second_at + 1000).unwrap();
+    assert_eq!((head.head_turn_id, head.head_depth), (second, 1));
+    let head = store.get_head_at(ctx, first_at - 1).unwrap();
+    assert_eq!((head.head_turn_id, head.head_depth), (0, 0));
+
+    // A fork's history includes the turns it inherited.
+    let fork = store.create_context(first).unwrap().context_id;
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    append_payload(&mut store, fork, &item_payload("c"));
+    assert_eq!(
+        store.get_head_at(fork, second_at).unwrap().head_turn_id,
+        first
+    );
+
+    assert!(matches!(
+        store.get_head_at(999, second_at),
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[test]
+fn fs_file_returns_inline_content() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context");
+    let turn_id = append_payload(&mut store, ctx.context_id, &item_payload("item-1"));
+
+    let content = b"key = value\n";
+    let entry = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from("app.toml")),
+        (rmpv::Value::from(2), rmpv::Value::from(0)),
+        (rmpv::Value::from(3), rmpv::Value::from(0o644)),
+        (rmpv::Value::from(4), rmpv::Value::from(content.len())),
+        (
+            rmpv::Value::from(5),
+            rmpv::Value::Binary(blake3::hash(content).as_bytes().to_vec()),
+        ),
+        (rmpv::Value::from(7), rmpv::Value::Binary(content.to_vec())),
+    ]);
+    let mut tree = Vec::new();
+    rmpv::encode::write_value(&mut tree, &rmpv::Value::Array(vec![entry])).unwrap();
+    let tree_hash = *blake3::hash(&tree).as_bytes();
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+    store
+        .attach_fs(turn_id, tree_hash.into())
+        .expect("attach fs");
+
+    // The file blob itself was never uploaded.
+    let (bytes, entry) = store.get_fs_file(turn_id, "app.toml").expect("get file");
+    assert_eq!(bytes, content);
+    assert_eq!(entry.inline_content.as_deref(), Some(content.as_slice()));
+}
+
+#[test]
+fn fs_file_kind_sniffs_blob_and_inline_files() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context");
+    let turn_id = append_payload(&mut store, ctx.context_id, &item_payload("item-1"));
+
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+    image.resize(1 << 20, 0x42);
+    let image_hash = *blake3::hash(&image).as_bytes();
+    store.blob_store.put_if_absent(image_hash, &image).unwrap();
+    let notes = "résumé notes\n".as_bytes();
+
+    let file = |name: &str, size: usize, hash: [u8; 32], inline: Option<&[u8]>| {
+        let mut fields = vec![
+            (rmpv::Value::from(1), rmpv::Value::from(name)),
+            (rmpv::Value::from(2), rmpv::Value::from(0)),
+            (rmpv::Value::from(3), rmpv::Value::from(0o644)),
+            (rmpv::Value::from(4), rmpv::Value::from(size)),
+            (rmpv::Value::from(5), rmpv::Value::Binary(hash.to_vec())),
+        ];
+        if let Some(inline) = inline {
+            fields.push((rmpv::Value::from(7), rmpv::Value::Binary(inline.to_vec())));
+        }
+        rmpv::Value::Map(fields)
+    };
+    let entries = vec![
+        file("logo.png", image.len(), image_hash, None),
+        file(
+            "notes.txt",
+            notes.len(),
+            *blake3::hash(notes).as_bytes(),
+            Some(notes),
+        ),
+    ];
+    let mut tree = Vec::new();
+    rmpv::encode::write_value(&mut tree, &rmpv::Value::Array(entries)).unwrap();
+    let tree_hash = *blake3::hash(&tree).as_bytes();
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+    store
+        .attach_fs(turn_id, tree_hash.into())
+        .expect("attach fs");
+
+    assert_eq!(
+        store.fs_file_kind(turn_id, "logo.png").expect("kind"),
+        FileKind::Image("png")
+    );
+    assert_eq!(
+        store.fs_file_kind(turn_id, "notes.txt").expect("kind"),
+        FileKind::Text
+    );
+    assert!(matches!(
+        store.fs_file_kind(turn_id, "missing.bin"),
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[test]
+fn keyed_blob_hashes_need_a_configured_key() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let data = b"tenant file".to_vec();
+    let key = [9u8; 32];
+    let keyed = *blake3::keyed_hash(&key, &data).as_bytes();
+
+    assert!(!store.blob_hash_matches(&keyed, &data));
+    store.set_hash_keys(vec![[1u8; 32], key]);
+    assert!(store.blob_hash_matches(&keyed, &data));
+    assert!(store.blob_hash_matches(blake3::hash(&data).as_bytes(), &data));
+
+    let (upload_id, _) = store
+        .begin_blob_upload(keyed, data.len() as u64)
+        .expect("begin")
+        .expect("blob not yet stored");
+    store
+        .blob_uploads
+        .append(upload_id, 0, &data)
+        .expect("chunk");
+    store.commit_blob_upload(upload_id).expect("commit");
+    assert!(store.check().expect("check").is_ok());
+}
+
+#[test]
+fn chunked_blob_upload_resumes_and_verifies_hash() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let data = b"chunked blob contents".to_vec();
+    let hash = *blake3::hash(&data).as_bytes();
+    let (upload_id, received) = store
+        .begin_blob_upload(hash, data.len() as u64)
+        .expect("begin")
+        .expect("blob not yet stored");
+    assert_eq!(received, 0);
+    store
+        .blob_uploads
+        .append(upload_id, 0, &data[..8])
+        .expect("first chunk");
+
+    // Out-of-order chunks and early commits are rejected.
+    assert!(store
+        .blob_uploads
+        .append(upload_id, 12, &data[12..])
+        .is_err());
+    assert!(store.commit_blob_upload(upload_id).is_err());
+
+    // Beginning again (e.g. after a reconnect) resumes the same upload.
+    let resumed = store
+        .begin_blob_upload(hash, data.len() as u64)
+        .expect("resume")
+        .expect("blob not yet stored");
+    assert_eq!(resumed, (upload_id, 8));
+    store
+        .blob_uploads
+        .append(upload_id, 8, &data[8..])
+        .expect("last chunk");
+    let (stored_hash, was_new) = store.commit_blob_upload(upload_id).expect("commit");
+    assert_eq!(stored_hash, hash);
+    assert!(was_new);
+    assert_eq!(store.get_blob(&hash).expect("get blob"), data);
+    assert!(store
+        .begin_blob_upload(hash, data.len() as u64)
+        .expect("begin stored")
+        .is_none());
+
+    // A declared hash that does not match the uploaded bytes is refused.
+    let bad_hash = [0xAB; 32];
+    let (upload_id, _) = store
+        .begin_blob_upload(bad_hash, 3)
+        .expect("begin")
+        .expect("not stored");
+    store
+        .blob_uploads
+        .append(upload_id, 0, b"abc")
+        .expect("chunk");
+    assert!(store.commit_blob_upload(upload_id).is_err());
+    assert!(!store.blob_store.contains(&bad_hash));
+}
+
+#[test]
+fn chunked_blob_uploads_spool_to_disk_and_are_capped() {
+    let dir = tempdir().expect("tempdir");
+    let uploads_dir = dir.path().join("blobs").join("uploads");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let (upload_id, _) = store
+        .begin_blob_upload([1; 32], 4)
+        .expect("begin")
+        .expect("not stored");
+    store
+        .blob_uploads
+        .append(upload_id, 0, b"ab")
+        .expect("chunk");
+    let part = uploads_dir.join(format!("{upload_id}.part"));
+    assert_eq!(std::fs::read(&part).expect("part file"), b"ab");
+
+    // Further uploads are refused once the pending limit is reached.
+    let mut refused = None;
+    for i in 0..1024u32 {
+        let mut hash = [2; 32];
+        hash[..4].copy_from_slice(&i.to_le_bytes());
+        if let Err(err) = store.begin_blob_upload(hash, 1) {
+            refused = Some(err);
+            break;
+        }
+    }
+    assert!(matches!(refused, Some(StoreError::QuotaExceeded(_))));
+
+    // Upload ids do not survive a restart, so their files are removed.
+    drop(store);
+    let _store = Store::open(dir.path()).expect("reopen store");
+    assert!(!part.exists());
+    assert_eq!(
+        std::fs::read_dir(&uploads_dir)
+            .expect("uploads dir")
+            .count(),
+        0
+    );
+}
+
+fn labeled_payload(labels: &[&str]) -> Vec<u8> {
+    let labels = labels.iter().map(|l| rmpv::Value::from(*l)).collect();
+    let value = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from("user_input")),
+        (
+            rmpv::Value::from(30),
+            rmpv::Value::Map(vec![(rmpv::Value::from(3), rmpv::Value::Array(labels))]),
+        ),
+    ]);
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &value).expect("encode payload");
+    buf
+}
+
+#[test]
+fn list_contexts_filters_by_labels() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let mut ids = Vec::new();
+    for labels in [&["prod", "eval"][..], &["prod"], &["dev"], &[]] {
+        let ctx = store.create_context(0).expect("create").context_id;
+        append_payload(&mut store, ctx, &labeled_payload(labels));
+        ids.push(ctx);
+    }
+    let ids_of = |heads: Vec<cxdb_server::turn_store::ContextHead>| {
+        let mut found: Vec<u64> = heads.iter().map(|h| h.context_id).collect();
+        found.sort();
+        found
+    };
+    let labels = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+    let all = store.list_contexts_with_labels(&labels(&["prod", "eval"]), true, 20);
+    assert_eq!(ids_of(all), vec![ids[0]]);
+
+    let any = store.list_contexts_with_labels(&labels(&["eval", "dev"]), false, 20);
+    assert_eq!(ids_of(any), vec![ids[0], ids[2]]);
+
+    let single = store.list_contexts_with_labels(&labels(&["prod"]), true, 20);
+    assert_eq!(ids_of(single), vec![ids[0], ids[1]]);
+
+    let limited = store.list_contexts_with_labels(&labels(&["prod"]), true, 1);
+    assert_eq!(limited.len(), 1);
+}
+
+fn single_file_tree(name: &str, content_hash: [u8; 32]) -> Vec<u8> {
+    let entry = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from(name)),
+        (rmpv::Value::from(2), rmpv::Value::from(0)),
+        (rmpv::Value::from(3), rmpv::Value::from(0o644)),
+        (rmpv::Value::from(4), rmpv::Value::from(5)),
+        (
+            rmpv::Value::from(5),
+            rmpv::Value::Binary(content_hash.to_vec()),
+        ),
+    ]);
+    let mut tree = Vec::new();
+    rmpv::encode::write_value(&mut tree, &rmpv::Value::Array(vec![entry])).unwrap();
+    tree
+}
+
+#[test]
+fn tree_bytes_are_returned_as_stored() {
+    use cxdb_server::fs_store::get_tree_bytes;
+
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    // Map keys out of the usual order: decoding and re-encoding the entry
+    // would not reproduce these bytes.
+    let sub_entry = rmpv::Value::Map(vec![
+        (
+            rmpv::Value::from(5),
+            rmpv::Value::Binary(blake3::hash(b"hello").as_bytes().to_vec()),
+        ),
+        (rmpv::Value::from(4), rmpv::Value::from(5)),
+        (rmpv::Value::from(1), rmpv::Value::from("main.rs")),
+        (rmpv::Value::from(2), rmpv::Value::from(0)),
+    ]);
+    let mut sub_tree = Vec::new();
+    rmpv::encode::write_value(&mut sub_tree, &rmpv::Value::Array(vec![sub_entry])).unwrap();
+    let sub_hash = *blake3::hash(&sub_tree).as_bytes();
+    store.blob_store.put_if_absent(sub_hash, &sub_tree).unwrap();
+
+    let dir_entry = rmpv::Value::Map(vec![
+        (rmpv::Value::from(1), rmpv::Value::from("src")),
+        (rmpv::Value::from(2), rmpv::Value::from(1)),
+        (rmpv::Value::from(3), rmpv::Value::from(0o755)),
+        (rmpv::Value::from(5), rmpv::Value::Binary(sub_hash.to_vec())),
+    ]);
+    let mut root_tree = Vec::new();
+    rmpv::encode::write_value(&mut root_tree, &rmpv::Value::Array(vec![dir_entry])).unwrap();
+    let root_hash = *blake3::hash(&root_tree).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(root_hash, &root_tree)
+        .unwrap();
+
+    let (hash, bytes) =
+        get_tree_bytes(&mut store.blob_store, &root_hash.into(), "/").expect("root tree");
+    assert_eq!(hash, root_hash);
+    assert_eq!(bytes, root_tree);
+
+    let (hash, bytes) =
+        get_tree_bytes(&mut store.blob_store, &root_hash.into(), "src").expect("sub tree");
+    assert_eq!(hash, sub_hash);
+    assert_eq!(bytes, sub_tree);
+    assert_eq!(*blake3::hash(&bytes).as_bytes(), hash);
+
+    assert!(matches!(
+        get_tree_bytes(&mut store.blob_store, &root_hash.into(), "src/main.rs"),
+        Err(StoreError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        get_tree_bytes(&mut store.blob_store, &root_hash.into(), "lib"),
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[test]
+fn verify_snapshot_reports_missing_objects_by_path() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+
+    let entry = |name: &str, kind: u8, hash: [u8; 32]| {
+        rmpv::Value::Map(vec![
+            (rmpv::Value::from(1), rmpv::Value::from(name)),
+            (rmpv::Value::from(2), rmpv::Value::from(kind)),
+            (rmpv::Value::from(5), rmpv::Value::Binary(hash.to_vec())),
+        ])
+    };
+    let tree = |entries: Vec<rmpv::Value>| {
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &rmpv::Value::Array(entries)).unwrap();
+        (*blake3::hash(&bytes).as_bytes(), bytes)
+    };
+
+    let present = *blake3::hash(b"present").as_bytes();
+    store.blob_store.put_if_absent(present, b"present").unwrap();
+    let absent = *blake3::hash(b"absent").as_bytes();
+    let (sub_hash, sub_tree) = tree(vec![entry("a.txt", 0, present), entry("b.txt", 0, absent)]);
+    store.blob_store.put_if_absent(sub_hash, &sub_tree).unwrap();
+    let (gone_hash, _) = tree(vec![entry("c.txt", 0, present)]);
+    let (root_hash, root_tree) = tree(vec![
+        entry("one", 1, sub_hash),
+        entry("two", 1, sub_hash),
+        entry("gone", 1, gone_hash),
+        entry("link", 2, present),
+    ]);
+    store
+        .blob_store
+        .put_if_absent(root_hash, &root_tree)
+        .unwrap();
+
+    let report = store.verify_snapshot(&root_hash).expect("verify");
+    assert_eq!(report.missing, 3);
+    assert_eq!(report.missing_paths, vec!["gone", "one/b.txt", "two/b.txt"]);
+    assert_eq!(report.trees_checked, 4);
+    assert_eq!(report.blobs_checked, 5);
+
+    store.blob_store.put_if_absent(absent, b"absent").unwrap();
+    let (_, gone_tree) = tree(vec![entry("c.txt", 0, present)]);
+    store
+        .blob_store
+        .put_if_absent(gone_hash, &gone_tree)
+        .unwrap();
+    let report = store.verify_snapshot(&root_hash).expect("verify");
+    assert_eq!(report.missing, 0);
+    assert!(report.missing_paths.is_empty());
+    // Once intact, the shared subtree is walked only once.
+    assert_eq!(report.trees_checked, 3);
+
+    let report = store.verify_snapshot(&absent).expect("verify");
+    assert_eq!(report.missing_paths, vec![""]);
+}
+
+#[test]
+fn fs_content_bytes_counts_shared_blobs_once_across_threads() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create").context_id;
+
+    let content_hash = *blake3::hash(b"hello").as_bytes();
+    store
+        .blob_store
+        .put_if_absent(content_hash, b"hello")
+        .unwrap();
+    let mut expected = 5;
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        let tree = single_file_tree(name, content_hash);
+        let tree_hash = *blake3::hash(&tree).as_bytes();
+        store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+        expected += tree.len() as u64;
+        // Two turns per root: duplicate roots are walked once.
+        for _ in 0..2 {
+            let turn_id = append_payload(&mut store, ctx, &item_payload(name));
+            store.attach_fs(turn_id, tree_hash.into()).unwrap();
+        }
+    }
+
+    for concurrency in [1, 3, 16] {
+        assert_eq!(
+            store.fs_roots.content_bytes(&store.blob_store, concurrency),
+            expected
+        );
+    }
+    assert_eq!(store.stats().fs_content_bytes, expected);
+}
+
+#[test]
+fn check_passes_after_reopen_and_reports_corruption() {
+    let dir = tempdir().expect("tempdir");
+    let (turn_id, content_hash) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create").context_id;
+        append_payload(&mut store, ctx, &item_payload("a"));
+        let turn_id = append_payload(&mut store, ctx, &item_payload("b"));
+
+        let content_hash = *blake3::hash(b"hello").as_bytes();
+        store
+            .blob_store
+            .put_if_absent(content_hash, b"hello")
+            .unwrap();
+        let tree = single_file_tree("hello.txt", content_hash);
+        let tree_hash = *blake3::hash(&tree).as_bytes();
+        store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+        store.attach_fs(turn_id, tree_hash.into()).expect("attach");
+        (turn_id, content_hash)
+    };
+
+    // Data survives a reopen and verifies clean.
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert!(store.turn_store.get_turn(turn_id).is_ok());
+    let report = store.check().expect("check");
+    assert!(report.is_ok(), "unexpected issues: {:?}", report.issues);
+    assert_eq!(report.turns_checked, 2);
+    assert_eq!(report.heads_checked, 1);
+    assert_eq!(report.blobs_checked, 4);
+    assert_eq!(report.fs_roots_checked, 1);
+
+    // Flip the last byte of the "hello" blob's stored bytes.
+    let pack_path = dir.path().join("blobs").join("blobs.pack");
+    let mut pack = std::fs::read(&pack_path).unwrap();
+    let blob_start = pack
+        .windows(32)
+        .position(|w| w == content_hash)
+        .expect("hello blob in pack");
+    pack[blob_start + 32 + 4] ^= 0xFF;
+    std::fs::write(&pack_path, &pack).unwrap();
+
+    // Torn trailing head record.
+    let heads_path = dir.path().join("turns").join("heads.tbl");
+    let mut heads = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&heads_path)
+        .unwrap();
+    std::io::Write::write_all(&mut heads, &[0u8; 7]).unwrap();
+    let heads_len = std::fs::metadata(&heads_path).unwrap().len();
+
+    let report = store.check().expect("check");
+    let files: Vec<&str> = report.issues.iter().map(|i| i.file.as_ref()).collect();
+    assert_eq!(
+        files,
+        vec!["turns/heads.tbl", "blobs/blobs.idx"],
+        "{:?}",
+        report.issues
+    );
+    assert!(report.issues[1].detail.contains(&hex::encode(content_hash)));
+
+    // Checking never repairs.
+    assert_eq!(std::fs::metadata(&heads_path).unwrap().len(), heads_len);
+}
+
+#[test]
+fn mid_file_corruption_requires_explicit_repair() {
+    let dir = tempdir().expect("tempdir");
+    {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create").context_id;
+        for id in ["a", "b", "c"] {
+            append_payload(&mut store, ctx, &item_payload(id));
+        }
+    }
+
+    // A torn final record is still dropped silently on open.
+    let roots_path = dir.path().join("fs").join("roots.idx");
+    std::fs::write(&roots_path, [0u8; 10]).unwrap();
+    drop(Store::open(dir.path()).expect("open with torn tail"));
+    // Only the format header remains.
+    assert_eq!(std::fs::metadata(&roots_path).unwrap().len(), 8);
+
+    // Corrupt the second of three turn records.
+    let log_path = dir.path().join("turns").join("turns.log");
+    let mut log = std::fs::read(&log_path).unwrap();
+    let record_len = log.len() / 3;
+    log[record_len + 20] ^= 0xFF;
+    std::fs::write(&log_path, &log).unwrap();
+
+    let err = Store::open(dir.path()).err().expect("open must fail");
+    assert!(err.to_string().contains("turns/turns.log"), "{err}");
+
+    let report = Store::repair(dir.path()).expect("repair");
+    assert_eq!(report.actions.len(), 1, "{:?}", report.actions);
+    let action = &report.actions[0];
+    assert_eq!(action.file, "turns/turns.log");
+    assert_eq!(action.truncated_at, record_len as u64);
+    assert_eq!(action.bytes_discarded, 2 * record_len as u64);
+    assert_eq!(action.records_discarded, Some(2));
+
+    let store = Store::open(dir.path()).expect("open after repair");
+    assert_eq!(store.turn_store.stats().turns_total, 1);
+    assert!(Store::repair(dir.path()).expect("repair again").is_clean());
+}
+
+#[test]
+fn fs_snapshot_meta_persists_and_is_inherited() {
+    let dir = tempdir().expect("tempdir");
+    let meta = SnapshotMeta {
+        captured_at_unix_ms: 1_748_736_000_000,
+        file_count: 4210,
+        dir_count: 312,
+        total_bytes: 9_000_000,
+        duration_ms: 850,
+    };
+    let (first, second, tree_hash) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create").context_id;
+        let first = append_payload(&mut store, ctx, &item_payload("a"));
+        let second = append_payload(&mut store, ctx, &item_payload("b"));
+
+        let content_hash = *blake3::hash(b"hello").as_bytes();
+        store
+            .blob_store
+            .put_if_absent(content_hash, b"hello")
+            .unwrap();
+        let tree = single_file_tree("hello.txt", content_hash);
+        let tree_hash = *blake3::hash(&tree).as_bytes();
+        store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+        store
+            .attach_fs_with_meta(first, tree_hash.into(), Some(meta))
+            .expect("attach");
+        (first, second, tree_hash)
+    };
+
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.get_fs_meta(first), Some(meta));
+    assert_eq!(store.get_fs_meta(second), Some(meta));
+    assert!(store.check().expect("check").is_ok());
+
+    // Re-attaching without metadata replaces it.
+    store.attach_fs(first, tree_hash.into()).expect("re-attach");
+    assert_eq!(store.get_fs_meta(first), None);
+    store.compact(0.0, 0).expect("compact");
+    drop(store);
+    let store = Store::open(dir.path()).expect("reopen after compact");
+    assert_eq!(store.get_fs_meta(first), None);
+    assert_eq!(
+        store.get_fs_root_direct(first).map(|h| *h.as_bytes()),
+        Some(tree_hash)
+    );
+}
+
+#[test]
+fn legacy_roots_index_is_upgraded_on_open() {
+    let dir = tempdir().expect("tempdir");
+    let (turn_id, tree_hash) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create").context_id;
+        let turn_id = append_payload(&mut store, ctx, &item_payload("a"));
+        let content_hash = *blake3::hash(b"hello").as_bytes();
+        store
+            .blob_store
+            .put_if_absent(content_hash, b"hello")
+            .unwrap();
+        let tree = single_file_tree("hello.txt", content_hash);
+        let tree_hash = *blake3::hash(&tree).as_bytes();
+        store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+        (turn_id, tree_hash)
+    };
+
+    // A version 1 file: headerless 44-byte records.
+    let mut record = Vec::new();
+    record.extend_from_slice(&turn_id.to_le_bytes());
+    record.extend_from_slice(&tree_hash);
+    let crc = crc32fast::hash(&record);
+    record.extend_from_slice(&crc.to_le_bytes());
+    let roots_path = dir.path().join("fs").join("roots.idx");
+    std::fs::write(&roots_path, &record).unwrap();
+
+    let mut store = Store::open(dir.path()).expect("open legacy index");
+    assert_eq!(
+        store.get_fs_root_direct(turn_id).map(|h| *h.as_bytes()),
+        Some(tree_hash)
+    );
+    assert_eq!(store.get_fs_meta(turn_id), None);
+    assert!(std::fs::read(&roots_path).unwrap().starts_with(b"CXRI"));
+    assert!(store.check().expect("check").is_ok());
+}
+
+#[test]
+fn context_quota_limits_turns_and_bytes() {
+    let dir = tempdir().expect("tempdir");
+    let payload = item_payload("a");
+    let turn_quota = ContextQuota {
+        max_turns: Some(2),
+        max_bytes: None,
+    };
+    let (capped, second) = {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let capped = store
+            .create_context_with_quota(0, Some(turn_quota))
+            .expect("create")
+            .context_id;
+        append_payload(&mut store, capped, &payload);
+        let second = append_payload(&mut store, capped, &payload);
+        let err = try_append_payload(&mut store, capped, &payload).unwrap_err();
+        assert!(matches!(err, StoreError::QuotaExceeded(_)), "{err}");
+        (capped, second)
+    };
+
+    // Quotas persist, and forks count the turns they inherit.
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.context_quota(capped), turn_quota);
+    assert!(matches!(
+        try_append_payload(&mut store, capped, &payload),
+        Err(StoreError::QuotaExceeded(_))
+    ));
+    let fork = store
+        .fork_context_with_quota(second, Some(ContextQuota::from_raw(3, 0)))
+        .expect("fork")
+        .context_id;
+    append_payload(&mut store, fork, &payload);
+    assert!(try_append_payload(&mut store, fork, &payload).is_err());
+    assert_eq!(store.context_usage(fork).unwrap().turns, 3);
+
+    // Attached snapshot content counts towards the byte limit.
+    let limited = store
+        .create_context_with_quota(0, Some(ContextQuota::from_raw(0, payload.len() as u64 + 8)))
+        .expect("create")
+        .context_id;
+    let turn = append_payload(&mut store, limited, &payload);
+    let content = vec![7u8; 64];
+    let content_hash = *blake3::hash(&content).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(content_hash, &content)
+        .unwrap();
+    let tree = single_file_tree("big.bin", content_hash);
+    let tree_hash = *blake3::hash(&tree).as_bytes();
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+    let err = store.attach_fs(turn, tree_hash.into()).unwrap_err();
+    assert!(matches!(err, StoreError::QuotaExceeded(_)), "{err}");
+    assert_eq!(store.get_fs_root(turn), None);
+
+    // Contexts without a quota of their own use the default.
+    store.set_default_quota(ContextQuota::from_raw(1, 0));
+    let defaulted = store.create_context(0).expect("create").context_id;
+    append_payload(&mut store, defaulted, &payload);
+    assert!(try_append_payload(&mut store, defaulted, &payload).is_err());
+    assert_eq!(store.context_quota(limited).max_turns, Some(1));
+
+    // An explicit unlimited quota opts out of the default.
+    let unlimited = store
+        .create_context_with_quota(0, Some(ContextQuota::from_raw(ContextQuota::UNLIMITED, 0)))
+        .expect("create")
+        .context_id;
+    append_payload(&mut store, unlimited, &payload);
+    append_payload(&mut store, unlimited, &payload);
+    assert_eq!(store.context_usage(unlimited).unwrap().turns, 2);
+}
+
+#[test]
+fn context_usage_follows_attaches_to_shared_turns() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let payload = item_payload("a");
+    let base = store.create_context(0).expect("create").context_id;
+    let shared = append_payload(&mut store, base, &payload);
+    let fork = store
+        .fork_context_with_quota(shared, Some(ContextQuota::from_raw(0, 1024)))
+        .expect("fork")
+        .context_id;
+    append_payload(&mut store, fork, &payload);
+    let before = store.context_usage(fork).unwrap();
+    assert_eq!(before.turns, 2);
+
+    // A snapshot attached to the shared turn counts for the fork as well.
+    let content = vec![7u8; 64];
+    let content_hash = *blake3::hash(&content).as_bytes();
+    store
+        .blob_store
+        .put_if_absent(content_hash, &content)
+        .unwrap();
+    let tree = single_file_tree("file.bin", content_hash);
+    let tree_hash = *blake3::hash(&tree).as_bytes();
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+    store.attach_fs(shared, tree_hash.into()).expect("attach");
+    let after = store.context_usage(fork).unwrap();
+    assert!(after.bytes >= before.bytes + 64, "{after:?}");
+    assert_eq!(
+        store.context_usage(base).unwrap().bytes,
+        after.bytes - payload.len() as u64
+    );
+
+    // Counters match a fresh walk after a reopen.
+    drop(store);
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert_eq!(store.context_usage(fork).unwrap(), after);
+}
+
+#[test]
+fn attach_fs_to_head_uses_current_head() {
+    let dir = tempdir().expect("tempdir");
+    let mut store = Store::open(dir.path()).expect("open store");
+    let ctx = store.create_context(0).expect("create context");
+
+    let tree = vec![0x90]; // empty msgpack array
+    let tree_hash = *blake3::hash(&tree).as_bytes();
+    store.blob_store.put_if_absent(tree_hash, &tree).unwrap();
+
+    let err = store
+        .attach_fs_to_head(ctx.context_id, tree_hash.into(), None)
+        .unwrap_err();
+    assert!(matches!(err, StoreError::InvalidInput(_)));
+
+    append_payload(&mut store, ctx.context_id, &item_payload("item-1"));
+    let second = append_payload(&mut store, ctx.context_id, &item_payload("item-2"));
+    let attached = store
+        .attach_fs_to_head(ctx.context_id, tree_hash.into(), None)
+        .expect("attach to head");
+    assert_eq!(attached, second);
+    assert_eq!(
+        store.get_fs_root_direct(second).map(|h| *h.as_bytes()),
+        Some(tree_hash)
+    );
+}
+
+#[test]
+fn trim_context_before_hides_old_turns_and_persists() {
+    let dir = tempdir().expect("tempdir");
+    let ctx_id;
+    let first;
+    let second;
+    let third;
+    {
+        let mut store = Store::open(dir.path()).expect("open store");
+        let ctx = store.create_context(0).expect("create context");
+        ctx_id = ctx.context_id;
+        first = append_payload(&mut store, ctx_id, &item_payload("item-1"));
+        second = append_payload(&mut store, ctx_id, &item_payload("item-2"));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let cutoff = store
+            .turn_store
+            .get_turn(second)
+            .unwrap()
+            .created_at_unix_ms
+            + 1;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        third = append_payload(&mut store, ctx_id, &item_payload("item-3"));
+        let fourth = append_payload(&mut store, ctx_id, &item_payload("item-4"));
+
+        let report = store.trim_context_before(ctx_id, cutoff).expect("trim");
+        assert_eq!(report.turns_trimmed, 2);
+        assert_eq!(report.gc_candidates.len(), 2);
+        assert_eq!(store.gc_candidates(), report.gc_candidates);
+
+        let turns = store.get_last(ctx_id, 10, true).expect("get last");
+        let ids: Vec<u64> = turns.iter().map(|t| t.record.turn_id).collect();
+        assert_eq!(ids, vec![third, fourth]);
+        assert!(store
+            .get_before(ctx_id, third, 10, false)
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.context_usage(ctx_id).unwrap().turns, 2);
+
+        // Trimming again at the same cutoff finds nothing new.
+        let again = store
+            .trim_context_before(ctx_id, cutoff)
+            .expect("trim again");
+        assert_eq!(again.turns_trimmed, 0);
+
+        // The head survives even when it is older than the cutoff.
+        let report = store
+            .trim_context_before(ctx_id, u64::MAX)
+            .expect("trim all");
+        assert_eq!(report.turns_trimmed, 1);
+        let turns = store.get_last(ctx_id, 10, false).expect("get last");
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].record.turn_id, fourth);
+    }
+
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    assert!(store.turn_store.is_trimmed(first));
+    assert!(store.turn_store.is_trimmed(third));
+    let turns = store.get_last(ctx_id, 10, false).expect("get last");
+    assert_eq!(turns.len(), 1);
+    assert!(store.check().expect("check").is_ok());
+}
+
+#[test]
+fn trim_context_before_keeps_turns_forks_still_reach() {
+    let dir = tempdir().expect("tempdir");
+    let (a, b, c);
+    let (t1, t2, t3, t4, b1);
+    {
+        let mut store = Store::open(dir.path()).expect("open store");
+        a = store.create_context(0).expect("create context").context_id;
+        t1 = append_payload(&mut store, a, &item_payload("item-1"));
+        t2 = append_payload(&mut store, a, &item_payload("item-2"));
+        t3 = append_payload(&mut store, a, &item_payload("item-3"));
+        b = store.fork_context(t1).expect("fork at t1").context_id;
+        b1 = append_payload(&mut store, b, &item_payload("b-1"));
+        c = store.fork_context(t2).expect("fork at t2").context_id;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let cutoff = store.turn_store.get_turn(b1).unwrap().created_at_unix_ms + 1;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        t4 = append_payload(&mut store, a, &item_payload("item-4"));
+
+        // a stops reading t3 and everything before it, but only t3 is gone
+        // for good: t2 is c's head and t1 is shared with b.
+        let report = store.trim_context_before(a, cutoff).expect("trim");
+        assert_eq!(report.turns_trimmed, 3);
+        assert_eq!(report.gc_candidates.len(), 1);
+        assert!(store.turn_store.is_trimmed(t3));
+        assert!(!store.turn_store.is_trimmed(t2));
+        assert!(!store.turn_store.is_trimmed(t1));
+
+        // a can no longer build on, or fork from, its trimmed turn.
+        let hash = blake3::hash(b"x");
+        let err = store
+            .append_turn(
+                a,
+                t3,
+                "cxdb.ConversationItem".to_string(),
+                3,
+                1,
+                0,
+                1,
+                *hash.as_bytes(),
+                b"x",
+            )
+            .unwrap_err();
+        assert!(matches!(err, StoreError::InvalidParent(_)), "{err:?}");
+        assert!(store.fork_context(t3).is_err());
+        assert!(store.get_before(a, t3, 10, false).unwrap().is_empty());
+
+        // Trimming a turn shared with another context hides it from the
+        // trimmed context only.
+        let report = store.trim_context_before(c, u64::MAX).expect("trim c");
+        assert_eq!(report.turns_trimmed, 1);
+        assert!(report.gc_candidates.is_empty());
+        assert!(!store.turn_store.is_trimmed(t1));
+    }
+
+    let mut store = Store::open(dir.path()).expect("reopen store");
+    let ids = |store: &mut Store, ctx| -> Vec<u64> {
+        store
+            .get_last(ctx, 10, false)
+            .expect("get last")
+            .iter()
+            .map(|t| t.record.turn_id)
+            .collect()
+    };
+    assert_eq!(ids(&mut store, a), vec![t4]);
+    assert_eq!(ids(&mut store, b), vec![t1, b1]);
+    assert_eq!(ids(&mut store, c), vec![t2]);
+    assert!(store.fork_context(t1).is_ok());
+    assert!(store.turn_store.is_trimmed(t3));
+    assert!(!store.turn_store.is_trimmed(t2));
+    assert!(store.check().expect("check").is_ok());
+}